@@ -0,0 +1,10 @@
+#!/bin/sh
+# Example ECC keys for SB3.1 containers (LPC55S3x)
+
+for name in rot0_p256 rot1_p256 isk_p256; do
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:prime256v1 -out "$name.pem"
+    openssl pkey -in "$name.pem" -pubout -out "$name.pub.pem"
+done
+
+openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:secp384r1 -out rot0_p384.pem
+openssl pkey -in rot0_p384.pem -pubout -out rot0_p384.pub.pem
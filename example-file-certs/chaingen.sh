@@ -0,0 +1,21 @@
+#!/bin/bash
+# RSA-4096 root certificate and an image signing key (ISK) certificate it issues,
+# for signing with a certificate chain instead of the root key itself
+
+set -e
+
+subject="/C=US/ST=Delaware/O=SoloKeys/CN=solokeys.com/emailAddress=ca@solokeys.com"
+
+openssl genrsa -traditional -out ca4096_private_key.pem 4096
+openssl req -new -key ca4096_private_key.pem -out ca4096.csr -subj "$subject/OU=Firmware Authority 4096"
+# NB: see NXP AN12283, section 3.3 on this serial number
+openssl x509 -req -days 18250 -in ca4096.csr -signkey ca4096_private_key.pem -sha256 -outform der \
+	-out ca4096_certificate.der -extfile v3-ca.ext -set_serial 0x3cc30000abababab
+
+openssl genrsa -traditional -out isk_private_key.pem 4096
+openssl req -new -key isk_private_key.pem -out isk.csr -subj "$subject/OU=Image Signing Key"
+openssl x509 -req -days 18250 -in isk.csr -CA ca4096_certificate.der -CAform der \
+	-CAkey ca4096_private_key.pem -sha256 -outform der -out isk_certificate.der \
+	-extfile v3.ext -set_serial 0x3cc30000abababac
+
+rm ca4096.csr isk.csr
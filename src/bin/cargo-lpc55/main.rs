@@ -0,0 +1,267 @@
+//! `cargo lpc55`: build, sign, package and optionally flash firmware in one step.
+//!
+//! The configuration is the same as for `lpc55 sign-fw` and `lpc55 assemble-sb`, it is
+//! either inlined in the `[package.metadata.lpc55]` table of `Cargo.toml`, or referenced
+//! from there via `config = "path/to/config.toml"`.
+//!
+//! The built ELF file replaces `firmware.image`. If `firmware.signed-image` or
+//! `firmware.secure-boot-image` are left out, the outputs are placed next to the ELF file.
+//! As with `lpc55`, relative paths are relative to the working directory.
+
+use core::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context as _};
+use clap::{crate_authors, crate_version, Arg};
+use log::info;
+use uuid::Uuid;
+
+use lpc55::bootloader::{simulator, Bootloader};
+use lpc55::secure_binary::{Config, SignedSb21File, UnsignedSb21File};
+use lpc55::signed_binary::assemble_image;
+
+#[path = "../lpc55/logger.rs"]
+mod logger;
+
+const ABOUT: &str = "
+cargo lpc55 builds firmware, signs it, assembles an SB2.1 file, and optionally flashes it.
+
+The configuration is taken from `[package.metadata.lpc55]` in Cargo.toml, either inline
+or as `config = \"path/to/config.toml\"`. Arguments after `--` are passed to `cargo build`.
+";
+
+fn app() -> clap::Command<'static> {
+    clap::Command::new("cargo-lpc55")
+        .bin_name("cargo")
+        .author(crate_authors!())
+        .version(crate_version!())
+        .subcommand_required(true)
+        .subcommand(
+            clap::Command::new("lpc55")
+                .version(crate_version!())
+                .about(ABOUT)
+                .arg(
+                    Arg::new("v")
+                        .short('v')
+                        .multiple_occurrences(true)
+                        .help("Sets the level of verbosity (use multiple times to increase: -v = INFO, -vv = DEBUG, -vvv = TRACE)"),
+                )
+                .arg(
+                    Arg::new("manifest-path")
+                        .long("manifest-path")
+                        .value_name("PATH")
+                        .help("Path to Cargo.toml"),
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .value_name("CONFIG")
+                        .help("Configuration file, instead of [package.metadata.lpc55]"),
+                )
+                .arg(Arg::new("release").long("release").help("Build in release mode"))
+                .arg(
+                    Arg::new("bin")
+                        .long("bin")
+                        .value_name("NAME")
+                        .help("Binary to build, if the package has several"),
+                )
+                .arg(
+                    Arg::new("elf")
+                        .long("elf")
+                        .value_name("FILE")
+                        .conflicts_with_all(&["release", "bin"])
+                        .help("Use this ELF file instead of building"),
+                )
+                .arg(
+                    Arg::new("flash")
+                        .long("flash")
+                        .help("Send the SB2.1 file to the bootloader"),
+                )
+                .arg(
+                    Arg::new("VID")
+                        .long("vid")
+                        .takes_value(true)
+                        .requires("flash")
+                        .help("VID of bootloader (hex)"),
+                )
+                .arg(
+                    Arg::new("PID")
+                        .long("pid")
+                        .takes_value(true)
+                        .requires("flash")
+                        .help("PID of bootloader (hex)"),
+                )
+                .arg(
+                    Arg::new("UUID")
+                        .long("uuid")
+                        .takes_value(true)
+                        .requires("flash")
+                        .help("UUID of bootloader (hex)"),
+                )
+                .arg(
+                    Arg::new("SIMULATOR")
+                        .long("simulator")
+                        .value_name("STATE")
+                        .requires("flash")
+                        .help("Use the in-process bootloader simulator, persisting flash in this file"),
+                )
+                .arg(
+                    Arg::new("cargo-args")
+                        .last(true)
+                        .multiple_values(true)
+                        .help("Arguments for `cargo build`"),
+                ),
+        )
+}
+
+fn main() {
+    let args = app().get_matches();
+    let args = args.subcommand_matches("lpc55").unwrap();
+    if let Err(err) = try_main(args) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn try_main(args: &clap::ArgMatches) -> anyhow::Result<()> {
+    logger::Logger::init().unwrap();
+    log::set_max_level(match args.occurrences_of("v") {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    });
+
+    let manifest_path = PathBuf::from(args.value_of("manifest-path").unwrap_or("Cargo.toml"));
+    let mut config = match args.value_of("config") {
+        Some(config) => Config::try_from(config)?,
+        None => config_from_manifest(&manifest_path)?,
+    };
+
+    let elf = match args.value_of("elf") {
+        Some(elf) => PathBuf::from(elf),
+        None => build(&manifest_path, args)?,
+    };
+    info!("firmware: {}", elf.display());
+
+    config.firmware.image = elf.display().to_string();
+    if config.firmware.signed_image.is_empty() {
+        config.firmware.signed_image = elf.with_extension("signed.bin").display().to_string();
+    }
+    if config.firmware.secure_boot_image.is_empty() {
+        config.firmware.secure_boot_image = elf.with_extension("sb2").display().to_string();
+    }
+
+    let signed_image = assemble_image(&config)?;
+    fs::write(&config.firmware.signed_image, &signed_image)?;
+    println!("signed image: {}", config.firmware.signed_image);
+
+    let unsigned_sb_file = UnsignedSb21File::try_assemble_from(&config)?;
+    let signing_key = lpc55::pki::SigningKey::try_from_uri(config.pki.signing_key.as_ref())?;
+    let sb_file: SignedSb21File = unsigned_sb_file.sign(&signing_key);
+    let sb_file = sb_file.to_bytes();
+    fs::write(&config.firmware.secure_boot_image, &sb_file)?;
+    println!("secure boot image: {}", config.firmware.secure_boot_image);
+    if let Some(sidecar) = config.confidentiality.sidecar() {
+        fs::write(sidecar, unsigned_sb_file.sealed_sidecar())?;
+        println!("sealed sidecar: {}", sidecar);
+    }
+
+    if args.is_present("flash") {
+        let parse_id = |name: &str| {
+            args.value_of(name)
+                .map(|id| {
+                    u16::from_str_radix(id.trim_start_matches("0x"), 16)
+                        .map_err(|_| anyhow!("Could not parse {}", name))
+                })
+                .transpose()
+        };
+        let bootloader = match args.value_of("SIMULATOR") {
+            Some(path) => Bootloader::try_from_transport(
+                simulator::Simulator::persistent(path)?,
+                simulator::VID,
+                simulator::PID,
+            )?
+            .boxed(),
+            None => Bootloader::try_find(
+                parse_id("VID")?,
+                parse_id("PID")?,
+                args.value_of("UUID").map(Uuid::parse_str).transpose()?,
+            )
+            .context("Could not attach to a bootloader")?
+            .boxed(),
+        };
+        bootloader.receive_sb_file(&sb_file)?;
+        println!("flashed");
+    }
+
+    Ok(())
+}
+
+/// Reads `[package.metadata.lpc55]`, following a `config` reference if present.
+fn config_from_manifest(manifest_path: &Path) -> anyhow::Result<Config> {
+    let manifest = fs::read_to_string(manifest_path)
+        .with_context(|| format!("Failed to read manifest {}", manifest_path.display()))?;
+    let manifest: toml::Value = toml::from_str(&manifest)?;
+    let metadata = manifest
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("lpc55"))
+        .ok_or_else(|| {
+            anyhow!(
+                "no [package.metadata.lpc55] in {}, and no --config given",
+                manifest_path.display()
+            )
+        })?;
+
+    match metadata.get("config").and_then(|config| config.as_str()) {
+        Some(config) => Config::try_from(config),
+        // round-trip through a string, as some fields only deserialize from borrowed data
+        None => Ok(toml::from_str(&toml::to_string(metadata)?)?),
+    }
+}
+
+/// Runs `cargo build`, returning the path of the built executable.
+fn build(manifest_path: &Path, args: &clap::ArgMatches) -> anyhow::Result<PathBuf> {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut command = Command::new(cargo);
+    command
+        .args(["build", "--message-format=json-render-diagnostics"])
+        .arg("--manifest-path")
+        .arg(manifest_path);
+    if args.is_present("release") {
+        command.arg("--release");
+    }
+    if let Some(bin) = args.value_of("bin") {
+        command.args(["--bin", bin]);
+    }
+    if let Some(cargo_args) = args.values_of("cargo-args") {
+        command.args(cargo_args);
+    }
+    info!("running {:?}", &command);
+
+    let output = command
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run cargo build")?;
+    if !output.status.success() {
+        return Err(anyhow!("cargo build failed"));
+    }
+
+    let executables: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .filter_map(|message| message["executable"].as_str().map(PathBuf::from))
+        .collect();
+    match executables.as_slice() {
+        [executable] => Ok(executable.clone()),
+        [] => Err(anyhow!("cargo build produced no executable")),
+        _ => Err(anyhow!(
+            "cargo build produced {} executables, select one with --bin",
+            executables.len()
+        )),
+    }
+}
@@ -65,6 +65,32 @@ pub fn app() -> clap::Command<'static> {
              .global(true)
         )
 
+        .arg(Arg::new("UART")
+             .long("uart")
+             .help("Serial port of a bootloader in UART ISP mode, instead of USB")
+             .help_heading("SELECTION")
+             .takes_value(true)
+             .global(true)
+        )
+
+        .arg(Arg::new("BAUDRATE")
+             .long("baudrate")
+             .help("Baud rate for --uart")
+             .help_heading("SELECTION")
+             .takes_value(true)
+             .default_value("57600")
+             .global(true)
+        )
+
+        .arg(Arg::new("SIMULATOR")
+             .long("simulator")
+             .help("Use a simulated bootloader instead of a device, keeping its flash in the given file")
+             .help_heading("SELECTION")
+             .takes_value(true)
+             .conflicts_with("UART")
+             .global(true)
+        )
+
         .arg(Arg::new("v")
               .short('v')
               .long("verbose")
@@ -111,6 +137,11 @@ pub fn app() -> clap::Command<'static> {
                      .help("Configuration file containing settings")
                      .required(true)
                 )
+                .arg(Arg::new("force")
+                     .long("force")
+                     .help("Write even if the settings fail validation")
+                     .takes_value(false)
+                )
             )
             .subcommand(Command::new("customer-settings")
                 .version(crate_version!())
@@ -139,6 +170,11 @@ pub fn app() -> clap::Command<'static> {
                      .help("Do not increment customer version number as needed to make PFR write, and use the exact version from config.")
                      .takes_value(false)
                 )
+                .arg(Arg::new("force")
+                     .long("force")
+                     .help("Write even if the settings fail validation")
+                     .takes_value(false)
+                )
             )
         )
 
@@ -181,13 +217,13 @@ pub fn app() -> clap::Command<'static> {
                 .arg(Arg::new("KEY")
                     .help("name of key code")
                     .required(true)
-                    .possible_values(&KEYSTORE_KEY_NAMES)
+                    .possible_values(KEYSTORE_KEY_NAMES)
                 )
                 .arg(Arg::new("LENGTH")
                     .help("length in bytes of key to be generated") // (typical values 16 or 32)")
                     .required(true)
                     // more are possible, but let's make things easy for ourselves
-                    .possible_values(&[
+                    .possible_values([
                         "16",
                         "32",
                     ])
@@ -201,7 +237,7 @@ pub fn app() -> clap::Command<'static> {
                 .arg(Arg::new("KEY")
                     .help("name of key code")
                     .required(true)
-                    .possible_values(&KEYSTORE_KEY_NAMES)
+                    .possible_values(KEYSTORE_KEY_NAMES)
                 )
                 .arg(Arg::new("KEYDATA_FILENAME")
                      .help("filename of file containing the raw key data bytes")
@@ -218,6 +254,27 @@ pub fn app() -> clap::Command<'static> {
                 .about("ReadNonVolatile")
             )
 
+            .subcommand(Command::new("backup")
+                .version(crate_version!())
+                .about("save the keystore to a file, bound to the device UUID")
+                .arg(Arg::new("OUTPUT")
+                    .help("keystore backup file")
+                    .short('o')
+                    .long("output-file")
+                    .value_name("OUTPUT")
+                    .required(true)
+                )
+            )
+
+            .subcommand(Command::new("restore")
+                .version(crate_version!())
+                .about("restore a keystore backup to the device it was taken from")
+                .arg(Arg::new("BACKUP")
+                    .help("keystore backup file")
+                    .required(true)
+                )
+            )
+
         )
 
         .subcommand(Command::new("info")
@@ -234,12 +291,12 @@ pub fn app() -> clap::Command<'static> {
         .subcommand(Command::new("pfr")
             .version(crate_version!())
             .long_version(LONG_VERSION.as_str())
-            .about("read out and parse PFR")
+            .about("read out and parse PFR, and audit the keystore")
             .arg(Arg::new("FORMAT")
                  .help("Format to output the parsed PFR")
                  .long("format")
                  .default_value("json")
-                 .possible_values(&[
+                 .possible_values([
                      "native",
                      "alt-native",
                      "json",
@@ -263,6 +320,24 @@ pub fn app() -> clap::Command<'static> {
                     .help("Output the customer pfr pages to a 1536 byte binary file (raw, ping, and pong pages).")
                     .required(false)
             )
+            .subcommand(Command::new("diff")
+                .about("compare two PFR snapshots field by field")
+                .arg(Arg::new("BEFORE")
+                     .help("PFR dump (as written by `pfr --format raw`)")
+                     .required(true)
+                )
+                .arg(Arg::new("AFTER")
+                     .help("PFR dump to compare with, instead of the attached device")
+                     .required(false)
+                )
+            )
+            .subcommand(Command::new("keystore")
+                .about("decode keystore keycodes and check them for consistency")
+                .arg(Arg::new("DUMP")
+                     .help("PFR dump (as written by `pfr --format raw`), instead of the attached device")
+                     .required(false)
+                )
+            )
 
         )
 
@@ -313,6 +388,98 @@ pub fn app() -> clap::Command<'static> {
                  .takes_value(true))
         )
 
+        .subcommand(Command::new("fill-memory")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("fill memory with a repeated 32-bit pattern")
+            .arg(Arg::new("ADDRESS")
+                 .help("Address to start filling at")
+                 .required(true))
+            .arg(Arg::new("LENGTH")
+                 .help("Number of bytes to fill")
+                 .required(true))
+            .arg(Arg::new("PATTERN")
+                 .help("32-bit pattern, stored little-endian")
+                 .required(true))
+        )
+
+        .subcommand(Command::new("execute")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("jump to an application (the bootloader does not return)")
+            .arg(Arg::new("ADDRESS")
+                 .help("Address to jump to")
+                 .required(true))
+            .arg(Arg::new("ARGUMENT")
+                 .help("Argument passed in R0")
+                 .long("argument")
+                 .takes_value(true)
+                 .default_value("0"))
+            .arg(Arg::new("STACK POINTER")
+                 .help("Initial stack pointer (zero keeps the current one)")
+                 .long("stack-pointer")
+                 .takes_value(true)
+                 .default_value("0"))
+        )
+
+        .subcommand(Command::new("call")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("call a function, then return to the bootloader")
+            .arg(Arg::new("ADDRESS")
+                 .help("Address of the function")
+                 .required(true))
+            .arg(Arg::new("ARGUMENT")
+                 .help("Argument passed in R0")
+                 .long("argument")
+                 .takes_value(true)
+                 .default_value("0"))
+        )
+
+        .subcommand(Command::new("flash-read-once")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("read words of the one time programmable area (eFuse)")
+            .arg(Arg::new("INDEX")
+                 .help("Index of the first word")
+                 .required(true))
+            .arg(Arg::new("COUNT")
+                 .help("Number of words (1 or 2)")
+                 .possible_values(["1", "2"])
+                 .default_value("1"))
+        )
+
+        .subcommand(Command::new("flash-program-once")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("program words of the one time programmable area (eFuse), irreversibly")
+            .arg(Arg::new("INDEX")
+                 .help("Index of the first word")
+                 .required(true))
+            .arg(Arg::new("WORDS")
+                 .help("One or two words to program")
+                 .required(true)
+                 .min_values(1)
+                 .max_values(2))
+        )
+
+        .subcommand(Command::new("flash-read-resource")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("read flash IFR or flash firmware ID")
+            .arg(Arg::new("ADDRESS")
+                 .help("Address to start reading from")
+                 .required(true))
+            .arg(Arg::new("LENGTH")
+                 .help("Number of bytes to read")
+                 .required(true))
+            .arg(Arg::new("OPTION")
+                 .help("0 for flash IFR, 1 for flash firmware ID")
+                 .long("option")
+                 .takes_value(true)
+                 .default_value("0"))
+        )
+
         .subcommand(Command::new("receive-sb-file")
             .version(crate_version!())
             .long_version(LONG_VERSION.as_str())
@@ -334,7 +501,7 @@ pub fn app() -> clap::Command<'static> {
         .subcommand(Command::new("sign-fw")
             .version(crate_version!())
             .long_version(LONG_VERSION.as_str())
-            .about("sign firmware")
+            .about("sign firmware, or assemble a plain or CRC image (config.firmware.image-type)")
             .arg(Arg::new("CONFIG")
                  .help("Configuration file")
                  .required(true))
@@ -350,6 +517,79 @@ pub fn app() -> clap::Command<'static> {
             )
         )
 
+        .subcommand(Command::new("verify-fw")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("verify signed firmware image, or the CRC of a CRC image")
+            .arg(Arg::new("IMAGE")
+                 .help("Signed firmware image")
+                 .required(true))
+            .arg(Arg::new("config")
+                 .help("Check RoT key table against config.pki.certificates and config.factory-settings.rot-fingerprint")
+                 .long("config")
+                 .value_name("CONFIG")
+            )
+            .arg(Arg::new("rot-fingerprint")
+                 .help("Check RoT key table hash against this ROTKH, as 64 hex digits")
+                 .long("rot-fingerprint")
+                 .value_name("HEX")
+            )
+        )
+
+        .subcommand(Command::new("debug-credential")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("create or show Debug Credentials (DC) for debug authentication")
+            .subcommand_required(true)
+            .subcommand(Command::new("create")
+                .about("create a Debug Credential, signed by a RoT key")
+                .arg(Arg::new("CONFIG")
+                     .help("Configuration file with [pki] and [debug-credential]")
+                     .required(true))
+                .arg(Arg::new("OUTPUT")
+                     .short('o')
+                     .long("output")
+                     .value_name("OUTPUT")
+                     .help("Path to place the Debug Credential")
+                     .required(true))
+                .arg(Arg::new("device-uuid")
+                     .long("device-uuid")
+                     .value_name("HEX")
+                     .help("Device UUID to bind to, replaces config.debug-credential.uuid"))
+            )
+            .subcommand(Command::new("show")
+                .about("show and verify a Debug Credential")
+                .arg(Arg::new("CREDENTIAL")
+                     .help("Debug Credential file")
+                     .required(true))
+            )
+            .subcommand(Command::new("respond")
+                .about("answer a Debug Authentication Challenge (DAC) with a Debug Authentication Response (DAR)")
+                .arg(Arg::new("CHALLENGE")
+                     .help("Debug Authentication Challenge, as received from the device")
+                     .required(true))
+                .arg(Arg::new("CREDENTIAL")
+                     .help("Debug Credential file")
+                     .required(true))
+                .arg(Arg::new("dck")
+                     .long("dck")
+                     .value_name("URI")
+                     .help("Debug Credential Key, as `file:` or `pkcs11:` URI like `pki.signing-key`")
+                     .required(true))
+                .arg(Arg::new("beacon")
+                     .long("beacon")
+                     .value_name("BEACON")
+                     .help("Authentication beacon, passed on to the application")
+                     .default_value("0"))
+                .arg(Arg::new("OUTPUT")
+                     .short('o')
+                     .long("output")
+                     .value_name("OUTPUT")
+                     .help("Path to place the Debug Authentication Response")
+                     .required(true))
+            )
+        )
+
         .subcommand(Command::new("assemble-sb")
             .version(crate_version!())
             .long_version(LONG_VERSION.as_str())
@@ -357,6 +597,16 @@ pub fn app() -> clap::Command<'static> {
             .arg(Arg::new("CONFIG")
                  .help("Configuration file")
                  .required(true))
+            .arg(Arg::new("command-file")
+                 .help("elftosb command file (.bd). Replaces config.commands, config.sections and the firmware versions and build number")
+                 .long("command-file")
+                 .value_name("BD-FILE")
+            )
+            .arg(Arg::new("SOURCES")
+                 .help("Files for extern(0), extern(1), ... of the command file")
+                 .multiple_values(true)
+                 .requires("command-file")
+            )
             .arg(Arg::new("signed-image")
                  .help("Input firmware. Replaces config.firmware.signed_image entry")
                  .long("signed-image")
@@ -391,6 +641,34 @@ pub fn app() -> clap::Command<'static> {
             )
         )
 
+        .subcommand(Command::new("assemble-sb31")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("assemble SB3.1 image (LPC55S3x)")
+            .arg(Arg::new("CONFIG")
+                 .help("Configuration file")
+                 .required(true))
+            .arg(Arg::new("secure-boot-image")
+                 .help("Output file. Replaces config.firmware.secure_boot_image entry")
+                 .long("secure-boot-image")
+                 .value_name("secure-boot-image")
+            )
+        )
+
+        .subcommand(Command::new("import-config")
+            .version(crate_version!())
+            .long_version(LONG_VERSION.as_str())
+            .about("convert an elftosb JSON or spsdk YAML signing configuration into a configuration file")
+            .arg(Arg::new("FILE")
+                 .help("elftosb JSON or spsdk YAML master boot image configuration")
+                 .required(true))
+            .arg(Arg::new("OUTPUT")
+                 .help("configuration file to write (default: standard output)")
+                 .short('o')
+                 .long("output-file")
+                 .value_name("OUTPUT"))
+        )
+
         .subcommand(Command::new("sb")
             .version(crate_version!())
             .long_version(LONG_VERSION.as_str())
@@ -402,6 +680,36 @@ pub fn app() -> clap::Command<'static> {
                 .arg(Arg::new("FILE")
                      .help("file to show")
                      .required(true))
+                .arg(Arg::new("sbkek")
+                     .help("SBKEK to unwrap the keyblob, as 64 hex digits or hex:, file:, env: or pkcs11: URI (default: 0xAA repeated)")
+                     .long("sbkek")
+                     .value_name("KEY"))
+                .arg(Arg::new("config")
+                     .help("Take the SBKEK from `keystore.sbkek` of this configuration file")
+                     .long("config")
+                     .value_name("CONFIG")
+                     .conflicts_with("sbkek"))
+                .arg(Arg::new("pck")
+                     .help("PCK to decrypt SB3.1 files, as 64 hex digits (default: all zeros)")
+                     .long("pck")
+                     .value_name("HEX"))
+            )
+            .subcommand(Command::new("unseal")
+                .version(crate_version!())
+                .long_version(LONG_VERSION.as_str())
+                .about("print the [reproducibility] section recorded in a sealed sidecar of a confidential SB2.1 file")
+                .arg(Arg::new("SIDECAR")
+                     .help("sealed sidecar, as written by `assemble-sb`")
+                     .required(true))
+                .arg(Arg::new("sbkek")
+                     .help("SBKEK the sidecar is sealed with, as 64 hex digits or hex:, file:, env: or pkcs11: URI")
+                     .long("sbkek")
+                     .value_name("KEY"))
+                .arg(Arg::new("config")
+                     .help("Take the SBKEK from `keystore.sbkek` of this configuration file")
+                     .long("config")
+                     .value_name("CONFIG")
+                     .conflicts_with("sbkek"))
             )
         )
 
@@ -5,11 +5,13 @@ use std::fs;
 use std::io::{self, Write as _};
 
 use anyhow::{anyhow, Context as _};
-use delog::hex_str;
+use delog::{hex_str, hexstr};
 use log::{info, trace, warn};
 use uuid::Uuid;
 
-use lpc55::bootloader::{command, Bootloader, UuidSelectable as _};
+use lpc55::bootloader::{
+    command, simulator, transport::Transport, Bootloader, UuidSelectable as _,
+};
 
 mod cli;
 mod logger;
@@ -23,13 +25,57 @@ fn main() {
 }
 
 fn check_align(number: usize) -> anyhow::Result<()> {
-    if number % 512 == 0 {
+    if number.is_multiple_of(512) {
         Ok(())
     } else {
         Err(anyhow!("{} is not a multiple of 512", number))
     }
 }
 
+fn read_protected_flash<T: Transport>(
+    bootloader: &Bootloader<T>,
+) -> anyhow::Result<lpc55::protected_flash::ProtectedFlash> {
+    let raw = bootloader.read_memory(
+        lpc55::protected_flash::CUSTOMER_SETTINGS_SCRATCH_ADDRESS,
+        512 * 7,
+    )?;
+    lpc55::protected_flash::ProtectedFlash::try_from(&raw[..])
+        .map_err(|()| anyhow!("PFR read from the device does not parse (erased or corrupt?)"))
+}
+
+fn pfr_from_file(filename: &str) -> anyhow::Result<lpc55::protected_flash::ProtectedFlash> {
+    let data = fs::read(filename)?;
+    if data.len() != 7 * 512 {
+        return Err(anyhow!(
+            "{} is not a PFR dump, expected {} bytes, got {}",
+            filename,
+            7 * 512,
+            data.len()
+        ));
+    }
+    lpc55::protected_flash::ProtectedFlash::try_from(&data[..])
+        .map_err(|()| anyhow!("{} is not a valid PFR dump (erased or corrupt?)", filename))
+}
+
+/// Prints the findings, fails on errors unless forced.
+fn check_rules(rules: lpc55::protected_flash::rules::Rules<'_>, force: bool) -> anyhow::Result<()> {
+    let report = rules.check();
+    for finding in &report.0 {
+        eprintln!("{}", finding);
+    }
+    if !report.is_ok() {
+        let errors = report.errors().count();
+        if !force {
+            return Err(anyhow!(
+                "refusing to write settings with {} error(s), use --force to override",
+                errors
+            ));
+        }
+        warn!("writing settings despite {} error(s)", errors);
+    }
+    Ok(())
+}
+
 fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
     logger::Logger::init().unwrap();
 
@@ -59,8 +105,28 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
 
     let uuid = args.value_of("UUID").map(Uuid::parse_str).transpose()?;
 
-    let bootloader =
-        || Bootloader::try_find(vid, pid, uuid).context("Could not attach to a bootloader");
+    let uart = args.value_of("UART");
+    let simulator = args.value_of("SIMULATOR");
+    let baudrate = args
+        .value_of("BAUDRATE")
+        .unwrap()
+        .parse::<u32>()
+        .map_err(|_| anyhow!("Could not parse baud rate"))?;
+
+    let bootloader = || -> anyhow::Result<Bootloader<Box<dyn Transport>>> {
+        if let Some(path) = simulator {
+            let simulator = simulator::Simulator::persistent(path)?;
+            return Ok(
+                Bootloader::try_from_transport(simulator, simulator::VID, simulator::PID)?.boxed(),
+            );
+        }
+        match uart {
+            Some(path) => Ok(Bootloader::try_new_uart(path, baudrate)?.boxed()),
+            None => Ok(Bootloader::try_find(vid, pid, uuid)
+                .context("Could not attach to a bootloader")?
+                .boxed()),
+        }
+    };
 
     if let Some(command) = args.subcommand_matches("http") {
         let bootloader = bootloader()?;
@@ -86,20 +152,20 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
 
     if let Some(_command) = args.subcommand_matches("info") {
         let bootloader = bootloader()?;
-        bootloader.info();
-        println!("{:#?}", bootloader.all_properties());
+        bootloader.info()?;
+        println!("{:#?}", bootloader.all_properties()?);
         return Ok(());
     }
 
     if args.subcommand_matches("reboot").is_some() {
         let bootloader = bootloader()?;
-        bootloader.reboot();
+        bootloader.reboot()?;
     }
 
     if let Some(subcommand) = args.subcommand_matches("configure") {
         if let Some(subcommand) = subcommand.subcommand_matches("factory-settings") {
             let config_path = std::path::Path::new(subcommand.value_of("CONFIG").unwrap());
-            let settings = fs::read_to_string(&config_path)?;
+            let settings = fs::read_to_string(config_path)?;
             let mut wrapped_settings: lpc55::protected_flash::WrappedFactorySettings =
                 match config_path.extension() {
                     Some(extension) => match extension {
@@ -117,28 +183,40 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
 
             info!("settings: {:#?}", &wrapped_settings.factory_settings);
 
+            let bootloader = match subcommand.value_of("OUTPUT") {
+                None => Some(bootloader()?),
+                Some(_) => None,
+            };
+            let device = match &bootloader {
+                Some(bootloader) => Some(read_protected_flash(bootloader)?),
+                None => None,
+            };
+            check_rules(
+                lpc55::protected_flash::rules::Rules {
+                    factory_settings: Some(&wrapped_settings.factory_settings),
+                    customer_settings: None,
+                    device: device.as_ref(),
+                },
+                subcommand.is_present("force"),
+            )?;
+
             let settings = Vec::from(wrapped_settings.factory_settings.to_bytes()?.as_ref());
 
             trace!("binary settings:\n{}", hex_str!(&settings, 4, sep: "\n"));
 
-            if subcommand.value_of("OUTPUT").is_none() {
-                let bootloader = bootloader()?;
-                bootloader.write_memory(lpc55::protected_flash::FACTORY_SETTINGS_ADDRESS, settings);
+            if let Some(bootloader) = bootloader {
+                bootloader
+                    .write_memory(lpc55::protected_flash::FACTORY_SETTINGS_ADDRESS, settings)?;
             } else {
                 let output_name = subcommand.value_of("OUTPUT").unwrap();
-                fs::write(&output_name, &settings).expect("Unable to write file");
+                fs::write(output_name, &settings).expect("Unable to write file");
                 println!("outputing to {}", output_name);
             }
         }
 
-        // TODO: https://github.com/NXPmicro/spsdk/blob/020a983e53769fe16cb9b49395d56f0201eccca6/spsdk/data/pfr/rules.json#L51-L61
-        //
-        // - ensure DICE key calc is disabled
-        // - if secure-boot is on, at least one of RoT keys must be enabled
-
         if let Some(subcommand) = subcommand.subcommand_matches("customer-settings") {
             let config_path = std::path::Path::new(subcommand.value_of("CONFIG").unwrap());
-            let settings = fs::read_to_string(&config_path)?;
+            let settings = fs::read_to_string(config_path)?;
             let wrapped_settings: lpc55::protected_flash::WrappedCustomerSettings =
                 match config_path.extension() {
                     Some(extension) => match extension {
@@ -160,9 +238,15 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
             if subcommand.value_of("OUTPUT").is_none() {
                 let bootloader = bootloader()?;
 
-                let current_pfr_raw = bootloader.read_memory(0x9_DE00, 512 * 7);
+                let current_pfr_raw = bootloader.read_memory(
+                    lpc55::protected_flash::CUSTOMER_SETTINGS_SCRATCH_ADDRESS,
+                    512 * 7,
+                )?;
                 let current_pfr =
-                    lpc55::protected_flash::ProtectedFlash::try_from(&current_pfr_raw[..]).unwrap();
+                    lpc55::protected_flash::ProtectedFlash::try_from(&current_pfr_raw[..])
+                        .map_err(|()| {
+                            anyhow!("PFR read from the device does not parse (erased or corrupt?)")
+                        })?;
                 let latest_pfr = current_pfr.customer.most_recent();
 
                 if !subcommand.is_present("dont-increment") {
@@ -191,15 +275,35 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
                     settings[protect.clone()].clone_from_slice(&current_pfr_raw[protect]);
                 }
 
+                // check what is actually written, after preserving fields
+                let effective =
+                    lpc55::protected_flash::CustomerSettings::try_from(&settings[..]).unwrap();
+                check_rules(
+                    lpc55::protected_flash::rules::Rules {
+                        factory_settings: None,
+                        customer_settings: Some(&effective),
+                        device: Some(&current_pfr),
+                    },
+                    subcommand.is_present("force"),
+                )?;
+
                 trace!("writing pfr: {}", hex_str!(&settings));
 
                 bootloader.write_memory(
                     lpc55::protected_flash::CUSTOMER_SETTINGS_SCRATCH_ADDRESS,
                     settings,
-                );
+                )?;
             } else {
+                check_rules(
+                    lpc55::protected_flash::rules::Rules {
+                        factory_settings: None,
+                        customer_settings: Some(&settings),
+                        device: None,
+                    },
+                    subcommand.is_present("force"),
+                )?;
                 let output_name = subcommand.value_of("OUTPUT").unwrap();
-                fs::write(&output_name, Vec::from(settings.to_bytes()?.as_ref()))
+                fs::write(output_name, Vec::from(settings.to_bytes()?.as_ref()))
                     .expect("Unable to write file");
                 println!("outputing to {}", output_name);
             }
@@ -209,7 +313,7 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
     if let Some(subcommand) = args.subcommand_matches("keystore") {
         if subcommand.subcommand_matches("enroll-puf").is_some() {
             let bootloader = bootloader()?;
-            bootloader.enroll_puf();
+            bootloader.enroll_puf()?;
             return Ok(());
         }
 
@@ -218,12 +322,12 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
             // let data = bootloader.read_memory(0x9_DE60, 3*512);
 
             let command = command::Command::Keystore(command::KeystoreOperation::ReadKeystore);
-            let response = bootloader.protocol.call(&command).expect("success");
+            let response = bootloader.protocol.call(&command)?;
 
             let data = if let command::Response::Data(data) = response {
                 data
             } else {
-                todo!();
+                return Err(anyhow!("Unexpected response {:?}", response));
             };
 
             let keystore = lpc55::protected_flash::Keystore::try_from(data.as_slice()).unwrap();
@@ -239,7 +343,7 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
             let command =
                 command::Command::Keystore(command::KeystoreOperation::GenerateKey { key, len });
 
-            bootloader.protocol.call(&command).expect("success");
+            bootloader.protocol.call(&command)?;
             return Ok(());
         }
 
@@ -252,7 +356,7 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
             let command =
                 command::Command::Keystore(command::KeystoreOperation::SetKey { key, data });
 
-            bootloader.protocol.call(&command).expect("success");
+            bootloader.protocol.call(&command)?;
             return Ok(());
         }
 
@@ -261,7 +365,23 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
 
             let command = command::Command::Keystore(command::KeystoreOperation::WriteNonVolatile);
 
-            bootloader.protocol.call(&command).expect("success");
+            bootloader.protocol.call(&command)?;
+            return Ok(());
+        }
+
+        if let Some(command) = subcommand.subcommand_matches("backup") {
+            let bootloader = bootloader()?;
+            let backup = bootloader.backup_keystore()?;
+            fs::write(command.value_of("OUTPUT").unwrap(), backup.to_bytes())?;
+            print!("{}", backup.keystore.report());
+            return Ok(());
+        }
+
+        if let Some(command) = subcommand.subcommand_matches("restore") {
+            let bootloader = bootloader()?;
+            let data = fs::read(command.value_of("BACKUP").unwrap())?;
+            let backup = lpc55::protected_flash::keystore::KeystoreBackup::from_bytes(&data)?;
+            bootloader.restore_keystore(&backup)?;
             return Ok(());
         }
 
@@ -270,25 +390,54 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
 
             let command = command::Command::Keystore(command::KeystoreOperation::ReadNonVolatile);
 
-            bootloader.protocol.call(&command).expect("success");
+            bootloader.protocol.call(&command)?;
             return Ok(());
         }
     }
 
     if let Some(command) = args.subcommand_matches("pfr") {
+        if let Some(command) = command.subcommand_matches("diff") {
+            let before = pfr_from_file(command.value_of("BEFORE").unwrap())?;
+            let after = match command.value_of("AFTER") {
+                Some(filename) => pfr_from_file(filename)?,
+                None => read_protected_flash(&bootloader()?)?,
+            };
+            print!("{}", before.diff(&after));
+            return Ok(());
+        }
+        if let Some(command) = command.subcommand_matches("keystore") {
+            let pfr = match command.value_of("DUMP") {
+                Some(filename) => pfr_from_file(filename)?,
+                None => read_protected_flash(&bootloader()?)?,
+            };
+            let report = pfr.keystore.report();
+            print!("{}", report);
+            if !report.is_ok() {
+                return Err(anyhow!(
+                    "keystore is inconsistent, {} error(s)",
+                    report.findings.errors().count()
+                ));
+            }
+            return Ok(());
+        }
+
         let bootloader = bootloader()?;
-        let data = bootloader.read_memory(0x9_DE00, 7 * 512);
+        let data = bootloader.read_memory(0x9_DE00, 7 * 512)?;
         // let empty = data.iter().all(|&byte| byte == 0);
         // if empty {
         //     println!("PFR region is completely zeroed out");
         // } else {
         //     println!("PFR region is not completely zeroed out");
         // }
-        let pfr = lpc55::protected_flash::ProtectedFlash::try_from(&data[..]).unwrap();
+        let pfr = lpc55::protected_flash::ProtectedFlash::try_from(&data[..]).map_err(|()| {
+            anyhow!("PFR read from the device does not parse (erased or corrupt?)")
+        })?;
         // println!("PFR = {:#?}", &pfr);
         // println!("PFR = {:?}", &pfr);
+        let keystore_report = pfr.keystore.report();
 
-        match command.value_of("FORMAT").unwrap() {
+        let format = command.value_of("FORMAT").unwrap();
+        match format {
             "alt-native" => println!("{:#?}", &pfr),
             "native" => println!("{:?}", &pfr),
             "json" => println!("{}", serde_json::to_string(&pfr).unwrap()),
@@ -300,17 +449,22 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
                     io::stdout().write_all(&data).unwrap()
                 }
             }
-            "toml" => println!("{}", toml::Value::try_from(&pfr).unwrap()),
+            "toml" => println!("{}", toml::Value::try_from(pfr).unwrap()),
             "yaml" => println!("{}", serde_yaml::to_string(&pfr).unwrap()),
             // "yaml-pretty" => println!("{}", serde_yaml::to_string_pretty(&pfr).unwrap()),
             _ => panic!(),
         }
+        // keep machine-readable output parseable
+        match format {
+            "native" | "alt-native" => print!("keystore:\n{}", keystore_report),
+            _ => eprint!("keystore:\n{}", keystore_report),
+        }
         if let Some(filename) = command.value_of("OUTPUT FACTORY") {
-            fs::write(&filename, &data[512 * 3..512 * 4]).expect("Unable to write file");
+            fs::write(filename, &data[512 * 3..512 * 4]).expect("Unable to write file");
         }
 
         if let Some(filename) = command.value_of("OUTPUT CUSTOMER") {
-            fs::write(&filename, &data[0..512 * 3]).expect("Unable to write file");
+            fs::write(filename, &data[0..512 * 3]).expect("Unable to write file");
         }
     }
 
@@ -320,7 +474,7 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
         check_align(address)?;
         let data = fs::read(command.value_of("INPUT").unwrap()).unwrap();
         check_align(data.len())?;
-        bootloader.write_memory(address, data);
+        bootloader.write_memory(address, data)?;
         return Ok(());
     }
 
@@ -334,8 +488,8 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
         if overshoot > 0 {
             data.resize(length + (512 - overshoot), 0);
         }
-        bootloader.erase_flash(address, data.len());
-        bootloader.write_memory(address, data);
+        bootloader.erase_flash(address, data.len())?;
+        bootloader.write_memory(address, data)?;
         return Ok(());
     }
 
@@ -344,7 +498,7 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
         let address = command.value_of_t("ADDRESS")?;
         let length = command.value_of_t("LENGTH")?;
         // let data = bootloader.read_memory_at_most_512(address, length);
-        let data = bootloader.read_memory(address, length);
+        let data = bootloader.read_memory(address, length)?;
 
         if let Some(output_filename) = command.value_of("OUTPUT") {
             let mut file = fs::File::create(output_filename)?;
@@ -358,11 +512,65 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
         return Ok(());
     }
 
+    if let Some(command) = args.subcommand_matches("fill-memory") {
+        let bootloader = bootloader()?;
+        let address = command.value_of_t("ADDRESS")?;
+        let length = command.value_of_t("LENGTH")?;
+        let pattern = command.value_of_t("PATTERN")?;
+        bootloader.fill_memory(address, length, pattern)?;
+        return Ok(());
+    }
+
+    if let Some(command) = args.subcommand_matches("execute") {
+        let bootloader = bootloader()?;
+        let address = command.value_of_t("ADDRESS")?;
+        let argument = command.value_of_t("ARGUMENT")?;
+        let stack_pointer = command.value_of_t("STACK POINTER")?;
+        bootloader.execute(address, argument, stack_pointer)?;
+        return Ok(());
+    }
+
+    if let Some(command) = args.subcommand_matches("call") {
+        let bootloader = bootloader()?;
+        let address = command.value_of_t("ADDRESS")?;
+        let argument = command.value_of_t("ARGUMENT")?;
+        bootloader.call(address, argument)?;
+        return Ok(());
+    }
+
+    if let Some(command) = args.subcommand_matches("flash-read-once") {
+        let bootloader = bootloader()?;
+        let index = command.value_of_t("INDEX")?;
+        let count = command.value_of_t("COUNT")?;
+        for word in bootloader.flash_read_once(index, count)? {
+            println!("{:08X}", word);
+        }
+        return Ok(());
+    }
+
+    if let Some(command) = args.subcommand_matches("flash-program-once") {
+        let bootloader = bootloader()?;
+        let index = command.value_of_t("INDEX")?;
+        let words = command.values_of_t("WORDS")?;
+        bootloader.flash_program_once(index, words)?;
+        return Ok(());
+    }
+
+    if let Some(command) = args.subcommand_matches("flash-read-resource") {
+        let bootloader = bootloader()?;
+        let address = command.value_of_t("ADDRESS")?;
+        let length = command.value_of_t("LENGTH")?;
+        let option = command.value_of_t("OPTION")?;
+        let data = bootloader.flash_read_resource(address, length, option)?;
+        println!("{}", hex_str!(&data, 16));
+        return Ok(());
+    }
+
     if let Some(command) = args.subcommand_matches("receive-sb-file") {
         let bootloader = bootloader()?;
         let filename = command.value_of("SB-FILE").unwrap();
-        let image = fs::read(&filename)?;
-        bootloader.receive_sb_file(&image);
+        let image = fs::read(filename)?;
+        bootloader.receive_sb_file(&image)?;
         return Ok(());
     }
 
@@ -389,7 +597,7 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
     }
 
     if let Some(command) = args.subcommand_matches("sign-fw") {
-        use lpc55::{secure_binary::Config, signed_binary::ImageSigningRequest};
+        use lpc55::secure_binary::Config;
         let config_filename = command.value_of("CONFIG").unwrap();
         let mut config = Config::try_from(config_filename)?;
         if let Some(image) = command.value_of("image") {
@@ -399,9 +607,8 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
             config.firmware.signed_image = signed_image.to_string();
         }
         // let _signed_image = lpc55::signed_binary::sign(&config)?;
-        let signing_request = ImageSigningRequest::try_from(&config)?;
-        let signed_image = signing_request.sign();
-        fs::write(&config.firmware.signed_image, &signed_image.0)?;
+        let image = lpc55::signed_binary::assemble_image(&config)?;
+        fs::write(&config.firmware.signed_image, &image)?;
 
         //////////////////////////////////////////////////////
         //
@@ -411,10 +618,112 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
         //////////////////////////////////////////////////////
     }
 
+    if let Some(command) = args.subcommand_matches("debug-credential") {
+        use lpc55::debug_auth;
+        if let Some(command) = command.subcommand_matches("create") {
+            let mut config =
+                debug_auth::DebugCredentialConfig::try_from(command.value_of("CONFIG").unwrap())?;
+            if let Some(uuid) = command.value_of("device-uuid") {
+                config.debug_credential.uuid = uuid.to_string();
+            }
+            let credential = debug_auth::CredentialSigningRequest::try_from(&config)?.sign();
+            let output = command.value_of("OUTPUT").unwrap();
+            fs::write(output, credential.to_bytes())?;
+            debug_auth::show(output)?;
+        }
+        if let Some(command) = command.subcommand_matches("show") {
+            let filename = command.value_of("CREDENTIAL").unwrap();
+            if !debug_auth::show(filename)?.verify() {
+                return Err(anyhow!("{} failed verification", filename));
+            }
+        }
+        if let Some(command) = command.subcommand_matches("respond") {
+            let challenge = debug_auth::DebugAuthenticationChallenge::from_bytes(&fs::read(
+                command.value_of("CHALLENGE").unwrap(),
+            )?)?;
+            println!("device uuid: {}", challenge.uuid.to_simple());
+            println!("device rotkh: {}", hexstr!(&challenge.rot_fingerprint.0));
+            println!("{:#?}", challenge.debug_settings());
+
+            let credential = debug_auth::DebugCredential::from_bytes(&fs::read(
+                command.value_of("CREDENTIAL").unwrap(),
+            )?)?;
+            let dck = lpc55::pki::SigningKey::try_from_uri(command.value_of("dck").unwrap())?;
+            let response = debug_auth::DebugAuthenticationResponse::sign(
+                &challenge,
+                &credential,
+                &dck,
+                command.value_of_t("beacon")?,
+            )?;
+            fs::write(command.value_of("OUTPUT").unwrap(), response.to_bytes())?;
+        }
+        return Ok(());
+    }
+
+    if let Some(command) = args.subcommand_matches("verify-fw") {
+        use lpc55::pki::{Certificates, Sha256Hash};
+        let filename = command.value_of("IMAGE").unwrap();
+        let header = lpc55::signed_binary::ImageHeader::from_bytes(
+            &fs::read(filename)
+                .with_context(|| format!("Failed to read image from {}", filename))?,
+        )?;
+        if !header.kind()?.is_signed() {
+            if !lpc55::signed_binary::show_unsigned(filename)? {
+                return Err(anyhow!("{} failed verification", filename));
+            }
+            return Ok(());
+        }
+        let verification = lpc55::signed_binary::show(filename)?;
+        let mut is_valid = verification.is_valid();
+        let status = |ok: bool| if ok { "ok" } else { "FAILED" };
+
+        let mut rot_fingerprints = Vec::new();
+        if let Some(config_filename) = command.value_of("config") {
+            let config = lpc55::secure_binary::Config::try_from(config_filename)?;
+            let certificates = Certificates::try_from_pki(&config.pki)?;
+            let matches = verification.matches_certificates(&certificates);
+            println!("  config certificates: {}", status(matches));
+            is_valid &= matches;
+            if config.factory_settings.rot_fingerprint != Sha256Hash::default() {
+                rot_fingerprints.push(config.factory_settings.rot_fingerprint);
+            }
+        }
+        if let Some(rot_fingerprint) = command.value_of("rot-fingerprint") {
+            let mut rot_fingerprint = rot_fingerprint.to_string();
+            rot_fingerprint.retain(|c| !c.is_whitespace());
+            rot_fingerprints.push(Sha256Hash(
+                hex::decode(&rot_fingerprint)?
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| anyhow!("ROTKH must be 32 bytes"))?,
+            ));
+        }
+        for rot_fingerprint in rot_fingerprints {
+            let matches = verification.matches_rot_fingerprint(&rot_fingerprint);
+            println!(
+                "  rot fingerprint {}: {}",
+                hexstr!(&rot_fingerprint.0),
+                status(matches)
+            );
+            is_valid &= matches;
+        }
+
+        if !is_valid {
+            return Err(anyhow!("{} failed verification", filename));
+        }
+    }
+
     if let Some(command) = args.subcommand_matches("assemble-sb") {
         use lpc55::secure_binary::{SignedSb21File, UnsignedSb21File};
         let config_filename = command.value_of("CONFIG").unwrap();
         let mut config = lpc55::secure_binary::Config::try_from(config_filename)?;
+        if let Some(command_file) = command.value_of("command-file") {
+            let externs: Vec<&str> = command
+                .values_of("SOURCES")
+                .map(|sources| sources.collect())
+                .unwrap_or_default();
+            lpc55::secure_binary::bd::read(command_file, &externs)?.apply(&mut config);
+        }
         if let Some(signed_image) = command.value_of("signed-image") {
             config.firmware.signed_image = signed_image.to_string();
         }
@@ -436,7 +745,8 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
             let date = NaiveDate::parse_from_str(product_date, "%Y-%m-%d")
                 .or_else(|_| NaiveDate::parse_from_str(product_date, "%Y%m%d"))
                 .or_else(|_| NaiveDate::parse_from_str(product_date, "%y%m%d"))?;
-            let days_since_twenties = (date - NaiveDate::from_ymd(2020, 1, 1)).num_days();
+            let days_since_twenties =
+                (date - NaiveDate::from_ymd_opt(2020, 1, 1).unwrap()).num_days();
             assert!(days_since_twenties > 0);
             info!(
                 "overriding product.major with date {}, i.e. {}",
@@ -450,13 +760,89 @@ fn try_main(args: clap::ArgMatches) -> anyhow::Result<()> {
         let signed_image: SignedSb21File = unsigned_image.sign(&signing_key);
         let signed_image_bytes = signed_image.to_bytes();
         fs::write(&config.firmware.secure_boot_image, &signed_image_bytes)?;
+        if let Some(sidecar) = config.confidentiality.sidecar() {
+            fs::write(sidecar, unsigned_image.sealed_sidecar())?;
+        }
         // dbg!(signed_image_bytes.len());
     }
 
+    if let Some(command) = args.subcommand_matches("assemble-sb31") {
+        use lpc55::secure_binary::sb31::{Config, EcSigningKey, UnsignedSb31File};
+        let config_filename = command.value_of("CONFIG").unwrap();
+        let mut config = Config::try_from(config_filename)?;
+        if let Some(secure_boot_image) = command.value_of("secure-boot-image") {
+            config.firmware.secure_boot_image = secure_boot_image.to_string();
+        }
+        let unsigned_image = UnsignedSb31File::try_assemble_from(&config)?;
+        // the ISK signs the container if there is one, the root key otherwise
+        let signing_key = config
+            .pki
+            .isk_key
+            .as_ref()
+            .unwrap_or(&config.pki.signing_key);
+        let signed_image = unsigned_image.sign(&EcSigningKey::try_from_uri(signing_key)?)?;
+        fs::write(&config.firmware.secure_boot_image, signed_image.to_bytes())?;
+    }
+
+    if let Some(command) = args.subcommand_matches("import-config") {
+        let import = lpc55::secure_binary::import::import(command.value_of("FILE").unwrap())?;
+        for unsupported in &import.unsupported {
+            warn!("not imported: {}", unsupported);
+        }
+        let toml = toml::to_string(&import.config)?;
+        match command.value_of("OUTPUT") {
+            Some(output) => fs::write(output, toml)?,
+            None => print!("{}", toml),
+        }
+    }
+
     if let Some(subcommand) = args.subcommand_matches("sb") {
+        let sbkek = |command: &clap::ArgMatches| -> anyhow::Result<[u8; 32]> {
+            Ok(
+                match (command.value_of("sbkek"), command.value_of("config")) {
+                    (Some(uri), _) => lpc55::secure_binary::sbkek_from_uri(uri)?,
+                    (None, Some(config)) => {
+                        lpc55::secure_binary::Config::try_from(config)?.sbkek()?
+                    }
+                    (None, None) => *lpc55::secure_binary::Keyblob::SBKEK,
+                },
+            )
+        };
+
+        if let Some(command) = subcommand.subcommand_matches("unseal") {
+            let sealed = fs::read(command.value_of("SIDECAR").unwrap())?;
+            let reproducibility =
+                lpc55::secure_binary::Reproducibility::unseal(&sealed, &sbkek(command)?)?;
+            print!("{}", reproducibility.to_toml());
+            return Ok(());
+        }
+
         if let Some(command) = subcommand.subcommand_matches("show") {
+            use lpc55::secure_binary::{sniff, Filetype};
             let filename = command.value_of("FILE").unwrap();
-            lpc55::secure_binary::show(filename)?;
+            let key = |name: &str| -> anyhow::Result<Option<[u8; 32]>> {
+                command
+                    .value_of(name)
+                    .map(|key| {
+                        let mut key = key.to_string();
+                        key.retain(|c| !c.is_whitespace());
+                        hex::decode(&key)?
+                            .as_slice()
+                            .try_into()
+                            .map_err(|_| anyhow::anyhow!("{} must be 32 bytes", name))
+                    })
+                    .transpose()
+            };
+            let is_valid = match sniff(&fs::read(filename)?)? {
+                Filetype::Sb31 => {
+                    let pck = key("pck")?.unwrap_or_default();
+                    lpc55::secure_binary::sb31::show(filename, &pck)?.is_valid()
+                }
+                _ => lpc55::secure_binary::show(filename, &sbkek(command)?)?.is_valid(),
+            };
+            if !is_valid {
+                return Err(anyhow::anyhow!("{} failed verification", filename));
+            }
         }
     }
 
@@ -11,6 +11,8 @@ use hidapi::HidApi;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::protected_flash::{keystore::KeystoreBackup, Keystore, KEYSTORE_ADDRESS};
+
 pub mod command;
 pub use command::{Command, KeystoreOperation, Response};
 pub mod error;
@@ -18,7 +20,10 @@ pub mod property;
 pub use property::{GetProperties, Properties, Property};
 pub mod protocol;
 pub mod provision;
+pub mod simulator;
+pub mod transport;
 use protocol::Protocol;
+use transport::{HidTransport, Transport};
 
 pub trait UuidSelectable: Sized {
     /// Returns the UUID associated with the thing, if it has a UUID.
@@ -61,8 +66,11 @@ pub trait UuidSelectable: Sized {
     }
 }
 
-pub struct Bootloader {
-    pub protocol: Protocol,
+/// Size of a key blob, as requested by `blhost generate-key-blob`
+const KEY_BLOB_SIZE: usize = 72;
+
+pub struct Bootloader<T: Transport = HidTransport> {
+    pub protocol: Protocol<T>,
     // move around; also "new" should scan the device_list iterator
     // to pull out all the info
     pub vid: u16,
@@ -70,7 +78,7 @@ pub struct Bootloader {
     pub uuid: u128,
 }
 
-impl fmt::Debug for Bootloader {
+impl<T: Transport> fmt::Debug for Bootloader<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Bootloader")
             .field("vid", &hexstr!(&self.vid.to_be_bytes()))
@@ -80,7 +88,7 @@ impl fmt::Debug for Bootloader {
     }
 }
 
-impl fmt::Display for Bootloader {
+impl<T: Transport> fmt::Display for Bootloader<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt::Debug::fmt(self, f)
     }
@@ -89,9 +97,6 @@ impl fmt::Display for Bootloader {
 /// Bootloader commands return a "status". The non-zero statii can be split
 /// as `100*group + code`. We map these groups into enum variants, containing
 /// the code interpreted as an error the area.
-///
-/// TODO: To implement StdError via thiserror::Error, we need to
-/// add error messages to all the error variants.
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Error {
     Generic(error::GenericError),
@@ -102,6 +107,17 @@ pub enum Error {
     Unknown(u32),
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unknown(status) => write!(f, "unknown status {}", status),
+            error => write!(f, "{:?} (status {})", error, u32::from(*error)),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 impl UuidSelectable for Bootloader {
@@ -168,11 +184,59 @@ impl UuidSelectable for Bootloader {
     }
 }
 
-impl Bootloader {
+impl<T: Transport> Bootloader<T> {
     fn uuid(&self) -> Uuid {
         Uuid::from_u128(self.uuid)
     }
 
+    /// Attach to the ROM bootloader on the other end of the given transport.
+    ///
+    /// Queries the device UUID; VID and PID are only meaningful for USB links,
+    /// pass zero otherwise.
+    pub fn try_from_transport(transport: T, vid: u16, pid: u16) -> anyhow::Result<Self> {
+        let protocol = Protocol::from_transport(transport);
+        let uuid = GetProperties {
+            protocol: &protocol,
+        }
+        .device_uuid()
+        .map_err(|error| anyhow!("Could not read device UUID: {}", error))?;
+        Ok(Self {
+            protocol,
+            vid,
+            pid,
+            uuid,
+        })
+    }
+
+    /// Erase the transport type, so bootloaders attached via different links can be
+    /// handled uniformly.
+    pub fn boxed(self) -> Bootloader<Box<dyn Transport>>
+    where
+        T: 'static,
+    {
+        Bootloader {
+            protocol: Protocol::from_transport(Box::new(self.protocol.into_transport())),
+            vid: self.vid,
+            pid: self.pid,
+            uuid: self.uuid,
+        }
+    }
+}
+
+#[cfg(feature = "uart")]
+impl Bootloader<transport::UartTransport> {
+    /// Attach to a ROM bootloader in UART ISP mode, on the given serial port.
+    ///
+    /// There is no VID/PID over UART, these are set to zero.
+    pub fn try_new_uart(path: &str, baudrate: u32) -> anyhow::Result<Self> {
+        use anyhow::Context as _;
+        let transport = transport::UartTransport::open(path, baudrate)
+            .with_context(|| format!("Could not ping bootloader on {}", path))?;
+        Self::try_from_transport(transport, 0, 0)
+    }
+}
+
+impl Bootloader {
     /// Select a unique ROM bootloader with the given VID and PID.
     pub fn try_new(vid: Option<u16>, pid: Option<u16>) -> anyhow::Result<Self> {
         Self::try_find(vid, pid, None)
@@ -198,33 +262,91 @@ impl Bootloader {
     pub fn find(vid: Option<u16>, pid: Option<u16>, uuid: Option<Uuid>) -> Vec<Self> {
         Self::list()
             .into_iter()
-            .filter(|bootloader| vid.map_or(true, |vid| vid == bootloader.vid))
-            .filter(|bootloader| pid.map_or(true, |pid| pid == bootloader.pid))
-            .filter(|bootloader| uuid.map_or(true, |uuid| uuid.as_u128() == bootloader.uuid))
+            .filter(|bootloader| vid.is_none_or(|vid| vid == bootloader.vid))
+            .filter(|bootloader| pid.is_none_or(|pid| pid == bootloader.pid))
+            .filter(|bootloader| uuid.is_none_or(|uuid| uuid.as_u128() == bootloader.uuid))
             .collect()
     }
+}
 
-    pub fn info(&self) {
+impl<T: Transport> Bootloader<T> {
+    /// Query all properties, ignoring those the device does not support.
+    pub fn info(&self) -> protocol::Result<()> {
         for property in Property::into_enum_iter() {
             // println!("\n{:?}", property);
-            self.property(property).ok();
+            match self.property(property) {
+                Err(protocol::Error::Status { .. }) => {}
+                result => {
+                    result?;
+                }
+            }
         }
+        Ok(())
     }
 
-    pub fn reboot(&self) {
+    pub fn reboot(&self) -> protocol::Result<()> {
         info!("calling Command::Reset");
-        self.protocol.call(&Command::Reset).expect("success");
+        self.protocol.call(&Command::Reset)?;
+        Ok(())
     }
 
-    pub fn enroll_puf(&self) {
+    pub fn enroll_puf(&self) -> protocol::Result<()> {
         // first time i ran this:
         // 03000C00 A0000002 00000000 15000000 00000000 00000000 00000000 00000000 00000000 00000030 FF5F0030 00000020 FF5F0020 00000000 00000000
         // second time i ran this:
         // 03000C00 A0000002 00000000 15000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000
         self.protocol
-            .call(&Command::Keystore(KeystoreOperation::Enroll))
-            .expect("success");
+            .call(&Command::Keystore(KeystoreOperation::Enroll))?;
         info!("PUF enrolled");
+        Ok(())
+    }
+
+    /// The keystore as stored in the PFR.
+    pub fn read_keystore(&self) -> protocol::Result<Keystore> {
+        let data = self.read_memory(KEYSTORE_ADDRESS, 3 * 512)?;
+        Keystore::try_from(data.as_slice()).map_err(|()| protocol::Error::MalformedResponse)
+    }
+
+    /// Replaces the keystore, and stores it to the PFR.
+    ///
+    /// Unlike setting or generating keys, this works without enrolling the PUF first,
+    /// so it also works after a reboot.
+    pub fn write_keystore(&self, keystore: &Keystore) -> protocol::Result<()> {
+        let data = keystore.to_bytes().to_vec();
+        self.protocol
+            .call(&Command::Keystore(KeystoreOperation::WriteKeystore {
+                data,
+            }))?;
+        self.protocol
+            .call(&Command::Keystore(KeystoreOperation::WriteNonVolatile))?;
+        info!("keystore written");
+        Ok(())
+    }
+
+    pub fn backup_keystore(&self) -> protocol::Result<KeystoreBackup> {
+        Ok(KeystoreBackup {
+            uuid: self.uuid(),
+            keystore: self.read_keystore()?,
+        })
+    }
+
+    /// Restores a keystore backup taken from this very device.
+    ///
+    /// Activation and key codes are bound to the PUF of the die they were created on,
+    /// restoring them on another one would leave it without usable keys.
+    pub fn restore_keystore(&self, backup: &KeystoreBackup) -> anyhow::Result<()> {
+        if backup.uuid != self.uuid() {
+            return Err(anyhow!(
+                "keystore backup is for device {:X}, refusing to restore it to {:X}",
+                backup.uuid.to_simple(),
+                self.uuid().to_simple()
+            ));
+        }
+        self.write_keystore(&backup.keystore)?;
+        if self.read_keystore()? != backup.keystore {
+            return Err(anyhow!("keystore read back differs from backup"));
+        }
+        Ok(())
     }
 
     /// The reason for this wrapper is that the device aborts early if more than 512 bytes are
@@ -257,77 +379,174 @@ impl Bootloader {
     /// TODO: should we just enter our desired length anyway, and handle such situations?
     /// As in retry at the new index, with the reduced length? Instead of using a fixed 512B chunking?
     ///
-    /// TODO: Need to expect errors such as: `Response status = 139 (0x8b) kStatus_FLASH_NmpaUpdateNotAllowed`
-    /// This happens with `read-memory $((0x0009_FC70)) 16`, which would be the UUID
-    ///
-    /// TODO: Need to expect errors such as: `Response status = 10200 (0x27d8) kStatusMemoryRangeInvalid`
-    /// This happens with `read-memory $((0x5000_0FFC)) 1`, which would be the DIEID (for chip rev)
-    pub fn read_memory(&self, address: usize, length: usize) -> Vec<u8> {
+    /// Errors such as `Response status = 139 (0x8b) kStatus_FLASH_NmpaUpdateNotAllowed`
+    /// (e.g. `read-memory $((0x0009_FC70)) 16`, which would be the UUID), or
+    /// `Response status = 10200 (0x27d8) kStatusMemoryRangeInvalid`
+    /// (e.g. `read-memory $((0x5000_0FFC)) 1`, which would be the DIEID for chip rev)
+    /// are returned as `protocol::Error::Status`.
+    pub fn read_memory(&self, address: usize, length: usize) -> protocol::Result<Vec<u8>> {
         let mut data = Vec::new();
         let mut remaining = length;
         let mut address = address;
         while remaining > 0 {
             let length = core::cmp::min(remaining, 512);
-            data.extend_from_slice(&self.read_memory_at_most_512(address, length));
+            data.extend_from_slice(&self.read_memory_at_most_512(address, length)?);
             remaining -= length;
             address += length;
         }
-        data
+        Ok(data)
     }
 
-    pub fn read_memory_at_most_512(&self, address: usize, length: usize) -> Vec<u8> {
-        let response = self
-            .protocol
-            .call(&Command::ReadMemory { address, length })
-            .expect("success");
-        if let Response::ReadMemory(data) = response {
-            data
-        } else {
-            todo!();
+    pub fn read_memory_at_most_512(
+        &self,
+        address: usize,
+        length: usize,
+    ) -> protocol::Result<Vec<u8>> {
+        let command = Command::ReadMemory { address, length };
+        match self.protocol.call(&command)? {
+            Response::ReadMemory(data) => Ok(data),
+            _ => Err(protocol::Error::UnexpectedResponse(command.tag())),
         }
     }
 
-    pub fn receive_sb_file(&self, data: &[u8]) {
-        let _response = self
-            .protocol
-            .call(&Command::ReceiveSbFile {
-                data: data.to_vec(),
-            })
-            .expect("success");
+    pub fn receive_sb_file(&self, data: &[u8]) -> protocol::Result<()> {
+        self.protocol.call(&Command::ReceiveSbFile {
+            data: data.to_vec(),
+        })?;
+        Ok(())
+    }
+
+    pub fn erase_flash(&self, address: usize, length: usize) -> protocol::Result<()> {
+        self.protocol
+            .call(&Command::EraseFlash { address, length })?;
+        Ok(())
+    }
+
+    pub fn write_memory(&self, address: usize, data: Vec<u8>) -> protocol::Result<()> {
+        self.protocol
+            .call(&Command::WriteMemory { address, data })?;
+        Ok(())
+    }
+
+    pub fn erase_flash_all(&self) -> protocol::Result<()> {
+        self.protocol.call(&Command::EraseFlashAll)?;
+        Ok(())
+    }
+
+    pub fn erase_flash_all_unlock(&self) -> protocol::Result<()> {
+        self.protocol.call(&Command::EraseFlashAllUnlock)?;
+        Ok(())
+    }
+
+    pub fn fill_memory(&self, address: usize, length: usize, pattern: u32) -> protocol::Result<()> {
+        self.protocol.call(&Command::FillMemory {
+            address,
+            length,
+            pattern,
+        })?;
+        Ok(())
+    }
+
+    pub fn flash_security_disable(&self, backdoor_key: [u8; 8]) -> protocol::Result<()> {
+        self.protocol
+            .call(&Command::FlashSecurityDisable { backdoor_key })?;
+        Ok(())
+    }
+
+    pub fn set_property(&self, property: Property, value: u32) -> protocol::Result<()> {
+        self.protocol
+            .call(&Command::SetProperty { property, value })?;
+        Ok(())
+    }
+
+    /// Jump to the application at `address`, the bootloader does not return.
+    pub fn execute(
+        &self,
+        address: usize,
+        argument: u32,
+        stack_pointer: usize,
+    ) -> protocol::Result<()> {
+        info!("calling Command::Execute");
+        self.protocol.call(&Command::Execute {
+            address,
+            argument,
+            stack_pointer,
+        })?;
+        Ok(())
     }
 
-    pub fn erase_flash(&self, address: usize, length: usize) {
-        let _response = self
-            .protocol
-            .call(&Command::EraseFlash { address, length })
-            .expect("success");
+    pub fn call(&self, address: usize, argument: u32) -> protocol::Result<()> {
+        self.protocol.call(&Command::Call { address, argument })?;
+        Ok(())
     }
 
-    pub fn write_memory(&self, address: usize, data: Vec<u8>) {
-        let _response = self
-            .protocol
-            .call(&Command::WriteMemory { address, data })
-            .expect("success");
+    pub fn flash_program_once(&self, index: u32, words: Vec<u32>) -> protocol::Result<()> {
+        self.protocol
+            .call(&Command::FlashProgramOnce { index, words })?;
+        Ok(())
     }
 
-    fn property(&self, property: property::Property) -> Result<Vec<u32>> {
+    pub fn flash_read_once(&self, index: u32, count: usize) -> protocol::Result<Vec<u32>> {
+        let command = Command::FlashReadOnce { index, count };
+        match self.protocol.call(&command)? {
+            Response::FlashReadOnce(words) => Ok(words),
+            _ => Err(protocol::Error::UnexpectedResponse(command.tag())),
+        }
+    }
+
+    pub fn flash_read_resource(
+        &self,
+        address: usize,
+        length: usize,
+        option: u32,
+    ) -> protocol::Result<Vec<u8>> {
+        let command = Command::FlashReadResource {
+            address,
+            length,
+            option,
+        };
+        match self.protocol.call(&command)? {
+            Response::FlashReadResource(data) => Ok(data),
+            _ => Err(protocol::Error::UnexpectedResponse(command.tag())),
+        }
+    }
+
+    pub fn reliable_update(&self, address: usize) -> protocol::Result<()> {
+        self.protocol.call(&Command::ReliableUpdate { address })?;
+        Ok(())
+    }
+
+    /// Wrap the data encryption key `dek` in a key blob, using the key selected by `key_selector`.
+    pub fn generate_key_blob(&self, key_selector: u32, dek: &[u8]) -> protocol::Result<Vec<u8>> {
+        self.protocol.call(&Command::GenerateKeyBlob {
+            key_selector,
+            dek: dek.to_vec(),
+        })?;
+        let command = Command::ReadKeyBlob {
+            key_selector,
+            length: KEY_BLOB_SIZE,
+        };
+        match self.protocol.call(&command)? {
+            Response::Data(blob) => Ok(blob),
+            _ => Err(protocol::Error::UnexpectedResponse(command.tag())),
+        }
+    }
+
+    fn property(&self, property: property::Property) -> protocol::Result<Vec<u32>> {
         self.protocol.property(property)
     }
 
-    pub fn properties(&self) -> property::GetProperties<'_> {
+    pub fn properties(&self) -> property::GetProperties<'_, T> {
         GetProperties {
             protocol: &self.protocol,
         }
     }
 
-    pub fn all_properties(&self) -> Properties {
+    pub fn all_properties(&self) -> protocol::Result<Properties> {
         self.properties().all()
     }
 
-    pub fn run_command(
-        &self,
-        cmd: Command,
-    ) -> std::result::Result<command::Response, protocol::Error> {
+    pub fn run_command(&self, cmd: Command) -> protocol::Result<command::Response> {
         self.protocol.call(&cmd)
     }
 }
@@ -337,5 +556,5 @@ fn all_properties() {
     // let (vid, pid) = (0x1fc9, 0x0021);
     let (vid, pid) = (0x1209, 0xb000);
     let bootloader = Bootloader::try_new(Some(vid), Some(pid)).unwrap();
-    insta::assert_debug_snapshot!(bootloader.all_properties());
+    insta::assert_debug_snapshot!(bootloader.all_properties().unwrap());
 }
@@ -1,4 +1,4 @@
-use core::convert::TryFrom;
+use core::convert::{TryFrom, TryInto};
 
 use serde::{Deserialize, Serialize};
 
@@ -25,22 +25,77 @@ pub enum Command {
         address: usize,
         words: Vec<u32>,
     },
-    FillMemory,
+    /// Fills `length` bytes at `address` with the repeated 32-bit `pattern`
+    FillMemory {
+        address: usize,
+        length: usize,
+        pattern: u32,
+    },
     /// cf. <https://www.nxp.com/docs/en/application-note/AN12527.pdf>
     ConfigureMemory {
         address: usize,
     },
-    FlashSecurityDisable,
+    /// Unlocks flash with the backdoor key, if enabled
+    FlashSecurityDisable {
+        backdoor_key: [u8; 8],
+    },
     // there is actually a second parameter, Memory ID
     // 0 = internal flash
     // 1 = QSPI0 memory (unused for LPC55)
     GetProperty(Property),
+    /// Only few properties are writable, e.g. `VerifyWrites`
+    SetProperty {
+        property: Property,
+        value: u32,
+    },
     ReceiveSbFile {
         data: Vec<u8>,
     },
-    Call,
+    /// Jumps to `address` with `argument` in R0, after setting the stack pointer
+    /// (if non-zero). The bootloader does not return.
+    Execute {
+        address: usize,
+        argument: u32,
+        stack_pointer: usize,
+    },
+    /// Calls the function at `address` with `argument` in R0, then continues
+    /// in the bootloader
+    Call {
+        address: usize,
+        argument: u32,
+    },
     Reset,
-    FlashReadResource,
+    EraseFlashAllUnlock,
+    /// Programs one or two words of the one time programmable (eFuse) area at `index`
+    FlashProgramOnce {
+        index: u32,
+        words: Vec<u32>,
+    },
+    /// Reads `count` (one or two) words of the one time programmable (eFuse) area at `index`
+    FlashReadOnce {
+        index: u32,
+        count: usize,
+    },
+    /// Reads from the flash IFR (option 0) or the flash firmware ID (option 1)
+    FlashReadResource {
+        address: usize,
+        length: usize,
+        option: u32,
+    },
+    /// Checks the backup application at `address`, and swaps it in if valid
+    ReliableUpdate {
+        address: usize,
+    },
+    /// First step of generating a key blob: sends the data encryption key
+    GenerateKeyBlob {
+        key_selector: u32,
+        dek: Vec<u8>,
+    },
+    /// Second step of generating a key blob: reads the blob wrapping the previously sent key
+    ReadKeyBlob {
+        key_selector: u32,
+        length: usize,
+    },
     Keystore(KeystoreOperation),
 }
 
@@ -74,6 +129,16 @@ pub enum CommandTag {
     ConfigureCan = 0xC3,
 }
 
+impl TryFrom<u8> for CommandTag {
+    type Error = u8;
+    fn try_from(byte: u8) -> Result<Self, u8> {
+        use enum_iterator::IntoEnumIterator;
+        CommandTag::into_enum_iter()
+            .find(|tag| *tag as u8 == byte)
+            .ok_or(byte)
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 /// Signifies which of the three cases of the protocol is used.
 ///
@@ -92,17 +157,15 @@ impl DataPhase {
 
 impl Command {
     pub fn data_phase(&self) -> DataPhase {
-        use CommandTag as Tag;
-        match (self, self.tag()) {
-            (_, Tag::ReadMemory) => DataPhase::ResponseData,
-            (_, Tag::EraseFlash) => DataPhase::None,
-            (_, Tag::EraseFlashAll) => DataPhase::None,
-            (_, Tag::GetProperty) => DataPhase::None,
-            (_, Tag::Reset) => DataPhase::None,
-            (_, Tag::ConfigureMemory) => DataPhase::None,
-
-            (Command::WriteMemory { address: _, data }, _) => DataPhase::CommandData(data.clone()),
-            (Command::WriteMemoryWords { address: _, words }, _) => {
+        use Command::*;
+        match self {
+            ReadMemory { .. }
+            | FlashReadResource { .. }
+            | ReadKeyBlob { .. }
+            | Keystore(KeystoreOperation::ReadKeystore) => DataPhase::ResponseData,
+
+            WriteMemory { address: _, data } => DataPhase::CommandData(data.clone()),
+            WriteMemoryWords { address: _, words } => {
                 use std::io::Write;
                 let mut bytes = Vec::with_capacity(words.len() * 4);
                 let cursor = &mut bytes;
@@ -113,20 +176,19 @@ impl Command {
 
                 DataPhase::CommandData(bytes)
             }
-            (Command::ReceiveSbFile { data }, _) => DataPhase::CommandData(data.clone()),
-
-            (Command::Keystore(KeystoreOperation::Enroll), _) => DataPhase::None,
-            (Command::Keystore(KeystoreOperation::ReadKeystore), _) => DataPhase::ResponseData,
-            (Command::Keystore(KeystoreOperation::SetKey { key: _, data }), _) => {
+            ReceiveSbFile { data } => DataPhase::CommandData(data.clone()),
+            GenerateKeyBlob {
+                key_selector: _,
+                dek,
+            } => DataPhase::CommandData(dek.clone()),
+            Keystore(KeystoreOperation::SetKey { key: _, data }) => {
                 DataPhase::CommandData(data.clone())
             }
-            (Command::Keystore(KeystoreOperation::GenerateKey { key: _, len: _ }), _) => {
-                DataPhase::None
+            Keystore(KeystoreOperation::WriteKeystore { data }) => {
+                DataPhase::CommandData(data.clone())
             }
-            (Command::Keystore(KeystoreOperation::WriteNonVolatile), _) => DataPhase::None,
-            (Command::Keystore(KeystoreOperation::ReadNonVolatile), _) => DataPhase::None,
 
-            _ => todo!(),
+            _ => DataPhase::None,
         }
     }
 
@@ -144,9 +206,64 @@ impl Command {
             EraseFlash { address, length } => {
                 vec![address as u32, length as u32]
             }
-            EraseFlashAll => {
+            EraseFlashAll | EraseFlashAllUnlock => {
                 vec![]
             }
+            FillMemory {
+                address,
+                length,
+                pattern,
+            } => {
+                vec![address as u32, length as u32, pattern]
+            }
+            FlashSecurityDisable { backdoor_key } => {
+                // like pyMBoot, each half of the key is sent as big-endian word
+                vec![
+                    u32::from_be_bytes(backdoor_key[..4].try_into().unwrap()),
+                    u32::from_be_bytes(backdoor_key[4..].try_into().unwrap()),
+                ]
+            }
+            SetProperty { property, value } => {
+                vec![property as u8 as u32, value]
+            }
+            Execute {
+                address,
+                argument,
+                stack_pointer,
+            } => {
+                vec![address as u32, argument, stack_pointer as u32]
+            }
+            Call { address, argument } => {
+                vec![address as u32, argument]
+            }
+            FlashProgramOnce { index, words } => {
+                let mut parameters = vec![index, (words.len() * 4) as u32];
+                parameters.extend_from_slice(&words);
+                parameters
+            }
+            FlashReadOnce { index, count } => {
+                vec![index, (count * 4) as u32]
+            }
+            FlashReadResource {
+                address,
+                length,
+                option,
+            } => {
+                vec![address as u32, length as u32, option]
+            }
+            ReliableUpdate { address } => {
+                vec![address as u32]
+            }
+            // third parameter selects the step: 0 = send DEK, 1 = read blob
+            GenerateKeyBlob { key_selector, dek } => {
+                vec![key_selector, dek.len() as u32, 0]
+            }
+            ReadKeyBlob {
+                key_selector,
+                length,
+            } => {
+                vec![key_selector, length as u32, 1]
+            }
             WriteMemory { address, data } => {
                 vec![address as u32, data.len() as u32, 0]
             }
@@ -183,10 +300,11 @@ impl Command {
                     ReadNonVolatile => {
                         vec![u32::from(&operation), 0]
                     }
-                    _ => todo!(),
+                    WriteKeystore { data } => {
+                        vec![u32::from(&operation), 0, data.len() as u32]
+                    }
                 }
             }
-            _ => todo!(),
         }
     }
 
@@ -211,14 +329,21 @@ impl Command {
                 address: _,
                 words: _,
             } => Tag::WriteMemory,
-            FillMemory => Tag::FillMemory,
-            FlashSecurityDisable => Tag::FlashSecurityDisable,
+            FillMemory { .. } => Tag::FillMemory,
+            FlashSecurityDisable { .. } => Tag::FlashSecurityDisable,
             GetProperty(_) => Tag::GetProperty,
+            SetProperty { .. } => Tag::SetProperty,
             ReceiveSbFile { data: _ } => Tag::ReceiveSbFile,
-            Call => Tag::Call,
+            Execute { .. } => Tag::Execute,
+            Call { .. } => Tag::Call,
             Reset => Tag::Reset,
-            FlashReadResource => Tag::FlashReadResource,
+            EraseFlashAllUnlock => Tag::EraseFlashAllUnlock,
+            FlashProgramOnce { .. } => Tag::FlashProgramOnce,
+            FlashReadOnce { .. } => Tag::FlashReadOnce,
+            FlashReadResource { .. } => Tag::FlashReadResource,
             ConfigureMemory { address: _ } => Tag::ConfigureMemory,
+            ReliableUpdate { .. } => Tag::ReliableUpdate,
+            GenerateKeyBlob { .. } | ReadKeyBlob { .. } => Tag::GenerateKeyBlob,
             Keystore(_) => Tag::Keystore,
         }
     }
@@ -231,23 +356,11 @@ pub struct HidHeader {
 }
 
 impl Command {
-    /// Not yet quite clear what comes from HID spec, and what's NXP framing command packets in HID.
+    /// The command packet, framed as USB HID report.
     pub fn hid_packet(&self) -> Vec<u8> {
-        let command_packet = self.command_packet();
-        // dbg!(to_hex_string(&command_packet.clone()));
-
-        let mut header = [0u8; 4];
-        // pyMBoot does: `pack('<2BH', report_id, 0x00, data_len)`
-        // MCU Bootloader 2.5.0 RM rev 1. (05/2018) says: 1 byte report ID, 2 bytes packet length
-        // It seems pyMBoot is right, and the RM is wrong
-        header[0] = ReportId::Command as u8;
-        header[2..].copy_from_slice(&(command_packet.len() as u16).to_le_bytes());
-
-        // umm...
-        let mut hid_packet = Vec::new();
-        hid_packet.extend_from_slice(&header);
-        hid_packet.extend_from_slice(&command_packet);
-        hid_packet
+        super::transport::HidTransport::report(&super::transport::Frame::Command(
+            self.command_packet(),
+        ))
     }
 
     pub fn header(&self) -> [u8; 4] {
@@ -266,7 +379,7 @@ impl Command {
     /// The command packet carries a 32-bit command header and a list of 32-bit little-endian parameters.
     ///
     /// In total, it is always 32 bytes long. This implies that there can be at most 7 parameters.
-    fn command_packet(&self) -> Vec<u8> {
+    pub fn command_packet(&self) -> Vec<u8> {
         let params = self.parameters();
         assert!(params.len() <= 7);
 
@@ -321,6 +434,8 @@ pub enum Response {
     // todo: model the properties
     GetProperty(Vec<u32>),
     ReadMemory(Vec<u8>),
+    FlashReadOnce(Vec<u32>),
+    FlashReadResource(Vec<u8>),
 }
 
 impl Response {
@@ -332,6 +447,8 @@ impl Response {
             Data(_) => Tag::Generic,
             GetProperty(_) => Tag::GetProperty,
             ReadMemory(_) => Tag::ReadMemory,
+            FlashReadOnce(_) => Tag::FlashReadOnce,
+            FlashReadResource(_) => Tag::FlashReadResource,
         }
     }
 }
@@ -558,13 +675,24 @@ impl TryFrom<&str> for Key {
 /// It doesn't however seem possible to set/generate new keys after reboot without
 /// re-enrolling PUF, calling set/generate key results in a `Generic(Fail)`. Calling
 /// ReadNonVolatile does not help; the author does not understand the effect of this command.
+///
+/// WriteKeystore does work after reboot; `Bootloader::{backup,restore}_keystore` build on it.
 pub enum KeystoreOperation {
     Enroll,
-    SetKey { key: Key, data: Vec<u8> },
-    GenerateKey { key: Key, len: u32 },
+    SetKey {
+        key: Key,
+        data: Vec<u8>,
+    },
+    GenerateKey {
+        key: Key,
+        len: u32,
+    },
     WriteNonVolatile,
     ReadNonVolatile,
-    WriteKeystore,
+    /// Replaces the keystore in RAM (all three pages, cf. `Keystore::to_bytes`)
+    WriteKeystore {
+        data: Vec<u8>,
+    },
     ReadKeystore,
 }
 
@@ -577,7 +705,7 @@ impl From<&KeystoreOperation> for u32 {
             GenerateKey { key: _, len: _ } => 2,
             WriteNonVolatile => 3,
             ReadNonVolatile => 4,
-            WriteKeystore => 5,
+            WriteKeystore { data: _ } => 5,
             ReadKeystore => 6,
         }
     }
@@ -585,11 +713,9 @@ impl From<&KeystoreOperation> for u32 {
 
 #[cfg(test)]
 mod test {
-    #[cfg(all(feature = "with-device", test))]
     use super::*;
 
-    // #[test]
-    #[cfg(all(feature = "with-device", test))]
+    #[test]
     fn command_packet() {
         // 7 0 0 2  1 0 0 0  0 0 0 0
         insta::assert_debug_snapshot!(
@@ -597,8 +723,34 @@ mod test {
         );
     }
 
-    // #[test]
-    #[cfg(all(feature = "with-device", test))]
+    #[test]
+    fn parameters() {
+        let command = Command::FlashSecurityDisable {
+            backdoor_key: [1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        assert_eq!(command.parameters(), [0x0102_0304, 0x0506_0708]);
+
+        let command = Command::FlashProgramOnce {
+            index: 3,
+            words: vec![0xAABB_CCDD, 0x1122_3344],
+        };
+        assert_eq!(command.header(), [0x0E, 0, 0, 4]);
+        assert_eq!(command.parameters(), [3, 8, 0xAABB_CCDD, 0x1122_3344]);
+
+        let command = Command::GenerateKeyBlob {
+            key_selector: 0,
+            dek: vec![0; 16],
+        };
+        assert_eq!(command.header(), [0x13, 1, 0, 3]);
+        let command = Command::ReadKeyBlob {
+            key_selector: 0,
+            length: 72,
+        };
+        assert_eq!(command.header(), [0x13, 0, 0, 3]);
+        assert_eq!(command.data_phase(), DataPhase::ResponseData);
+    }
+
+    #[test]
     fn hid_packet() {
         // 1 0 C 0  7 0 0 2  1 0 0 0  0 0 0 0
         insta::assert_debug_snapshot!(Command::GetProperty(Property::CurrentVersion).hid_packet());
@@ -141,6 +141,9 @@ impl From<BootloaderError> for (ErrorGroup, u8) {
 
 impl From<BootloaderError> for u32 {
     fn from(error: BootloaderError) -> u32 {
+        if let BootloaderError::Unknown(status) = error {
+            return status;
+        }
         let (group, code) = error.into();
         (group as u32 * 100) + code as u32
     }
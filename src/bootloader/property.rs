@@ -2,11 +2,17 @@ use serde::{Deserialize, Serialize};
 
 use crate::bootloader::{
     command::{CommandTag, Version},
-    Error, Protocol, Result,
+    protocol::{self, Result},
+    transport::{HidTransport, Transport},
+    Error, Protocol,
 };
 
-pub struct GetProperties<'a> {
-    pub protocol: &'a Protocol,
+fn unexpected() -> protocol::Error {
+    protocol::Error::UnexpectedResponse(CommandTag::GetProperty)
+}
+
+pub struct GetProperties<'a, T: Transport = HidTransport> {
+    pub protocol: &'a Protocol<T>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -155,29 +161,29 @@ impl From<u32> for IrqNotificationPin {
     }
 }
 
-impl GetProperties<'_> {
-    pub fn all(&self) -> Properties {
-        Properties {
-            current_version: self.current_version().unwrap(),
-            target_version: self.target_version().unwrap(),
-            available_commands: self.available_commands().unwrap(),
-            available_peripherals: self.available_peripherals().unwrap(),
-            pfr_keystore_update_option: self.pfr_keystore_update_option().unwrap(),
-            ram_start_address: self.ram_start_address().unwrap(),
-            ram_size: self.ram_size().unwrap(),
-            flash_start_address: self.flash_start_address().unwrap(),
-            flash_size: self.flash_size().unwrap(),
-            flash_page_size: self.flash_page_size().unwrap(),
-            flash_sector_size: self.flash_sector_size().unwrap(),
-            verify_writes: self.verify_writes().unwrap(),
-            flash_locked: self.flash_locked().unwrap(),
-            max_packet_size: self.max_packet_size().unwrap(),
-            device_uuid: self.device_uuid().unwrap(),
-            system_uuid: self.system_uuid().unwrap(),
-            crc_check_status: self.crc_check_status().unwrap(),
-            reserved_regions: self.reserved_regions().unwrap(),
-            irq_notification_pin: self.irq_notification_pin().unwrap(),
-        }
+impl<T: Transport> GetProperties<'_, T> {
+    pub fn all(&self) -> Result<Properties> {
+        Ok(Properties {
+            current_version: self.current_version()?,
+            target_version: self.target_version()?,
+            available_commands: self.available_commands()?,
+            available_peripherals: self.available_peripherals()?,
+            pfr_keystore_update_option: self.pfr_keystore_update_option()?,
+            ram_start_address: self.ram_start_address()?,
+            ram_size: self.ram_size()?,
+            flash_start_address: self.flash_start_address()?,
+            flash_size: self.flash_size()?,
+            flash_page_size: self.flash_page_size()?,
+            flash_sector_size: self.flash_sector_size()?,
+            verify_writes: self.verify_writes()?,
+            flash_locked: self.flash_locked()?,
+            max_packet_size: self.max_packet_size()?,
+            device_uuid: self.device_uuid()?,
+            system_uuid: self.system_uuid()?,
+            crc_check_status: self.crc_check_status()?,
+            reserved_regions: self.reserved_regions()?,
+            irq_notification_pin: self.irq_notification_pin()?,
+        })
     }
 
     pub fn current_version(&self) -> Result<Version> {
@@ -230,17 +236,17 @@ impl GetProperties<'_> {
         Ok(self.protocol.property(Property::VerifyWrites)?[0] == 1)
     }
     pub fn flash_locked(&self) -> Result<bool> {
-        Ok(
-            match self.protocol.property(Property::FlashSecurityState)?[0] {
-                0x0 | 0x5AA55AA5 => false,
-                0x1 | 0xC33CC33C => true,
-                _ => panic!(),
-            },
-        )
+        match self.protocol.property(Property::FlashSecurityState)?[0] {
+            0x0 | 0x5AA55AA5 => Ok(false),
+            0x1 | 0xC33CC33C => Ok(true),
+            _ => Err(unexpected()),
+        }
     }
     pub fn device_uuid(&self) -> Result<u128> {
         let values = self.protocol.property(Property::UniqueDeviceIdent)?;
-        assert_eq!(values.len(), 4);
+        if values.len() != 4 {
+            return Err(unexpected());
+        }
         let wrong_endian =
             ((values[3] as u128) << 96) +
             ((values[2] as u128) << 64) +
@@ -255,7 +261,9 @@ impl GetProperties<'_> {
     }
     pub fn system_uuid(&self) -> Result<u64> {
         let values = self.protocol.property(Property::SystemDeviceIdent)?;
-        assert_eq!(values.len(), 2);
+        if values.len() != 2 {
+            return Err(unexpected());
+        }
         Ok(((values[1] as u64) << 32) + (values[0] as u64))
     }
 
@@ -267,12 +275,16 @@ impl GetProperties<'_> {
 
     pub fn reserved_regions(&self) -> Result<Vec<(usize, usize)>> {
         let values = self.protocol.property(Property::ReservedRegions)?;
-        assert_eq!(values.len() % 2, 0);
+        if values.len() % 2 != 0 {
+            return Err(unexpected());
+        }
         let mut pairs = Vec::new();
         for pair in values.chunks_exact(2) {
             let left = pair[0];
             let right = pair[1];
-            assert!(right >= left);
+            if right < left {
+                return Err(unexpected());
+            }
             if right > left {
                 pairs.push((left as usize, right as usize));
             }
@@ -33,15 +33,17 @@
 //  Device may abort data phase early by sending zero-length packet
 //  Host may abort data phase by sending generic response (?is this a thing?)
 
+use super::transport::{Frame, HidTransport, Transport};
 use super::Error as BootloaderError;
 use crate::bootloader::{command, property};
 use core::convert::{TryFrom, TryInto};
-
-use hidapi::{HidDevice, HidResult};
+use core::time::Duration;
 
 /// The NXP bootloader protocol. Interact via `fn call(Command) -> Result<Response>`
-pub struct Protocol {
-    device: HidDevice,
+///
+/// Generic over the link to the device, which defaults to USB HID.
+pub struct Protocol<T: Transport = HidTransport> {
+    transport: T,
 }
 
 /// The NXP bootloader protocol error type
@@ -53,10 +55,32 @@ pub enum Error {
     ExpectedDataPacket,
     #[error("expected (non-data) response packet")]
     ExpectedResponsePacket,
+    #[error("{command:?} failed with status {status}")]
+    Status {
+        command: command::CommandTag,
+        status: BootloaderError,
+    },
     #[error("error from underlying hidapi")]
     HidApi(#[from] hidapi::HidError),
     #[error("invalid HID report ID ({0})")]
     InvalidReportId(u8),
+    #[error("CRC mismatch in received packet")]
+    InvalidCrc,
+    #[error("response packet is malformed")]
+    MalformedResponse,
+    #[error("I/O error on underlying link")]
+    Io(#[from] std::io::Error),
+    #[error("packet not acknowledged after {0} attempts")]
+    NotAcknowledged(usize),
+    #[cfg(feature = "uart")]
+    #[error("error from underlying serial port")]
+    Serial(#[from] serialport::Error),
+    #[error("timed out waiting for the device")]
+    Timeout,
+    #[error("unexpected packet type ({0:#04X})")]
+    UnexpectedPacketType(u8),
+    #[error("unexpected response to {0:?}")]
+    UnexpectedResponse(command::CommandTag),
     #[error("unknown response tag ({0})")]
     UnknownResponseTag(u8),
 
@@ -64,6 +88,16 @@ pub enum Error {
     Unspecified,
 }
 
+impl Error {
+    /// The status the device returned, if this error is due to a failed command.
+    pub fn status(&self) -> Option<BootloaderError> {
+        match self {
+            Error::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
 /// The NXP bootloader protocol result type, with split status as error
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -102,30 +136,69 @@ impl TryFrom<ReceivedPacket> for Vec<u8> {
     }
 }
 
-pub const READ_TIMEOUT: i32 = 2000;
+pub const READ_TIMEOUT: Duration = Duration::from_millis(2000);
+
+impl ResponsePacket {
+    /// Turn a non-success status into an error, attributed to the command it answers.
+    fn check_status(self, command: &command::Command) -> Result<Self> {
+        match self.status {
+            Some(status) => Err(Error::Status {
+                command: command.tag(),
+                status,
+            }),
+            None => Ok(self),
+        }
+    }
+
+    /// The final response of every exchange is a generic one, mirroring our command tag.
+    ///
+    /// It seems the device "forgets" about the parameters the original command
+    /// contained (address + length). Table 4-11 ("The Command tag parameter identifies
+    /// the response to the command sent by the host.") just means that the command tag is set.
+    /// It doesn't even reflect the second byte (has-data flag),
+    /// e.g.: we send: 15010003, we get back: 15000000
+    fn check_generic(self, command: &command::Command) -> Result<Self> {
+        let packet = self.check_status(command)?;
+        if packet.has_data
+            || packet.tag != command::ResponseTag::Generic
+            || packet.parameters.len() != 1
+            || packet.parameters[0].to_le_bytes()[0] != command.header()[0]
+        {
+            return Err(Error::UnexpectedResponse(command.tag()));
+        }
+        Ok(packet)
+    }
 
-impl Protocol {
-    pub fn property(
+    /// Initial responses of response data phases announce the number of bytes to be sent.
+    fn check_data_length(
         &self,
-        property: property::Property,
-    ) -> core::result::Result<Vec<u32>, crate::bootloader::Error> {
-        let response = self
-            .call(&command::Command::GetProperty(property))
-            .expect("success");
-        if let command::Response::GetProperty(values) = response {
-            Ok(values)
-        } else {
-            todo!();
+        command: &command::Command,
+        tag: command::ResponseTag,
+        length: usize,
+    ) -> Result<()> {
+        if !self.has_data
+            || self.tag != tag
+            || self.parameters.len() != 1
+            || self.parameters[0] as usize != length
+        {
+            return Err(Error::UnexpectedResponse(command.tag()));
         }
+        Ok(())
     }
+}
 
-    pub fn call(&self, command: &command::Command) -> Result<command::Response> {
-        // construct command packet
-        let command_packet = command.hid_packet();
+impl<T: Transport> Protocol<T> {
+    pub fn property(&self, property: property::Property) -> Result<Vec<u32>> {
+        let command = command::Command::GetProperty(property);
+        match self.call(&command)? {
+            command::Response::GetProperty(values) => Ok(values),
+            _ => Err(Error::UnexpectedResponse(command.tag())),
+        }
+    }
 
-        // send command packet
-        self.write(command_packet.as_slice())?;
-        trace!("--> {}", hex_str!(&command_packet));
+    pub fn call(&self, command: &command::Command) -> Result<command::Response> {
+        // construct and send command packet
+        self.write(&Frame::Command(command.command_packet()))?;
 
         let initial_response = self.read_packet()?;
 
@@ -135,325 +208,348 @@ impl Protocol {
             (command, _tag, command::DataPhase::None) => {
                 // we expect a non-data packet, not signaling additional data packets, with
                 // successful status, mirroring our command header
-                let packet = ResponsePacket::try_from(initial_response)?;
-
-                assert!(!packet.has_data);
-                if let Some(status) = packet.status {
-                    panic!("{:?}", status);
+                let packet = ResponsePacket::try_from(initial_response)?.check_status(&command)?;
+                if packet.has_data {
+                    return Err(Error::UnexpectedResponse(command.tag()));
                 }
 
                 use command::Command::*;
                 match command {
-                    Reset
-                    | EraseFlash {
-                        address: _,
-                        length: _,
+                    GetProperty(_property) => {
+                        if packet.tag != command::ResponseTag::GetProperty
+                            || packet.parameters.is_empty()
+                        {
+                            return Err(Error::UnexpectedResponse(command.tag()));
+                        }
+                        Ok(command::Response::GetProperty(packet.parameters))
                     }
-                    | EraseFlashAll
-                    | ConfigureMemory { .. }
-                    | Keystore(command::KeystoreOperation::Enroll)
-                    | Keystore(command::KeystoreOperation::GenerateKey { key: _, len: _ })
-                    | Keystore(command::KeystoreOperation::WriteNonVolatile)
-                    | Keystore(command::KeystoreOperation::ReadNonVolatile) => {
-                        assert_eq!(packet.tag, command::ResponseTag::Generic);
+                    FlashReadOnce { index: _, count } => {
+                        // FlashReadOnce response: status, number of bytes read, then the words
+                        if packet.tag != command::ResponseTag::FlashReadOnce
+                            || packet.parameters.len() != 1 + count
+                            || packet.parameters[0] as usize != 4 * count
+                        {
+                            return Err(Error::UnexpectedResponse(command.tag()));
+                        }
+                        Ok(command::Response::FlashReadOnce(
+                            packet.parameters[1..].to_vec(),
+                        ))
+                    }
+                    command => {
                         // general property of generic responses: 2 parameters, status and mirrored command header
-                        assert_eq!(packet.parameters.len(), 1);
-                        assert_eq!(
-                            packet.parameters[0].to_le_bytes()[..2],
-                            command.header()[..2]
-                        );
+                        if packet.tag != command::ResponseTag::Generic
+                            || packet.parameters.len() != 1
+                            || packet.parameters[0].to_le_bytes()[..2] != command.header()[..2]
+                        {
+                            return Err(Error::UnexpectedResponse(command.tag()));
+                        }
 
                         Ok(command::Response::Generic)
                     }
-                    GetProperty(_property) => {
-                        assert_eq!(packet.tag, command::ResponseTag::GetProperty);
-                        assert!(!packet.parameters.is_empty());
-                        Ok(command::Response::GetProperty(packet.parameters))
-                    }
-                    _ => todo!(),
                 }
             }
 
             // case 2: command data phases
             (command, _tag, command::DataPhase::CommandData(data)) => {
-                let packet = ResponsePacket::try_from(initial_response)?;
-
-                // for SetKey, LHS is true, whereas for WriteMemory, it is not (unexpectedly?)
-                // assert_eq!(packet.has_data, command.data_phase().has_command_data());
-                assert!(packet.status.is_none());
-                match command.clone() {
-                    command::Command::Keystore(command::KeystoreOperation::SetKey {
-                        key: _,
-                        data: _,
-                    }) => {
-                        // todo: can we use bigger chunks?
-                        for chunk in data.chunks(32) {
-                            // // TODO: somewhere in here, should "peek" a read to see if device sent
-                            // // an abort (i.e. a generic response)
-                            // let mut minibuf = [0u8; 4];
-                            // dbg!(self.device.read_timeout(&mut minibuf, 1000).unwrap());
-                            //
-                            // I guess the device would just ignore our sent data if it were
-                            // unhappy, so we'd find out after the fact. Although maybe sending
-                            // might block?
-
-                            let mut data_packet = vec![
-                                command::ReportId::CommandData as u8,
-                                0,
-                                chunk.len() as u8,
-                                0,
-                            ];
-                            data_packet.extend_from_slice(chunk);
-                            data_packet.resize(4 + 32, 0);
-                            trace!("--> {}", hex_str!(&data_packet, 4));
-                            self.write(data_packet.as_slice())?;
-                        }
-
-                        let packet = ResponsePacket::try_from(self.read_packet()?)?;
-                        assert!(!packet.has_data);
-                        if let Some(status) = packet.status {
-                            panic!("unexpected status {:?}", &status);
-                        }
-                        // assert!(packet.status.is_none());
+                // for SetKey, has_data is set, whereas for WriteMemory, it is not (unexpectedly?)
+                ResponsePacket::try_from(initial_response)?.check_status(&command)?;
+                self.write_data(&command, &data)?;
+                Ok(command::Response::Generic)
+            }
 
-                        assert_eq!(packet.tag, command::ResponseTag::Generic);
-                        // general property of generic responses: 2 parameters, status and mirrored command header
-                        assert_eq!(packet.parameters.len(), 1);
-                        // it seems the device "forgets" about the parameters the original command
-                        // contained (address + length)
-                        // ooorrr, Table 4-11 ("The Command tag parameter identifies the response to the command sent by the host.")
-                        // just means that the command tag is set
-                        //
-                        // UPDATE: doesn't even reflect the second byte (has-data flag)
-                        // e.g.: we send: 15010003, we get back: 15000000
-                        assert_eq!(packet.parameters[0].to_le_bytes()[0], command.header()[0]);
+            // case 3: reponse data phases
+            (command, _tag, command::DataPhase::ResponseData) => {
+                let packet = ResponsePacket::try_from(initial_response)?.check_status(&command)?;
 
-                        Ok(command::Response::Generic)
+                use command::Command::*;
+                match command {
+                    Keystore(_) => {
+                        let data = self.read_data(&command, 3 * 512)?;
+                        debug!("read {} in total", data.len());
+                        Ok(command::Response::Data(data))
                     }
-                    command::Command::WriteMemory {
-                        address: _,
-                        data: _,
+                    ReadMemory { address: _, length } => {
+                        packet.check_data_length(
+                            &command,
+                            command::ResponseTag::ReadMemory,
+                            length,
+                        )?;
+                        let data = self.read_data(&command, length)?;
+                        Ok(command::Response::ReadMemory(data))
                     }
-                    | command::Command::WriteMemoryWords { .. } => {
-                        for chunk in data.chunks(32) {
-                            let mut data_packet = vec![
-                                command::ReportId::CommandData as u8,
-                                0,
-                                chunk.len() as u8,
-                                0,
-                            ];
-                            data_packet.extend_from_slice(chunk);
-                            data_packet.resize(4 + 32, 0);
-                            trace!("--> {}", hex_str!(&data_packet, 4));
-                            self.write(data_packet.as_slice())?;
-                        }
-
-                        let packet = ResponsePacket::try_from(self.read_packet()?)?;
-                        assert!(!packet.has_data);
-                        if let Some(status) = packet.status {
-                            panic!("unexpected status {:?}", &status);
-                        }
-                        assert_eq!(packet.tag, command::ResponseTag::Generic);
-                        assert_eq!(packet.parameters.len(), 1);
-                        assert_eq!(packet.parameters[0].to_le_bytes()[0], command.header()[0]);
-
-                        Ok(command::Response::Generic)
+                    FlashReadResource { length, .. } => {
+                        packet.check_data_length(
+                            &command,
+                            command::ResponseTag::FlashReadResource,
+                            length,
+                        )?;
+                        let data = self.read_data(&command, length)?;
+                        Ok(command::Response::FlashReadResource(data))
                     }
-                    command::Command::ReceiveSbFile { data } => {
-                        #[cfg(feature = "progressbar")]
-                        let bar = indicatif::ProgressBar::new(data.len() as u64);
-                        for chunk in data.chunks(32) {
-                            #[cfg(feature = "progressbar")]
-                            bar.inc(32);
-                            let mut data_packet = vec![
-                                command::ReportId::CommandData as u8,
-                                0,
-                                chunk.len() as u8,
-                                0,
-                            ];
-                            data_packet.extend_from_slice(chunk);
-                            data_packet.resize(4 + 32, 0);
-                            trace!("--> {}", hex_str!(&data_packet, 4));
-                            self.write(data_packet.as_slice())?;
-                            // let packet = self.read_packet().unwrap();
-                            // let what = self.device.read_timeout(&mut [], 0).unwrap();
-                        }
-
-                        let packet = ResponsePacket::try_from(match self.read_packet() {
-                            Err(Error::AbortDataPhase) => {
-                                println!("aborting");
-                                self.read_packet().unwrap()
-                            }
-                            x => x?,
-                        })?;
-                        // let packet = ResponsePacket::try_from(self.read_packet()?)?;
-                        assert!(!packet.has_data);
-                        if let Some(status) = packet.status {
-                            panic!("unexpected status {:?}", &status);
+                    command => {
+                        // the device decides on the length of the key blob
+                        if !packet.has_data || packet.parameters.len() != 1 {
+                            return Err(Error::UnexpectedResponse(command.tag()));
                         }
-                        assert_eq!(packet.tag, command::ResponseTag::Generic);
-                        assert_eq!(packet.parameters.len(), 1);
-                        assert_eq!(packet.parameters[0].to_le_bytes()[0], command.header()[0]);
-
-                        Ok(command::Response::Generic)
+                        let data = self.read_data(&command, packet.parameters[0] as usize)?;
+                        Ok(command::Response::Data(data))
                     }
-                    _ => todo!(),
                 }
             }
+        }
+    }
 
-            // case 3: reponse data phases
-            (command::Command::Keystore(command::KeystoreOperation::ReadKeystore), _, _) => {
-                let _packet = ResponsePacket::try_from(initial_response)?;
-
-                let mut data = Vec::new();
-                let length = 3 * 512;
-                while data.len() < length {
-                    let partial_data: Vec<u8> = self.read_packet()?.try_into()?;
-                    assert!(data.len() + partial_data.len() <= length);
-                    data.extend_from_slice(&partial_data);
+    /// Send a command data phase, and read the final response.
+    ///
+    /// I guess the device would just ignore our sent data if it were unhappy, so over USB
+    /// we find out after the fact. Links with acknowledgements may signal the abort early.
+    fn write_data(&self, command: &command::Command, data: &[u8]) -> Result<()> {
+        #[cfg(feature = "progressbar")]
+        let bar = indicatif::ProgressBar::new(data.len() as u64);
+        let mut aborted = false;
+        // todo: can we use bigger chunks?
+        for chunk in data.chunks(32) {
+            #[cfg(feature = "progressbar")]
+            bar.inc(32);
+            match self.write(&Frame::Data(chunk.to_vec())) {
+                Err(Error::AbortDataPhase) => {
+                    debug!("device aborted data phase");
+                    aborted = true;
+                    break;
                 }
-
-                let packet = ResponsePacket::try_from(self.read_packet()?)?;
-                assert_eq!(packet.parameters[0].to_le_bytes()[0], command.header()[0]);
-
-                debug!("read {} in total", data.len());
-                Ok(command::Response::Data(data))
+                result => result?,
             }
+        }
 
-            (command::Command::ReadMemory { address: _, length }, _, _) => {
-                let packet = ResponsePacket::try_from(initial_response)?;
-                // assert_eq!([0x03, 0x00, 0x0C, 0x00], &initial_generic_response[..4]);
-                assert!(packet.has_data);
-                assert!(packet.status.is_none());
-                assert_eq!(packet.tag, command::ResponseTag::ReadMemory);
-
-                // ReadMemory response: 2 parameters, status and then number of bytes to be
-                // sent in data phase
-                assert_eq!(packet.parameters.len(), 1);
-                assert_eq!(packet.parameters[0] as usize, length);
-
-                let mut data = Vec::new();
-                while data.len() < length {
-                    let partial_data: Vec<u8> = self.read_packet()?.try_into()?;
-                    assert!(data.len() + partial_data.len() <= length);
-                    data.extend_from_slice(&partial_data);
-                }
+        self.read_final_response(command)?;
+        if aborted {
+            return Err(Error::AbortDataPhase);
+        }
+        Ok(())
+    }
 
+    /// Read the final generic response after a command data phase.
+    ///
+    /// The device may abort the data phase with an empty response, in which case
+    /// the actual status follows in another response.
+    fn read_final_response(&self, command: &command::Command) -> Result<ResponsePacket> {
+        let packet = match self.read_packet() {
+            Err(Error::AbortDataPhase) => {
+                debug!("device aborted data phase");
                 let packet = ResponsePacket::try_from(self.read_packet()?)?;
-                assert!(!packet.has_data);
-                assert!(packet.status.is_none());
-
-                assert_eq!(packet.tag, command::ResponseTag::Generic);
-                // general property of generic responses: 2 parameters, status and mirrored command header
-                assert_eq!(packet.parameters.len(), 1);
-                // it seems the device "forgets" about the parameters the original command
-                // contained (address + length)
-                // ooorrr, Table 4-11 ("The Command tag parameter identifies the response to the command sent by the host.")
-                // just means that the command tag is set
-                assert_eq!(
-                    packet.parameters[0].to_le_bytes()[..2],
-                    command.header()[..2]
-                );
-
-                Ok(command::Response::ReadMemory(data))
+                // an abort with success status makes no sense
+                if packet.status.is_none() {
+                    return Err(Error::AbortDataPhase);
+                }
+                packet
             }
-            _ => todo!(),
-        }
+            packet => ResponsePacket::try_from(packet?)?,
+        };
+        packet.check_generic(command)
     }
 
-    pub fn read_packet(&self) -> Result<ReceivedPacket> {
-        // read data with timeout
+    /// Read a response data phase of (at most) `length` bytes, and the final response.
+    ///
+    /// The device may abort the data phase early by sending a zero-length packet,
+    /// the final response then carries the reason.
+    fn read_data(&self, command: &command::Command, length: usize) -> Result<Vec<u8>> {
         let mut data = Vec::new();
-        data.resize(256, 0);
-        let read = self.device.read_timeout(&mut data, READ_TIMEOUT)?;
-        data.resize(read, 0);
+        while data.len() < length {
+            let partial_data: Vec<u8> = self.read_packet()?.try_into()?;
+            if partial_data.is_empty() {
+                debug!("device aborted data phase after {} bytes", data.len());
+                self.read_final_response(command)?;
+                return Err(Error::AbortDataPhase);
+            }
+            if data.len() + partial_data.len() > length {
+                return Err(Error::UnexpectedResponse(command.tag()));
+            }
+            data.extend_from_slice(&partial_data);
+        }
 
-        let report_id = command::ReportId::try_from(data[0]).map_err(Error::InvalidReportId)?;
+        self.read_final_response(command)?;
+        Ok(data)
+    }
 
-        // the device often sends "extra junk"; we split this off early
-        let expected_packet_len = u16::from_le_bytes(data[2..4].try_into().unwrap()) as usize;
-        data.resize(4 + expected_packet_len, 0);
-        trace!("--> {} ({}B)", hex_str!(&data, 4), data.len());
+    pub fn read_packet(&self) -> Result<ReceivedPacket> {
+        let response_packet = match self.read_timeout(READ_TIMEOUT)? {
+            Frame::Data(data) => return Ok(ReceivedPacket::Data(data)),
+            Frame::Command(response_packet) => response_packet,
+        };
+
+        // NB: this can be  "short" answer (just `03 00 00 00`), which means an
+        // "AbortDataPhase".
+        // In this case, need to pull naother response to get the error.
+        if response_packet.is_empty() {
+            return Err(Error::AbortDataPhase);
+        }
+        if response_packet.len() < 4 {
+            return Err(Error::MalformedResponse);
+        }
+        let tag = command::ResponseTag::try_from(response_packet[0])
+            .map_err(Error::UnknownResponseTag)?;
+        let has_data = (response_packet[1] & 1) != 0;
+        let expected_param_count = response_packet[3] as usize;
+
+        let mut parameters: Vec<u32> = response_packet[4..]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        if parameters.is_empty() || expected_param_count != parameters.len() {
+            return Err(Error::MalformedResponse);
+        }
+
+        // first parameter is always status
+        let status_code = parameters.remove(0);
+        let status = match status_code {
+            0 => None,
+            code => Some(BootloaderError::from(code)),
+        };
 
-        let response_packet = data.split_off(4);
+        // NB: this is only true for Generic responses
+        // // second parameter is always mirrored command header
+        // let mirrored_command_header = parameters.remove(0).to_le_bytes();
 
         // now handle the response packet
-        Ok(match report_id {
-            command::ReportId::Response => {
-                // NB: this can be  "short" answer (just `03 00 00 00`), which means an
-                // "AbortDataPhase".
-                // In this case, need to pull naother response to get the error.
-                if response_packet.is_empty() {
-                    return Err(Error::AbortDataPhase);
-                }
-                let tag = command::ResponseTag::try_from(response_packet[0])
-                    .map_err(Error::UnknownResponseTag)?;
-                let has_data = (response_packet[1] & 1) != 0;
-                let expected_param_count = response_packet[3] as usize;
-
-                let mut parameters: Vec<u32> = response_packet[4..]
-                    .chunks(4)
-                    .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
-                    .collect();
-                assert_eq!(expected_param_count, parameters.len());
-
-                // first parameter is always status
-                let status_code = parameters.remove(0);
-                let status = match status_code {
-                    0 => None,
-                    code => Some(BootloaderError::from(code)),
-                };
-
-                // NB: this is only true for Generic responses
-                // // second parameter is always mirrored command header
-                // let mirrored_command_header = parameters.remove(0).to_le_bytes();
-
-                ReceivedPacket::Response(ResponsePacket {
-                    tag,
-                    has_data,
-                    status,
-                    // mirrored_command_header,
-                    parameters,
-                })
-            }
-            command::ReportId::ResponseData => ReceivedPacket::Data(response_packet),
-            _ => todo!(),
-        })
+        Ok(ReceivedPacket::Response(ResponsePacket {
+            tag,
+            has_data,
+            status,
+            // mirrored_command_header,
+            parameters,
+        }))
     }
 
-    pub fn write(&self, data: &[u8]) -> Result<()> {
-        let sent = self.device.write(data)?;
-        let all = data.len();
-        if sent >= all {
-            Ok(())
-        } else {
-            Err(hidapi::HidError::IncompleteSendError { sent, all }.into())
-        }
+    pub fn write(&self, frame: &Frame) -> Result<()> {
+        self.transport.write_frame(frame)
     }
 
-    pub fn read_timeout(&self, timeout: usize) -> HidResult<Vec<u8>> {
-        let mut data = Vec::new();
-        data.resize(256, 0);
-        let read = self.device.read_timeout(&mut data, timeout as i32)?;
-        data.resize(read, 0);
-        Ok(data)
+    pub fn read_timeout(&self, timeout: Duration) -> Result<Frame> {
+        self.transport.read_frame(timeout)
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
     }
 }
 
 impl Protocol {
-    pub fn new(device: HidDevice) -> Self {
-        Self { device }
+    pub fn new(device: hidapi::HidDevice) -> Self {
+        Self::from_transport(HidTransport::new(device))
     }
 }
 
-impl std::fmt::Debug for Protocol {
+impl<T: Transport> Protocol<T> {
+    pub fn from_transport(transport: T) -> Self {
+        Self { transport }
+    }
+}
+
+impl<T: Transport + std::fmt::Debug> std::fmt::Debug for Protocol<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Device")
-            // .debug_struct("HidDevice")
-            .field("manufacturer", &self.device.get_manufacturer_string())
-            .field("product", &self.device.get_product_string())
-            .field("serial number", &self.device.get_serial_number_string())
-            // .finish()
-            .finish()
+        self.transport.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bootloader::command::{Command, Response};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Replays canned responses, for response types the simulator does not produce.
+    struct Scripted {
+        responses: RefCell<VecDeque<Frame>>,
+    }
+
+    impl Transport for Scripted {
+        fn write_frame(&self, _frame: &Frame) -> Result<()> {
+            Ok(())
+        }
+
+        fn read_frame(&self, _timeout: Duration) -> Result<Frame> {
+            self.responses
+                .borrow_mut()
+                .pop_front()
+                .ok_or(Error::Timeout)
+        }
+    }
+
+    fn protocol(responses: &[Frame]) -> Protocol<Scripted> {
+        Protocol::from_transport(Scripted {
+            responses: RefCell::new(responses.iter().cloned().collect()),
+        })
+    }
+
+    fn words(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn flash_read_once() {
+        let mut packet = vec![0xAF, 0, 0, 4];
+        packet.extend(words(&[0, 8, 0x1234_5678, 0x9ABC_DEF0]));
+        let protocol = protocol(&[Frame::Command(packet)]);
+        let response = protocol
+            .call(&Command::FlashReadOnce { index: 1, count: 2 })
+            .unwrap();
+        assert_eq!(
+            response,
+            Response::FlashReadOnce(vec![0x1234_5678, 0x9ABC_DEF0])
+        );
+    }
+
+    #[test]
+    fn read_key_blob() {
+        let mut initial = vec![0xA3, 1, 0, 2];
+        initial.extend(words(&[0, 72]));
+        let mut last = vec![0xA0, 0, 0, 2];
+        last.extend(words(&[0, 0x13]));
+        let protocol = protocol(&[
+            Frame::Command(initial),
+            Frame::Data(vec![0x11; 56]),
+            Frame::Data(vec![0x22; 16]),
+            Frame::Command(last),
+        ]);
+        let response = protocol
+            .call(&Command::ReadKeyBlob {
+                key_selector: 0,
+                length: 72,
+            })
+            .unwrap();
+        let mut expected = vec![0x11; 56];
+        expected.extend_from_slice(&[0x22; 16]);
+        assert_eq!(response, Response::Data(expected));
+    }
+
+    #[test]
+    fn status_of_final_response() {
+        let mut initial = vec![0xA3, 1, 0, 2];
+        initial.extend(words(&[0, 8]));
+        let mut last = vec![0xA0, 0, 0, 2];
+        last.extend(words(&[101, 0x03]));
+        let protocol = protocol(&[
+            Frame::Command(initial),
+            Frame::Data(vec![0; 4]),
+            Frame::Data(vec![]),
+            Frame::Command(last),
+        ]);
+        let error = protocol
+            .call(&Command::ReadMemory {
+                address: 0,
+                length: 8,
+            })
+            .unwrap_err();
+        assert_eq!(
+            error.status(),
+            Some(BootloaderError::FlashDriver(
+                crate::bootloader::error::FlashDriverError::Alignment
+            ))
+        );
     }
 }
@@ -0,0 +1,1080 @@
+//! A simulated LPC55 ROM bootloader, to exercise `Bootloader` without hardware.
+//!
+//! The `Simulator` is a `Transport` which, instead of talking to a device, executes
+//! the commands it receives against an in-memory model of an LPC55S69:
+//!
+//! - internal flash (with the protected flash region, PFR) and SRAM
+//! - the CFPA scratch/ping/pong mechanism, with version checks and the page swap on reset
+//! - sealing of the CMPA page
+//! - the PUF keystore operations, with a volatile keystore that is only
+//!   persisted on `WriteNonVolatile`
+//!
+//! Responses follow the behaviour observed on real devices, including the three data phase
+//! cases, aborted data phases and status codes from `bootloader::error`. The properties
+//! mirror those of a real LPC55S69 (see the `all_properties` snapshot).
+//!
+//! Simplifications: erased flash reads as `0xFF`, and SB2.1 files are only checked for a
+//! valid header; their boot commands are not executed. `Execute` and `Call` are recorded
+//! instead of jumping anywhere, `FlashReadResource` reads the PFR pages. Like on the real
+//! device, the one time programmable area, reliable update and key blobs are not available.
+
+use core::convert::{TryFrom, TryInto};
+use core::time::Duration;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::command::{CommandTag, Key, ResponseTag};
+use super::error::{FlashDriverError, GenericError, PropertyStoreError, SbLoaderError};
+use super::property::{
+    AvailableCommands as Commands, AvailablePeripherals as Peripherals, Property,
+};
+use super::protocol::{Error as ProtocolError, Result as ProtocolResult};
+use super::transport::{Frame, Transport};
+use super::Error;
+use crate::protected_flash::{
+    CUSTOMER_SETTINGS_SCRATCH_ADDRESS, FACTORY_SETTINGS_ADDRESS, KEYSTORE_ADDRESS,
+};
+
+/// VID of the LPC55 ROM bootloader
+pub const VID: u16 = 0x1fc9;
+/// PID of the LPC55 ROM bootloader
+pub const PID: u16 = 0x0021;
+
+pub const FLASH_SIZE: usize = 0x9_DE00;
+pub const PAGE_SIZE: usize = 512;
+pub const SECTOR_SIZE: usize = 0x8000;
+pub const RAM_START: usize = 0x2000_0000;
+pub const RAM_SIZE: usize = 0x4_0000;
+/// Size of the memory image, user flash followed by the modelled PFR pages.
+pub const MEMORY_SIZE: usize = KEYSTORE_ADDRESS + 3 * PAGE_SIZE;
+
+const PING_ADDRESS: usize = CUSTOMER_SETTINGS_SCRATCH_ADDRESS + PAGE_SIZE;
+const PONG_ADDRESS: usize = CUSTOMER_SETTINGS_SCRATCH_ADDRESS + 2 * PAGE_SIZE;
+const MAX_PACKET_SIZE: usize = 56;
+const KEYSTORE_HEADER: u32 = 0x9595_9595;
+const KEYCODE_MARKER: [u8; 4] = [0x59; 4];
+
+/// kStatus_UnknownCommand (not modelled in `bootloader::Error`)
+const UNKNOWN_COMMAND: Error = Error::Unknown(10000);
+/// kStatusMemoryRangeInvalid (not modelled in `bootloader::Error`)
+const MEMORY_RANGE_INVALID: Error = Error::Unknown(10200);
+
+// ROM regions reported as reserved, writes to these are rejected
+const RESERVED_REGIONS: [(u32, u32); 4] = [
+    (0x1400_0000, 0x1400_5FFF),
+    (0x0400_0000, 0x0400_7FFF),
+    (0x3000_0000, 0x3000_5FFF),
+    (0x2000_0000, 0x2000_5FFF),
+];
+
+type Status = core::result::Result<(), Error>;
+
+/// A command whose command data phase is in progress.
+struct Pending {
+    tag: CommandTag,
+    parameters: Vec<u32>,
+    expected: usize,
+    data: Vec<u8>,
+    aborted: bool,
+}
+
+struct State {
+    memory: Vec<u8>,
+    ram: Vec<u8>,
+    uuid: u128,
+    // volatile PUF state, lost on reset
+    puf_enrolled: bool,
+    keystore: Vec<u8>,
+    keys: [Option<Vec<u8>>; 16],
+    pending: Option<Pending>,
+    outgoing: VecDeque<Frame>,
+    resets: usize,
+    verify_writes: bool,
+    calls: Vec<(CommandTag, Vec<u32>)>,
+}
+
+/// The simulated bootloader, see module documentation.
+pub struct Simulator {
+    state: Mutex<State>,
+    path: Option<PathBuf>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    /// A factory fresh device: erased flash, zeroed PFR, PUF not enrolled.
+    pub fn new() -> Self {
+        let mut memory = vec![0xFF; MEMORY_SIZE];
+        memory[FLASH_SIZE..].iter_mut().for_each(|byte| *byte = 0);
+        Self::with_memory(memory)
+    }
+
+    fn with_memory(memory: Vec<u8>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                memory,
+                ram: vec![0; RAM_SIZE],
+                uuid: 0x5349_4D55_4C41_5445_4400_0000_0000_0001,
+                puf_enrolled: false,
+                keystore: vec![0; 3 * PAGE_SIZE],
+                keys: Default::default(),
+                pending: None,
+                outgoing: VecDeque::new(),
+                resets: 0,
+                verify_writes: true,
+                calls: Vec::new(),
+            }),
+            path: None,
+        }
+    }
+
+    /// A simulator whose flash (including PFR) is loaded from and saved back to a file,
+    /// so its state persists across invocations.
+    pub fn persistent(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut simulator = if path.exists() {
+            let memory = std::fs::read(&path)?;
+            if memory.len() != MEMORY_SIZE {
+                return Err(anyhow::anyhow!(
+                    "simulator state {:?} has {} bytes, expected {}",
+                    &path,
+                    memory.len(),
+                    MEMORY_SIZE
+                ));
+            }
+            Self::with_memory(memory)
+        } else {
+            Self::new()
+        };
+        simulator.path = Some(path);
+        Ok(simulator)
+    }
+
+    pub fn with_uuid(self, uuid: u128) -> Self {
+        self.state.lock().unwrap().uuid = uuid;
+        self
+    }
+
+    /// Contents of flash (and the modelled PFR pages), starting at address zero.
+    pub fn memory(&self) -> Vec<u8> {
+        self.state.lock().unwrap().memory.clone()
+    }
+
+    pub fn ram(&self) -> Vec<u8> {
+        self.state.lock().unwrap().ram.clone()
+    }
+
+    /// Number of `Reset` commands executed so far.
+    pub fn resets(&self) -> usize {
+        self.state.lock().unwrap().resets
+    }
+
+    /// The `Execute` and `Call` commands received so far, with their parameters.
+    pub fn calls(&self) -> Vec<(CommandTag, Vec<u32>)> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// The plaintext of a key set or generated since the last reset.
+    pub fn key(&self, key: Key) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().keys[key as usize].clone()
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.as_ref() {
+            let state = self.state.lock().unwrap();
+            if let Err(error) = std::fs::write(path, &state.memory) {
+                warn!("could not save simulator state to {:?}: {}", path, error);
+            }
+        }
+    }
+}
+
+impl Transport for Simulator {
+    fn write_frame(&self, frame: &Frame) -> ProtocolResult<()> {
+        let mut state = self.state.lock().unwrap();
+        match frame {
+            Frame::Command(packet) => state.command(packet),
+            Frame::Data(data) => state.data(data),
+        }
+        Ok(())
+    }
+
+    fn read_frame(&self, _timeout: Duration) -> ProtocolResult<Frame> {
+        self.state
+            .lock()
+            .unwrap()
+            .outgoing
+            .pop_front()
+            .ok_or(ProtocolError::Timeout)
+    }
+}
+
+impl std::fmt::Debug for Simulator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Simulator")
+            .field(
+                "uuid",
+                &hexstr!(&self.state.lock().unwrap().uuid.to_be_bytes()),
+            )
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+fn status_code(status: Status) -> u32 {
+    match status {
+        Ok(()) => 0,
+        Err(error) => error.into(),
+    }
+}
+
+fn response(tag: ResponseTag, has_data: bool, status: Status, parameters: &[u32]) -> Frame {
+    let mut packet = vec![tag as u8, has_data as u8, 0, 1 + parameters.len() as u8];
+    packet.extend_from_slice(&status_code(status).to_le_bytes());
+    for parameter in parameters {
+        packet.extend_from_slice(&parameter.to_le_bytes());
+    }
+    Frame::Command(packet)
+}
+
+/// Generic responses carry the status and the tag of the command they respond to.
+fn generic_response(tag: CommandTag, status: Status) -> Frame {
+    response(ResponseTag::Generic, false, status, &[tag as u32])
+}
+
+fn flash_error(error: FlashDriverError) -> Error {
+    Error::FlashDriver(error)
+}
+
+/// Deterministic stand-in for PUF-derived material.
+fn derive(uuid: u128, label: &[u8], length: usize) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut counter = 0u32;
+    while output.len() < length {
+        let mut input = uuid.to_be_bytes().to_vec();
+        input.extend_from_slice(label);
+        input.extend_from_slice(&counter.to_le_bytes());
+        output.extend_from_slice(&crate::crypto::sha256(&input));
+        counter += 1;
+    }
+    output.truncate(length);
+    output
+}
+
+/// Offset of the key code for the given key in the keystore.
+fn keycode_offset(key: Key) -> usize {
+    let slot = match key {
+        Key::SecureBootKek => 0,
+        Key::UserPsk => 1,
+        Key::UniqueDeviceSecret => 2,
+        Key::PrinceRegion0 => 3,
+        Key::PrinceRegion1 => 4,
+        Key::PrinceRegion2 => 5,
+    };
+    4 + 4 + 1192 + 56 * slot
+}
+
+fn key_from_index(index: u32) -> Option<Key> {
+    use Key::*;
+    [
+        PrinceRegion0,
+        PrinceRegion1,
+        PrinceRegion2,
+        SecureBootKek,
+        UniqueDeviceSecret,
+        UserPsk,
+    ]
+    .iter()
+    .copied()
+    .find(|key| *key as u32 == index)
+}
+
+fn valid_key_size(key: Key, size: usize) -> bool {
+    match key {
+        // the example configuration provisions a 128 bit KEK, so accept both AES key sizes
+        Key::SecureBootKek => size == 16 || size == 32,
+        Key::UniqueDeviceSecret => size == 32,
+        Key::PrinceRegion0 | Key::PrinceRegion1 | Key::PrinceRegion2 => size == 16,
+        Key::UserPsk => size.is_multiple_of(8) && (8..=32).contains(&size),
+    }
+}
+
+impl State {
+    fn respond(&mut self, frame: Frame) {
+        self.outgoing.push_back(frame);
+    }
+
+    fn command(&mut self, packet: &[u8]) {
+        if packet.len() < 4 {
+            warn!("simulator: ignoring short command packet");
+            return;
+        }
+        let count = packet[3] as usize;
+        let parameters: Vec<u32> = packet[4..]
+            .chunks_exact(4)
+            .take(count)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        // a new command cancels any pending data phase
+        self.pending = None;
+
+        let tag = match CommandTag::try_from(packet[0]) {
+            Ok(tag) => tag,
+            Err(byte) => {
+                debug!("simulator: unknown command tag {:02X}", byte);
+                let mut packet = vec![ResponseTag::Generic as u8, 0, 0, 2];
+                packet.extend_from_slice(&u32::from(UNKNOWN_COMMAND).to_le_bytes());
+                packet.extend_from_slice(&(byte as u32).to_le_bytes());
+                self.respond(Frame::Command(packet));
+                return;
+            }
+        };
+        if parameters.len() < count {
+            self.respond(generic_response(
+                tag,
+                Err(Error::Generic(GenericError::InvalidArgument)),
+            ));
+            return;
+        }
+        let parameter = |i: usize| parameters.get(i).copied().unwrap_or(0);
+        trace!("simulator: {:?} {:X?}", tag, &parameters);
+
+        match tag {
+            CommandTag::EraseFlashAll => {
+                self.memory[..FLASH_SIZE]
+                    .iter_mut()
+                    .for_each(|byte| *byte = 0xFF);
+                self.respond(generic_response(tag, Ok(())));
+            }
+            CommandTag::EraseFlash => {
+                let status = self.erase(parameter(0) as usize, parameter(1) as usize);
+                self.respond(generic_response(tag, status));
+            }
+            CommandTag::ReadMemory => {
+                let (address, length) = (parameter(0) as usize, parameter(1) as usize);
+                match self.read(address, length) {
+                    Ok(data) => self.send_data(tag, ResponseTag::ReadMemory, data),
+                    Err(error) => {
+                        self.respond(response(ResponseTag::ReadMemory, false, Err(error), &[0]))
+                    }
+                }
+            }
+            CommandTag::WriteMemory => {
+                let (address, length) = (parameter(0) as usize, parameter(1) as usize);
+                let status = self.check_write(address, length);
+                self.start_data_phase(tag, parameters, length, status);
+            }
+            CommandTag::FillMemory => {
+                let (address, length) = (parameter(0) as usize, parameter(1) as usize);
+                let pattern = parameter(2).to_le_bytes();
+                let status = self.check_write(address, length).and_then(|()| {
+                    let data: Vec<u8> = pattern.iter().copied().cycle().take(length).collect();
+                    self.write(address, &data)
+                });
+                self.respond(generic_response(tag, status));
+            }
+            // flash is never secured
+            CommandTag::FlashSecurityDisable => self.respond(generic_response(tag, Ok(()))),
+            CommandTag::SetProperty => {
+                let status = match (self.property(parameter(0)), parameter(0)) {
+                    (Err(error), _) => Err(error),
+                    (Ok(_), property) if property == Property::VerifyWrites as u32 => {
+                        match parameter(1) {
+                            0 | 1 => {
+                                self.verify_writes = parameter(1) == 1;
+                                Ok(())
+                            }
+                            _ => Err(Error::PropertyStore(PropertyStoreError::InvalidValue)),
+                        }
+                    }
+                    (Ok(_), _) => Err(Error::PropertyStore(PropertyStoreError::ReadOnlyProperty)),
+                };
+                self.respond(generic_response(tag, status));
+            }
+            CommandTag::Execute | CommandTag::Call => {
+                self.calls.push((tag, parameters.clone()));
+                self.respond(generic_response(tag, Ok(())));
+            }
+            CommandTag::FlashReadResource => {
+                let (address, length) = (parameter(0) as usize, parameter(1) as usize);
+                let start = CUSTOMER_SETTINGS_SCRATCH_ADDRESS;
+                match (parameter(2), self.read(start + address, length)) {
+                    (0, Ok(data)) if start + address + length <= MEMORY_SIZE => {
+                        self.send_data(tag, ResponseTag::FlashReadResource, data)
+                    }
+                    _ => self.respond(response(
+                        ResponseTag::FlashReadResource,
+                        false,
+                        Err(Error::Generic(GenericError::InvalidArgument)),
+                        &[0],
+                    )),
+                }
+            }
+            CommandTag::GetProperty => {
+                let frame = match self.property(parameter(0)) {
+                    Ok(values) => response(ResponseTag::GetProperty, false, Ok(()), &values),
+                    Err(error) => response(ResponseTag::GetProperty, false, Err(error), &[]),
+                };
+                self.respond(frame);
+            }
+            CommandTag::ReceiveSbFile => {
+                let length = parameter(0) as usize;
+                self.start_data_phase(tag, parameters, length, Ok(()));
+            }
+            CommandTag::Reset => {
+                self.respond(generic_response(tag, Ok(())));
+                self.reset();
+            }
+            CommandTag::ConfigureMemory => {
+                let address = parameter(1) as usize;
+                let status = match self.read(address, 4) {
+                    // PRINCE configuration blocks are tagged 0x5000_000N
+                    Ok(word) if word[3] >> 4 == 0x5 => Ok(()),
+                    Ok(_) => Err(Error::Generic(GenericError::InvalidArgument)),
+                    Err(error) => Err(error),
+                };
+                self.respond(generic_response(tag, status));
+            }
+            CommandTag::Keystore => self.keystore_command(&parameters),
+            _ => self.respond(generic_response(tag, Err(UNKNOWN_COMMAND))),
+        }
+    }
+
+    fn keystore_command(&mut self, parameters: &[u32]) {
+        let tag = CommandTag::Keystore;
+        let parameter = |i: usize| parameters.get(i).copied().unwrap_or(0);
+        match parameter(0) {
+            // Enroll
+            0 => {
+                self.puf_enrolled = true;
+                self.keys = Default::default();
+                self.keystore = vec![0; 3 * PAGE_SIZE];
+                self.keystore[..4].copy_from_slice(&KEYSTORE_HEADER.to_le_bytes());
+                let activation_code = derive(self.uuid, b"activation code", 1192);
+                self.keystore[8..8 + 1192].copy_from_slice(&activation_code);
+                self.respond(generic_response(tag, Ok(())));
+            }
+            // SetKey
+            1 => {
+                let size = parameter(2) as usize;
+                let status = self.check_key(parameter(1), size);
+                self.start_data_phase(tag, parameters.to_vec(), size, status);
+            }
+            // GenerateKey
+            2 => {
+                let size = parameter(2) as usize;
+                let status = self.check_key(parameter(1), size);
+                if status.is_ok() {
+                    let key = key_from_index(parameter(1)).unwrap();
+                    let data = derive(self.uuid, &[b'k', key as u8, self.resets as u8], size);
+                    self.store_key(key, &data, true);
+                }
+                self.respond(generic_response(tag, status));
+            }
+            // WriteNonVolatile, needs an enrolled or written keystore
+            3 => {
+                let status = if self.keystore[..4] == KEYSTORE_HEADER.to_le_bytes() {
+                    let keystore = self.keystore.clone();
+                    self.memory[KEYSTORE_ADDRESS..][..3 * PAGE_SIZE].copy_from_slice(&keystore);
+                    Ok(())
+                } else {
+                    Err(Error::Generic(GenericError::Fail))
+                };
+                self.respond(generic_response(tag, status));
+            }
+            // ReadNonVolatile
+            4 => {
+                self.keystore = self.memory[KEYSTORE_ADDRESS..][..3 * PAGE_SIZE].to_vec();
+                self.respond(generic_response(tag, Ok(())));
+            }
+            // WriteKeystore: reserved parameter, then the length of the whole keystore
+            5 => {
+                let size = parameter(2) as usize;
+                let status = match parameters.len() == 3 && size == 3 * PAGE_SIZE {
+                    true => Ok(()),
+                    false => Err(Error::Generic(GenericError::InvalidArgument)),
+                };
+                self.start_data_phase(tag, parameters.to_vec(), size, status);
+            }
+            // ReadKeystore
+            6 => {
+                let keystore = self.keystore.clone();
+                self.send_data(tag, ResponseTag::Keystore, keystore);
+            }
+            _ => self.respond(generic_response(
+                tag,
+                Err(Error::Generic(GenericError::InvalidArgument)),
+            )),
+        }
+    }
+
+    fn check_key(&self, index: u32, size: usize) -> Status {
+        let key = key_from_index(index).ok_or(Error::Generic(GenericError::InvalidArgument))?;
+        if !valid_key_size(key, size) {
+            return Err(Error::Generic(GenericError::InvalidArgument));
+        }
+        // after a reset, keys can not be set without enrolling again
+        if !self.puf_enrolled {
+            return Err(Error::Generic(GenericError::Fail));
+        }
+        Ok(())
+    }
+
+    fn store_key(&mut self, key: Key, data: &[u8], generated: bool) {
+        let mut keycode = KEYCODE_MARKER.to_vec();
+        keycode.extend_from_slice(&[generated as u8, key as u8, 0, (data.len() / 8) as u8]);
+        let mask = derive(self.uuid, &[b'm', key as u8], 48);
+        keycode.extend(
+            mask.iter()
+                .zip(data.iter().chain([0u8; 48].iter()))
+                .map(|(m, d)| m ^ d),
+        );
+        self.keystore[keycode_offset(key)..][..56].copy_from_slice(&keycode);
+        self.keys[key as usize] = Some(data.to_vec());
+    }
+
+    /// Responds with an initial response, the data packets, and a final generic response.
+    fn send_data(&mut self, tag: CommandTag, response_tag: ResponseTag, data: Vec<u8>) {
+        self.respond(response(response_tag, true, Ok(()), &[data.len() as u32]));
+        for chunk in data.chunks(MAX_PACKET_SIZE) {
+            self.respond(Frame::Data(chunk.to_vec()));
+        }
+        self.respond(generic_response(tag, Ok(())));
+    }
+
+    fn start_data_phase(
+        &mut self,
+        tag: CommandTag,
+        parameters: Vec<u32>,
+        expected: usize,
+        status: Status,
+    ) {
+        self.respond(generic_response(tag, status));
+        if status.is_ok() {
+            self.pending = Some(Pending {
+                tag,
+                parameters,
+                expected,
+                data: Vec::new(),
+                aborted: false,
+            });
+            if expected == 0 {
+                self.complete();
+            }
+        }
+    }
+
+    fn data(&mut self, data: &[u8]) {
+        let pending = match self.pending.as_mut() {
+            Some(pending) if !pending.aborted => pending,
+            _ => {
+                debug!("simulator: ignoring unexpected data packet");
+                return;
+            }
+        };
+        let received = pending.data.len();
+        let remaining = pending.expected - received;
+        pending
+            .data
+            .extend_from_slice(&data[..core::cmp::min(remaining, data.len())]);
+
+        // the ROM checks the SB header early on, and aborts the data phase
+        if pending.tag == CommandTag::ReceiveSbFile && received < 56 && pending.data.len() >= 56 {
+            let header = &pending.data;
+            let valid = &header[20..24] == b"STMP"
+                && header[24] == 2
+                && header[25] == 1
+                && &header[52..56] == b"sgtl";
+            if !valid {
+                pending.aborted = true;
+                self.respond(Frame::Command(Vec::new()));
+                self.respond(generic_response(
+                    CommandTag::ReceiveSbFile,
+                    Err(Error::SbLoader(SbLoaderError::Signature)),
+                ));
+                return;
+            }
+        }
+
+        if pending.data.len() == pending.expected {
+            self.complete();
+        }
+    }
+
+    fn complete(&mut self) {
+        let pending = self.pending.take().unwrap();
+        let status = match pending.tag {
+            CommandTag::WriteMemory => self.write(pending.parameters[0] as usize, &pending.data),
+            CommandTag::ReceiveSbFile => Ok(()),
+            CommandTag::Keystore => match pending.parameters[0] {
+                // SetKey
+                1 => {
+                    let key = key_from_index(pending.parameters[1]).unwrap();
+                    self.store_key(key, &pending.data, false);
+                    Ok(())
+                }
+                // WriteKeystore, the PUF is not enrolled by this
+                _ => {
+                    self.keystore = pending.data;
+                    Ok(())
+                }
+            },
+            _ => unreachable!(),
+        };
+        self.respond(generic_response(pending.tag, status));
+    }
+
+    fn erase(&mut self, address: usize, length: usize) -> Status {
+        if !address.is_multiple_of(PAGE_SIZE) || !length.is_multiple_of(PAGE_SIZE) {
+            return Err(flash_error(FlashDriverError::Alignment));
+        }
+        if address + length > FLASH_SIZE {
+            return Err(flash_error(FlashDriverError::Address));
+        }
+        self.memory[address..][..length]
+            .iter_mut()
+            .for_each(|byte| *byte = 0xFF);
+        Ok(())
+    }
+
+    fn read(&self, address: usize, length: usize) -> core::result::Result<Vec<u8>, Error> {
+        if address + length <= MEMORY_SIZE {
+            Ok(self.memory[address..][..length].to_vec())
+        } else if address >= RAM_START && address + length <= RAM_START + RAM_SIZE {
+            Ok(self.ram[address - RAM_START..][..length].to_vec())
+        } else {
+            Err(MEMORY_RANGE_INVALID)
+        }
+    }
+
+    fn check_write(&self, address: usize, length: usize) -> Status {
+        let end = address + length;
+        if end <= FLASH_SIZE {
+            return Ok(());
+        }
+        if address >= RAM_START && end <= RAM_START + RAM_SIZE {
+            let reserved = RESERVED_REGIONS
+                .iter()
+                .any(|(start, last)| address <= *last as usize && end > *start as usize);
+            return if reserved {
+                Err(MEMORY_RANGE_INVALID)
+            } else {
+                Ok(())
+            };
+        }
+        match (address, length) {
+            (CUSTOMER_SETTINGS_SCRATCH_ADDRESS, PAGE_SIZE) => Ok(()),
+            (FACTORY_SETTINGS_ADDRESS, PAGE_SIZE) => {
+                if self.cmpa_sealed() {
+                    Err(flash_error(FlashDriverError::ProtectionViolation))
+                } else {
+                    Ok(())
+                }
+            }
+            (address, _) if address < MEMORY_SIZE => Err(flash_error(FlashDriverError::Address)),
+            _ => Err(MEMORY_RANGE_INVALID),
+        }
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> Status {
+        if address >= RAM_START {
+            self.ram[address - RAM_START..][..data.len()].copy_from_slice(data);
+            return Ok(());
+        }
+        if address == CUSTOMER_SETTINGS_SCRATCH_ADDRESS {
+            let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+            if version <= self.customer_version() {
+                return Err(flash_error(
+                    FlashDriverError::CustomerScratchVersionBehindActualCustomerVersion,
+                ));
+            }
+        }
+        self.memory[address..][..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn cmpa_sealed(&self) -> bool {
+        self.memory[FACTORY_SETTINGS_ADDRESS + 0x1E0..][..32]
+            .iter()
+            .any(|byte| *byte != 0)
+    }
+
+    fn page_version(&self, address: usize) -> u32 {
+        u32::from_le_bytes(self.memory[address + 4..][..4].try_into().unwrap())
+    }
+
+    /// Version of the active CFPA page (the more recent of ping and pong)
+    fn customer_version(&self) -> u32 {
+        core::cmp::max(
+            self.page_version(PING_ADDRESS),
+            self.page_version(PONG_ADDRESS),
+        )
+    }
+
+    fn reset(&mut self) {
+        // the ROM copies a newer scratch page over the older of ping and pong
+        let scratch_version = self.page_version(CUSTOMER_SETTINGS_SCRATCH_ADDRESS);
+        if scratch_version > self.customer_version() {
+            let older = if self.page_version(PING_ADDRESS) <= self.page_version(PONG_ADDRESS) {
+                PING_ADDRESS
+            } else {
+                PONG_ADDRESS
+            };
+            self.memory
+                .copy_within(CUSTOMER_SETTINGS_SCRATCH_ADDRESS..PING_ADDRESS, older);
+        }
+        self.puf_enrolled = false;
+        self.keys = Default::default();
+        self.keystore = vec![0; 3 * PAGE_SIZE];
+        self.ram.iter_mut().for_each(|byte| *byte = 0);
+        self.resets += 1;
+    }
+
+    fn property(&self, property: u32) -> core::result::Result<Vec<u32>, Error> {
+        use Property::*;
+        let property = <Property as enum_iterator::IntoEnumIterator>::into_enum_iter()
+            .find(|candidate| *candidate as u32 == property)
+            .ok_or(Error::PropertyStore(PropertyStoreError::UnknownProperty))?;
+
+        Ok(match property {
+            // K3.0.0
+            CurrentVersion => vec![0x4B03_0000],
+            // T1.1.4
+            TargetVersion => vec![0x5401_0104],
+            AvailablePeripherals => vec![(Peripherals::UART
+                | Peripherals::I2C
+                | Peripherals::SPI
+                | Peripherals::USB_HID)
+                .bits()],
+            AvailableCommands => vec![(Commands::ERASE_FLASH_ALL
+                | Commands::ERASE_FLASH
+                | Commands::READ_MEMORY
+                | Commands::FLASH_SECURITY_DISABLE
+                | Commands::GET_PROPERTY
+                | Commands::RECEIVE_SB_FILE
+                | Commands::CALL
+                | Commands::RESET
+                | Commands::FLASH_READ_RESOURCE)
+                .bits()],
+            FlashStartAddress => vec![0],
+            FlashSize => vec![FLASH_SIZE as u32],
+            FlashSectorSize => vec![SECTOR_SIZE as u32],
+            FlashPageSize => vec![PAGE_SIZE as u32],
+            CrcCheckStatus => vec![u32::from(Error::CrcChecker(
+                super::error::CrcCheckerError::Invalid,
+            ))],
+            VerifyWrites => vec![self.verify_writes as u32],
+            MaxPacketSize => vec![MAX_PACKET_SIZE as u32],
+            ReservedRegions => RESERVED_REGIONS
+                .iter()
+                .flat_map(|(start, end)| [*start, *end])
+                .collect(),
+            RamStartAddress => vec![RAM_START as u32],
+            RamSize => vec![RAM_SIZE as u32],
+            SystemDeviceIdent => vec![0x5010_00C5, 0x0004_26B1],
+            FlashSecurityState => vec![0x5AA5_5AA5],
+            UniqueDeviceIdent => {
+                // inverse of `GetProperties::device_uuid`
+                let words = u128::from_le_bytes(self.uuid.to_be_bytes());
+                (0..4).map(|i| (words >> (32 * i)) as u32).collect()
+            }
+            IrqNotificationPin => vec![0],
+            PfrKeystoreUpdateOptions => vec![0],
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bootloader::{Bootloader, Command, KeystoreOperation};
+
+    fn bootloader() -> Bootloader<Simulator> {
+        Bootloader::try_from_transport(Simulator::new(), VID, PID).unwrap()
+    }
+
+    #[test]
+    fn write_and_read_flash() {
+        let bootloader = bootloader();
+        let data: Vec<u8> = (0..2048).map(|i| i as u8).collect();
+        bootloader.erase_flash(0x1000, 2048).unwrap();
+        bootloader.write_memory(0x1000, data.clone()).unwrap();
+        assert_eq!(bootloader.read_memory(0x1000, 2048).unwrap(), data);
+        assert_eq!(
+            &bootloader.protocol.transport().memory()[0x1000..0x1800],
+            &data[..]
+        );
+    }
+
+    #[test]
+    fn customer_settings_swap_on_reset() {
+        let bootloader = bootloader();
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[4] = 1;
+        bootloader
+            .write_memory(CUSTOMER_SETTINGS_SCRATCH_ADDRESS, page.clone())
+            .unwrap();
+        bootloader.reboot().unwrap();
+
+        let pfr = bootloader
+            .read_memory(CUSTOMER_SETTINGS_SCRATCH_ADDRESS, 7 * PAGE_SIZE)
+            .unwrap();
+        let pfr = crate::protected_flash::ProtectedFlash::try_from(&pfr[..]).unwrap();
+        assert_eq!(pfr.customer.most_recent().customer_version.read(), 1);
+
+        // the version must increase
+        let mut state = bootloader.protocol.transport().state.lock().unwrap();
+        assert_eq!(
+            state.write(CUSTOMER_SETTINGS_SCRATCH_ADDRESS, &page),
+            Err(flash_error(
+                FlashDriverError::CustomerScratchVersionBehindActualCustomerVersion
+            ))
+        );
+    }
+
+    #[test]
+    fn sealed_factory_settings() {
+        let simulator = Simulator::new();
+        let mut state = simulator.state.lock().unwrap();
+        assert!(state
+            .check_write(FACTORY_SETTINGS_ADDRESS, PAGE_SIZE)
+            .is_ok());
+        state
+            .write(FACTORY_SETTINGS_ADDRESS, &[0x11; PAGE_SIZE])
+            .unwrap();
+        assert_eq!(
+            state.check_write(FACTORY_SETTINGS_ADDRESS, PAGE_SIZE),
+            Err(flash_error(FlashDriverError::ProtectionViolation))
+        );
+    }
+
+    #[test]
+    fn all_properties() {
+        // same device as in the `all_properties` snapshot, taken from a real LPC55S69
+        let simulator = Simulator::new().with_uuid(256287365343490487041797348548817407015);
+        let bootloader = Bootloader::try_from_transport(simulator, VID, PID).unwrap();
+        insta::with_settings!({
+            snapshot_path => "../snapshots",
+            prepend_module_to_snapshot => false,
+        }, {
+            insta::assert_debug_snapshot!(
+                "lpc55__bootloader__all_properties",
+                bootloader.all_properties().unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn keystore_operations() {
+        let bootloader = bootloader();
+        bootloader.enroll_puf().unwrap();
+        let kek = vec![0xAA; 32];
+        bootloader
+            .run_command(Command::Keystore(KeystoreOperation::SetKey {
+                key: Key::SecureBootKek,
+                data: kek.clone(),
+            }))
+            .unwrap();
+        bootloader
+            .run_command(Command::Keystore(KeystoreOperation::GenerateKey {
+                key: Key::PrinceRegion0,
+                len: 16,
+            }))
+            .unwrap();
+        bootloader
+            .run_command(Command::Keystore(KeystoreOperation::WriteNonVolatile))
+            .unwrap();
+
+        let simulator = bootloader.protocol.transport();
+        assert_eq!(simulator.key(Key::SecureBootKek), Some(kek));
+
+        let data = bootloader
+            .read_memory(KEYSTORE_ADDRESS, 3 * PAGE_SIZE)
+            .unwrap();
+        let keystore = crate::protected_flash::Keystore::try_from(&data[..]).unwrap();
+        assert_eq!(keystore.header.0, KEYSTORE_HEADER);
+        assert!(keystore.secure_boot_kek.valid());
+        assert!(keystore.secure_boot_kek.user_key());
+        assert!(keystore.prince_region_0.generated_key());
+        assert!(!keystore.user_key.valid());
+
+        let response = bootloader
+            .run_command(Command::Keystore(KeystoreOperation::ReadKeystore))
+            .unwrap();
+        assert_eq!(response, crate::bootloader::Response::Data(data));
+    }
+
+    #[test]
+    fn keystore_backup_and_restore() {
+        let bootloader = bootloader();
+        bootloader.enroll_puf().unwrap();
+        bootloader
+            .run_command(Command::Keystore(KeystoreOperation::GenerateKey {
+                key: Key::PrinceRegion0,
+                len: 16,
+            }))
+            .unwrap();
+        bootloader
+            .run_command(Command::Keystore(KeystoreOperation::WriteNonVolatile))
+            .unwrap();
+        let backup = bootloader.backup_keystore().unwrap();
+        assert!(backup.keystore.prince_region_0.valid());
+
+        // after a reset, keys can only be added by enrolling again, which loses the others
+        bootloader.reboot().unwrap();
+        bootloader.enroll_puf().unwrap();
+        bootloader
+            .run_command(Command::Keystore(KeystoreOperation::WriteNonVolatile))
+            .unwrap();
+        assert!(!bootloader.read_keystore().unwrap().prince_region_0.valid());
+
+        bootloader.reboot().unwrap();
+        bootloader.restore_keystore(&backup).unwrap();
+        assert_eq!(bootloader.read_keystore().unwrap(), backup.keystore);
+
+        // different die
+        let other =
+            Bootloader::try_from_transport(Simulator::new().with_uuid(42), VID, PID).unwrap();
+        let error = other.restore_keystore(&backup).unwrap_err();
+        assert!(error.to_string().contains("refusing to restore"));
+        assert_eq!(
+            other.read_keystore().unwrap(),
+            crate::protected_flash::Keystore::default()
+        );
+    }
+
+    #[test]
+    fn write_keystore_parameters() {
+        let data = vec![0u8; 3 * PAGE_SIZE];
+        let command = Command::Keystore(KeystoreOperation::WriteKeystore { data });
+        assert_eq!(command.parameters(), [5, 0, 3 * PAGE_SIZE as u32]);
+
+        // like the ROM, expect the length and no data phase otherwise
+        let simulator = Simulator::new();
+        let mut state = simulator.state.lock().unwrap();
+        state.keystore_command(&[5]);
+        assert!(state.pending.is_none());
+        state.keystore_command(&[5, 0, 512]);
+        assert!(state.pending.is_none());
+        state.keystore_command(&[5, 0, 3 * PAGE_SIZE as u32]);
+        assert!(state.pending.is_some());
+        drop(state);
+
+        // a written keystore needs a valid header to be stored
+        let bootloader = bootloader();
+        let keystore = crate::protected_flash::Keystore::default();
+        assert!(bootloader.write_keystore(&keystore).is_err());
+    }
+
+    #[test]
+    fn status_errors() {
+        use crate::bootloader::protocol::Error as ProtocolError;
+        use crate::bootloader::{command::CommandTag, error::SbLoaderError, Error};
+
+        let bootloader = bootloader();
+        bootloader
+            .write_memory(FACTORY_SETTINGS_ADDRESS, vec![0x11; PAGE_SIZE])
+            .unwrap();
+        match bootloader.write_memory(FACTORY_SETTINGS_ADDRESS, vec![0x22; PAGE_SIZE]) {
+            Err(ProtocolError::Status { command, status }) => {
+                assert_eq!(command, CommandTag::WriteMemory);
+                assert_eq!(status, flash_error(FlashDriverError::ProtectionViolation));
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+
+        let error = bootloader.read_memory(RAM_START + RAM_SIZE, 4).unwrap_err();
+        assert_eq!(error.status(), Some(MEMORY_RANGE_INVALID));
+
+        let error = bootloader.receive_sb_file(&[0u8; 1024]).unwrap_err();
+        assert_eq!(
+            error.status(),
+            Some(Error::SbLoader(SbLoaderError::Signature))
+        );
+
+        // the device stays usable after failed commands
+        assert_eq!(bootloader.read_memory(0, 4).unwrap(), [0xFF; 4]);
+    }
+
+    #[test]
+    fn additional_commands() {
+        use crate::bootloader::error::PropertyStoreError;
+
+        let bootloader = bootloader();
+        bootloader.fill_memory(0x2000, 10, 0x1234_5678).unwrap();
+        bootloader
+            .fill_memory(RAM_START + 0x1_0000, 8, 0xAABB_CCDD)
+            .unwrap();
+        let simulator = bootloader.protocol.transport();
+        assert_eq!(
+            &simulator.memory()[0x2000..0x200B],
+            &[0x78, 0x56, 0x34, 0x12, 0x78, 0x56, 0x34, 0x12, 0x78, 0x56, 0xFF]
+        );
+        assert_eq!(
+            &simulator.ram()[0x1_0000..0x1_0008],
+            &[0xDD, 0xCC, 0xBB, 0xAA, 0xDD, 0xCC, 0xBB, 0xAA]
+        );
+
+        bootloader.set_property(Property::VerifyWrites, 0).unwrap();
+        assert!(!bootloader.properties().verify_writes().unwrap());
+        assert_eq!(
+            bootloader
+                .set_property(Property::FlashSize, 0)
+                .unwrap_err()
+                .status(),
+            Some(Error::PropertyStore(PropertyStoreError::ReadOnlyProperty))
+        );
+
+        bootloader.call(0x1001, 42).unwrap();
+        bootloader.execute(0x1001, 7, RAM_START + 0x8000).unwrap();
+        assert_eq!(
+            simulator.calls(),
+            [
+                (CommandTag::Call, vec![0x1001, 42]),
+                (CommandTag::Execute, vec![0x1001, 7, 0x2000_8000])
+            ]
+        );
+
+        assert_eq!(
+            bootloader.flash_read_resource(0, 3 * PAGE_SIZE, 0).unwrap(),
+            bootloader
+                .read_memory(CUSTOMER_SETTINGS_SCRATCH_ADDRESS, 3 * PAGE_SIZE)
+                .unwrap()
+        );
+
+        // not available on LPC55
+        assert_eq!(
+            bootloader.flash_read_once(0, 1).unwrap_err().status(),
+            Some(UNKNOWN_COMMAND)
+        );
+    }
+
+    #[test]
+    fn device_uuid() {
+        let uuid = 0x0011_2233_4455_6677_8899_AABB_CCDD_EEFF;
+        let bootloader =
+            Bootloader::try_from_transport(Simulator::new().with_uuid(uuid), VID, PID).unwrap();
+        assert_eq!(bootloader.uuid, uuid);
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("simulator.bin");
+        {
+            let bootloader =
+                Bootloader::try_from_transport(Simulator::persistent(&path).unwrap(), VID, PID)
+                    .unwrap();
+            bootloader.write_memory(0, vec![0x42; 512]).unwrap();
+        }
+        let simulator = Simulator::persistent(&path).unwrap();
+        assert_eq!(&simulator.memory()[..512], &[0x42; 512][..]);
+    }
+}
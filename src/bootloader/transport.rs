@@ -0,0 +1,163 @@
+//! Links over which the bootloader protocol can be spoken.
+//!
+//! The MCUboot protocol itself (commands, responses, data phases) is independent of the
+//! physical link. Each link wraps the command and data packets in its own framing; for
+//! USB HID this is a 4 byte header carrying the report ID and packet length.
+//!
+//! `Protocol` only deals in [`Frame`]s, a `Transport` maps them to and from the wire.
+//!
+//! Besides USB HID, a UART transport is available behind the `uart` feature.
+
+use core::convert::{TryFrom, TryInto};
+use core::time::Duration;
+
+use hidapi::HidDevice;
+
+use super::command::ReportId;
+use super::protocol::{Error, Result};
+
+#[cfg(feature = "uart")]
+pub mod uart;
+#[cfg(feature = "uart")]
+pub use uart::UartTransport;
+
+/// The two kinds of packets in the MCUboot protocol.
+///
+/// In the host-to-device direction, `Command` carries a command packet, in the device-to-host
+/// direction a response packet. `Data` carries command data and response data respectively.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Frame {
+    Command(Vec<u8>),
+    Data(Vec<u8>),
+}
+
+impl Frame {
+    pub fn payload(&self) -> &[u8] {
+        match self {
+            Frame::Command(payload) | Frame::Data(payload) => payload,
+        }
+    }
+}
+
+/// A link to the bootloader, able to send and receive single frames.
+pub trait Transport {
+    /// Send one frame to the device.
+    fn write_frame(&self, frame: &Frame) -> Result<()>;
+
+    /// Receive one frame from the device, waiting at most `timeout`.
+    fn read_frame(&self, timeout: Duration) -> Result<Frame>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn write_frame(&self, frame: &Frame) -> Result<()> {
+        (**self).write_frame(frame)
+    }
+
+    fn read_frame(&self, timeout: Duration) -> Result<Frame> {
+        (**self).read_frame(timeout)
+    }
+}
+
+/// The USB HID link, as used by the ROM bootloader when attached via USB.
+pub struct HidTransport {
+    device: HidDevice,
+}
+
+/// Command data packets are always sent as full reports.
+const HID_DATA_REPORT_SIZE: usize = 4 + 32;
+
+impl HidTransport {
+    pub fn new(device: HidDevice) -> Self {
+        Self { device }
+    }
+
+    pub fn device(&self) -> &HidDevice {
+        &self.device
+    }
+
+    /// Not yet quite clear what comes from HID spec, and what's NXP framing command packets in HID.
+    pub fn report(frame: &Frame) -> Vec<u8> {
+        let (report_id, payload) = match frame {
+            Frame::Command(payload) => (ReportId::Command, payload),
+            Frame::Data(payload) => (ReportId::CommandData, payload),
+        };
+        // pyMBoot does: `pack('<2BH', report_id, 0x00, data_len)`
+        // MCU Bootloader 2.5.0 RM rev 1. (05/2018) says: 1 byte report ID, 2 bytes packet length
+        // It seems pyMBoot is right, and the RM is wrong
+        let mut report = vec![report_id as u8, 0];
+        report.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        report.extend_from_slice(payload);
+        if report_id == ReportId::CommandData {
+            report.resize(HID_DATA_REPORT_SIZE, 0);
+        }
+        report
+    }
+}
+
+impl Transport for HidTransport {
+    fn write_frame(&self, frame: &Frame) -> Result<()> {
+        let report = Self::report(frame);
+        trace!("--> {}", hex_str!(&report, 4));
+        let sent = self.device.write(&report)?;
+        let all = report.len();
+        if sent >= all {
+            Ok(())
+        } else {
+            Err(hidapi::HidError::IncompleteSendError { sent, all }.into())
+        }
+    }
+
+    fn read_frame(&self, timeout: Duration) -> Result<Frame> {
+        let mut data = vec![0; 256];
+        let read = self
+            .device
+            .read_timeout(&mut data, timeout.as_millis() as i32)?;
+        data.resize(read, 0);
+        if data.len() < 4 {
+            return Err(Error::Timeout);
+        }
+
+        let report_id = ReportId::try_from(data[0]).map_err(Error::InvalidReportId)?;
+
+        // the device often sends "extra junk"; we split this off early
+        let expected_packet_len = u16::from_le_bytes(data[2..4].try_into().unwrap()) as usize;
+        data.resize(4 + expected_packet_len, 0);
+        trace!("<-- {} ({}B)", hex_str!(&data, 4), data.len());
+
+        let payload = data.split_off(4);
+        match report_id {
+            ReportId::Response => Ok(Frame::Command(payload)),
+            ReportId::ResponseData => Ok(Frame::Data(payload)),
+            report_id => Err(Error::InvalidReportId(report_id as u8)),
+        }
+    }
+}
+
+impl std::fmt::Debug for HidTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Device")
+            .field("manufacturer", &self.device.get_manufacturer_string())
+            .field("product", &self.device.get_product_string())
+            .field("serial number", &self.device.get_serial_number_string())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hid_reports() {
+        let command = Frame::Command(vec![7, 0, 0, 1, 1, 0, 0, 0]);
+        assert_eq!(
+            HidTransport::report(&command),
+            [1, 0, 8, 0, 7, 0, 0, 1, 1, 0, 0, 0]
+        );
+
+        let data = HidTransport::report(&Frame::Data(vec![0xAA; 3]));
+        assert_eq!(data.len(), HID_DATA_REPORT_SIZE);
+        assert_eq!(&data[..7], &[2, 0, 3, 0, 0xAA, 0xAA, 0xAA]);
+        assert!(data[7..].iter().all(|byte| *byte == 0));
+    }
+}
@@ -0,0 +1,396 @@
+//! The UART link, as used by the ROM bootloader in ISP mode when USB is not available.
+//!
+//! Every packet is framed as
+//!
+//! ```text
+//! 5A <packet type> <length: u16> <crc16: u16> <payload>
+//! ```
+//!
+//! with the CRC-16/XMODEM taken over everything except the CRC field itself.
+//! Each framed packet is acknowledged by the receiver with a bare `5A A1` (ACK), or
+//! `5A A2` (NAK) to request a retransmission. During a command data phase,
+//! the device may answer `5A A3` (ACK abort) to stop the transfer.
+//!
+//! Before the first command, the host sends a ping (`5A A6`), to which the device replies
+//! with its framing protocol version and options.
+
+use core::convert::TryFrom;
+use core::time::Duration;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+
+use serialport::SerialPort;
+
+use super::{Frame, Transport};
+use crate::bootloader::protocol::{Error, Result};
+use crate::crypto::crc16;
+
+pub const START_BYTE: u8 = 0x5A;
+
+/// Timeout for ACKs and ping responses.
+pub const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Number of times a packet is sent before giving up.
+pub const RETRIES: usize = 3;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PacketType {
+    Ack = 0xA1,
+    Nak = 0xA2,
+    AckAbort = 0xA3,
+    Command = 0xA4,
+    Data = 0xA5,
+    Ping = 0xA6,
+    PingResponse = 0xA7,
+}
+
+impl core::convert::TryFrom<u8> for PacketType {
+    type Error = u8;
+    fn try_from(byte: u8) -> core::result::Result<Self, u8> {
+        use PacketType::*;
+        Ok(match byte {
+            0xA1 => Ack,
+            0xA2 => Nak,
+            0xA3 => AckAbort,
+            0xA4 => Command,
+            0xA5 => Data,
+            0xA6 => Ping,
+            0xA7 => PingResponse,
+            _ => return Err(byte),
+        })
+    }
+}
+
+/// The device's reply to a ping.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PingResponse {
+    /// Framing protocol version, e.g. `P1.2.0`
+    pub version: crate::bootloader::command::Version,
+    pub options: u16,
+}
+
+impl PingResponse {
+    /// Parses the 8 bytes following the start byte and packet type.
+    pub fn from_bytes(bytes: [u8; 8]) -> Result<Self> {
+        let crc = u16::from_le_bytes([bytes[6], bytes[7]]);
+        let mut covered = vec![START_BYTE, PacketType::PingResponse as u8];
+        covered.extend_from_slice(&bytes[..6]);
+        if crc16(&covered) != crc {
+            return Err(Error::InvalidCrc);
+        }
+        Ok(Self {
+            version: crate::bootloader::command::Version {
+                mark: Some(bytes[3] as char),
+                major: bytes[2],
+                minor: bytes[1],
+                fixation: bytes[0],
+            },
+            options: u16::from_le_bytes([bytes[4], bytes[5]]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 10] {
+        let mut bytes = [0u8; 10];
+        bytes[0] = START_BYTE;
+        bytes[1] = PacketType::PingResponse as u8;
+        bytes[2] = self.version.fixation;
+        bytes[3] = self.version.minor;
+        bytes[4] = self.version.major;
+        bytes[5] = self.version.mark.unwrap_or('P') as u8;
+        bytes[6..8].copy_from_slice(&self.options.to_le_bytes());
+        let crc = crc16(&bytes[..8]);
+        bytes[8..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+}
+
+/// Frames a command or data packet for the wire.
+pub fn framed_packet(frame: &Frame) -> Vec<u8> {
+    let (packet_type, payload) = match frame {
+        Frame::Command(payload) => {
+            // command packets are padded to 32 bytes for HID, over UART
+            // only the header and the actual parameters are sent
+            let length = match payload.get(3) {
+                Some(count) => core::cmp::min(4 + 4 * *count as usize, payload.len()),
+                None => payload.len(),
+            };
+            (PacketType::Command, &payload[..length])
+        }
+        Frame::Data(payload) => (PacketType::Data, &payload[..]),
+    };
+    framed(packet_type, payload)
+}
+
+/// Frames an arbitrary payload with the given packet type.
+pub fn framed(packet_type: PacketType, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![START_BYTE, packet_type as u8];
+    packet.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    let mut covered = packet.clone();
+    covered.extend_from_slice(payload);
+    packet.extend_from_slice(&crc16(&covered).to_le_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// The UART (serial) link to the bootloader.
+pub struct UartTransport {
+    port: Mutex<Box<dyn SerialPort>>,
+    ping_response: PingResponse,
+}
+
+impl UartTransport {
+    /// Opens the serial port at the given path and pings the bootloader.
+    ///
+    /// The LPC55 ROM detects the baud rate from the ping, 57600 is a safe choice.
+    pub fn open(path: &str, baudrate: u32) -> Result<Self> {
+        let port = serialport::new(path, baudrate)
+            .timeout(ACK_TIMEOUT)
+            .open()?;
+        Self::new(port)
+    }
+
+    /// Uses an already opened serial port, and pings the bootloader.
+    pub fn new(port: Box<dyn SerialPort>) -> Result<Self> {
+        let port = Mutex::new(port);
+        let ping_response = Self::ping(&mut port.lock().unwrap())?;
+        info!("UART bootloader responded to ping: {:?}", &ping_response);
+        Ok(Self {
+            port,
+            ping_response,
+        })
+    }
+
+    /// The framing protocol version and options the device reported.
+    pub fn ping_response(&self) -> PingResponse {
+        self.ping_response
+    }
+
+    fn ping(port: &mut Box<dyn SerialPort>) -> Result<PingResponse> {
+        port.set_timeout(ACK_TIMEOUT)?;
+        let mut last_error = Error::Timeout;
+        for _ in 0..RETRIES {
+            trace!("--> ping");
+            port.write_all(&[START_BYTE, PacketType::Ping as u8])?;
+            match Self::read_packet_type(port) {
+                Ok(PacketType::PingResponse) => {
+                    let mut bytes = [0u8; 8];
+                    read_exact(port, &mut bytes)?;
+                    match PingResponse::from_bytes(bytes) {
+                        Ok(response) => return Ok(response),
+                        Err(error) => last_error = error,
+                    }
+                }
+                Ok(packet_type) => last_error = Error::UnexpectedPacketType(packet_type as u8),
+                Err(error) => last_error = error,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Skips bytes until a start byte is found, then returns the packet type.
+    fn read_packet_type(port: &mut Box<dyn SerialPort>) -> Result<PacketType> {
+        let mut byte = [0u8; 1];
+        loop {
+            read_exact(port, &mut byte)?;
+            if byte[0] == START_BYTE {
+                break;
+            }
+            trace!("skipping {:02X}", byte[0]);
+        }
+        read_exact(port, &mut byte)?;
+        PacketType::try_from(byte[0]).map_err(Error::UnexpectedPacketType)
+    }
+
+    fn send_control(port: &mut Box<dyn SerialPort>, packet_type: PacketType) -> Result<()> {
+        port.write_all(&[START_BYTE, packet_type as u8])?;
+        Ok(())
+    }
+}
+
+fn read_exact(port: &mut Box<dyn SerialPort>, buf: &mut [u8]) -> Result<()> {
+    port.read_exact(buf).map_err(|error| match error.kind() {
+        std::io::ErrorKind::TimedOut => Error::Timeout,
+        _ => Error::Io(error),
+    })
+}
+
+impl Transport for UartTransport {
+    fn write_frame(&self, frame: &Frame) -> Result<()> {
+        let packet = framed_packet(frame);
+        let mut port = self.port.lock().unwrap();
+        port.set_timeout(ACK_TIMEOUT)?;
+
+        for attempt in 1..=RETRIES {
+            trace!("--> {}", hex_str!(&packet, 4));
+            port.write_all(&packet)?;
+
+            match Self::read_packet_type(&mut port) {
+                Ok(PacketType::Ack) => return Ok(()),
+                Ok(PacketType::AckAbort) => return Err(Error::AbortDataPhase),
+                Ok(PacketType::Nak) => debug!("NAK for attempt {}", attempt),
+                Ok(packet_type) => return Err(Error::UnexpectedPacketType(packet_type as u8)),
+                Err(Error::Timeout) => debug!("no ACK for attempt {}", attempt),
+                Err(error) => return Err(error),
+            }
+        }
+        Err(Error::NotAcknowledged(RETRIES))
+    }
+
+    fn read_frame(&self, timeout: Duration) -> Result<Frame> {
+        let mut port = self.port.lock().unwrap();
+        port.set_timeout(timeout)?;
+
+        for _ in 0..RETRIES {
+            let packet_type = Self::read_packet_type(&mut port)?;
+            if !matches!(packet_type, PacketType::Command | PacketType::Data) {
+                return Err(Error::UnexpectedPacketType(packet_type as u8));
+            }
+
+            let mut header = [0u8; 4];
+            read_exact(&mut port, &mut header)?;
+            let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+            let crc = u16::from_le_bytes([header[2], header[3]]);
+            let mut payload = vec![0u8; length];
+            read_exact(&mut port, &mut payload)?;
+
+            let mut covered = vec![START_BYTE, packet_type as u8, header[0], header[1]];
+            covered.extend_from_slice(&payload);
+            if crc16(&covered) != crc {
+                warn!("CRC mismatch in received packet, sending NAK");
+                Self::send_control(&mut port, PacketType::Nak)?;
+                continue;
+            }
+            trace!("<-- {:02X} {}", packet_type as u8, hex_str!(&payload, 4));
+            Self::send_control(&mut port, PacketType::Ack)?;
+
+            return Ok(match packet_type {
+                PacketType::Command => Frame::Command(payload),
+                _ => Frame::Data(payload),
+            });
+        }
+        Err(Error::InvalidCrc)
+    }
+}
+
+impl std::fmt::Debug for UartTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Uart")
+            .field("port", &self.port.lock().unwrap().name())
+            .field("protocol", &self.ping_response.version.to_string())
+            .finish()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use crate::bootloader::{command::Version, property::Property, Command, Protocol, Response};
+    use serialport::TTYPort;
+
+    const PING_RESPONSE: PingResponse = PingResponse {
+        version: Version {
+            mark: Some('P'),
+            major: 1,
+            minor: 2,
+            fixation: 0,
+        },
+        options: 0,
+    };
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn ping_response_roundtrip() {
+        let bytes = PING_RESPONSE.to_bytes();
+        assert_eq!(&bytes[..6], &[0x5A, 0xA7, 0x00, 0x02, 0x01, 0x50]);
+        let parsed = PingResponse::from_bytes(bytes[2..].try_into().unwrap()).unwrap();
+        assert_eq!(parsed, PING_RESPONSE);
+    }
+
+    /// The device end of the pseudo-terminal.
+    struct Device(TTYPort);
+
+    impl Device {
+        fn expect(&mut self, expected: &[u8]) {
+            let mut buf = vec![0u8; expected.len()];
+            self.0.read_exact(&mut buf).unwrap();
+            assert_eq!(hex::encode(&buf), hex::encode(expected));
+        }
+
+        fn send(&mut self, bytes: &[u8]) {
+            self.0.write_all(bytes).unwrap();
+        }
+    }
+
+    fn pair() -> (TTYPort, Device) {
+        let (mut device, host) = TTYPort::pair().unwrap();
+        device.set_timeout(Duration::from_secs(2)).unwrap();
+        (host, Device(device))
+    }
+
+    #[test]
+    fn get_property_with_nak_retry() {
+        let (host, mut device) = pair();
+
+        let command = Command::GetProperty(Property::CurrentVersion);
+        let command_packet = framed_packet(&Frame::Command(command.command_packet()));
+        // 4 byte header and two parameters
+        assert_eq!(command_packet.len(), 6 + 12);
+
+        let device_thread = std::thread::spawn(move || {
+            device.expect(&[0x5A, 0xA6]);
+            device.send(&PING_RESPONSE.to_bytes());
+
+            // garbled first transmission
+            device.expect(&command_packet);
+            device.send(&[0x5A, 0xA2]);
+            device.expect(&command_packet);
+            device.send(&[0x5A, 0xA1]);
+
+            let response = [0xA7, 0, 0, 2, 0, 0, 0, 0, 0, 3, 3, 0x4B];
+            let framed = framed(PacketType::Command, &response);
+
+            // first send with broken CRC, host must NAK
+            let mut broken = framed.clone();
+            broken[5] ^= 0xFF;
+            device.send(&broken);
+            device.expect(&[0x5A, 0xA2]);
+            device.send(&framed);
+            device.expect(&[0x5A, 0xA1]);
+            device
+        });
+
+        let transport = UartTransport::new(Box::new(host)).unwrap();
+        assert_eq!(transport.ping_response(), PING_RESPONSE);
+        let protocol = Protocol::from_transport(transport);
+        let response = protocol.call(&command).unwrap();
+        assert_eq!(response, Response::GetProperty(vec![0x4B030300]));
+
+        device_thread.join().unwrap();
+    }
+
+    #[test]
+    fn abort_data_phase() {
+        let (host, mut device) = pair();
+
+        let data = vec![0x42; 8];
+        let device_thread = std::thread::spawn(move || {
+            device.expect(&[0x5A, 0xA6]);
+            device.send(&PING_RESPONSE.to_bytes());
+            device.expect(&framed_packet(&Frame::Data(vec![0x42; 8])));
+            device.send(&[0x5A, 0xA3]);
+            // keep the pseudo-terminal open until the host is done
+            device
+        });
+
+        let transport = UartTransport::new(Box::new(host)).unwrap();
+        let result = transport.write_frame(&Frame::Data(data));
+        assert!(matches!(result, Err(Error::AbortDataPhase)), "{:?}", result);
+
+        device_thread.join().unwrap();
+    }
+}
@@ -1,14 +1,25 @@
-//! NXP's CRC32 and AES-CTR algorithms
+//! NXP's CRC32 and AES-CTR algorithms, primitives for SB3.1 containers, and PRINCE
+
+pub mod prince;
 
 pub fn sha256(data: &[u8]) -> [u8; 32] {
     use sha2::Digest;
     let mut hasher = sha2::Sha256::new();
-    hasher.update(&data);
+    hasher.update(data);
     let mut digest = [0u8; 32];
     digest.copy_from_slice(&hasher.finalize());
     digest
 }
 
+pub fn sha384(data: &[u8]) -> [u8; 48] {
+    use sha2::Digest;
+    let mut hasher = sha2::Sha384::new();
+    hasher.update(data);
+    let mut digest = [0u8; 48];
+    digest.copy_from_slice(&hasher.finalize());
+    digest
+}
+
 pub fn hmac(mac_key: [u8; 32], data: &[u8]) -> [u8; 32] {
     use hmac::{Hmac, Mac};
     use sha2::Sha256;
@@ -55,15 +66,85 @@ pub fn nxp_aes_ctr_cipher(
     plaintext
 }
 
+/// AES-256-CMAC (RFC 4493, NIST SP 800-38B).
+pub fn cmac(key: [u8; 32], data: &[u8]) -> [u8; 16] {
+    use aes::cipher::generic_array::GenericArray;
+    use aes::{BlockEncrypt, NewBlockCipher};
+    let aes = aes::Aes256::new(&key.into());
+
+    // subkeys
+    let double = |block: u128| (block << 1) ^ if block >> 127 == 1 { 0x87 } else { 0 };
+    let mut l = [0u8; 16];
+    aes.encrypt_block(GenericArray::from_mut_slice(&mut l));
+    let k1 = double(u128::from_be_bytes(l));
+    let k2 = double(k1);
+
+    let blocks = core::cmp::max(1, data.len().div_ceil(16));
+    let mut x = [0u8; 16];
+    for (i, chunk) in data
+        .chunks(16)
+        .chain(data.is_empty().then_some(&[][..]))
+        .enumerate()
+    {
+        let mut block = [0u8; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let mut block = u128::from_be_bytes(block);
+        if i + 1 == blocks {
+            if chunk.len() == 16 {
+                block ^= k1;
+            } else {
+                block ^= (0x80u128 << (8 * (15 - chunk.len()))) ^ k2;
+            }
+        }
+        x = (u128::from_be_bytes(x) ^ block).to_be_bytes();
+        aes.encrypt_block(GenericArray::from_mut_slice(&mut x));
+    }
+    x
+}
+
+/// AES-256-CBC encryption without padding, `data` must be block aligned.
+pub fn aes_cbc_encrypt(key: [u8; 32], iv: [u8; 16], data: &[u8]) -> Vec<u8> {
+    use aes::cipher::generic_array::GenericArray;
+    use aes::{BlockEncrypt, NewBlockCipher};
+    assert_eq!(data.len() % 16, 0);
+    let aes = aes::Aes256::new(&key.into());
+
+    let mut ciphertext = Vec::from(data);
+    let mut previous = iv;
+    for block in ciphertext.chunks_mut(16) {
+        for (byte, mask) in block.iter_mut().zip(previous.iter()) {
+            *byte ^= mask;
+        }
+        aes.encrypt_block(GenericArray::from_mut_slice(block));
+        previous.copy_from_slice(block);
+    }
+    ciphertext
+}
+
+/// AES-256-CBC decryption without padding, `data` must be block aligned.
+pub fn aes_cbc_decrypt(key: [u8; 32], iv: [u8; 16], data: &[u8]) -> Vec<u8> {
+    use aes::cipher::generic_array::GenericArray;
+    use aes::{BlockDecrypt, NewBlockCipher};
+    assert_eq!(data.len() % 16, 0);
+    let aes = aes::Aes256::new(&key.into());
+
+    let mut plaintext = Vec::from(data);
+    let mut previous = iv;
+    for block in plaintext.chunks_mut(16) {
+        let ciphertext: [u8; 16] = (&*block).try_into().unwrap();
+        aes.decrypt_block(GenericArray::from_mut_slice(block));
+        for (byte, mask) in block.iter_mut().zip(previous.iter()) {
+            *byte ^= mask;
+        }
+        previous = ciphertext;
+    }
+    plaintext
+}
+
 /// Soooo.... This "uses" the "Ethernet CRC algorithm" values,
 /// but it doesn't really do CRC32.
 pub fn crc32(data: &[u8]) -> u32 {
-    let mut crc: u32 = 0xFFFF_FFFF;
-
-    for byte in data.iter() {
-        let i = ((crc >> 24) as u8) ^ *byte;
-        crc = (crc << 8) ^ TABLE[i as usize];
-    }
+    let mut crc = crc32_update(0xFFFF_FFFF, data);
 
     let jagged = data.len() % 16;
     if jagged > 0 {
@@ -78,12 +159,7 @@ pub fn crc32(data: &[u8]) -> u32 {
 }
 
 pub fn crc32_2(data: &[u8]) -> u32 {
-    let mut crc: u32 = 0x0;
-
-    for byte in data.iter() {
-        let i = ((crc >> 24) as u8) ^ *byte;
-        crc = (crc << 8) ^ TABLE[i as usize];
-    }
+    let mut crc = crc32_update(0, data);
 
     let jagged = data.len() % 16;
     if jagged > 0 {
@@ -97,6 +173,38 @@ pub fn crc32_2(data: &[u8]) -> u32 {
     crc
 }
 
+/// CRC-32/MPEG-2 (polynomial 0x04c11db7, initial value 0xffffffff, no reflection or final XOR),
+/// i.e. [`crc32`] without the padding, as in the header of CRC images.
+pub fn crc32_mpeg2(data: &[u8]) -> u32 {
+    crc32_update(0xFFFF_FFFF, data)
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data.iter() {
+        let i = ((crc >> 24) as u8) ^ *byte;
+        crc = (crc << 8) ^ TABLE[i as usize];
+    }
+    crc
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, initial value 0), as used by the MCUboot UART framing.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+
+    for byte in data.iter() {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
 pub const TABLE: [u32; 256] = [
     0x00000000, 0x04c11db7, 0x09823b6e, 0x0d4326d9, 0x130476dc, 0x17c56b6b, 0x1a864db2, 0x1e475005,
     0x2608edb8, 0x22c9f00f, 0x2f8ad6d6, 0x2b4bcb61, 0x350c9b64, 0x31cd86d3, 0x3c8ea00a, 0x384fbdbd,
@@ -131,3 +239,77 @@ pub const TABLE: [u32; 256] = [
     0x89b8fd09, 0x8d79e0be, 0x803ac667, 0x84fbdbd0, 0x9abc8bd5, 0x9e7d9662, 0x933eb0bb, 0x97ffad0c,
     0xafb010b1, 0xab710d06, 0xa6322bdf, 0xa2f33668, 0xbcb4666d, 0xb8757bda, 0xb5365d03, 0xb1f740b4,
 ];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // NIST SP 800-38B, D.3
+    #[test]
+    fn cmac_vectors() {
+        let key: [u8; 32] =
+            hex::decode("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let message = hex::decode(concat!(
+            "6bc1bee22e409f96e93d7e117393172a",
+            "ae2d8a571e03ac9c9eb76fac45af8e51",
+            "30c81c46a35ce411e5fbc1191a0a52ef",
+            "f69f2445df4f9b17ad2b417be66c3710",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            cmac(key, &[]).as_ref(),
+            hex::decode("028962f61b7bf89efc6b551f4667d983").unwrap()
+        );
+        assert_eq!(
+            cmac(key, &message[..16]).as_ref(),
+            hex::decode("28a7023f452e8f82bd4bf28d8c37c35c").unwrap()
+        );
+        assert_eq!(
+            cmac(key, &message[..40]).as_ref(),
+            hex::decode("aaf3d8f1de5640c232f5b169b9c911e6").unwrap()
+        );
+        assert_eq!(
+            cmac(key, &message).as_ref(),
+            hex::decode("e1992190549f6ed5696a2c056c315410").unwrap()
+        );
+    }
+
+    // NIST SP 800-38A, F.2.5 and F.2.6
+    #[test]
+    fn cbc_vectors() {
+        let key: [u8; 32] =
+            hex::decode("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let iv: [u8; 16] = hex::decode("000102030405060708090a0b0c0d0e0f")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let plaintext = hex::decode(concat!(
+            "6bc1bee22e409f96e93d7e117393172a",
+            "ae2d8a571e03ac9c9eb76fac45af8e51",
+        ))
+        .unwrap();
+        let ciphertext = hex::decode(concat!(
+            "f58c4c04d6e5f1ba779eabfb5f7bfbd6",
+            "9cfc4e967edb808d679f777bc6702c7d",
+        ))
+        .unwrap();
+
+        assert_eq!(aes_cbc_encrypt(key, iv, &plaintext), ciphertext);
+        assert_eq!(aes_cbc_decrypt(key, iv, &ciphertext), plaintext);
+    }
+
+    // check value of the CRC catalogue
+    #[test]
+    fn crc32_mpeg2_check() {
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376_e6e7);
+        // the padded variant agrees on whole blocks
+        assert_eq!(crc32(&[0x42; 32]), crc32_mpeg2(&[0x42; 32]));
+    }
+}
@@ -0,0 +1,194 @@
+//! The PRINCE block cipher (Borghoff et al., "PRINCE – A Low-latency Block Cipher for Pervasive
+//! Computing Applications", ASIACRYPT 2012), 64-bit blocks and 128-bit keys.
+//!
+//! The LPC55 uses it for on-the-fly flash encryption, with a key per PRINCE region
+//! (`Keystore::prince_region_N`), IVs in `CustomerSettings::prince_ivs` and encryption enabled
+//! per 8K subregion (`FactorySettings::prince_subregions`). How the ROM combines key, IV and
+//! address is not implemented here until it can be checked against flash encrypted by the
+//! vendor tools; the cipher itself is tested against the reference vectors.
+
+const SBOX: [u8; 16] = [
+    0xb, 0xf, 0x3, 0x2, 0xa, 0xc, 0x9, 0x1, 0x6, 0x7, 0x8, 0x0, 0xe, 0x5, 0xd, 0x4,
+];
+const SBOX_INVERSE: [u8; 16] = [
+    0xb, 0x7, 0x3, 0x2, 0xf, 0xd, 0x8, 0x9, 0xa, 0x6, 0x4, 0x0, 0x5, 0xe, 0xc, 0x1,
+];
+
+const ROUND_CONSTANTS: [u64; 12] = [
+    0x0000000000000000,
+    0x13198a2e03707344,
+    0xa4093822299f31d0,
+    0x082efa98ec4e6c89,
+    0x452821e638d01377,
+    0xbe5466cf34e90c6c,
+    0x7ef84f78fd955cb1,
+    0x85840851f1ac43aa,
+    0xc882d32f25323c54,
+    0x64a51195e0e3610d,
+    0xd3b5a399ca0c2399,
+    0xc0ac29b7c97c50dd,
+];
+/// Every `RC[i] ^ RC[11 - i]` equals this, which makes decryption encryption with a related key.
+const ALPHA: u64 = 0xc0ac29b7c97c50dd;
+
+/// Nibble `i` (counting from the most significant) of the output of the shift rows step is
+/// nibble `SHIFT_ROWS[i]` of the input.
+const SHIFT_ROWS: [usize; 16] = [0, 5, 10, 15, 4, 9, 14, 3, 8, 13, 2, 7, 12, 1, 6, 11];
+
+/// PRINCE with key `k0 || k1`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Prince {
+    k0: u64,
+    k1: u64,
+}
+
+impl Prince {
+    /// The key is `k0 || k1`, both big-endian.
+    pub fn new(key: [u8; 16]) -> Self {
+        let mut k0 = [0u8; 8];
+        let mut k1 = [0u8; 8];
+        k0.copy_from_slice(&key[..8]);
+        k1.copy_from_slice(&key[8..]);
+        Self {
+            k0: u64::from_be_bytes(k0),
+            k1: u64::from_be_bytes(k1),
+        }
+    }
+
+    pub fn encrypt(&self, block: u64) -> u64 {
+        core(self.k1, block ^ self.k0) ^ self.k0_prime()
+    }
+
+    /// The whitening keys swap roles, and the core key is offset by `ALPHA`.
+    pub fn decrypt(&self, block: u64) -> u64 {
+        core(self.k1 ^ ALPHA, block ^ self.k0_prime()) ^ self.k0
+    }
+
+    /// `k0' = (k0 >>> 1) ^ (k0 >> 63)`
+    fn k0_prime(&self) -> u64 {
+        self.k0.rotate_right(1) ^ (self.k0 >> 63)
+    }
+}
+
+fn core(k1: u64, block: u64) -> u64 {
+    let mut state = block ^ k1 ^ ROUND_CONSTANTS[0];
+    for constant in &ROUND_CONSTANTS[1..6] {
+        state = shift_rows(m_prime(sbox(state, &SBOX)));
+        state ^= constant ^ k1;
+    }
+    state = sbox(m_prime(sbox(state, &SBOX)), &SBOX_INVERSE);
+    for constant in &ROUND_CONSTANTS[6..11] {
+        state ^= constant ^ k1;
+        state = sbox(m_prime(shift_rows_inverse(state)), &SBOX_INVERSE);
+    }
+    state ^ k1 ^ ROUND_CONSTANTS[11]
+}
+
+fn nibble(state: u64, i: usize) -> u64 {
+    (state >> (60 - 4 * i)) & 0xf
+}
+
+fn sbox(state: u64, sbox: &[u8; 16]) -> u64 {
+    (0..16).fold(0, |out, i| {
+        out | ((sbox[nibble(state, i) as usize] as u64) << (60 - 4 * i))
+    })
+}
+
+fn shift_rows(state: u64) -> u64 {
+    (0..16).fold(0, |out, i| {
+        out | (nibble(state, SHIFT_ROWS[i]) << (60 - 4 * i))
+    })
+}
+
+fn shift_rows_inverse(state: u64) -> u64 {
+    (0..16).fold(0, |out, i| {
+        out | (nibble(state, i) << (60 - 4 * SHIFT_ROWS[i]))
+    })
+}
+
+/// The involutive linear layer: the 16-bit chunks are multiplied by `M̂0, M̂1, M̂1, M̂0`.
+fn m_prime(state: u64) -> u64 {
+    (0..4).fold(0, |out, chunk| {
+        let shift = 48 - 16 * chunk;
+        let offset = if chunk == 0 || chunk == 3 { 0 } else { 1 };
+        out | (m_hat(((state >> shift) & 0xffff) as u16, offset) as u64) << shift
+    })
+}
+
+/// `M̂` consists of 4x4 blocks `M_{(i + j + offset) mod 4}`, where `M_k` is the identity
+/// with the k-th diagonal entry cleared.
+fn m_hat(chunk: u16, offset: usize) -> u16 {
+    let nibble = |j: usize| (chunk >> (12 - 4 * j)) & 0xf;
+    let mut out = 0;
+    for i in 0..4 {
+        let mut output_nibble = 0;
+        for j in 0..4 {
+            let k = (i + j + offset) % 4;
+            output_nibble ^= nibble(j) & !(0x8 >> k);
+        }
+        out |= output_nibble << (12 - 4 * i);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn prince(k0: u64, k1: u64) -> Prince {
+        let mut key = [0u8; 16];
+        key[..8].copy_from_slice(&k0.to_be_bytes());
+        key[8..].copy_from_slice(&k1.to_be_bytes());
+        Prince::new(key)
+    }
+
+    #[test]
+    fn test_vectors() {
+        // Appendix A of the PRINCE paper
+        let vectors = [
+            (
+                0x0000000000000000,
+                0x0000000000000000,
+                0x0000000000000000,
+                0x818665aa0d02dfda,
+            ),
+            (
+                0xffffffffffffffff,
+                0x0000000000000000,
+                0x0000000000000000,
+                0x604ae6ca03c20ada,
+            ),
+            (
+                0x0000000000000000,
+                0xffffffffffffffff,
+                0x0000000000000000,
+                0x9fb51935fc3df524,
+            ),
+            (
+                0x0000000000000000,
+                0x0000000000000000,
+                0xffffffffffffffff,
+                0x78a54cbe737bb7ef,
+            ),
+            (
+                0x0123456789abcdef,
+                0x0000000000000000,
+                0xfedcba9876543210,
+                0xae25ad3ca8fa9ccf,
+            ),
+        ];
+        for (plaintext, k0, k1, ciphertext) in vectors {
+            let prince = prince(k0, k1);
+            assert_eq!(prince.encrypt(plaintext), ciphertext);
+            assert_eq!(prince.decrypt(ciphertext), plaintext);
+        }
+    }
+
+    #[test]
+    fn inverses() {
+        let state = 0x0123456789abcdef;
+        assert_eq!(shift_rows_inverse(shift_rows(state)), state);
+        assert_eq!(m_prime(m_prime(state)), state);
+        assert_eq!(sbox(sbox(state, &SBOX), &SBOX_INVERSE), state);
+    }
+}
@@ -0,0 +1,689 @@
+//! Debug authentication: Debug Credential (DC) certificates.
+//!
+//! Debug access that is set to `Authenticate` in the PFR can be unlocked by a debugger
+//! presenting a Debug Credential (cf. NXP AN13037). The credential binds
+//! - the device UUID (or all zeros, for any device with UUID checking off)
+//! - the SoC class
+//! - the granted permissions (`CC_SOCU`) and vendor usage (`CC_VU`)
+//! - the public Debug Credential Key (DCK)
+//!
+//! and is signed by one of the RoT keys, whose table hash is the ROTKH in the factory settings.
+//! The DCK private key stays with whoever debugs the device: to authenticate, the device sends
+//! a [`DebugAuthenticationChallenge`], which is answered by a [`DebugAuthenticationResponse`]
+//! containing the credential and the challenge, signed with the DCK (cf. [`respond`]).
+//!
+//! Only the RSA-2048 variant (version 1.0) of the LPC55S6x family is implemented.
+
+use core::convert::{TryFrom, TryInto as _};
+use std::fs;
+
+use anyhow::{anyhow, Context as _, Result};
+use rsa::PublicKeyParts as _;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::crypto::sha256;
+use crate::pki::{
+    CertificateSlot, Certificates, Pki, PublicKey, Sha256Hash, Signature, SigningKey,
+};
+use crate::protected_flash::{DebugAccess, DebugSettings};
+use crate::util::is_default;
+
+/// Version of the RSA-2048 credential format
+pub const VERSION: [u16; 2] = [1, 0];
+
+const MODULUS_SIZE: usize = 256;
+const EXPONENT_SIZE: usize = 4;
+const PUBLIC_KEY_SIZE: usize = MODULUS_SIZE + EXPONENT_SIZE;
+/// Size of an encoded RSA-2048 Debug Credential
+pub const SIZE: usize = 4 + 4 + 16 + 4 * 32 + PUBLIC_KEY_SIZE + 3 * 4 + PUBLIC_KEY_SIZE + 256;
+
+/// Configuration for `lpc55 debug-credential`, shares the `[pki]` with the firmware config.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DebugCredentialConfig {
+    pub pki: Pki,
+    pub debug_credential: DebugCredentialParameters,
+}
+
+impl TryFrom<&'_ str> for DebugCredentialConfig {
+    type Error = anyhow::Error;
+    fn try_from(config_filename: &str) -> anyhow::Result<Self> {
+        let config = fs::read_to_string(config_filename)
+            .with_context(|| format!("Failed to read config from {}", config_filename))?;
+        let config: DebugCredentialConfig = toml::from_str(&config)?;
+        trace!("{:#?}", &config);
+        Ok(config)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct DebugCredentialParameters {
+    /// UUID of the device, as 32 hex digits.
+    ///
+    /// If empty, the credential is valid for any device that does not have `check-uuid` set.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub uuid: String,
+    /// SoC class, as reported by the device in its debug authentication challenge
+    pub socc: u32,
+    /// Granted access, in the same format as the factory settings; only `Enabled` settings are
+    /// granted.
+    pub debug_access: DebugAccess,
+    /// Passed on to the application, which may interpret it
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub vendor_usage: u32,
+    /// Credential beacon, passed on to the application
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub beacon: u32,
+    /// Path to the Debug Credential Key's public key, as PEM file (PKCS #1 or SPKI)
+    pub dck: String,
+}
+
+/// A signed Debug Credential.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DebugCredential {
+    pub socc: u32,
+    pub uuid: Uuid,
+    /// The RoT key table, its hash is the ROTKH
+    pub rot_fingerprints: [Sha256Hash; 4],
+    pub dck: PublicKey,
+    /// Called `CC_SOCU`, cf. [`DebugSettings::credential_permissions`]
+    pub permissions: u32,
+    /// Called `CC_VU`
+    pub vendor_usage: u32,
+    pub beacon: u32,
+    /// The RoT key that signed the credential
+    pub rot_key: PublicKey,
+    pub signature: Signature,
+}
+
+impl DebugCredential {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signed_data();
+        bytes.extend_from_slice(self.signature.as_ref());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != SIZE {
+            return Err(anyhow!(
+                "Debug Credential has {} bytes, expected {}",
+                bytes.len(),
+                SIZE
+            ));
+        }
+        let mut i = bytes;
+        let mut take = |n: usize| {
+            let (head, tail) = i.split_at(n);
+            i = tail;
+            head
+        };
+        let word = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+
+        let version = take(4);
+        let version = [
+            u16::from_le_bytes(version[..2].try_into().unwrap()),
+            u16::from_le_bytes(version[2..].try_into().unwrap()),
+        ];
+        if version != VERSION {
+            return Err(anyhow!(
+                "unsupported Debug Credential version {}.{}",
+                version[0],
+                version[1]
+            ));
+        }
+        let socc = word(take(4));
+        let uuid = Uuid::from_slice(take(16))?;
+        let mut rot_fingerprints = [Sha256Hash::default(); 4];
+        for fingerprint in rot_fingerprints.iter_mut() {
+            fingerprint.0.copy_from_slice(take(32));
+        }
+        let dck = public_key_from_bytes(take(PUBLIC_KEY_SIZE))?;
+        let permissions = word(take(4));
+        let vendor_usage = word(take(4));
+        let beacon = word(take(4));
+        let rot_key = public_key_from_bytes(take(PUBLIC_KEY_SIZE))?;
+        let signature = Signature(take(256).to_vec());
+
+        Ok(Self {
+            socc,
+            uuid,
+            rot_fingerprints,
+            dck,
+            permissions,
+            vendor_usage,
+            beacon,
+            rot_key,
+            signature,
+        })
+    }
+
+    /// Everything but the signature
+    fn signed_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SIZE);
+        data.extend_from_slice(&VERSION[0].to_le_bytes());
+        data.extend_from_slice(&VERSION[1].to_le_bytes());
+        data.extend_from_slice(&self.socc.to_le_bytes());
+        data.extend_from_slice(self.uuid.as_bytes());
+        for fingerprint in self.rot_fingerprints.iter() {
+            data.extend_from_slice(fingerprint.as_ref());
+        }
+        data.extend_from_slice(&public_key_to_bytes(&self.dck));
+        data.extend_from_slice(&self.permissions.to_le_bytes());
+        data.extend_from_slice(&self.vendor_usage.to_le_bytes());
+        data.extend_from_slice(&self.beacon.to_le_bytes());
+        data.extend_from_slice(&public_key_to_bytes(&self.rot_key));
+        data
+    }
+
+    /// The ROTKH the device must have for this credential
+    pub fn rot_fingerprint(&self) -> Sha256Hash {
+        Certificates::fingerprint_from_bytes(
+            &self
+                .rot_fingerprints
+                .iter()
+                .flat_map(|fingerprint| fingerprint.0)
+                .collect::<Vec<u8>>(),
+        )
+    }
+
+    /// Slot of the signing RoT key in the RoT key table, if it is there
+    pub fn slot(&self) -> Option<CertificateSlot> {
+        let fingerprint = self.rot_key.fingerprint();
+        self.rot_fingerprints
+            .iter()
+            .position(|rot_fingerprint| *rot_fingerprint == fingerprint)
+            .map(CertificateSlot::from)
+    }
+
+    /// Permissions, as debug settings
+    pub fn debug_settings(&self) -> DebugSettings {
+        DebugSettings::from_credential_permissions(self.permissions)
+    }
+
+    /// The signing key is in the RoT key table, and the signature is valid.
+    pub fn verify(&self) -> bool {
+        self.slot().is_some() && verify(&self.rot_key, &self.signed_data(), &self.signature)
+    }
+}
+
+/// Debug Credential to be signed by a RoT key.
+pub struct CredentialSigningRequest {
+    pub socc: u32,
+    pub uuid: Uuid,
+    pub permissions: u32,
+    pub vendor_usage: u32,
+    pub beacon: u32,
+    pub dck: PublicKey,
+    certificates: Certificates,
+    signing_key: SigningKey,
+    rot_key: PublicKey,
+}
+
+impl CredentialSigningRequest {
+    /// Parse config, load all data checking for validity.
+    pub fn try_from(config: &DebugCredentialConfig) -> Result<Self> {
+        let parameters = &config.debug_credential;
+        let uuid = match parameters.uuid.as_str() {
+            "" => Uuid::nil(),
+            uuid => Uuid::parse_str(uuid)?,
+        };
+        let dck = public_key_from_pem_file(&parameters.dck)?;
+        let certificates = Certificates::try_from_pki(&config.pki)?;
+        let signing_key = SigningKey::try_from_uri(config.pki.signing_key.as_ref())?;
+        let rot_key = signing_key.public_key()?;
+        if !certificates.fingerprints().contains(&rot_key.fingerprint()) {
+            return Err(anyhow!(
+                "signing key is not one of the RoT keys, Debug Credentials must be signed by one"
+            ));
+        }
+        // the credential has room for an RSA-2048 RoT key only
+        if rot_key.0.size() != MODULUS_SIZE || rot_key.0.e().bits() > 8 * EXPONENT_SIZE {
+            return Err(anyhow!(
+                "signing key is not an RSA-2048 key, Debug Credentials must be signed by one"
+            ));
+        }
+
+        Ok(Self {
+            socc: parameters.socc,
+            uuid,
+            permissions: DebugSettings::from(parameters.debug_access).credential_permissions(),
+            vendor_usage: parameters.vendor_usage,
+            beacon: parameters.beacon,
+            dck,
+            certificates,
+            signing_key,
+            rot_key,
+        })
+    }
+
+    pub fn sign(&self) -> DebugCredential {
+        let mut credential = DebugCredential {
+            socc: self.socc,
+            uuid: self.uuid,
+            rot_fingerprints: self.certificates.fingerprints(),
+            dck: self.dck.clone(),
+            permissions: self.permissions,
+            vendor_usage: self.vendor_usage,
+            beacon: self.beacon,
+            rot_key: self.rot_key.clone(),
+            signature: Signature(Vec::new()),
+        };
+        credential.signature = self.signing_key.sign(&credential.signed_data());
+        credential
+    }
+}
+
+/// Size of an encoded Debug Authentication Challenge
+pub const CHALLENGE_SIZE: usize = 4 + 4 + 16 + 4 + 32 + 3 * 4 + 32;
+
+/// Debug Authentication Challenge (DAC), sent by the device when a debugger starts debug
+/// authentication.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DebugAuthenticationChallenge {
+    pub version: [u16; 2],
+    pub socc: u32,
+    pub uuid: Uuid,
+    /// Revocation state of the RoT keys
+    pub rot_key_revocation: u32,
+    /// The device's ROTKH
+    pub rot_fingerprint: Sha256Hash,
+    /// `CC_SOCU_PIN` in effect on the device
+    pub pinned: u32,
+    /// `CC_SOCU_DFLT` in effect on the device
+    pub default: u32,
+    /// `CC_VU` of the device
+    pub vendor_usage: u32,
+    /// Random challenge vector, to be signed in the response
+    pub challenge: [u8; 32],
+}
+
+impl DebugAuthenticationChallenge {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != CHALLENGE_SIZE {
+            return Err(anyhow!(
+                "Debug Authentication Challenge has {} bytes, expected {}",
+                bytes.len(),
+                CHALLENGE_SIZE
+            ));
+        }
+        let word = |offset: usize| u32::from_le_bytes(bytes[offset..][..4].try_into().unwrap());
+
+        Ok(Self {
+            version: [
+                u16::from_le_bytes(bytes[0..2].try_into().unwrap()),
+                u16::from_le_bytes(bytes[2..4].try_into().unwrap()),
+            ],
+            socc: word(4),
+            uuid: Uuid::from_slice(&bytes[8..24])?,
+            rot_key_revocation: word(24),
+            rot_fingerprint: Sha256Hash(bytes[28..60].try_into().unwrap()),
+            pinned: word(60),
+            default: word(64),
+            vendor_usage: word(68),
+            challenge: bytes[72..104].try_into().unwrap(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CHALLENGE_SIZE);
+        bytes.extend_from_slice(&self.version[0].to_le_bytes());
+        bytes.extend_from_slice(&self.version[1].to_le_bytes());
+        bytes.extend_from_slice(&self.socc.to_le_bytes());
+        bytes.extend_from_slice(self.uuid.as_bytes());
+        bytes.extend_from_slice(&self.rot_key_revocation.to_le_bytes());
+        bytes.extend_from_slice(self.rot_fingerprint.as_ref());
+        bytes.extend_from_slice(&self.pinned.to_le_bytes());
+        bytes.extend_from_slice(&self.default.to_le_bytes());
+        bytes.extend_from_slice(&self.vendor_usage.to_le_bytes());
+        bytes.extend_from_slice(&self.challenge);
+        bytes
+    }
+
+    /// Debug settings currently in effect on the device
+    pub fn debug_settings(&self) -> DebugSettings {
+        DebugSettings::from([self.pinned, self.default])
+    }
+
+    /// Fails if the device would reject the credential.
+    pub fn check(&self, credential: &DebugCredential) -> Result<()> {
+        if self.version[0] != VERSION[0] {
+            return Err(anyhow!(
+                "device expects Debug Credential version {}.{}, not {}.{}",
+                self.version[0],
+                self.version[1],
+                VERSION[0],
+                VERSION[1]
+            ));
+        }
+        if credential.socc != self.socc {
+            return Err(anyhow!(
+                "credential is for SoC class 0x{:08x}, device has 0x{:08x}",
+                credential.socc,
+                self.socc
+            ));
+        }
+        if !credential.uuid.is_nil() && credential.uuid != self.uuid {
+            return Err(anyhow!(
+                "credential is for device {}, not {}",
+                credential.uuid.to_simple(),
+                self.uuid.to_simple()
+            ));
+        }
+        if credential.rot_fingerprint() != self.rot_fingerprint {
+            return Err(anyhow!(
+                "credential RoT key table does not match the device's ROTKH {}",
+                hexstr!(&self.rot_fingerprint.0)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Debug Authentication Response (DAR): the credential, the authentication beacon and the
+/// challenge vector, signed with the Debug Credential Key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DebugAuthenticationResponse {
+    pub credential: DebugCredential,
+    /// Passed on to the application
+    pub beacon: u32,
+    pub challenge: [u8; 32],
+    pub signature: Signature,
+}
+
+impl DebugAuthenticationResponse {
+    /// Checks the credential against the challenge, and that the key is the credential's DCK.
+    pub fn sign(
+        challenge: &DebugAuthenticationChallenge,
+        credential: &DebugCredential,
+        dck: &SigningKey,
+        beacon: u32,
+    ) -> Result<Self> {
+        challenge.check(credential)?;
+        if dck.public_key()? != credential.dck {
+            return Err(anyhow!(
+                "signing key is not the Debug Credential Key of the credential"
+            ));
+        }
+        let mut response = Self {
+            credential: credential.clone(),
+            beacon,
+            challenge: challenge.challenge,
+            signature: Signature(Vec::new()),
+        };
+        response.signature = dck.sign(&response.signed_data());
+        Ok(response)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signed_data();
+        bytes.extend_from_slice(self.signature.as_ref());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != SIZE + 4 + 32 + 256 {
+            return Err(anyhow!(
+                "Debug Authentication Response has {} bytes, expected {}",
+                bytes.len(),
+                SIZE + 4 + 32 + 256
+            ));
+        }
+        Ok(Self {
+            credential: DebugCredential::from_bytes(&bytes[..SIZE])?,
+            beacon: u32::from_le_bytes(bytes[SIZE..][..4].try_into().unwrap()),
+            challenge: bytes[SIZE + 4..][..32].try_into().unwrap(),
+            signature: Signature(bytes[SIZE + 4 + 32..].to_vec()),
+        })
+    }
+
+    fn signed_data(&self) -> Vec<u8> {
+        let mut data = self.credential.to_bytes();
+        data.extend_from_slice(&self.beacon.to_le_bytes());
+        data.extend_from_slice(&self.challenge);
+        data
+    }
+
+    /// The credential is valid, and the response is signed by its DCK.
+    pub fn verify(&self) -> bool {
+        self.credential.verify()
+            && verify(&self.credential.dck, &self.signed_data(), &self.signature)
+    }
+}
+
+/// Builds the response to send back to the device, with authentication beacon zero.
+///
+/// The transport (e.g. via a debug probe's access ports) is up to the caller.
+pub fn respond(
+    challenge: &[u8],
+    credential: &DebugCredential,
+    dck: &SigningKey,
+) -> Result<Vec<u8>> {
+    let challenge = DebugAuthenticationChallenge::from_bytes(challenge)?;
+    Ok(DebugAuthenticationResponse::sign(&challenge, credential, dck, 0)?.to_bytes())
+}
+
+fn verify(key: &PublicKey, data: &[u8], signature: &Signature) -> bool {
+    use rsa::PublicKey as _;
+    let padding_scheme = rsa::PaddingScheme::new_pkcs1v15_sign(Some(rsa::Hash::SHA2_256));
+    key.0
+        .verify(padding_scheme, &sha256(data), signature.as_ref())
+        .is_ok()
+}
+
+fn public_key_to_bytes(key: &PublicKey) -> Vec<u8> {
+    let mut bytes = vec![0u8; PUBLIC_KEY_SIZE];
+    let n = key.0.n().to_bytes_be();
+    let e = key.0.e().to_bytes_be();
+    bytes[MODULUS_SIZE - n.len()..MODULUS_SIZE].copy_from_slice(&n);
+    bytes[PUBLIC_KEY_SIZE - e.len()..].copy_from_slice(&e);
+    bytes
+}
+
+fn public_key_from_bytes(bytes: &[u8]) -> Result<PublicKey> {
+    let n = rsa::BigUint::from_bytes_be(&bytes[..MODULUS_SIZE]);
+    let e = rsa::BigUint::from_bytes_be(&bytes[MODULUS_SIZE..]);
+    Ok(PublicKey(rsa::RsaPublicKey::new(n, e)?))
+}
+
+/// Reads an RSA-2048 public key from a PKCS #1 (`RSA PUBLIC KEY`) or SPKI (`PUBLIC KEY`) PEM file.
+pub fn public_key_from_pem_file(filename: &str) -> Result<PublicKey> {
+    use rsa::pkcs1::DecodeRsaPublicKey as _;
+    use rsa::pkcs8::DecodePublicKey as _;
+
+    let pem = fs::read_to_string(filename)
+        .with_context(|| format!("Failed to read public key from PEM file {}", filename))?;
+    let pem = pem::parse(&pem)?;
+    let key = match pem.tag.as_str() {
+        "RSA PUBLIC KEY" => rsa::RsaPublicKey::from_pkcs1_der(&pem.contents)?,
+        "PUBLIC KEY" => rsa::RsaPublicKey::from_public_key_der(&pem.contents)?,
+        tag => return Err(anyhow!("{} contains a {}, not a public key", filename, tag)),
+    };
+    if key.size() != MODULUS_SIZE {
+        return Err(anyhow!("{} is not an RSA-2048 key", filename));
+    }
+    Ok(PublicKey(key))
+}
+
+/// Prints the credential and its verification, returns it for further checks.
+pub fn show(filename: &str) -> Result<DebugCredential> {
+    let credential = DebugCredential::from_bytes(
+        &fs::read(filename)
+            .with_context(|| format!("Failed to read Debug Credential from {}", filename))?,
+    )?;
+
+    println!("version: {}.{}", VERSION[0], VERSION[1]);
+    println!("socc: 0x{:08x}", credential.socc);
+    println!("uuid: {}", credential.uuid.to_simple());
+    println!("rotkh: {}", hexstr!(&credential.rot_fingerprint().0));
+    match credential.slot() {
+        Some(slot) => println!("slot: {}", usize::from(slot)),
+        None => println!("slot: none"),
+    }
+    println!(
+        "dck fingerprint: {}",
+        hexstr!(&credential.dck.fingerprint().0)
+    );
+    println!("permissions: 0x{:04x}", credential.permissions);
+    println!("{:#?}", credential.debug_settings());
+    println!("vendor usage: 0x{:08x}", credential.vendor_usage);
+    println!("beacon: 0x{:08x}", credential.beacon);
+
+    let status = |ok: bool| if ok { "ok" } else { "FAILED" };
+    println!("verification:");
+    println!("  root of trust: {}", status(credential.slot().is_some()));
+    println!("  signature: {}", status(credential.verify()));
+
+    Ok(credential)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> DebugCredentialConfig {
+        toml::from_str(
+            r#"
+            [pki]
+            signing-key = "file:example-file-certs/ca_private_key_1.pem"
+            certificates = [
+                "file:example-file-certs/ca_certificate_0.der",
+                "file:example-file-certs/ca_certificate_1.der",
+                "file:example-file-certs/ca_certificate_2.der",
+                "file:example-file-certs/ca_certificate_3.der",
+            ]
+
+            [debug-credential]
+            uuid = "0123456789abcdef0123456789abcdef"
+            socc = 1
+            debug-access = { Custom = { nonsecure-invasive = "Disabled", isp-boot-command = "Authenticate" } }
+            vendor-usage = 0x1234
+            dck = "example-file-certs/dck_public_key.pem"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let config = config();
+        let credential = CredentialSigningRequest::try_from(&config).unwrap().sign();
+        assert!(credential.verify());
+        assert_eq!(credential.slot(), Some(CertificateSlot::from(1)));
+        // all but bits 1 (nonsecure invasive) and 6 (ISP boot command)
+        assert_eq!(credential.permissions, 0b11_1011_1101);
+
+        let certificates = Certificates::try_from_pki(&config.pki).unwrap();
+        assert_eq!(credential.rot_fingerprint(), certificates.fingerprint());
+
+        let bytes = credential.to_bytes();
+        assert_eq!(bytes.len(), SIZE);
+        assert_eq!(bytes[..8], [1, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(
+            bytes[8..24],
+            hex::decode(&config.debug_credential.uuid).unwrap()
+        );
+        assert_eq!(DebugCredential::from_bytes(&bytes).unwrap(), credential);
+    }
+
+    #[test]
+    fn rsa4096_rot_key() {
+        let mut config = config();
+        config.pki.signing_key = "file:example-file-certs/ca4096_private_key.pem".into();
+        config.pki.certificates[1] = crate::pki::CertificateUriChain::Root(
+            "file:example-file-certs/ca4096_certificate.der".into(),
+        );
+        let error = CredentialSigningRequest::try_from(&config)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("not an RSA-2048 key"), "{}", error);
+    }
+
+    #[test]
+    fn tampered() {
+        let credential = CredentialSigningRequest::try_from(&config())
+            .unwrap()
+            .sign();
+        let mut bytes = credential.to_bytes();
+        // grant nonsecure invasive debugging
+        let offset = 4 + 4 + 16 + 128 + PUBLIC_KEY_SIZE;
+        bytes[offset] |= 0b10;
+        assert!(!DebugCredential::from_bytes(&bytes).unwrap().verify());
+    }
+
+    /// A challenge in the layout the device sends, for `config()` and UUID
+    /// fedcba98..., with all debug access set to `Authenticate`
+    fn challenge() -> Vec<u8> {
+        hex::decode(concat!(
+            "01000000",
+            "01000000",
+            "fedcba9876543210fedcba9876543210",
+            "00000000",
+            "d826e2fd44f5c254bc58c62ebf96a93895c19dc225810c95c8b9e6fd9f7cc9cb",
+            "0000ffff",
+            "0000ffff",
+            "00000000",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn challenge_roundtrip() {
+        let bytes = challenge();
+        let challenge = DebugAuthenticationChallenge::from_bytes(&bytes).unwrap();
+        assert_eq!(challenge.version, [1, 0]);
+        assert_eq!(challenge.socc, 1);
+        assert_eq!(challenge.challenge[31], 0x1f);
+        assert_eq!(
+            challenge.debug_settings(),
+            DebugSettings::from(DebugAccess::Authenticate)
+        );
+        assert_eq!(challenge.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn response() {
+        let credential = CredentialSigningRequest::try_from(&config())
+            .unwrap()
+            .sign();
+        let dck = SigningKey::try_from_uri("file:example-file-certs/dck_private_key.pem").unwrap();
+
+        // credential is for another device
+        assert!(respond(&challenge(), &credential, &dck).is_err());
+
+        let mut config = config();
+        config.debug_credential.uuid = String::new();
+        let credential = CredentialSigningRequest::try_from(&config).unwrap().sign();
+        let bytes = respond(&challenge(), &credential, &dck).unwrap();
+        assert_eq!(bytes.len(), SIZE + 4 + 32 + 256);
+        assert_eq!(bytes[..SIZE], credential.to_bytes()[..]);
+        assert_eq!(bytes[SIZE + 4..][..32], challenge()[72..]);
+
+        let response = DebugAuthenticationResponse::from_bytes(&bytes).unwrap();
+        assert!(response.verify());
+
+        // only the DCK can sign
+        let rot_key = SigningKey::try_from_uri(&config.pki.signing_key).unwrap();
+        assert!(respond(&challenge(), &credential, &rot_key).is_err());
+
+        // the device's ROTKH must match
+        let mut other = challenge();
+        other[28] ^= 1;
+        assert!(respond(&other, &credential, &dck).is_err());
+    }
+
+    #[test]
+    fn signing_key_must_be_rot_key() {
+        let mut config = config();
+        config.pki.certificates.swap(1, 2);
+        config.pki.certificates[2] = config.pki.certificates[0].clone();
+        assert!(CredentialSigningRequest::try_from(&config).is_err());
+    }
+}
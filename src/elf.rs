@@ -0,0 +1,262 @@
+//! Flatten firmware ELF files into plain images.
+//!
+//! Only what `cargo build` produces for the LPC55 is supported: 32-bit little-endian
+//! ARM executables. The loadable segments are placed at their physical (load) addresses,
+//! which puts initialized data behind the code, as the startup code expects.
+//! Gaps between segments are filled with zeros, like `objcopy -O binary` does.
+
+use core::convert::TryInto as _;
+use core::ops::Range;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{le_u16, le_u32},
+    sequence::tuple,
+};
+
+use crate::secure_binary::{sniff, Filetype};
+
+/// Flash available to firmware, up to the protected flash region (PFR)
+pub const FLASH: Range<u32> = 0x0000_0000..0x0009_DE00;
+/// SRAM 0 to 4, which RAM images are loaded into
+pub const RAM: Range<u32> = 0x2000_0000..0x2004_4000;
+/// Flash and RAM addresses with this bit set are the secure alias of the same memory
+pub const SECURE_ALIAS: u32 = 0x1000_0000;
+
+const PT_LOAD: u32 = 1;
+const EM_ARM: u16 = 40;
+
+/// Loadable segment, at its physical address.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// Plain image, as it would be in flash.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Image {
+    /// Address of the first byte, with the secure alias bit cleared
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// Program headers of loadable segments with contents in the file.
+pub fn segments(elf: &[u8]) -> Result<Vec<Segment>> {
+    let (_, (_magic, _class_and_data, _ident, _type, machine)) = tuple((
+        tag::<_, _, ()>(b"\x7fELF"),
+        // 32-bit, little-endian
+        tag([1u8, 1].as_ref()),
+        take(10u8),
+        le_u16,
+        le_u16,
+    ))(elf)
+    .map_err(|_| anyhow::anyhow!("not a 32-bit little-endian ELF file"))?;
+    if machine != EM_ARM {
+        return Err(anyhow::anyhow!("not an ARM ELF file (machine {})", machine));
+    }
+
+    let field = |offset: usize, size: usize| -> Result<u32> {
+        let bytes = elf
+            .get(offset..offset + size)
+            .ok_or_else(|| anyhow::anyhow!("truncated ELF header"))?;
+        Ok(match size {
+            2 => u16::from_le_bytes(bytes.try_into().unwrap()) as u32,
+            _ => u32::from_le_bytes(bytes.try_into().unwrap()),
+        })
+    };
+    let phoff = field(0x1C, 4)? as usize;
+    let phentsize = field(0x2A, 2)? as usize;
+    let phnum = field(0x2C, 2)? as usize;
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let header = elf
+            .get(phoff + i * phentsize..)
+            .ok_or_else(|| anyhow::anyhow!("truncated program header table"))?;
+        let (_, (p_type, p_offset, _p_vaddr, p_paddr, p_filesz)) =
+            tuple((le_u32::<_, ()>, le_u32, le_u32, le_u32, le_u32))(header)
+                .map_err(|_| anyhow::anyhow!("truncated program header {}", i))?;
+        // segments without file contents (.bss, stack) need no flash
+        if p_type != PT_LOAD || p_filesz == 0 {
+            continue;
+        }
+        let data = elf
+            .get(p_offset as usize..)
+            .and_then(|data| data.get(..p_filesz as usize))
+            .ok_or_else(|| anyhow::anyhow!("segment {} exceeds the file", i))?;
+        segments.push(Segment {
+            address: p_paddr,
+            data: Vec::from(data),
+        });
+    }
+    Ok(segments)
+}
+
+impl Image {
+    /// Lays out the loadable segments within flash.
+    ///
+    /// Segments outside flash are skipped with a warning.
+    pub fn from_elf(elf: &[u8]) -> Result<Self> {
+        Self::from_elf_within(elf, FLASH)
+    }
+
+    /// Lays out the loadable segments within `memory`, e.g. [`RAM`] for RAM images.
+    ///
+    /// Segments outside `memory` are skipped with a warning.
+    pub fn from_elf_within(elf: &[u8], memory: Range<u32>) -> Result<Self> {
+        let name = memory_name(&memory);
+        let mut segments: Vec<Segment> = segments(elf)?
+            .into_iter()
+            .filter_map(|mut segment| {
+                let start = segment.address & !SECURE_ALIAS;
+                let end = start as u64 + segment.data.len() as u64;
+                if start < memory.start || end > memory.end as u64 {
+                    warn!(
+                        "skipping segment at 0x{:08x} ({} bytes), it is outside {}",
+                        segment.address,
+                        segment.data.len(),
+                        name
+                    );
+                    return None;
+                }
+                segment.address = start;
+                Some(segment)
+            })
+            .collect();
+        segments.sort_by_key(|segment| segment.address);
+
+        let address = segments
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("ELF file has no loadable segments in {}", name))?
+            .address;
+        let mut data = Vec::new();
+        for segment in segments {
+            let offset = (segment.address - address) as usize;
+            if offset < data.len() {
+                return Err(anyhow::anyhow!(
+                    "segment at 0x{:08x} overlaps the previous one",
+                    segment.address
+                ));
+            }
+            if offset > data.len() {
+                debug!("filling gap of {} bytes", offset - data.len());
+            }
+            data.resize(offset, 0);
+            data.extend_from_slice(&segment.data);
+        }
+
+        Ok(Self { address, data })
+    }
+}
+
+fn memory_name(memory: &Range<u32>) -> String {
+    match memory {
+        memory if *memory == FLASH => "flash".to_string(),
+        memory if *memory == RAM => "RAM".to_string(),
+        memory => format!("0x{:08x}..0x{:08x}", memory.start, memory.end),
+    }
+}
+
+/// Reads a firmware image, flattening it if it is an ELF file.
+///
+/// Other files are returned as-is. For ELF files, the image is expected to start
+/// at the beginning of flash, as does a plain `objcopy -O binary` output.
+pub fn read_image<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    read_image_at(path, FLASH.start)
+}
+
+/// Reads a firmware image like [`read_image`], ELF files need to start at `address`.
+///
+/// Addresses in [`RAM`] are for images the ROM copies into RAM before running them.
+pub fn read_image_at<P: AsRef<Path>>(path: P, address: u32) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let data = fs::read(path)
+        .with_context(|| format!("Failed to read firmware image from {}", path.display()))?;
+    if sniff(&data).ok() != Some(Filetype::Elf) {
+        return Ok(data);
+    }
+    let memory = match RAM.contains(&(address & !SECURE_ALIAS)) {
+        true => RAM,
+        false => FLASH,
+    };
+    let image = Image::from_elf_within(&data, memory)
+        .with_context(|| format!("Failed to flatten ELF file {}", path.display()))?;
+    if image.address != address & !SECURE_ALIAS {
+        return Err(anyhow::anyhow!(
+            "{} loads at 0x{:08x}, not at {}",
+            path.display(),
+            image.address,
+            match address {
+                0 => "the start of flash".to_string(),
+                address => format!("0x{:08x}", address),
+            }
+        ));
+    }
+    Ok(image.data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flatten_blinky() {
+        let elf = fs::read("example-binaries/blinky-red.elf").unwrap();
+        let segments = segments(&elf).unwrap();
+        // vector table, text and rodata; bss has no file contents
+        assert_eq!(segments.len(), 3);
+
+        let image = Image::from_elf(&elf).unwrap();
+        assert_eq!(image.address, 0);
+        assert_eq!(image.data.len(), 856);
+        assert_eq!(sniff(&image.data).unwrap(), Filetype::UnsignedBin);
+        for segment in segments {
+            assert_eq!(
+                &image.data[segment.address as usize..][..segment.data.len()],
+                segment.data.as_slice()
+            );
+        }
+
+        assert_eq!(
+            read_image("example-binaries/blinky-red.elf").unwrap(),
+            image.data
+        );
+    }
+
+    #[test]
+    fn secure_alias_and_gaps() {
+        let mut elf = fs::read("example-binaries/blinky-red.elf").unwrap();
+        // move the rodata segment (third program header) behind a gap, in the secure alias
+        let paddr = 52 + 2 * 32 + 12;
+        elf[paddr..][..4].copy_from_slice(&(SECURE_ALIAS | 0x400).to_le_bytes());
+
+        let image = Image::from_elf(&elf).unwrap();
+        assert_eq!(image.data.len(), 0x400 + 0x2c);
+        assert!(image.data[0x32c..0x400].iter().all(|byte| *byte == 0));
+
+        // outside flash
+        elf[paddr..][..4].copy_from_slice(&0x2000_0000u32.to_le_bytes());
+        let image = Image::from_elf(&elf).unwrap();
+        assert_eq!(image.data.len(), 0x32c);
+    }
+
+    #[test]
+    fn ram() {
+        let mut elf = fs::read("example-binaries/blinky-red.elf").unwrap();
+        let flash = Image::from_elf(&elf).unwrap();
+        // relink all three segments to RAM
+        for n in 0..3 {
+            let paddr = &mut elf[52 + n * 32 + 12..][..4];
+            let address = u32::from_le_bytes(paddr.try_into().unwrap());
+            paddr.copy_from_slice(&(RAM.start + address).to_le_bytes());
+        }
+        assert!(Image::from_elf(&elf).is_err());
+        let image = Image::from_elf_within(&elf, RAM).unwrap();
+        assert_eq!(image.address, RAM.start);
+        assert_eq!(image.data, flash.data);
+    }
+}
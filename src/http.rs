@@ -5,7 +5,10 @@ use std::io;
 
 use tiny_http as http;
 
-use crate::bootloader;
+use crate::bootloader::{
+    self,
+    transport::{HidTransport, Transport},
+};
 use anyhow::Result;
 
 #[derive(Clone, Debug)]
@@ -27,14 +30,14 @@ impl Default for HttpConfig {
     }
 }
 
-pub struct Server {
+pub struct Server<T: Transport = HidTransport> {
     config: HttpConfig,
     server: http::Server,
-    bootloader: bootloader::Bootloader,
+    bootloader: bootloader::Bootloader<T>,
 }
 
-impl Server {
-    pub fn new(config: &HttpConfig, bootloader: bootloader::Bootloader) -> Result<Server> {
+impl<T: Transport> Server<T> {
+    pub fn new(config: &HttpConfig, bootloader: bootloader::Bootloader<T>) -> Result<Self> {
         let server = http::Server::http(format!("{}:{}", &config.addr, config.port))
             .map_err(|e| anyhow::format_err!("couldn't create HTTP server: {}", e))?;
 
@@ -83,8 +86,9 @@ impl Server {
             "lpc55::http[{:04x}:{:04x}, {}:{}]: GET /pfr",
             &self.bootloader.vid, &self.bootloader.pid, &self.config.addr, &self.config.port,
         );
-        let data = self.bootloader.read_memory(0x9_DE00, 7 * 512);
-        let pfr = crate::protected_flash::ProtectedFlash::try_from(&data[..]).unwrap();
+        let data = self.bootloader.read_memory(0x9_DE00, 7 * 512)?;
+        let pfr = crate::protected_flash::ProtectedFlash::try_from(&data[..])
+            .map_err(|()| anyhow::anyhow!("PFR read from the device does not parse"))?;
         let json = serde_json::to_string_pretty(&pfr).unwrap();
 
         Ok(http::Response::from_string(json))
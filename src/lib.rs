@@ -32,6 +32,8 @@ extern crate delog;
 // modules
 pub mod bootloader;
 pub mod crypto;
+pub mod debug_auth;
+pub mod elf;
 pub mod pki;
 pub mod protected_flash;
 pub mod secure_binary;
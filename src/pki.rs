@@ -8,8 +8,8 @@ use anyhow::{Context as _, Result};
 use serde::{Deserialize, Serialize};
 use x509_parser::{certificate::X509Certificate, traits::FromDer};
 
-use rsa::pkcs1::FromRsaPrivateKey;
-use rsa::pkcs1::FromRsaPublicKey;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1::DecodeRsaPublicKey;
 
 use crate::util::is_default;
 
@@ -29,7 +29,7 @@ pub fn split_once(s: &str, delimiter: char) -> Option<(&str, &str)> {
 #[serde(rename_all = "kebab-case")]
 #[serde(deny_unknown_fields)]
 pub struct Pki {
-    /// URI specifying the private RSA2K key used for signing firmware.
+    /// URI specifying the private RSA2K or RSA4K key used for signing firmware.
     ///
     /// Currently, two options are supported
     /// - `file:` path to PKCS #1 encoded PEM file containing private key
@@ -45,10 +45,11 @@ pub struct Pki {
     #[serde(default)]
     pub signing_key: String,
 
-    /// Paths to the four root certificates.
+    /// Paths to the four root certificates, each optionally followed by a chain of
+    /// intermediate certificates (`{ root = "...", chain = ["..."] }`).
     ///
-    /// The appropriate certificate to include in signed firmware and containers is selected
-    /// using the signing key's public key.
+    /// The appropriate certificate chain to include in signed firmware and containers is
+    /// selected using the signing key's public key, which must match the last certificate.
     ///
     /// Encoded as X.509 DER files.
     pub certificates: [CertificateUriChain; 4],
@@ -77,7 +78,7 @@ impl CertificateUriChain {
     pub fn chain(&self) -> &[String] {
         match self {
             Self::Root(_) => &[],
-            Self::Chain { root: _, chain } => &**chain,
+            Self::Chain { root: _, chain } => chain,
         }
     }
 }
@@ -119,17 +120,137 @@ impl TryFrom<&'_ str> for SigningKeySource {
     }
 }
 
+/// Source of a symmetric key, such as the SBKEK.
+///
+/// URIs:
+/// - `hex:` the key itself, as hex digits (the scheme can be left out)
+/// - `file:` path to a file containing the raw key, or its hex digits
+/// - `env:` name of an environment variable containing the hex digits
+/// - `pkcs11:` PKCS #11 URI of a secret key object; the key is read out, so it must be extractable
+///   and not sensitive.
+///
+/// Whitespace in hex digits is ignored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SecretKeySource {
+    Hex(std::string::String),
+    File(std::path::PathBuf),
+    Env(std::string::String),
+    Pkcs11Uri(std::string::String),
+}
+
+impl TryFrom<&'_ str> for SecretKeySource {
+    type Error = anyhow::Error;
+    fn try_from(uri: &str) -> anyhow::Result<Self> {
+        let (scheme, content) = split_once(uri, ':').unwrap_or(("hex", uri));
+        let key_source = match scheme {
+            "hex" => SecretKeySource::Hex(content.to_string()),
+            "file" => SecretKeySource::File(std::path::PathBuf::from(content)),
+            "env" => SecretKeySource::Env(content.to_string()),
+            "pkcs11" => SecretKeySource::Pkcs11Uri(uri.to_string()),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "only hex, file, env and pkcs11 secret key URIs supported"
+                ))
+            }
+        };
+        Ok(key_source)
+    }
+}
+
+impl SecretKeySource {
+    pub fn load(&self) -> Result<Vec<u8>> {
+        use SecretKeySource::*;
+        Ok(match self {
+            Hex(digits) => decode_hex(digits)?,
+            File(path) => {
+                let data = fs::read(path).with_context(|| {
+                    format!("Failed to read secret key from {}", path.display())
+                })?;
+                match core::str::from_utf8(&data) {
+                    Ok(digits) if is_hex(digits) => decode_hex(digits)?,
+                    _ => data,
+                }
+            }
+            Env(variable) => {
+                let digits = std::env::var(variable).with_context(|| {
+                    format!(
+                        "Failed to read secret key from environment variable {}",
+                        variable
+                    )
+                })?;
+                decode_hex(&digits)?
+            }
+            Pkcs11Uri(uri) => {
+                use pkcs11::types::{CKA_VALUE, CK_ATTRIBUTE};
+
+                let uri = pkcs11_uri::Pkcs11Uri::try_from(uri)?;
+                let (context, session, object) = uri.identify_object()?;
+
+                let buffer = [0u8; 64];
+                let attribute = CK_ATTRIBUTE::new(CKA_VALUE).with_bytes(&buffer);
+                let mut template = vec![attribute];
+                let (rv, attributes) = context
+                    .get_attribute_value(session, object, &mut template)
+                    .context("Failed to read secret key value, is it extractable?")?;
+                if rv != 0 {
+                    return Err(anyhow::anyhow!(
+                        "Failed to read secret key value (CKR 0x{:x}), is it extractable?",
+                        rv
+                    ));
+                }
+                attributes[0].get_bytes()?
+            }
+        })
+    }
+
+    /// Loads a key of the given size.
+    pub fn load_array<const N: usize>(&self) -> Result<[u8; N]> {
+        let key = self.load()?;
+        let length = key.len();
+        key.try_into()
+            .map_err(|_| anyhow::anyhow!("secret key must be {} bytes, not {}", N, length))
+    }
+}
+
+fn is_hex(digits: &str) -> bool {
+    digits
+        .chars()
+        .all(|c| c.is_ascii_hexdigit() || c.is_whitespace())
+}
+
+fn decode_hex(digits: &str) -> Result<Vec<u8>> {
+    let mut digits = digits.to_string();
+    digits.retain(|c| !c.is_whitespace());
+    hex::decode(&digits).context("secret key is not valid hex")
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum SigningKey {
     Pkcs1(rsa::RsaPrivateKey),
     Pkcs11Uri(pkcs11_uri::Pkcs11Uri),
 }
 
-/// An RSA2k public key
+/// An RSA public key, the ROM verifies with 2048 and 4096 bit keys
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct PublicKey(pub rsa::RsaPublicKey);
 
 impl PublicKey {
+    /// Checks the ROM can verify signatures made with this key: RSA-2048 or RSA-4096, with
+    /// public exponent 65537.
+    pub fn check_rom_support(&self) -> Result<()> {
+        use rsa::PublicKeyParts as _;
+        let bits = self.0.n().bits();
+        let e = self.0.e();
+        if !matches!(bits, 2048 | 4096) || *e != rsa::BigUint::from(65537u32) {
+            return Err(anyhow::anyhow!(
+                "RSA key has {} bits and exponent {}, the ROM needs 2048 or 4096 bits and exponent 65537",
+                bits,
+                e
+            ));
+        }
+        Ok(())
+    }
+
     pub fn fingerprint(&self) -> Sha256Hash {
         use rsa::PublicKeyParts as _;
         let n = self.0.n();
@@ -139,7 +260,7 @@ impl PublicKey {
         let mut hasher = sha2::Sha256::new();
         hasher.update(n.to_bytes_be());
         hasher.update(e.to_bytes_be());
-        let hash = <[u8; 32]>::try_from(hasher.finalize()).unwrap();
+        let hash = <[u8; 32]>::from(hasher.finalize());
         Sha256Hash(hash)
     }
 }
@@ -208,16 +329,18 @@ impl SigningKey {
         Signature(signature)
     }
 
-    pub fn public_key(&self) -> PublicKey {
+    /// The public key; for PKCS #11 keys, read from the token and checked with
+    /// [`PublicKey::check_rom_support`].
+    pub fn public_key(&self) -> anyhow::Result<PublicKey> {
         use SigningKey::*;
-        PublicKey(match self {
-            Pkcs1(key) => key.to_public_key(),
+        Ok(match self {
+            Pkcs1(key) => PublicKey(key.to_public_key()),
             Pkcs11Uri(uri) => {
-                let (context, session, object) = uri.identify_object().unwrap();
+                let (context, session, object) = uri.identify_object()?;
 
                 use pkcs11::types::{CKA_MODULUS, CKA_PUBLIC_EXPONENT, CK_ATTRIBUTE};
 
-                let /*mut*/ n_buffer = [0u8; 256]; // rust-pkcs11 API is sloppy about mut here; 256B = 2048b is enough for RSA2k keys
+                let /*mut*/ n_buffer = [0u8; 512]; // rust-pkcs11 API is sloppy about mut here; 512B = 4096b is enough for RSA4k keys
                 let /*mut*/ e_buffer = [0u8; 3]; // always 0x10001 = u16::MAX + 2 anyway
                 let mut n_attribute = CK_ATTRIBUTE::new(CKA_MODULUS);
                 n_attribute.set_biginteger(&n_buffer);
@@ -227,24 +350,28 @@ impl SigningKey {
 
                 let (rv, attributes) = context
                     .get_attribute_value(session, object, &mut template)
-                    .unwrap();
-                assert_eq!(rv, 0);
-                let n = attributes[0].get_biginteger().unwrap();
-                let e = attributes[1].get_biginteger().unwrap();
-                assert!(n.bits() > 2012 && n.bits() <= 2048);
-                // dbg!(n.to_str_radix(10));
-                assert_eq!(e.to_str_radix(10), "65537");
+                    .context("Failed to read public key from PKCS #11 token")?;
+                if rv != 0 {
+                    return Err(anyhow::anyhow!(
+                        "Failed to read public key from PKCS #11 token (CKR 0x{:x})",
+                        rv
+                    ));
+                }
+                let n = attributes[0].get_biginteger()?;
+                let e = attributes[1].get_biginteger()?;
 
                 // https://github.com/mheese/rust-pkcs11/issues/44
                 let n = rsa::BigUint::from_bytes_be(&n.to_bytes_le());
                 let e = rsa::BigUint::from_bytes_be(&e.to_bytes_le());
-                rsa::RsaPublicKey::new(n, e).unwrap()
+                let key = PublicKey(rsa::RsaPublicKey::new(n, e)?);
+                key.check_rom_support()?;
+                key
             }
         })
     }
 
-    pub fn fingerprint(&self) -> Sha256Hash {
-        self.public_key().fingerprint()
+    pub fn fingerprint(&self) -> anyhow::Result<Sha256Hash> {
+        Ok(self.public_key()?.fingerprint())
     }
 }
 
@@ -427,6 +554,10 @@ impl CertificateChain {
         }
     }
 
+    pub fn new(root: Certificate, chain: Vec<Certificate>) -> Self {
+        Self { root, chain }
+    }
+
     pub fn try_from(uris: &CertificateUriChain) -> Result<Self> {
         let root = Certificate::try_from(&uris.root().try_into()?)?;
         let chain: Result<Vec<_>, _> = uris
@@ -460,6 +591,39 @@ impl CertificateChain {
     pub fn len(&self) -> usize {
         1 + self.chain.len()
     }
+
+    /// Each certificate is signed by its predecessor, the root certificate by itself.
+    pub fn verify_signatures(&self) -> bool {
+        let certificates: Vec<_> = self.all().map(|c| c.certificate()).collect();
+        certificates.iter().enumerate().all(|(n, certificate)| {
+            let issuer = n.checked_sub(1).map(|m| certificates[m].public_key());
+            certificate.verify_signature(issuer).is_ok()
+        })
+    }
+
+    /// Checks the ROM accepts the chain: all keys pass [`PublicKey::check_rom_support`]
+    /// (and are RSA4K if `rsa4096_only`, cf. `SecureBootConfiguration::use_rsa4096_keys`),
+    /// and the signatures verify.
+    pub fn check(&self, rsa4096_only: bool) -> Result<()> {
+        use rsa::PublicKeyParts as _;
+        for certificate in self.all() {
+            let subject = || format!("certificate {}", certificate.certificate().subject());
+            let key = certificate.public_key();
+            key.check_rom_support().with_context(subject)?;
+            if rsa4096_only && key.0.size() != 512 {
+                return Err(anyhow::anyhow!(
+                    "{}: RSA2K key, but the factory settings require RSA4K keys",
+                    subject()
+                ));
+            }
+        }
+        if !self.verify_signatures() {
+            return Err(anyhow::anyhow!(
+                "certificate chain does not verify, each certificate must be issued by the previous one"
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -549,17 +713,17 @@ impl Certificates {
         use sha2::Digest;
         let mut hash = sha2::Sha256::new();
         for fingerprint in self.fingerprints().iter() {
-            hash.update(&fingerprint);
+            hash.update(fingerprint);
         }
-        let hash = <[u8; 32]>::try_from(hash.finalize()).unwrap();
+        let hash = <[u8; 32]>::from(hash.finalize());
         Sha256Hash(hash)
     }
 
     pub fn fingerprint_from_bytes(fingerprints: &[u8]) -> Sha256Hash {
         use sha2::Digest;
         let mut hash = sha2::Sha256::new();
-        hash.update(&fingerprints);
-        let hash = <[u8; 32]>::try_from(hash.finalize()).unwrap();
+        hash.update(fingerprints);
+        let hash = <[u8; 32]>::from(hash.finalize());
         Sha256Hash(hash)
     }
 }
@@ -605,3 +769,72 @@ pub(crate) fn format_bytes(bytes: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Res
     //     info,
     // ))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn secret_key_sources() {
+        let key: Vec<u8> = (0..32).collect();
+        let digits = "00010203 04050607 08090a0b 0c0d0e0f 10111213 14151617 18191a1b 1c1d1e1f";
+
+        let source = SecretKeySource::try_from(digits).unwrap();
+        assert_eq!(source, SecretKeySource::Hex(digits.to_string()));
+        assert_eq!(source.load().unwrap(), key);
+        let hex_uri = format!("hex:{}", digits);
+        assert_eq!(SecretKeySource::try_from(hex_uri.as_str()).unwrap(), source);
+
+        std::env::set_var("LPC55_PKI_TEST_SECRET_KEY", digits);
+        let source = SecretKeySource::try_from("env:LPC55_PKI_TEST_SECRET_KEY").unwrap();
+        assert_eq!(source.load_array::<32>().unwrap().to_vec(), key);
+        assert!(source.load_array::<16>().is_err());
+
+        let dir = std::env::temp_dir().join(format!("lpc55-pki-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let raw = dir.join("raw.bin");
+        fs::write(&raw, &key).unwrap();
+        let text = dir.join("key.txt");
+        fs::write(&text, format!("{}\n", digits)).unwrap();
+        for path in [raw, text] {
+            let uri = format!("file:{}", path.display());
+            let source = SecretKeySource::try_from(uri.as_str()).unwrap();
+            assert_eq!(source.load().unwrap(), key);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(SecretKeySource::try_from("https://example.com/key").is_err());
+        assert!(SecretKeySource::try_from("hex:xyz")
+            .unwrap()
+            .load()
+            .is_err());
+    }
+
+    #[test]
+    fn rom_support() {
+        let uris = CertificateUriChain::Chain {
+            root: "file:example-file-certs/ca4096_certificate.der".to_string(),
+            chain: vec!["file:example-file-certs/isk_certificate.der".to_string()],
+        };
+        let chain = CertificateChain::try_from(&uris).unwrap();
+        assert_eq!(
+            rsa::PublicKeyParts::size(&chain.signer().public_key().0),
+            512
+        );
+        chain.check(true).unwrap();
+
+        // the intermediate certificate does not sign the root
+        let reversed = CertificateChain::new(chain.signer().clone(), vec![chain.root().clone()]);
+        assert!(!reversed.verify_signatures());
+        assert!(reversed.check(false).is_err());
+
+        let root = CertificateUriChain::Root("file:example-file-certs/ca_certificate_0.der".into());
+        let chain = CertificateChain::try_from(&root).unwrap();
+        chain.check(false).unwrap();
+        assert!(chain.check(true).is_err());
+
+        let n = (rsa::BigUint::from(1u8) << 3071usize) + 1u8;
+        let key = PublicKey(rsa::RsaPublicKey::new(n, 65537u32.into()).unwrap());
+        assert!(key.check_rom_support().is_err());
+    }
+}
@@ -4,13 +4,14 @@ use core::convert::TryInto;
 use core::fmt;
 use std::io::Write as _;
 
+use crate::bootloader::command::Key;
 use crate::pki::{format_bytes, Sha256Hash};
 use crate::util::{hex_deserialize_256, hex_serialize, is_default};
 
 use serde::{Deserialize, Serialize};
 use sha2::Digest as _;
 
-use nom::{bytes::complete::take, number::complete::le_u32, IResult};
+use nom::{bytes::complete::take, combinator::verify, number::complete::le_u32, IResult};
 
 use serde_big_array::big_array;
 big_array! {
@@ -20,18 +21,22 @@ big_array! {
 
 pub mod debug;
 pub use debug::{DebugAccess, DebugSettings};
+pub mod diff;
+pub mod keystore;
+pub mod rules;
 
 pub const FACTORY_SETTINGS_ADDRESS: usize = 0x9_E400;
 pub const CUSTOMER_SETTINGS_SCRATCH_ADDRESS: usize = 0x9_DE00;
+pub const KEYSTORE_ADDRESS: usize = 0x9_E600;
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 /// For a graphical overview: <https://whimsical.com/lpc55-flash-memory-map-4eU3ei4wsqiAD7D2cAiv5s>
 ///
 /// - customer page: one flash page (512B) of configuration data that may be updated during the device's
-/// lifecycle via a scratch/ping/pong process
+///   lifecycle via a scratch/ping/pong process
 /// - factory page: one flash page (512B) of configuration data, to be set during manufacturing process
 /// - keystore: three flash pages, technically considered part of the factory configuration data,
-/// containing activation and key codes for the PUF keys.
+///   containing activation and key codes for the PUF keys.
 #[serde(rename_all = "kebab-case")]
 pub struct ProtectedFlash {
     #[serde(default)]
@@ -224,10 +229,10 @@ pub struct KeystoreHeader(pub u32);
 #[derive(Clone, Copy, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 /// Input to regenerate keys "stored" in the PUF.
 ///
-/// TODO: split into
-/// - key code valid (first four bytes)
-/// - key parameters (user/generated, length, ...?)
-/// - actual keycode = last 52 bytes
+/// Consists of
+/// - key code valid marker (first four bytes), cf. [`Keycode::valid`]
+/// - key parameters (user/generated, index, length), cf. [`Keycode::header`]
+/// - actual keycode = remaining 48 bytes
 ///
 /// Empirically, these arrays start with either '59595959 01000002' for 16B keys,
 /// or '59595959 00000004' for 32B keys.
@@ -254,6 +259,60 @@ impl Keycode {
     pub fn user_key(&self) -> bool {
         self.0[4] == 0
     }
+
+    /// The key parameters, if the keycode is valid.
+    pub fn header(&self) -> Option<KeycodeHeader> {
+        if !self.valid() {
+            return None;
+        }
+        Some(KeycodeHeader {
+            kind: match self.0[4] {
+                0 => KeyKind::User,
+                1 => KeyKind::Generated,
+                kind => KeyKind::Unknown(kind),
+            },
+            index: self.0[5],
+            size: 8 * self.0[7] as usize,
+        })
+    }
+
+    /// All zeros (never written) or all ones (erased).
+    pub fn is_blank(&self) -> bool {
+        self.0.iter().all(|&byte| byte == 0) || self.0.iter().all(|&byte| byte == 0xff)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Bytes 4 to 7 of a [`Keycode`], following the valid marker.
+///
+/// This matches the observed headers (`01000002`, `00000004`, `010F0004`), but is not
+/// documented in UM 11126; in particular the third byte is assumed to be reserved.
+pub struct KeycodeHeader {
+    /// Set via `SetKey`, or generated by the PUF via `GenerateKey`
+    pub kind: KeyKind,
+    /// PUF key index (0 to 15), index 0 keys can only be routed to hardware (AES, PRINCE)
+    pub index: u8,
+    /// Size of the key in bytes
+    pub size: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyKind {
+    User,
+    Generated,
+    Unknown(u8),
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyKind::User => f.write_str("user"),
+            KeyKind::Generated => f.write_str("generated"),
+            KeyKind::Unknown(kind) => write!(f, "unknown (0x{:02x})", kind),
+        }
+    }
 }
 
 impl Default for Keycode {
@@ -366,6 +425,20 @@ pub struct NxpArea {
 }
 
 impl Keystore {
+    /// Value of `header` once the PUF is enrolled.
+    pub const ENROLLED: KeystoreHeader = KeystoreHeader(0x9595_9595);
+
+    pub fn keycode(&self, key: Key) -> &Keycode {
+        match key {
+            Key::SecureBootKek => &self.secure_boot_kek,
+            Key::UserPsk => &self.user_key,
+            Key::UniqueDeviceSecret => &self.unique_device_secret,
+            Key::PrinceRegion0 => &self.prince_region_0,
+            Key::PrinceRegion1 => &self.prince_region_1,
+            Key::PrinceRegion2 => &self.prince_region_2,
+        }
+    }
+
     pub fn to_bytes(&self) -> [u8; 3 * 512] {
         let mut buf = [0u8; 3 * 512];
         let mut cursor = buf.as_mut();
@@ -416,6 +489,11 @@ where
     CustomerData: FactorySettingsCustomerData,
     VendorUsage: FactorySettingsVendorUsage,
 {
+    /// The settings carry a SHA256 hash, after which the ROM refuses changes.
+    pub fn is_sealed(&self) -> bool {
+        self.sha256_hash != Sha256Hash::default()
+    }
+
     pub fn to_bytes(&mut self) -> anyhow::Result<[u8; 512]> {
         let mut buf = [0u8; 512];
         let mut cursor = buf.as_mut();
@@ -447,7 +525,7 @@ where
 
             let mut hasher = sha2::Sha256::new();
             hasher.update(&buf[0..480]);
-            self.sha256_hash = Sha256Hash(hasher.finalize().try_into().unwrap());
+            self.sha256_hash = Sha256Hash(hasher.finalize().into());
 
             buf[480..512].as_mut().write_all(&self.sha256_hash.0).ok();
         }
@@ -463,11 +541,10 @@ fn parse_factory<
     input: &[u8],
 ) -> IResult<&[u8], FactorySettings<CustomerData, VendorUsage>> {
     let (input, boot_cfg) = le_u32(input)?;
-    let (input, _spi_flash_cfg) = le_u32(input)?;
-    assert_eq!(_spi_flash_cfg, 0);
+    // SPI flash and SDIO configuration, reserved on the LPC55S6x
+    let (input, _spi_flash_cfg) = verify(le_u32, |word| *word == 0)(input)?;
     let (input, usb_id) = le_u32(input)?;
-    let (input, _sdio_cfg) = le_u32(input)?;
-    assert_eq!(_sdio_cfg, 0);
+    let (input, _sdio_cfg) = verify(le_u32, |word| *word == 0)(input)?;
     let (input, cc_socu_pin) = le_u32(input)?;
     let (input, cc_socu_default) = le_u32(input)?;
 
@@ -516,7 +593,9 @@ fn parse_factory<
 #[repr(u8)]
 /// Purposely swapped 48MHz and 96MHz values from what they are in
 /// reference manual (Rev. 2.1).  These are the correct values.
+#[derive(Default)]
 pub enum BootSpeed {
+    #[default]
     Nxp = 0,
     #[serde(rename = "96MHz")]
     Fro96 = 1,
@@ -525,12 +604,6 @@ pub enum BootSpeed {
     Reserved = 3,
 }
 
-impl Default for BootSpeed {
-    fn default() -> Self {
-        Self::Nxp
-    }
-}
-
 impl From<u8> for BootSpeed {
     fn from(value: u8) -> Self {
         use BootSpeed::*;
@@ -543,8 +616,11 @@ impl From<u8> for BootSpeed {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Default,
+)]
 pub enum IspMode {
+    #[default]
     Auto,
     Usb,
     Uart,
@@ -554,12 +630,6 @@ pub enum IspMode {
     Reserved(u8),
 }
 
-impl Default for IspMode {
-    fn default() -> Self {
-        Self::Auto
-    }
-}
-
 impl From<u8> for IspMode {
     fn from(value: u8) -> Self {
         use IspMode::*;
@@ -661,7 +731,7 @@ impl From<u32> for UsbId {
 fn multibool(bits: u32) -> bool {
     match bits {
         0b00 => false,
-        0b01 | 0b10 | 0b11 => true,
+        0b01..=0b11 => true,
         _ => panic!(),
     }
 }
@@ -675,7 +745,9 @@ fn boolmulti(value: bool) -> u32 {
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 #[repr(u8)]
+#[derive(Default)]
 pub enum TrustzoneMode {
+    #[default]
     FromImageHeader = 0b00,
     DisabledBootToNonsecure = 0b01,
     EnabledBootToSecure = 0b10,
@@ -683,12 +755,6 @@ pub enum TrustzoneMode {
     PresetTrustzoneCheckerFromImageHeader = 0b11,
 }
 
-impl Default for TrustzoneMode {
-    fn default() -> Self {
-        Self::FromImageHeader
-    }
-}
-
 impl From<u32> for TrustzoneMode {
     fn from(value: u32) -> Self {
         use TrustzoneMode::*;
@@ -869,9 +935,12 @@ bitflags::bitflags! {
 impl core::convert::TryFrom<&[u8]> for ProtectedFlash {
     type Error = ();
     fn try_from(input: &[u8]) -> ::std::result::Result<Self, Self::Error> {
-        let factory_settings = FactorySettings::try_from(&input[3 * 512..4 * 512]).unwrap();
-        let customer = CustomerSettingsArea::try_from(&input[..3 * 512]).unwrap();
-        let keystore = Keystore::try_from(&input[4 * 512..7 * 512]).unwrap();
+        if input.len() != 7 * 512 {
+            return Err(());
+        }
+        let factory_settings = FactorySettings::try_from(&input[3 * 512..4 * 512])?;
+        let customer = CustomerSettingsArea::try_from(&input[..3 * 512])?;
+        let keystore = Keystore::try_from(&input[4 * 512..7 * 512])?;
 
         let pfr = ProtectedFlash {
             customer,
@@ -946,9 +1015,12 @@ where
 impl core::convert::TryFrom<&[u8]> for CustomerSettingsArea {
     type Error = ();
     fn try_from(input: &[u8]) -> ::std::result::Result<Self, Self::Error> {
-        let scratch = CustomerSettings::try_from(&input[..512]).unwrap();
-        let ping = CustomerSettings::try_from(&input[512..2 * 512]).unwrap();
-        let pong = CustomerSettings::try_from(&input[2 * 512..3 * 512]).unwrap();
+        if input.len() != 3 * 512 {
+            return Err(());
+        }
+        let scratch = CustomerSettings::try_from(&input[..512])?;
+        let ping = CustomerSettings::try_from(&input[512..2 * 512])?;
+        let pong = CustomerSettings::try_from(&input[2 * 512..3 * 512])?;
 
         let customer_settings = CustomerSettingsArea {
             scratch,
@@ -1070,18 +1142,14 @@ impl MonotonicCounter {
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 #[repr(u8)]
+#[derive(Default)]
 pub enum RotKeyStatus {
+    #[default]
     Invalid = 0,
     Enabled = 1,
     Revoked = 3,
 }
 
-impl Default for RotKeyStatus {
-    fn default() -> Self {
-        Self::Invalid
-    }
-}
-
 impl From<u8> for RotKeyStatus {
     fn from(value: u8) -> Self {
         use RotKeyStatus::*;
@@ -1174,7 +1242,7 @@ where
 
             let mut hasher = sha2::Sha256::new();
             hasher.update(&buf[0..480]);
-            self.sha256_hash = Sha256Hash(hasher.finalize().try_into().unwrap());
+            self.sha256_hash = Sha256Hash(hasher.finalize().into());
 
             buf[480..512].as_mut().write_all(&self.sha256_hash.0).ok();
         }
@@ -1189,7 +1257,6 @@ fn parse_customer_page<
 >(
     input: &[u8],
 ) -> IResult<&[u8], CustomerSettings<CustomerData, VendorUsage>> {
-    assert!(input.len() == 512);
     let (input, header) = le_u32(input)?;
     let (input, customer_version) = le_u32(input)?;
     let (input, secure_firmware_version) = le_u32(input)?;
@@ -1224,7 +1291,6 @@ fn parse_customer_page<
 
     let (input, sha256_hash) = take(32u8)(input)?;
 
-    assert!(input.is_empty());
     let page = CustomerSettings {
         header: Header(header),
         customer_version: MonotonicCounter::from(customer_version),
@@ -1257,8 +1323,10 @@ where
 {
     type Error = ();
     fn try_from(input: &[u8]) -> ::std::result::Result<Self, Self::Error> {
-        let (_input, page) = parse_customer_page(input).unwrap();
-        Ok(page)
+        match parse_customer_page(input) {
+            Ok(([], page)) => Ok(page),
+            _ => Err(()),
+        }
     }
 }
 
@@ -1270,15 +1338,17 @@ where
 {
     type Error = ();
     fn try_from(input: &[u8]) -> ::std::result::Result<Self, Self::Error> {
-        let (_input, page) = parse_factory(input).unwrap();
-        Ok(page)
+        match parse_factory(input) {
+            Ok(([], page)) => Ok(page),
+            _ => Err(()),
+        }
     }
 }
 
 impl core::convert::TryFrom<&[u8]> for Keystore {
     type Error = ();
     fn try_from(input: &[u8]) -> ::std::result::Result<Self, Self::Error> {
-        let (_input, keystore) = parse_keystore(input).unwrap();
+        let (_input, keystore) = parse_keystore(input).map_err(|_| ())?;
         Ok(keystore)
     }
 }
@@ -19,8 +19,7 @@ pub enum DebugAccess {
     Disabled,
     /// Debugging access is possible
     Enabled,
-    /// Debugger must present a Debug Credential (this functionality
-    /// is not currently implemented or further exposed).
+    /// Debugger must present a Debug Credential, cf. `debug_auth`.
     Authenticate,
     /// "Bring your own settings"
     Custom(DebugSettings),
@@ -243,6 +242,56 @@ impl DebugSettings {
     pub fn are_all_default(&self) -> bool {
         *self == DebugAccess::Default.into()
     }
+
+    /// The `CC_SOCU` bits of a Debug Credential granting the settings that are `Enabled`.
+    ///
+    /// Same bit positions as in the PFR; `check_uuid` is a device setting, not a permission.
+    pub fn credential_permissions(&self) -> u32 {
+        self.with_bits()
+            .iter()
+            .filter(|(setting, _)| *setting == DebugSetting::Enabled)
+            .fold(0, |permissions, (_, bit)| permissions | (1 << bit))
+    }
+
+    /// Inverse of [`Self::credential_permissions`].
+    pub fn from_credential_permissions(permissions: u32) -> Self {
+        let mut settings = Self::from(DebugAccess::Disabled);
+        settings.check_uuid = false;
+        let setting = |bit: usize| {
+            if (permissions >> bit) & 1 != 0 {
+                DebugSetting::Enabled
+            } else {
+                DebugSetting::Disabled
+            }
+        };
+        settings.nonsecure_noninvasive = setting(0);
+        settings.nonsecure_invasive = setting(1);
+        settings.secure_noninvasive = setting(2);
+        settings.secure_invasive = setting(3);
+        settings.jtag_tap = setting(4);
+        settings.cpu1_invasive = setting(5);
+        settings.isp_boot_command = setting(6);
+        settings.fault_analysis_command = setting(7);
+        settings.flash_mass_erase_command = setting(8);
+        settings.cpu1_noninvasive = setting(9);
+        settings
+    }
+
+    /// The settings with their bit position in `CC_SOCU_PIN` / `CC_SOCU_DFLT`.
+    fn with_bits(&self) -> [(DebugSetting, usize); 10] {
+        [
+            (self.nonsecure_noninvasive, 0),
+            (self.nonsecure_invasive, 1),
+            (self.secure_noninvasive, 2),
+            (self.secure_invasive, 3),
+            (self.jtag_tap, 4),
+            (self.cpu1_invasive, 5),
+            (self.isp_boot_command, 6),
+            (self.fault_analysis_command, 7),
+            (self.flash_mass_erase_command, 8),
+            (self.cpu1_noninvasive, 9),
+        ]
+    }
 }
 
 impl From<DebugAccess> for DebugSettings {
@@ -332,21 +381,10 @@ impl From<DebugSettings> for [u32; 2] {
         let mut fixed: u32 = 0;
         let mut enabled: u32 = 0;
 
-        let mut set_bits = |setting: DebugSetting, bit: usize| {
+        for (setting, bit) in settings.with_bits() {
             fixed |= setting.fixed_bit() << bit;
             enabled |= setting.enabled_bit() << bit;
-        };
-
-        set_bits(settings.nonsecure_noninvasive, 0);
-        set_bits(settings.nonsecure_invasive, 1);
-        set_bits(settings.secure_noninvasive, 2);
-        set_bits(settings.secure_invasive, 3);
-        set_bits(settings.jtag_tap, 4);
-        set_bits(settings.cpu1_invasive, 5);
-        set_bits(settings.isp_boot_command, 6);
-        set_bits(settings.fault_analysis_command, 7);
-        set_bits(settings.flash_mass_erase_command, 8);
-        set_bits(settings.cpu1_noninvasive, 9);
+        }
 
         fixed |= (settings.check_uuid as u32) << 15;
 
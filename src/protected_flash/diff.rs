@@ -0,0 +1,270 @@
+//! Field-level comparison of two protected flash (PFR) snapshots.
+//!
+//! Fields are named by their dotted path in the serialized form (as in `lpc55 pfr`), e.g.
+//! `factory-settings.secure-boot-configuration.secure-boot-enabled`. Packed bitfields are
+//! expanded: debug access into the individual `DebugSettings`, PRINCE subregions into their
+//! flags. Of the keystore, only the headers are compared, not the key material.
+
+use core::fmt;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{DebugSettings, Keycode, ProtectedFlash};
+
+/// A field that differs between two snapshots.
+///
+/// `None` means the field has its default value (which is not serialized).
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Change {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |value: &Option<String>| value.clone().unwrap_or_else(|| "(default)".into());
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.field,
+            value(&self.before),
+            value(&self.after)
+        )
+    }
+}
+
+/// Result of [`ProtectedFlash::diff`], ordered by field.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Diff(pub Vec<Change>);
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.0 {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+impl ProtectedFlash {
+    /// Compares factory settings, the scratch, ping and pong customer pages,
+    /// and the keystore headers.
+    pub fn diff(&self, other: &ProtectedFlash) -> Diff {
+        let before = fields(self);
+        let mut after = fields(other);
+
+        let mut changes = Vec::new();
+        for (field, value) in before {
+            let other_value = after.remove(&field);
+            if other_value.as_ref() != Some(&value) {
+                changes.push(Change {
+                    field,
+                    before: Some(value),
+                    after: other_value,
+                });
+            }
+        }
+        changes.extend(after.into_iter().map(|(field, value)| Change {
+            field,
+            before: None,
+            after: Some(value),
+        }));
+        changes.sort_by(|a, b| a.field.cmp(&b.field));
+
+        Diff(changes)
+    }
+}
+
+fn fields(pfr: &ProtectedFlash) -> BTreeMap<String, String> {
+    let mut fields = BTreeMap::new();
+
+    let factory_settings = &pfr.factory_settings;
+    let mut factory = serde_json::to_value(factory_settings).unwrap();
+    factory["debug-access"] =
+        serde_json::to_value(DebugSettings::from(factory_settings.debug_access)).unwrap();
+    factory["prince-subregions"] = factory_settings
+        .prince_subregions
+        .iter()
+        .map(|subregion| format!("{:?}", subregion))
+        .collect();
+    flatten("factory-settings", &factory, &mut fields);
+
+    let customer = &pfr.customer;
+    for (name, page) in [
+        ("scratch", &customer.scratch),
+        ("ping", &customer.ping),
+        ("pong", &customer.pong),
+    ] {
+        let mut settings = serde_json::to_value(page).unwrap();
+        settings["debug-access"] = serde_json::to_value(page.debug_settings()).unwrap();
+        flatten(&format!("customer.{}", name), &settings, &mut fields);
+    }
+
+    let keystore = &pfr.keystore;
+    let mut insert = |field: &str, value: String| {
+        fields.insert(format!("keystore.{}", field), value);
+    };
+    insert("header", format!("0x{:08x}", keystore.header.0));
+    insert(
+        "puf-discharge-time-milliseconds",
+        keystore.puf_discharge_time_milliseconds.to_string(),
+    );
+    for (name, keycode) in [
+        ("secure-boot-kek", &keystore.secure_boot_kek),
+        ("user-key", &keystore.user_key),
+        ("unique-device-secret", &keystore.unique_device_secret),
+        ("prince-region-0", &keystore.prince_region_0),
+        ("prince-region-1", &keystore.prince_region_1),
+        ("prince-region-2", &keystore.prince_region_2),
+    ] {
+        insert(name, keycode_header(keycode));
+    }
+
+    fields
+}
+
+/// The first eight bytes of a keycode, cf. the notes on `Keycode`.
+fn keycode_header(keycode: &Keycode) -> String {
+    if !keycode.valid() {
+        return "invalid".into();
+    }
+    let kind = if keycode.generated_key() {
+        "generated"
+    } else if keycode.user_key() {
+        "user"
+    } else {
+        "unknown"
+    };
+    format!("{} ({})", kind, hex::encode(&keycode.as_ref()[..8]))
+}
+
+fn flatten(path: &str, value: &Value, fields: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                flatten(&format!("{}.{}", path, key), value, fields);
+            }
+        }
+        // byte arrays (e.g. PRINCE IV codes) are compared as a whole
+        Value::Array(array) if array.len() > 4 && array.iter().all(is_byte) => {
+            let bytes: Vec<u8> = array
+                .iter()
+                .map(|byte| byte.as_u64().unwrap() as u8)
+                .collect();
+            fields.insert(path.to_string(), hex::encode(bytes));
+        }
+        Value::Array(array) => {
+            for (i, value) in array.iter().enumerate() {
+                flatten(&format!("{}[{}]", path, i), value, fields);
+            }
+        }
+        Value::String(string) => {
+            fields.insert(path.to_string(), string.clone());
+        }
+        value => {
+            fields.insert(path.to_string(), value.to_string());
+        }
+    }
+}
+
+fn is_byte(value: &Value) -> bool {
+    value.as_u64().is_some_and(|value| value <= 0xff)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protected_flash::{
+        DebugAccess, MonotonicCounter, PrinceSubregion, RotKeyStatus, RotKeysStatus,
+    };
+
+    fn empty() -> ProtectedFlash {
+        ProtectedFlash {
+            customer: Default::default(),
+            factory_settings: Default::default(),
+            keystore: Default::default(),
+        }
+    }
+
+    fn fields(diff: &Diff) -> Vec<&str> {
+        diff.0.iter().map(|change| change.field.as_str()).collect()
+    }
+
+    #[test]
+    fn identical() {
+        assert!(empty().diff(&empty()).is_empty());
+    }
+
+    #[test]
+    fn named_fields() {
+        let before = empty();
+        let mut after = empty();
+        let factory = &mut after.factory_settings;
+        factory.secure_boot_configuration.secure_boot_enabled = true;
+        factory.debug_access = DebugAccess::Disabled;
+        factory.prince_subregions[1] = PrinceSubregion::REGION_00 | PrinceSubregion::REGION_01;
+        after.customer.pong.customer_version = MonotonicCounter::from(2);
+        after.customer.pong.rot_keys_status = RotKeysStatus([
+            RotKeyStatus::Enabled,
+            RotKeyStatus::Invalid,
+            RotKeyStatus::Invalid,
+            RotKeyStatus::Invalid,
+        ]);
+
+        let diff = before.diff(&after);
+        assert!(fields(&diff).contains(&"factory-settings.debug-access.jtag-tap"));
+        assert!(fields(&diff).contains(&"factory-settings.prince-subregions[1]"));
+        assert!(fields(&diff).contains(&"customer.pong.rot-keys-status[0]"));
+
+        let change = diff
+            .0
+            .iter()
+            .find(|change| {
+                change.field == "factory-settings.secure-boot-configuration.secure-boot-enabled"
+            })
+            .unwrap();
+        assert_eq!(change.before, None);
+        assert_eq!(change.after.as_deref(), Some("true"));
+
+        let change = diff
+            .0
+            .iter()
+            .find(|change| change.field == "customer.pong.customer-version")
+            .unwrap();
+        assert_eq!(
+            change.to_string(),
+            "customer.pong.customer-version: (default) -> 2"
+        );
+
+        // symmetric
+        let reverse = after.diff(&before);
+        assert_eq!(fields(&reverse), fields(&diff));
+    }
+
+    #[test]
+    fn keystore_headers() {
+        let before = empty();
+        let mut after = empty();
+        after.keystore.header.0 = 0x9595_9595;
+        let mut keycode = [0u8; 56];
+        keycode[..8].copy_from_slice(&[0x59, 0x59, 0x59, 0x59, 0x01, 0x00, 0x00, 0x02]);
+        keycode[8..].copy_from_slice(&[0x42; 48]);
+        after.keystore.secure_boot_kek = Keycode(keycode);
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff.to_string(),
+            "keystore.header: 0x00000000 -> 0x95959595\n\
+             keystore.secure-boot-kek: invalid -> generated (5959595901000002)\n"
+        );
+    }
+}
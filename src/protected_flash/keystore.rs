@@ -0,0 +1,359 @@
+//! Decoded view of the PUF keystore, for audits.
+//!
+//! The keycode headers are decoded (cf. [`KeycodeHeader`]) and cross-checked against the
+//! keystore header and the requirements of the key's consumer. The activation code format
+//! is undocumented, so only whether it's present and a fingerprint (to compare snapshots)
+//! are reported.
+//!
+//! A [`KeystoreBackup`] binds a keystore snapshot to the device it was taken from: activation
+//! and key codes are only meaningful for the PUF of that particular die.
+
+use core::fmt;
+
+use anyhow::anyhow;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::rules::{Finding, Report};
+use super::{KeyKind, KeycodeHeader, Keystore};
+use crate::bootloader::command::{Key, KEYSTORE_KEY_NAMES};
+use crate::crypto::sha256;
+
+/// Result of [`Keystore::report`].
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct KeystoreReport {
+    pub header: u32,
+    /// Header is [`Keystore::ENROLLED`]
+    pub enrolled: bool,
+    /// First 8 bytes of the activation code's SHA256, `None` if blank
+    pub activation_code: Option<String>,
+    pub keys: Vec<KeyReport>,
+    #[serde(skip)]
+    pub findings: Report,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct KeyReport {
+    /// Name as in `keystore` commands and the serialized `Keystore`
+    pub name: &'static str,
+    pub key: Key,
+    /// `None` if the keycode is not valid, i.e. the key is not set
+    pub header: Option<KeycodeHeader>,
+}
+
+impl Keystore {
+    pub fn report(&self) -> KeystoreReport {
+        let mut findings = Report::default();
+        let enrolled = self.header == Self::ENROLLED;
+        let activation_code = self.activation_code.as_ref();
+        let blank = activation_code.iter().all(|&byte| byte == 0)
+            || activation_code.iter().all(|&byte| byte == 0xff);
+
+        if enrolled && blank {
+            findings.error(
+                "activation-code",
+                "keystore header marks the PUF as enrolled, but the activation code is blank"
+                    .into(),
+            );
+        }
+        if !enrolled && self.header.0 != 0 {
+            findings.error(
+                "keystore-header",
+                format!(
+                    "unknown keystore header 0x{:08x}, expected 0x{:08x}",
+                    self.header.0,
+                    Self::ENROLLED.0
+                ),
+            );
+        }
+
+        let mut keys = Vec::new();
+        for name in KEYSTORE_KEY_NAMES {
+            let key = Key::try_from(name).unwrap();
+            let keycode = self.keycode(key);
+            let header = keycode.header();
+            match header {
+                Some(header) => check_keycode(name, key, &header, enrolled, &mut findings),
+                None if !keycode.is_blank() => findings.warning(
+                    "keycode-marker",
+                    format!("{} keycode contains data, but no valid marker", name),
+                ),
+                None => {}
+            }
+            keys.push(KeyReport { name, key, header });
+        }
+
+        KeystoreReport {
+            header: self.header.0,
+            enrolled,
+            activation_code: (!blank).then(|| hex::encode(&sha256(activation_code)[..8])),
+            keys,
+            findings,
+        }
+    }
+}
+
+/// Key sizes the consumers of the keys need, where known.
+fn expected_size(key: Key) -> Option<usize> {
+    match key {
+        // PRINCE keys are 128 bit
+        Key::PrinceRegion0 | Key::PrinceRegion1 | Key::PrinceRegion2 => Some(16),
+        // AES-256 key wrap of the SB2.1 keyblob
+        Key::SecureBootKek => Some(32),
+        Key::UniqueDeviceSecret | Key::UserPsk => None,
+    }
+}
+
+fn check_keycode(
+    name: &str,
+    key: Key,
+    header: &KeycodeHeader,
+    enrolled: bool,
+    findings: &mut Report,
+) {
+    if !enrolled {
+        findings.error(
+            "keycode-without-puf",
+            format!(
+                "{} keycode is valid, but the keystore header does not mark the PUF as enrolled",
+                name
+            ),
+        );
+    }
+    if let KeyKind::Unknown(kind) = header.kind {
+        findings.warning(
+            "keycode-header",
+            format!("{} keycode has unknown key type 0x{:02x}", name, kind),
+        );
+    }
+    if header.index > 15 {
+        findings.error(
+            "keycode-header",
+            format!(
+                "{} keycode has key index {}, the PUF has 16",
+                name, header.index
+            ),
+        );
+    }
+    match expected_size(key) {
+        Some(size) if size != header.size => findings.error(
+            "key-size",
+            format!(
+                "{} is {} bytes, but needs to be {} bytes",
+                name, header.size, size
+            ),
+        ),
+        None if header.size == 0 => {
+            findings.error("key-size", format!("{} keycode has key size 0", name))
+        }
+        _ => {}
+    }
+}
+
+impl KeystoreReport {
+    pub fn is_ok(&self) -> bool {
+        self.findings.is_ok()
+    }
+
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings.0
+    }
+}
+
+impl fmt::Display for KeystoreReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "header: 0x{:08x} ({})",
+            self.header,
+            if self.enrolled {
+                "enrolled"
+            } else {
+                "not enrolled"
+            }
+        )?;
+        match &self.activation_code {
+            Some(fingerprint) => {
+                writeln!(f, "activation code: present (sha256 {}...)", fingerprint)?
+            }
+            None => writeln!(f, "activation code: blank")?,
+        }
+        for key in &self.keys {
+            write!(f, "{} ({}): ", key.name, key.key as u8)?;
+            match &key.header {
+                Some(header) => writeln!(
+                    f,
+                    "{} key, index {}, {} bytes",
+                    header.kind, header.index, header.size
+                )?,
+                None => writeln!(f, "not set")?,
+            }
+        }
+        for finding in self.findings() {
+            writeln!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keystore snapshot bound to a device UUID, cf. `Bootloader::backup_keystore`.
+///
+/// File format (little-endian): magic `LKSB`, format version (u32), device UUID (16 bytes, as
+/// reported by the bootloader), keystore (`Keystore::to_bytes`), SHA256 of all of the above.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeystoreBackup {
+    pub uuid: Uuid,
+    pub keystore: Keystore,
+}
+
+impl KeystoreBackup {
+    pub const MAGIC: [u8; 4] = *b"LKSB";
+    pub const VERSION: u32 = 1;
+    pub const SIZE: usize = 4 + 4 + 16 + 3 * 512 + 32;
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(Self::SIZE);
+        data.extend_from_slice(&Self::MAGIC);
+        data.extend_from_slice(&Self::VERSION.to_le_bytes());
+        data.extend_from_slice(self.uuid.as_bytes());
+        data.extend_from_slice(&self.keystore.to_bytes());
+        let digest = sha256(&data);
+        data.extend_from_slice(&digest);
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < 8 || data[..4] != Self::MAGIC {
+            return Err(anyhow!("not a keystore backup"));
+        }
+        let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        if version != Self::VERSION {
+            return Err(anyhow!(
+                "unsupported keystore backup version {}, expected {}",
+                version,
+                Self::VERSION
+            ));
+        }
+        if data.len() != Self::SIZE {
+            return Err(anyhow!(
+                "keystore backup has {} bytes, expected {}",
+                data.len(),
+                Self::SIZE
+            ));
+        }
+        let (data, digest) = data.split_at(Self::SIZE - 32);
+        if sha256(data) != digest {
+            return Err(anyhow!("keystore backup is corrupted (checksum mismatch)"));
+        }
+        Ok(Self {
+            uuid: Uuid::from_slice(&data[8..24])?,
+            keystore: Keystore::try_from(&data[24..]).unwrap(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protected_flash::{ActivationCode, Keycode, KeystoreHeader};
+
+    fn keycode(header: [u8; 4]) -> Keycode {
+        let mut keycode = [0x42u8; 56];
+        keycode[..4].copy_from_slice(&[0x59; 4]);
+        keycode[4..8].copy_from_slice(&header);
+        Keycode(keycode)
+    }
+
+    fn rules(report: &KeystoreReport) -> Vec<&'static str> {
+        report
+            .findings()
+            .iter()
+            .map(|finding| finding.rule)
+            .collect()
+    }
+
+    #[test]
+    fn keycode_headers() {
+        let header = keycode([0x01, 0x0f, 0x00, 0x04]).header().unwrap();
+        assert_eq!(header.kind, KeyKind::Generated);
+        assert_eq!(header.index, 15);
+        assert_eq!(header.size, 32);
+
+        let header = keycode([0x00, 0x00, 0x00, 0x02]).header().unwrap();
+        assert_eq!(header.kind, KeyKind::User);
+        assert_eq!(header.size, 16);
+
+        assert_eq!(Keycode::default().header(), None);
+    }
+
+    #[test]
+    fn empty() {
+        let report = Keystore::default().report();
+        assert!(report.is_ok());
+        assert!(report.findings().is_empty());
+        assert!(!report.enrolled);
+        assert_eq!(report.activation_code, None);
+        assert!(report.keys.iter().all(|key| key.header.is_none()));
+    }
+
+    #[test]
+    fn backup() {
+        let backup = KeystoreBackup {
+            uuid: Uuid::from_u128(0x0011_2233_4455_6677_8899_aabb_ccdd_eeff),
+            keystore: Keystore {
+                header: Keystore::ENROLLED,
+                activation_code: ActivationCode([0x17; 1192]),
+                prince_region_0: keycode([0x01, 0x00, 0x00, 0x02]),
+                ..Default::default()
+            },
+        };
+        let data = backup.to_bytes();
+        assert_eq!(data.len(), KeystoreBackup::SIZE);
+        assert_eq!(KeystoreBackup::from_bytes(&data).unwrap(), backup);
+
+        let mut corrupted = data.clone();
+        corrupted[100] ^= 1;
+        assert!(KeystoreBackup::from_bytes(&corrupted).is_err());
+        assert!(KeystoreBackup::from_bytes(&data[..data.len() - 1]).is_err());
+        assert!(KeystoreBackup::from_bytes(&[0u8; KeystoreBackup::SIZE]).is_err());
+    }
+
+    #[test]
+    fn consistency() {
+        let mut keystore = Keystore {
+            header: Keystore::ENROLLED,
+            activation_code: ActivationCode([0x17; 1192]),
+            secure_boot_kek: keycode([0x00, 0x00, 0x00, 0x04]),
+            unique_device_secret: keycode([0x01, 0x0f, 0x00, 0x04]),
+            prince_region_0: keycode([0x01, 0x00, 0x00, 0x02]),
+            ..Default::default()
+        };
+        let report = keystore.report();
+        assert_eq!(rules(&report), Vec::<&str>::new());
+        assert!(report.activation_code.is_some());
+        assert_eq!(
+            report.to_string().lines().nth(2),
+            Some("secure-boot-kek (3): user key, index 0, 32 bytes")
+        );
+
+        // 32 byte PRINCE key, garbage in user key, not enrolled
+        keystore.prince_region_1 = keycode([0x01, 0x00, 0x00, 0x04]);
+        keystore.user_key = Keycode([0x42; 56]);
+        keystore.header = KeystoreHeader(0);
+        let report = keystore.report();
+        assert!(!report.is_ok());
+        assert_eq!(
+            rules(&report),
+            [
+                "keycode-without-puf",
+                "keycode-marker",
+                "keycode-without-puf",
+                "keycode-without-puf",
+                "keycode-without-puf",
+                "key-size",
+            ]
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! Sanity checks for protected flash (PFR) settings, before writing them.
+//!
+//! Loosely modeled after the rules of spsdk (`spsdk/data/pfr/rules.json`): some settings
+//! render the device unbootable, or are irreversible once written. The checks cross-check
+//! factory (CMPA) and customer (CFPA) settings against each other, and against what is
+//! currently on the device, if available.
+
+use core::fmt;
+
+use super::{CustomerSettings, FactorySettings, ProtectedFlash, RotKeyStatus};
+use crate::pki::Sha256Hash;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// Probably intended, but worth a second look
+    Warning,
+    /// Writing would fail, brick the device or lock it in an unintended state
+    Error,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Short kebab-case identifier of the rule
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{} [{}]: {}", severity, self.rule, self.message)
+    }
+}
+
+/// Findings of [`Rules::check`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Report(pub Vec<Finding>);
+
+impl Report {
+    pub fn errors(&self) -> impl Iterator<Item = &Finding> {
+        self.0.iter().filter(|f| f.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Finding> {
+        self.0.iter().filter(|f| f.severity == Severity::Warning)
+    }
+
+    /// No errors, warnings are allowed.
+    pub fn is_ok(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub(super) fn error(&mut self, rule: &'static str, message: String) {
+        self.0.push(Finding {
+            severity: Severity::Error,
+            rule,
+            message,
+        });
+    }
+
+    pub(super) fn warning(&mut self, rule: &'static str, message: String) {
+        self.0.push(Finding {
+            severity: Severity::Warning,
+            rule,
+            message,
+        });
+    }
+}
+
+/// Settings to be written, and the current state of the device.
+///
+/// Leave out what is not known, the corresponding checks are skipped.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rules<'a> {
+    /// Factory settings (CMPA) to be written
+    pub factory_settings: Option<&'a FactorySettings>,
+    /// Customer settings (CFPA) to be written
+    pub customer_settings: Option<&'a CustomerSettings>,
+    /// Protected flash as currently on the device
+    pub device: Option<&'a ProtectedFlash>,
+}
+
+impl Rules<'_> {
+    pub fn check(&self) -> Report {
+        let mut report = Report::default();
+
+        // the settings in effect after writing
+        let device_customer = self.device.map(|device| device.customer.most_recent());
+        let factory = self
+            .factory_settings
+            .or_else(|| self.device.map(|device| &device.factory_settings));
+        let customer = self.customer_settings.or(device_customer.as_ref());
+
+        if let Some(factory) = self.factory_settings {
+            self.check_factory_settings(factory, &mut report);
+        }
+        if let Some(factory) = factory {
+            let secure_boot = factory.secure_boot_configuration.secure_boot_enabled;
+            match customer {
+                Some(customer)
+                    if secure_boot
+                        && !customer.rot_keys_status.0.contains(&RotKeyStatus::Enabled) =>
+                {
+                    report.error(
+                        "secure-boot-needs-rot-key",
+                        "secure boot is enabled, but none of the RoT keys is enabled".into(),
+                    )
+                }
+                Some(_) => {}
+                None if secure_boot => report.warning(
+                    "secure-boot-needs-rot-key",
+                    "secure boot is enabled, but RoT key status is unknown".into(),
+                ),
+                _ => {}
+            }
+        }
+        if let (Some(customer), Some(device)) = (self.customer_settings, device_customer.as_ref()) {
+            check_customer_update(customer, device, &mut report);
+        }
+        if self.customer_settings.is_some() && factory.is_none() {
+            report.warning(
+                "checks-skipped",
+                "neither factory settings nor the device are available, the RoT key and monotonic counter checks were skipped".into(),
+            );
+        }
+
+        report
+    }
+
+    fn check_factory_settings(&self, factory: &FactorySettings, report: &mut Report) {
+        let secure_boot = &factory.secure_boot_configuration;
+        if !secure_boot.dice_computation_disabled {
+            report.error(
+                "dice-disabled",
+                "DICE computation is enabled, it needs an enrolled PUF and breaks booting otherwise"
+                    .into(),
+            );
+        }
+        if secure_boot.secure_boot_enabled && factory.rot_fingerprint == Sha256Hash::default() {
+            report.error(
+                "secure-boot-needs-rotkh",
+                "secure boot is enabled, but the RoT fingerprint (ROTKH) is empty".into(),
+            );
+        }
+        if factory.seal {
+            report.warning(
+                "seal",
+                "factory settings will be sealed, they can not be changed afterwards".into(),
+            );
+        }
+        if let Some(device) = self.device {
+            if device.factory_settings.is_sealed() {
+                report.error(
+                    "factory-sealed",
+                    "factory settings on the device are sealed, writing them will fail".into(),
+                );
+            }
+        }
+    }
+}
+
+fn check_customer_update(
+    customer: &CustomerSettings,
+    device: &CustomerSettings,
+    report: &mut Report,
+) {
+    let counters = [
+        (
+            "customer version",
+            customer.customer_version,
+            device.customer_version,
+        ),
+        (
+            "secure firmware version",
+            customer.secure_firmware_version,
+            device.secure_firmware_version,
+        ),
+        (
+            "nonsecure firmware version",
+            customer.nonsecure_firmware_version,
+            device.nonsecure_firmware_version,
+        ),
+        (
+            "image key revocation ID",
+            customer.image_key_revocation_id,
+            device.image_key_revocation_id,
+        ),
+    ];
+    for (name, new, current) in counters {
+        if new.read() < current.read() {
+            report.error(
+                "monotonic-counters",
+                format!(
+                    "{} would decrease from {} to {}, which the ROM rejects",
+                    name,
+                    current.read(),
+                    new.read()
+                ),
+            );
+        }
+    }
+    if customer.customer_version.read() == device.customer_version.read() {
+        report.error(
+            "customer-version-increment",
+            format!(
+                "customer version {} is not greater than the device's, the update is ignored",
+                customer.customer_version.read()
+            ),
+        );
+    }
+
+    for (i, (new, current)) in customer
+        .rot_keys_status
+        .0
+        .iter()
+        .zip(device.rot_keys_status.0.iter())
+        .enumerate()
+    {
+        match (current, new) {
+            (RotKeyStatus::Revoked, RotKeyStatus::Revoked) => {}
+            (RotKeyStatus::Revoked, _) => report.error(
+                "rot-key-revocation",
+                format!("RoT key {} is revoked, this can not be undone", i),
+            ),
+            (RotKeyStatus::Enabled, RotKeyStatus::Revoked) => report.warning(
+                "rot-key-revocation",
+                format!("RoT key {} will be revoked permanently", i),
+            ),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protected_flash::{MonotonicCounter, RotKeysStatus};
+
+    fn factory_settings() -> FactorySettings {
+        let mut factory = FactorySettings::default();
+        factory.secure_boot_configuration.dice_computation_disabled = true;
+        factory.secure_boot_configuration.secure_boot_enabled = true;
+        factory.rot_fingerprint = Sha256Hash([0x42; 32]);
+        factory
+    }
+
+    fn rules(report: &Report) -> Vec<&'static str> {
+        report.0.iter().map(|finding| finding.rule).collect()
+    }
+
+    #[test]
+    fn factory_settings_only() {
+        let factory = factory_settings();
+        let report = Rules {
+            factory_settings: Some(&factory),
+            ..Default::default()
+        }
+        .check();
+        assert!(report.is_ok());
+        assert_eq!(rules(&report), ["secure-boot-needs-rot-key"]);
+
+        let mut factory = FactorySettings::default();
+        factory.secure_boot_configuration.secure_boot_enabled = true;
+        let report = Rules {
+            factory_settings: Some(&factory),
+            ..Default::default()
+        }
+        .check();
+        assert!(!report.is_ok());
+        assert_eq!(
+            rules(&report),
+            [
+                "dice-disabled",
+                "secure-boot-needs-rotkh",
+                "secure-boot-needs-rot-key"
+            ]
+        );
+    }
+
+    #[test]
+    fn secure_boot_needs_enabled_rot_key() {
+        let factory = factory_settings();
+        let mut customer = CustomerSettings::default();
+        let check = |customer: &CustomerSettings| {
+            Rules {
+                factory_settings: Some(&factory),
+                customer_settings: Some(customer),
+                device: None,
+            }
+            .check()
+        };
+        assert!(!check(&customer).is_ok());
+
+        customer.rot_keys_status = RotKeysStatus([
+            RotKeyStatus::Revoked,
+            RotKeyStatus::Enabled,
+            RotKeyStatus::Invalid,
+            RotKeyStatus::Invalid,
+        ]);
+        assert_eq!(check(&customer), Report::default());
+    }
+
+    #[test]
+    fn customer_settings_only() {
+        let customer = CustomerSettings::default();
+        let report = Rules {
+            customer_settings: Some(&customer),
+            ..Default::default()
+        }
+        .check();
+        assert!(report.is_ok());
+        assert_eq!(rules(&report), ["checks-skipped"]);
+    }
+
+    #[test]
+    fn customer_update_against_device() {
+        let mut device = ProtectedFlash {
+            customer: Default::default(),
+            factory_settings: Default::default(),
+            keystore: Default::default(),
+        };
+        device.customer.ping.customer_version = MonotonicCounter::from(3);
+        device.customer.ping.secure_firmware_version = MonotonicCounter::from(5);
+        device.customer.ping.rot_keys_status = RotKeysStatus([RotKeyStatus::Revoked; 4]);
+
+        let mut customer = device.customer.ping;
+        customer.customer_version.increment();
+        let report = Rules {
+            customer_settings: Some(&customer),
+            device: Some(&device),
+            ..Default::default()
+        }
+        .check();
+        assert_eq!(report, Report::default());
+
+        customer.secure_firmware_version = MonotonicCounter::from(4);
+        customer.rot_keys_status = RotKeysStatus([RotKeyStatus::Enabled; 4]);
+        let report = Rules {
+            customer_settings: Some(&customer),
+            device: Some(&device),
+            ..Default::default()
+        }
+        .check();
+        assert_eq!(report.errors().count(), 5);
+        assert_eq!(rules(&report)[0], "monotonic-counters");
+    }
+}
@@ -12,7 +12,7 @@
 //! - ROT fingerprints (8 blocks, 4x32B = 128B)
 //! - Signature (16 blocks, 256B = 2048 bits)
 //!
-//! Sb21CommandPart:
+//! Sb21CommandPart (once per boot section, cf. [`Config::sections`]):
 //! - encrypted boot tag (16B)
 //! - boot tag HMAC (32B = HMAC(encrypted boot tag))
 //! - section HMAC (32B = HMAC(encrypted command section))
@@ -20,7 +20,8 @@
 //!
 //! Key blob is the AES-keywrap (with SBKEK) of a 32B "data encryption key" (DEK)
 //! and a 32B "message authentication key" (MAC). Keywrap adds an 8B tag, which is
-//! further block padded with 8 zeros to 80B.
+//! further block padded with 8 zeros to 80B. Unless in confidential mode (cf. [`Confidentiality`]),
+//! the SBKEK is well-known and DEK and MAC are all zeros.
 //!
 //! The RSA2k signature is over all that precedes it, in particular the HMAC of the
 //! HMACs of the command part.
@@ -32,7 +33,7 @@ use std::fs;
 
 use anyhow::{Context as _, Result};
 pub use chrono::naive::NaiveDate;
-use rsa::{pkcs1::FromRsaPublicKey, PublicKeyParts};
+use rsa::{pkcs1::DecodeRsaPublicKey, PublicKeyParts};
 use serde::{Deserialize, Serialize};
 use x509_parser::{certificate::X509Certificate, traits::FromDer};
 
@@ -40,23 +41,31 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take, take_while_m_n},
     combinator::{map, value, verify},
-    multi::fill,
+    multi::{fill, length_data},
     number::complete::{be_u16, be_u32, le_u128, le_u16, le_u32, le_u64, u8},
     sequence::tuple,
 };
 
 use crate::crypto::{crc32, hmac, nxp_aes_ctr_cipher, sha256};
-use crate::pki::{CertificateSlot, Certificates, Pki, Sha256Hash, SigningKey, SigningKeySource};
+use crate::pki::{
+    Certificate, CertificateChain, CertificateSlot, Certificates, Pki, SecretKeySource, Sha256Hash,
+    SigningKey, SigningKeySource,
+};
 use crate::protected_flash::{CustomerSettings, FactorySettings};
+use crate::signed_binary::trustzone::{self, TzPreset};
+use crate::signed_binary::ImageType;
 use crate::util::{
     hex_deserialize_256, hex_deserialize_32, hex_serialize, is_default, word_pad_len, word_padded,
 };
 use signature::Signature as _;
 
+pub mod bd;
 pub mod command;
+pub mod import;
+pub mod sb31;
 
 use command::{
-    BootCommand, BootCommandDescription, BootCommandSequenceDescription,
+    BootCommand, BootCommandDescription, BootCommandSequenceDescription, BootSectionDescription,
     SingleBootCommandDescription,
 };
 
@@ -83,10 +92,216 @@ pub struct Config {
     #[serde(skip_serializing_if = "is_default")]
     pub customer_settings: CustomerSettings,
 
-    /// Commands and command sequences for the SB file
+    /// Confidential SB2.1 files with random keys
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub confidentiality: Confidentiality,
+
+    /// Keys as provisioned in the device's keystore
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub keystore: KeystoreKeys,
+
+    /// TrustZone-M image type and preset of signed images
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub trust_zone: TrustZone,
+
+    /// Commands and command sequences for the SB file, in boot section 0
     #[serde(default)]
     #[serde(skip_serializing_if = "is_default")]
     pub commands: Vec<BootCommandDescription>,
+
+    /// Numbered boot sections, alternatively to `commands`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub sections: Vec<BootSectionDescription>,
+}
+
+impl Config {
+    /// The configured SBKEK, or the well-known [`Keyblob::SBKEK`].
+    pub fn sbkek(&self) -> Result<[u8; 32]> {
+        self.keystore.sbkek()
+    }
+
+    /// The `[reproducibility]` section, with random DEK, MAC key and nonce in confidential mode.
+    ///
+    /// In confidential mode, values that are set (e.g., from an unsealed sidecar) are kept,
+    /// and a SBKEK other than the well-known one is required.
+    pub fn reproducibility(&self) -> Result<Reproducibility> {
+        let mut reproducibility = self.reproducibility.clone();
+        if !self.confidentiality.enabled {
+            if !self.confidentiality.sidecar.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "confidentiality.sidecar is only written in confidential mode, set confidentiality.enabled"
+                ));
+            }
+            return Ok(reproducibility);
+        }
+
+        let sbkek = self.sbkek()?;
+        if self.keystore.sbkek.is_empty() || sbkek == *Keyblob::SBKEK || sbkek == [0u8; 32] {
+            return Err(anyhow::anyhow!(
+                "confidential mode needs a secret SBKEK in keystore.sbkek"
+            ));
+        }
+
+        use rand::{Rng as _, RngCore as _};
+        let mut rng = rand::rngs::OsRng;
+        if reproducibility.dek == [0u8; 32] {
+            rng.fill_bytes(&mut reproducibility.dek);
+        }
+        if reproducibility.mac == [0u8; 32] {
+            rng.fill_bytes(&mut reproducibility.mac);
+        }
+        if reproducibility.nonce == [0u32; 4] {
+            reproducibility.nonce = rng.gen();
+        }
+        if reproducibility.dek == [0u8; 32]
+            || reproducibility.mac == [0u8; 32]
+            || reproducibility.nonce == [0u32; 4]
+        {
+            return Err(anyhow::anyhow!(
+                "refusing zero DEK, MAC key or nonce in confidential mode"
+            ));
+        }
+        Ok(reproducibility)
+    }
+
+    /// The boot sections of the SB file: either `sections`, or `commands` as section 0.
+    pub fn sections(&self) -> Result<Vec<BootSectionDescription>> {
+        if self.sections.is_empty() {
+            return Ok(vec![BootSectionDescription {
+                id: 0,
+                flags: BootSectionDescription::BOOTABLE,
+                commands: self.commands.clone(),
+            }]);
+        }
+        if !self.commands.is_empty() {
+            return Err(anyhow::anyhow!(
+                "use either commands or sections, commands can be moved to a section with id 0"
+            ));
+        }
+        for (n, section) in self.sections.iter().enumerate() {
+            if self.sections[..n]
+                .iter()
+                .any(|other| other.id == section.id)
+            {
+                return Err(anyhow::anyhow!("duplicate section id {}", section.id));
+            }
+        }
+        Ok(self.sections.clone())
+    }
+}
+
+/// Confidential mode: the firmware in SB2.1 files can only be decrypted with the SBKEK.
+///
+/// Instead of the all-zero defaults, DEK, MAC key and nonce are drawn from the OS CSPRNG,
+/// cf. [`Config::reproducibility`].
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Confidentiality {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub enabled: bool,
+    /// Path to write the values drawn to, sealed with the SBKEK, cf. [`Reproducibility::seal`].
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub sidecar: String,
+}
+
+impl Confidentiality {
+    /// Where to write the sealed sidecar, if anywhere (only in confidential mode).
+    pub fn sidecar(&self) -> Option<&str> {
+        (self.enabled && !self.sidecar.is_empty()).then_some(self.sidecar.as_str())
+    }
+}
+
+/// Keys that are also set in the device's keystore (via `KeystoreOperation::SetKey`).
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct KeystoreKeys {
+    /// Source of the SBKEK (`Key::SecureBootKek`), which wraps the SB2.1 keyblob.
+    ///
+    /// Given as [`SecretKeySource`] URI, e.g. `env:SBKEK` or `file:sbkek.txt`. If left out,
+    /// the well-known [`Keyblob::SBKEK`] is used, i.e., the firmware is not confidential.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub sbkek: String,
+}
+
+impl KeystoreKeys {
+    pub fn sbkek(&self) -> Result<[u8; 32]> {
+        sbkek_from_uri(&self.sbkek)
+    }
+}
+
+/// Loads an SBKEK from a [`SecretKeySource`] URI, an empty one means [`Keyblob::SBKEK`].
+pub fn sbkek_from_uri(uri: &str) -> Result<[u8; 32]> {
+    if uri.is_empty() {
+        return Ok(*Keyblob::SBKEK);
+    }
+    SecretKeySource::try_from(uri)?
+        .load_array()
+        .context("Failed to load SBKEK")
+}
+
+/// TrustZone-M settings of signed images, cf. [`crate::signed_binary::trustzone`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct TrustZone {
+    /// `false` marks the image as not using TrustZone-M.
+    ///
+    /// NOTE: lpc55-hal's "boot to bootrom" does not work with such images.
+    #[serde(default = "TrustZone::enabled")]
+    pub enabled: bool,
+    /// Path to a TOML or YAML [`TzPreset`] the ROM applies before jumping to the image
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub preset: String,
+}
+
+impl Default for TrustZone {
+    fn default() -> Self {
+        Self {
+            enabled: Self::enabled(),
+            preset: String::new(),
+        }
+    }
+}
+
+impl TrustZone {
+    fn enabled() -> bool {
+        true
+    }
+
+    /// Loads the preset, if there is one.
+    pub fn preset(&self) -> Result<Option<TzPreset>> {
+        if self.preset.is_empty() {
+            return Ok(None);
+        }
+        if !self.enabled {
+            return Err(anyhow::anyhow!(
+                "trust-zone.preset needs TrustZone-M to be enabled"
+            ));
+        }
+        TzPreset::read(&self.preset).map(Some)
+    }
+
+    /// Image type header field (offset 0x24) of a `kind` image with these settings
+    pub fn image_type(&self, kind: ImageType) -> u32 {
+        let mut image_type = kind.code();
+        if !self.enabled {
+            image_type |= trustzone::TZM_DISABLED;
+        }
+        if !self.preset.is_empty() {
+            image_type |= trustzone::TZM_PRESET;
+        }
+        image_type
+    }
 }
 
 impl TryFrom<&'_ str> for Config {
@@ -105,13 +320,19 @@ impl TryFrom<&'_ str> for Config {
 #[serde(rename_all = "kebab-case")]
 #[serde(deny_unknown_fields)]
 pub struct Firmware {
-    /// Path to the input image (can be ~~ELF,~~ signed or unsigned BIN)
+    /// Path to the input image (can be ELF, signed or unsigned BIN)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
     pub image: String,
 
     /// Path to place signed binary
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
     pub signed_image: String,
 
     /// Path to place signed SB2.1 file
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
     pub secure_boot_image: String,
     // pub factory: FactorySettings,
     // pub customer: CustomerSettings,
@@ -120,6 +341,15 @@ pub struct Firmware {
     pub build: u32,
     pub component: Version,
     pub product: Version,
+
+    /// Image type `sign-fw` produces, signed XIP by default
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub image_type: ImageType,
+    /// Address RAM images are copied to and linked for
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub load_address: u32,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
@@ -149,7 +379,7 @@ pub struct Reproducibility {
     /// If left out, all zeros are used.
     ///
     /// This differs from vendor's `elftosb`, in order to ensure default
-    /// reproducibility, and we don't have the encrypted firmware use case.
+    /// reproducibility. With a confidential SBKEK (`keystore.sbkek`), set a random one.
     pub nonce: [u32; 4],
     #[serde(skip_serializing_if = "is_default")]
     #[serde(default)]
@@ -173,6 +403,50 @@ pub struct Reproducibility {
     pub sb_header_padding: [u8; 4],
 }
 
+impl Reproducibility {
+    const SEALED_MAGIC: [u8; 4] = *b"SBRS";
+    const SEALED_VERSION: u32 = 1;
+
+    /// AES-keywraps the values (as `[reproducibility]` TOML) with `sbkek`.
+    ///
+    /// Format: magic `SBRS`, version (u32, little-endian), wrapped TOML padded with newlines.
+    pub fn seal(&self, sbkek: &[u8; 32]) -> Vec<u8> {
+        let mut toml = toml::to_string(self).unwrap().into_bytes();
+        toml.resize(toml.len().next_multiple_of(8), b'\n');
+        let mut sealed = Self::SEALED_MAGIC.to_vec();
+        sealed.extend_from_slice(&Self::SEALED_VERSION.to_le_bytes());
+        sealed.extend_from_slice(&aes_wrap(*sbkek, &toml));
+        sealed
+    }
+
+    pub fn unseal(sealed: &[u8], sbkek: &[u8; 32]) -> Result<Self> {
+        if sealed.len() < 8 || sealed[..4] != Self::SEALED_MAGIC {
+            return Err(anyhow::anyhow!("not a sealed reproducibility sidecar"));
+        }
+        let version = u32::from_le_bytes(sealed[4..8].try_into().unwrap());
+        if version != Self::SEALED_VERSION {
+            return Err(anyhow::anyhow!("unsupported sidecar version {}", version));
+        }
+        // RFC 3394 wraps at least 16 bytes, into at least 24
+        if sealed.len() < 8 + 24 || !(sealed.len() - 8).is_multiple_of(8) {
+            return Err(anyhow::anyhow!("truncated sidecar"));
+        }
+        let toml = aes_unwrap(*sbkek, &sealed[8..])
+            .ok_or_else(|| anyhow::anyhow!("sidecar does not unwrap with the given SBKEK"))?;
+        Ok(toml::from_str(core::str::from_utf8(&toml)?)?)
+    }
+
+    /// The `[reproducibility]` section to reproduce a build.
+    pub fn to_toml(&self) -> String {
+        let mut table = toml::value::Table::new();
+        table.insert(
+            "reproducibility".into(),
+            toml::Value::try_from(self).unwrap(),
+        );
+        toml::to_string(&table).unwrap()
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum Filetype {
     Elf,
@@ -180,25 +454,33 @@ pub enum Filetype {
     SignedBin,
     Sb20,
     Sb21,
+    Sb31,
 }
 
+/// Guesses the file type from the first bytes; files too short to tell are an error.
 pub fn sniff(file: &[u8]) -> Result<Filetype> {
-    Ok(match &file[..4] {
+    let bytes = |range: core::ops::Range<usize>| {
+        file.get(range)
+            .ok_or_else(|| anyhow::anyhow!("file too short ({} bytes)", file.len()))
+    };
+    Ok(match bytes(0..4)? {
         // ELF
         b"\x7fELF" => Filetype::Elf,
+        // SB3.1
+        b"sbv3" => Filetype::Sb31,
         // BIN
         // this criterion is a bit unstable I guess.
         // https://interrupt.memfault.com/blog/zero-to-main-1
         // firmware starts with SP (4b) then PC (4B)
         // maybe: fallback to viewing as "bin" if not ELF or SB?
-        &[0x00, 0x00, 0x04, 0x20] => match file[0x20..0x24] {
+        &[0x00, 0x00, 0x04, 0x20] => match bytes(0x20..0x24)? {
             [0x00, 0x00, 0x00, 0x00] => Filetype::UnsignedBin,
             _ => Filetype::SignedBin,
         },
         _ => {
-            match &file[20..24] {
+            match bytes(20..24)? {
                 // SB2.0 or SB2.1
-                b"STMP" => match &file[52..56] {
+                b"STMP" => match bytes(52..56)? {
                     b"sgtl" => Filetype::Sb21,
                     _ => Filetype::Sb20,
                 },
@@ -239,15 +521,27 @@ pub struct UnsignedSb21File {
     pub certificates: Certificates,
     pub slot: CertificateSlot,
     pub keyblob: Keyblob,
+    /// Wraps the keyblob
+    pub sbkek: [u8; 32],
+    pub sections: Vec<Sb21Section>,
+}
+
+/// Boot section, each is preceded by its own boot tag.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sb21Section {
+    pub id: u32,
+    /// Section flags of the boot tag, cf. [`BootSectionDescription::flags`]
+    pub flags: u32,
     pub commands: Vec<BootCommand>,
 }
 
+/// Serialized boot section.
 #[derive(Clone, Debug)]
 pub struct Sb21CommandPart {
-    encrypted_boot_tag: [u8; 16],
-    unencrypted_hmac_of_encrypted_boot_tag: [u8; 32],
-    unencrypted_hmac_of_encrypted_section: [u8; 32],
-    encrypted_section: Vec<u8>,
+    pub encrypted_boot_tag: [u8; 16],
+    pub unencrypted_hmac_of_encrypted_boot_tag: [u8; 32],
+    pub unencrypted_hmac_of_encrypted_section: [u8; 32],
+    pub encrypted_section: Vec<u8>,
 }
 
 impl Sb21CommandPart {
@@ -276,12 +570,53 @@ impl Sb21CommandPart {
     }
 }
 
+/// A signed SB2.1 file, either freshly signed or decoded via [`SignedSb21File::from_bytes`].
+///
+/// Next to the serialized parts, the decrypted content is kept, so that
+/// [`verify`](SignedSb21File::verify) needs no further secrets.
 #[derive(Clone, Debug)]
 pub struct SignedSb21File {
-    pub unsigned_file: UnsignedSb21File,
     pub header_part: Sb21HeaderPart,
-    pub command_part: Sb21CommandPart,
+    /// One per section
+    pub command_parts: Vec<Sb21CommandPart>,
     pub signature: Vec<u8>,
+
+    pub keyblob: Keyblob,
+    /// Certificates in the file, the last one is the signer
+    pub certificates: CertificateChain,
+    /// One per section
+    pub boot_tags: Vec<BootCommand>,
+    pub sections: Vec<Sb21Section>,
+}
+
+/// Outcome of the checks of [`SignedSb21File::verify`].
+///
+/// Each field is `true` if the corresponding check passed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sb21Verification {
+    /// Each certificate is signed by its predecessor, the first one by itself.
+    pub certificate_chain: bool,
+    /// The fingerprint of the first certificate is one of the four RoT fingerprints.
+    pub root_of_trust: bool,
+    /// The RSA signature over the header part verifies with the last certificate.
+    pub signature: bool,
+    /// HMACs of the encrypted boot tags
+    pub boot_tag_hmac: bool,
+    /// HMACs of the encrypted boot sections
+    pub section_hmac: bool,
+    /// HMAC of the first section's two HMACs, in the (signed) header part
+    pub digest_hmac: bool,
+}
+
+impl Sb21Verification {
+    pub fn is_valid(&self) -> bool {
+        self.certificate_chain
+            && self.root_of_trust
+            && self.signature
+            && self.boot_tag_hmac
+            && self.section_hmac
+            && self.digest_hmac
+    }
 }
 
 impl Sb21HeaderPart {
@@ -300,7 +635,7 @@ impl Sb21HeaderPart {
             cert_block.extend_from_slice(fp.0.as_ref());
         }
         // Pad 16
-        cert_block.resize(((cert_block.len() + 15) / 16) * 16, 0);
+        cert_block.resize(cert_block.len().div_ceil(16) * 16, 0);
 
         bytes.extend_from_slice(&cert_block);
 
@@ -310,13 +645,13 @@ impl Sb21HeaderPart {
 
 #[derive(Clone, Debug)]
 pub struct Sb21HeaderPart {
-    header: Sb2Header,
-    digest: [u8; 32],
+    pub header: Sb2Header,
+    pub digest: [u8; 32],
     // not sure if the 8 bytes padding can be set to zero or not
-    encrypted_keyblob: [u8; 80],
-    certificate_block_header: FullCertificateBlockHeader,
-    padded_certs: Vec<Vec<u8>>,
-    rot_fingerprints: [Sha256Hash; 4],
+    pub encrypted_keyblob: [u8; 80],
+    pub certificate_block_header: FullCertificateBlockHeader,
+    pub padded_certs: Vec<Vec<u8>>,
+    pub rot_fingerprints: [Sha256Hash; 4],
 }
 
 impl UnsignedSb21File {
@@ -329,35 +664,72 @@ impl UnsignedSb21File {
         // pub keyblob: Keyblob,
         // pub commands: Vec<BootCommand>,
 
+        let reproducibility = config.reproducibility()?;
         let parameters = Sb21FileParameters {
-            nonce: config.reproducibility.nonce,
+            nonce: reproducibility.nonce,
             // nonce: {
             //     match config.reproducibility.nonce {
             //         [0, 0, 0, 0] => rand::random(),
             //         nonce => nonce,
             //     }
             // },
-            timestamp: match config.reproducibility.timestamp {
+            timestamp: match reproducibility.timestamp {
                 0 => config.firmware.product.timestamp_micros(),
                 timestamp => timestamp,
             },
             build: config.firmware.build,
             component: config.firmware.component,
             product: config.firmware.product,
-            sb_header_padding: config.reproducibility.sb_header_padding,
+            sb_header_padding: reproducibility.sb_header_padding,
         };
 
         let certificates = Certificates::try_from_pki(&config.pki)?;
         let signing_key = SigningKey::try_from_uri(config.pki.signing_key.as_ref())?;
-        let slot = certificates.index_of(signing_key.public_key())?;
+        let slot = certificates.index_of(signing_key.public_key()?)?;
+        certificates
+            .chain(slot)
+            .check(
+                config
+                    .factory_settings
+                    .secure_boot_configuration
+                    .use_rsa4096_keys,
+            )
+            .with_context(|| format!("certificate chain {}", usize::from(slot)))?;
 
         let keyblob = Keyblob {
-            dek: config.reproducibility.dek,
-            mac: config.reproducibility.mac,
+            dek: reproducibility.dek,
+            mac: reproducibility.mac,
         };
 
+        let mut sections = Vec::new();
+        for section in config.sections()? {
+            let commands = Self::boot_commands(config, &section.commands)?;
+            sections.push(Sb21Section {
+                id: section.id,
+                flags: section.flags,
+                commands,
+            });
+        }
+
+        let return_value = Self {
+            parameters,
+            certificates,
+            slot,
+            keyblob,
+            sbkek: config.sbkek()?,
+            sections,
+        };
+        // dbg!(return_value.clone());
+        // panic!();
+        Ok(return_value)
+    }
+
+    fn boot_commands(
+        config: &Config,
+        descriptions: &[BootCommandDescription],
+    ) -> anyhow::Result<Vec<BootCommand>> {
         let mut commands: Vec<BootCommand> = Vec::new();
-        for command_or_sequence in config.commands.iter() {
+        for command_or_sequence in descriptions.iter() {
             match command_or_sequence {
                 BootCommandDescription::Single(command) => commands.push(command.try_into()?),
 
@@ -433,16 +805,7 @@ impl UnsignedSb21File {
             }
         }
 
-        let return_value = Self {
-            parameters,
-            certificates,
-            slot,
-            keyblob,
-            commands,
-        };
-        // dbg!(return_value.clone());
-        // panic!();
-        Ok(return_value)
+        Ok(commands)
     }
 
     // let (i, header) = Sb2Header::inner_from_bytes(&data)?;//.unwrap();//.1;//.map_err(|_| anyhow::anyhow!("could not parse SB2 file"))?.1;
@@ -453,12 +816,28 @@ impl UnsignedSb21File {
     // let (i, certificate_data) = take::<_, _, ()>(certificate_length)(i)?;
     // let (i, _rot_key_hashes) = take::<_, _, ()>(128usize)(i)?;
     // let (i, signature) = take::<_, _, ()>(256usize)(i)?;
+    /// The values to reproduce this file, e.g., after random ones were drawn in confidential mode.
+    pub fn reproducibility(&self) -> Reproducibility {
+        Reproducibility {
+            dek: self.keyblob.dek,
+            mac: self.keyblob.mac,
+            nonce: self.parameters.nonce,
+            timestamp: self.parameters.timestamp,
+            sb_header_padding: self.parameters.sb_header_padding,
+        }
+    }
+
+    /// [`Self::reproducibility`], sealed with the SBKEK.
+    pub fn sealed_sidecar(&self) -> Vec<u8> {
+        self.reproducibility().seal(&self.sbkek)
+    }
+
     pub fn header_part(&self) -> Sb21HeaderPart {
         // todo: should we mark this method as unsafe and pass command part
         // as another parameter "for efficiency"?
-        let digest = self.command_part().digest_hmac(self.keyblob.mac);
+        let digest = self.command_parts()[0].digest_hmac(self.keyblob.mac);
 
-        let encrypted_keyblob = self.keyblob.to_bytes();
+        let encrypted_keyblob = self.keyblob.wrap(&self.sbkek);
 
         let mut padded_certs = Vec::new();
 
@@ -480,7 +859,7 @@ impl UnsignedSb21File {
             padded_certs.len() * 4 + padded_certs.iter().fold(0, |acc, c| acc + c.len());
         // really?
         // let total_image_length_in_bytes = self.signed_data_length() as _;
-        let total_image_length_in_bytes = (((cert_table_len + 368) + 15) / 16) * 16;
+        let total_image_length_in_bytes = (cert_table_len + 368).div_ceil(16) * 16;
         let certificate_block_header = FullCertificateBlockHeader {
             header_length_in_bytes: 32,
             build_number: self.parameters.build,
@@ -502,7 +881,7 @@ impl UnsignedSb21File {
             boot_tag_offset_blocks: self.boot_tag_offset_blocks() as u32,
 
             // 6 entries that seem only here to "fit in" the general SB scheme
-            boot_section_id: 0,
+            boot_section_id: self.sections[0].id,
             // 16 * ( header(6) + digest(2) + keyblob(5))
             certificate_block_header_offset_bytes: 16 * 13,
             // "fact of life"
@@ -531,27 +910,30 @@ impl UnsignedSb21File {
     pub fn total_serialized_length(&self) -> usize {
         // this needs to be everything-everything (i.e., len(file.sb2))
         let blocks = self.boot_tag_offset_blocks()
-            // boot tag(1) + hmac table(2*2)
-            + 5
-            // the actual payload
-            + self.command_part().encrypted_section.len()/16;
+            + self
+                .command_parts()
+                .iter()
+                // boot tag(1) + hmac table(2*2) + the actual payload
+                .map(|part| 5 + part.encrypted_section.len() / 16)
+                .sum::<usize>();
 
         blocks * 16
     }
 
     pub fn signed_data_length(&self) -> usize {
         // need "padded" length here
-        let certificate_length = self
+        // each certificate has a length prefix
+        let certificate_table_length = self
             .certificates
             .chain_der(self.slot)
-            .fold(0, |acc, der| acc + word_pad_len(der.len()));
+            .fold(0, |acc, der| acc + 4 + word_pad_len(der.len()));
 
         // let header_blocks = 16;
         // let keyblob_blocks = 5;
-        let signed_data_length = 16 * (6 + 2 + 5 + 2) + 4 + certificate_length + 128;
+        let signed_data_length = 16 * (6 + 2 + 5 + 2) + certificate_table_length + 128;
 
         // pad 16
-        16 * ((signed_data_length + 15) / 16)
+        16 * signed_data_length.div_ceil(16)
     }
 
     pub fn boot_tag_offset_blocks(&self) -> usize {
@@ -567,7 +949,7 @@ impl UnsignedSb21File {
             / 16
     }
 
-    // alright, BootTag, Hmac, Section
+    // alright, BootTag, Hmac, Section (and again, for each further section)
     //
     // here's what happens:
     // - encryption is weird... (big-endian AES-CTR, but with nonce modified by adding
@@ -583,9 +965,20 @@ impl UnsignedSb21File {
     // TODO: since everything is private (?) and we only use shared references,
     // should be possible to cache this part. Alternatively, inject a "with rendered command part"
     // typestate between unsigned and signed image (we need the hmacs, and the length)
-    pub fn command_part(&self) -> Sb21CommandPart {
+    pub fn command_parts(&self) -> Vec<Sb21CommandPart> {
+        let mut offset_blocks = self.boot_tag_offset_blocks() as u32;
+        let mut parts = Vec::new();
+        for index in 0..self.sections.len() {
+            let part = self.command_part(index, offset_blocks);
+            offset_blocks += 5 + part.encrypted_section.len() as u32 / 16;
+            parts.push(part);
+        }
+        parts
+    }
+
+    fn command_part(&self, index: usize, boot_tag_offset_blocks: u32) -> Sb21CommandPart {
         let mut section = Vec::new();
-        for command in self.commands.iter() {
+        for command in self.sections[index].commands.iter() {
             section.append(&mut command.to_bytes());
         }
         let encrypted_section = nxp_aes_ctr_cipher(
@@ -593,7 +986,7 @@ impl UnsignedSb21File {
             self.keyblob.dek,
             self.parameters.nonce,
             // 1 block bot tag, 2 blocks each per HMAC
-            self.boot_tag_offset_blocks() as u32 + 5,
+            boot_tag_offset_blocks + 5,
         );
 
         // let expected_load_command = nxp_aes_ctr_cipher(
@@ -629,27 +1022,13 @@ impl UnsignedSb21File {
         // // expected: DE010180 00000000 01000000 01000000
         // println!("expected: {}", hex_str!(&expected_decrypted, 4));
 
-        let last = self.commands.is_empty();
-        // TODO: figure out tag and flags here
-        let tag = 0x0;
-        // https://github.com/NXPmicro/spsdk/blob/90fdc7e60917bdd01c0d1467bff7931551fe80f3/spsdk/sbfile/sb1/headers.py#L22
-        // bit 0 = bootable (is set)
-        // bit 1 = cleartext (is not set)
-        // 0b01 = not cleartext bit 1 = "bootable", bit
-        let flags = 0x1;
-        let cipher_blocks = encrypted_section.len() as u32 / 16;
-        let boot_tag = BootCommand::Tag {
-            last,
-            tag,
-            flags,
-            cipher_blocks,
-        };
+        let boot_tag = self.boot_tag(index, encrypted_section.len() as u32 / 16);
         // println!("boot tag: {}", hex_str!(&boot_tag.to_bytes(), 4));
         let encrypted_boot_tag = nxp_aes_ctr_cipher(
             &boot_tag.to_bytes(),
             self.keyblob.dek,
             self.parameters.nonce,
-            self.boot_tag_offset_blocks() as u32,
+            boot_tag_offset_blocks,
         );
         // println!("encr tag: {}", hex_str!(&encrypted_boot_tag, 4));
         // boot tag: 5D010000 00000000 01000000 01000000
@@ -667,6 +1046,19 @@ impl UnsignedSb21File {
         }
     }
 
+    fn boot_tag(&self, index: usize, cipher_blocks: u32) -> BootCommand {
+        let section = &self.sections[index];
+        BootCommand::Tag {
+            last: index + 1 == self.sections.len(),
+            tag: section.id,
+            // https://github.com/NXPmicro/spsdk/blob/90fdc7e60917bdd01c0d1467bff7931551fe80f3/spsdk/sbfile/sb1/headers.py#L22
+            // bit 0 = bootable
+            // bit 1 = cleartext
+            flags: section.flags,
+            cipher_blocks,
+        }
+    }
+
     /// TODO: figure out how generic this "key" should be. We want to cover
     /// - on-disk/file keys (cf. RFC 8089: The "file" URI Scheme)
     /// - PKCS#11 keys, so any kind of HSM can be used (cf. RFC 7512: The PKCS #11 URI Scheme)
@@ -685,11 +1077,21 @@ impl UnsignedSb21File {
         // let signature = secret_key.sign(padding_scheme, &hashed_header).expect("signatures work");
         // assert_eq!(256, signature.len());
 
+        let command_parts = self.command_parts();
+        let boot_tags = command_parts
+            .iter()
+            .enumerate()
+            .map(|(index, part)| self.boot_tag(index, part.encrypted_section.len() as u32 / 16))
+            .collect();
+
         SignedSb21File {
-            unsigned_file: self.clone(),
             header_part,
-            command_part: self.command_part(),
+            command_parts,
             signature: Vec::from(signature.as_bytes()),
+            keyblob: self.keyblob.clone(),
+            certificates: self.certificates.chain(self.slot).clone(),
+            boot_tags,
+            sections: self.sections.clone(),
         }
     }
 }
@@ -698,196 +1100,268 @@ impl SignedSb21File {
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = self.header_part.to_bytes();
         bytes.extend_from_slice(&self.signature);
-        bytes.append(&mut self.command_part.to_bytes());
+        for command_part in &self.command_parts {
+            bytes.append(&mut command_part.to_bytes());
+        }
         bytes
     }
-}
-
-// impl SignedSb21File {
-//     // TODO: Convert `pub fn show` into a proper decoder
-//     pub fn read<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
-//         let data = fs::read(path.as_ref())
-//             .with_context(|| format!("Failed to read data from from {}", path.as_ref().display()))?;
 
-//         if sniff(&data)? != Filetype::Sb21 {
-//             return Err(anyhow::anyhow!("Doesn't look like an SB 2.1 file"));
-//         }
+    /// Reads and decodes an SB2.1 file, cf. [`from_bytes`](Self::from_bytes).
+    pub fn read<P: AsRef<std::path::Path>>(path: P, sbkek: &[u8; 32]) -> Result<Self> {
+        let data = fs::read(path.as_ref())
+            .with_context(|| format!("Failed to read data from {}", path.as_ref().display()))?;
+        Self::from_bytes(&data, sbkek)
+    }
 
-//     }
-// }
+    /// Parses an SB2.1 file, unwraps its keyblob with `sbkek` and decrypts the boot sections.
+    ///
+    /// Malformed files are rejected, but neither signature nor HMACs are checked here,
+    /// use [`verify`](Self::verify) for this.
+    pub fn from_bytes(data: &[u8], sbkek: &[u8; 32]) -> Result<Self> {
+        if sniff(data)? != Filetype::Sb21 {
+            return Err(anyhow::anyhow!("Doesn't look like an SB 2.1 file"));
+        }
 
-pub fn show(filename: &str) -> Result<Vec<u8>> {
-    let data = fs::read(filename)
-        .with_context(|| format!("Failed to read data from from {}", filename))?;
-    trace!("filename: {}", filename);
-    trace!("filesize: {}B", data.len());
-
-    let filetype = sniff(&data)?;
-    trace!("filetype: {:?}", filetype);
-
-    if let Filetype::Sb21 = filetype {
-        let (i, header) = Sb2Header::inner_from_bytes(&data)?; //.unwrap();//.1;//.map_err(|_| anyhow::anyhow!("could not parse SB2 file"))?.1;
-        let (i, digest_hmac) = take::<_, _, ()>(32u8)(i)?;
-        let (i, keyblob) = Keyblob::from_bytes(i)?;
-        let (i, certificate_block_header) = FullCertificateBlockHeader::from_bytes(i)?;
-        let (mut i, mut certificates): (_, Vec<X509Certificate<'_>>) = (i, Vec::new());
-        for _ in 0..certificate_block_header.certificate_count {
-            let (itmp, certificate_length) = le_u32::<_, ()>(i).unwrap();
-            let (itmp, certificate_data) = take::<_, _, ()>(certificate_length)(itmp)?;
-            println!("certificate length: {}", certificate_length);
-            println!("end of cert data: {:>16x}", hex_str!(&certificate_data));
-            i = itmp;
-            match X509Certificate::from_der(certificate_data) {
-                Ok((rem, cert)) => {
-                    println!("Remainder: {}", hex_str!(rem));
-                    assert_eq!(
-                        cert.tbs_certificate.version,
-                        x509_parser::x509::X509Version::V3
-                    );
-
-                    // Verify that the certificate is signed by the previous certificate, or by
-                    // itself if it is the first.
-                    let prev_public_key = certificates.last().map(|c| c.public_key());
-                    assert!(cert.verify_signature(prev_public_key).is_ok());
-                    certificates.push(cert);
-                }
-                _ => panic!("Invalid certificate"),
-            }
+        let (i, header) = Sb2Header::inner_from_bytes(data).context("malformed SB2 header")?;
+        let (i, digest) = take::<_, _, ()>(32u8)(i).context("truncated digest HMAC")?;
+        let (i, encrypted_keyblob) = take::<_, _, ()>(80u8)(i).context("truncated keyblob")?;
+        let encrypted_keyblob: [u8; 80] = encrypted_keyblob.try_into().unwrap();
+        let keyblob = Keyblob::try_unwrap(&encrypted_keyblob, sbkek)?;
+        let (i, certificate_block_header) = FullCertificateBlockHeader::from_bytes(i)
+            .context("malformed certificate block header")?;
+
+        let (i, certificates, padded_certs) =
+            certificate_table_from_bytes(i, certificate_block_header.certificate_count)?;
+        let (i, rot_fingerprints) = rot_fingerprints_from_bytes(i)?;
+
+        // signed data is padded to 16 bytes
+        let padding = (16 - (data.len() - i.len()) % 16) % 16;
+        let (i, _) = take::<_, _, ()>(padding)(i).context("truncated header part")?;
+
+        let signature_length = certificates.signer().public_key().0.size();
+        let (i, signature) =
+            take::<_, _, ()>(signature_length)(i).context("truncated signature")?;
+
+        let boot_tag_offset = data.len() - i.len();
+        if boot_tag_offset != 16 * header.boot_tag_offset_blocks as usize {
+            return Err(anyhow::anyhow!(
+                "boot tag at offset 0x{:x}, but header has 0x{:x}",
+                boot_tag_offset,
+                16 * header.boot_tag_offset_blocks
+            ));
         }
 
-        let (i, rot_key_hashes) = take::<_, _, ()>(128usize)(i)?;
+        let mut command_parts = Vec::new();
+        let mut boot_tags = Vec::new();
+        let mut sections = Vec::new();
+        let mut i = i;
+        while !i.is_empty() {
+            let boot_tag_offset_blocks = ((data.len() - i.len()) / 16) as u32;
+            let (rest, (encrypted_boot_tag, boot_tag_hmac, section_hmac)) = tuple((
+                take::<_, _, ()>(16u8),
+                take::<_, _, ()>(32u8),
+                take::<_, _, ()>(32u8),
+            ))(i)
+            .context("truncated command part")?;
+
+            let boot_tag = nxp_aes_ctr_cipher(
+                encrypted_boot_tag,
+                keyblob.dek,
+                header.nonce,
+                boot_tag_offset_blocks,
+            );
+            let (_, boot_tag) = BootCommand::from_bytes(&boot_tag).context("invalid boot tag")?;
+            let (last, id, flags, cipher_blocks) = match boot_tag {
+                BootCommand::Tag {
+                    last,
+                    tag,
+                    flags,
+                    cipher_blocks,
+                } => (last, tag, flags, cipher_blocks),
+                _ => return Err(anyhow::anyhow!("expected boot tag, found {:?}", boot_tag)),
+            };
+            let (rest, encrypted_section) = take::<_, _, ()>(16 * cipher_blocks as usize)(rest)
+                .with_context(|| format!("truncated boot section {}", id))?;
+
+            let section = nxp_aes_ctr_cipher(
+                encrypted_section,
+                keyblob.dek,
+                header.nonce,
+                // 1 block boot tag, 2 blocks each per HMAC
+                boot_tag_offset_blocks + 5,
+            );
+            let mut commands = Vec::new();
+            let mut j = section.as_slice();
+            while !j.is_empty() {
+                let offset = section.len() - j.len();
+                let (rest, command) = BootCommand::from_bytes(j).with_context(|| {
+                    format!(
+                        "invalid boot command at offset 0x{:x} of section {}",
+                        offset, id
+                    )
+                })?;
+                trace!("command: {:?}", &command);
+                commands.push(command);
+                j = rest;
+            }
 
-        info!(
-            "rotkh: {}",
-            hex_str!(&crate::pki::Certificates::fingerprint_from_bytes(rot_key_hashes).0)
-        );
+            command_parts.push(Sb21CommandPart {
+                encrypted_boot_tag: encrypted_boot_tag.try_into().unwrap(),
+                unencrypted_hmac_of_encrypted_boot_tag: boot_tag_hmac.try_into().unwrap(),
+                unencrypted_hmac_of_encrypted_section: section_hmac.try_into().unwrap(),
+                encrypted_section: Vec::from(encrypted_section),
+            });
+            boot_tags.push(boot_tag);
+            sections.push(Sb21Section {
+                id,
+                flags,
+                commands,
+            });
+            i = rest;
+
+            if last && !i.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{} bytes after the last boot section {}",
+                    i.len(),
+                    id
+                ));
+            }
+        }
+        if sections.is_empty() {
+            return Err(anyhow::anyhow!("no boot section"));
+        }
+        if sections[0].id != header.boot_section_id {
+            return Err(anyhow::anyhow!(
+                "first boot section is {}, but header has {}",
+                sections[0].id,
+                header.boot_section_id
+            ));
+        }
 
-        // the weird sectionAllignment (sic!)
-        info!("SB2 header: \n{:#?}", &header);
-        info!("  nonce: {:?}", &header.nonce);
-        info!("  tstmp: {}", header.timestamp_microseconds_since_millenium);
-        info!("  junk: {}", hexstr!(&header.sb_header_padding));
-        info!("HMAC:       \n{:?}", &digest_hmac);
-        info!("keyblob:    \n{:?}", &keyblob);
-        info!("  DEK: {}", hexstr!(&keyblob.dek));
-        info!("  MAC: {}", hexstr!(&keyblob.mac));
-        info!("CTH:        \n{:?}", &certificate_block_header);
+        Ok(Self {
+            header_part: Sb21HeaderPart {
+                header,
+                digest: digest.try_into().unwrap(),
+                encrypted_keyblob,
+                certificate_block_header,
+                padded_certs,
+                rot_fingerprints,
+            },
+            command_parts,
+            signature: Vec::from(signature),
+            keyblob,
+            certificates,
+            boot_tags,
+            sections,
+        })
+    }
 
-        let certificate = &certificates[certificates.len() - 1];
+    /// Checks certificate chain, RoT fingerprint, RSA signature and HMACs.
+    pub fn verify(&self) -> Sb21Verification {
+        let certificate_chain = self.certificates.verify_signatures();
 
-        // info!("cert: \n{:?}", &certificate);
+        let root_of_trust = self
+            .header_part
+            .rot_fingerprints
+            .contains(&self.certificates.root().fingerprint());
 
-        // now let's verify the signature
-        // pad 16
-        //println!("unpadded signed data length: 0x{:x}", unpadded_signed_data_length);
-        // let signed_data_length = signed_data_length + (16 - (signed_data_length % 16));
-        //let signed_data_length = 16 * ((unpadded_signed_data_length + 15) / 16);
-        let unpadded_signed_data_length = data.len() - i.len();
-        let i = if (unpadded_signed_data_length % 16) != 0 {
-            take::<_, _, ()>(16u8 - (unpadded_signed_data_length % 16) as u8)(i)?.0
-        } else {
-            i
+        let signature = {
+            use rsa::PublicKey as _;
+            let padding_scheme = rsa::PaddingScheme::new_pkcs1v15_sign(Some(rsa::Hash::SHA2_256));
+            self.certificates
+                .signer()
+                .public_key()
+                .0
+                .verify(
+                    padding_scheme,
+                    &sha256(&self.header_part.to_bytes()),
+                    &self.signature,
+                )
+                .is_ok()
         };
-        let signed_data_length = data.len() - i.len();
-
-        // let signed_data_length = 0x5f0;
-        println!("signed data length: 0x{:x}", signed_data_length);
-
-        let signed_data_hash = sha256(&data[..signed_data_length]);
-        println!("data hash: {}", hex_str!(&signed_data_hash, 4));
-
-        let spki = &certificate.tbs_certificate.subject_pki;
-        trace!("alg: {:?}", spki.algorithm.algorithm);
-        assert_eq!(
-            oid_registry::OID_PKCS1_RSAENCRYPTION,
-            spki.algorithm.algorithm
-        );
 
-        println!("rsa pub key: {:?}", &spki.subject_public_key.data);
-        let public_key = rsa::RsaPublicKey::from_pkcs1_der(spki.subject_public_key.data)
-            .expect("can parse public key");
-        let (i, signature) = take::<_, _, ()>(public_key.size())(i)?;
-        println!("signature: {}", hexstr!(&signature));
-        let padding_scheme = rsa::PaddingScheme::new_pkcs1v15_sign(Some(rsa::Hash::SHA2_256));
-        use rsa::PublicKey;
-        public_key
-            .verify(padding_scheme, &signed_data_hash, signature)
-            .expect("signature valid");
-        // let signature = secret_key.sign(padding_scheme, &hashed_image).expect("signatures work");
-
-        let calculated_boot_tag_offset_bytes = signed_data_length + public_key.size();
-        assert_eq!(
-            calculated_boot_tag_offset_bytes,
-            (header.boot_tag_offset_blocks * 16) as usize
-        );
-
-        // alright, BootTag, Hmac, Section
-        //
-        // here's what happens:
-        // - encryption is weird... (big-endian AES-CTR, but with nonce modified by adding
-        // block number to little-endian encoding of last nonce-value)
-        //
-        // - first: encrypted boot tag
-        // - then: unencrypted HMAC of encrypted boot tag
-        // - then: unencrypted HMAC of encrypted section data (commands and their data)
-        // - then: encrypted section data
-        //
-        // the digest HMAC at the top after image header is HMAC(first HMAC || second HMAC)
-        //
-        //
-        let _boot_tag_offset_blocks = header.boot_tag_offset_blocks;
-
-        let (i, enciphered_boot_tag) = take::<_, _, ()>(16u8)(i)?;
-        let calculated_boot_tag_hmac = hmac(keyblob.mac, enciphered_boot_tag);
-
-        let deciphered_boot_tag = nxp_aes_ctr_cipher(
-            enciphered_boot_tag,
-            keyblob.dek,
-            header.nonce,
-            header.boot_tag_offset_blocks,
-        );
-
-        let (_, boot_tag) = BootCommand::from_bytes(&deciphered_boot_tag)?;
-        println!("boot tag: {:?}", &boot_tag);
-        // TODO? check cipher blocks
-
-        let (i, hmac_table) = take::<_, _, ()>(64u8)(i)?;
-
-        let (_, (boot_tag_hmac, section_hmac)) =
-            tuple((take::<_, _, ()>(32u8), take::<_, _, ()>(32u8)))(hmac_table)?;
-
-        assert_eq!(boot_tag_hmac, calculated_boot_tag_hmac);
-
-        // let (i, section_hmac) = take::<_, _, ()>(32u8)(i)?;
-
-        let enciphered_section = i;
-
-        let calculated_section_hmac = hmac(keyblob.mac, enciphered_section);
-        assert_eq!(section_hmac, calculated_section_hmac);
+        let mac = self.keyblob.mac;
+        let parts = &self.command_parts;
+        Sb21Verification {
+            certificate_chain,
+            root_of_trust,
+            signature,
+            boot_tag_hmac: parts.iter().all(|part| {
+                hmac(mac, &part.encrypted_boot_tag) == part.unencrypted_hmac_of_encrypted_boot_tag
+            }),
+            section_hmac: parts.iter().all(|part| {
+                hmac(mac, &part.encrypted_section) == part.unencrypted_hmac_of_encrypted_section
+            }),
+            digest_hmac: parts[0].digest_hmac(mac) == self.header_part.digest,
+        }
+    }
+}
 
-        let deciphered_section = nxp_aes_ctr_cipher(
-            enciphered_section,
-            keyblob.dek,
-            header.nonce,
-            header.boot_tag_offset_blocks + 5,
+/// Decodes an SB2.1 file, prints a summary and returns its verification report.
+pub fn show(filename: &str, sbkek: &[u8; 32]) -> Result<Sb21Verification> {
+    let file = SignedSb21File::read(filename, sbkek)?;
+    let header = &file.header_part.header;
+
+    println!("product version: {}", header.product_version.to_semver());
+    println!(
+        "component version: {}",
+        header.component_version.to_semver()
+    );
+    println!("build number: {}", header.build_number);
+    println!(
+        "timestamp: {}µs since 2000-01-01",
+        header.timestamp_microseconds_since_millenium
+    );
+    println!("certificates:");
+    for certificate in file.certificates.all() {
+        println!(
+            "- {} (fingerprint {})",
+            certificate.certificate().subject(),
+            hexstr!(&certificate.fingerprint().0)
         );
-
-        let calculated_digest_hmac = hmac(keyblob.mac, hmac_table);
-        assert_eq!(digest_hmac, calculated_digest_hmac);
-
-        let mut i = deciphered_section.as_ref();
-        loop {
-            let (j, command) = BootCommand::from_bytes(i)?;
-            i = j;
-            trace!("command: {:?}", &command);
-            if i.is_empty() {
-                break;
+    }
+    let rot_fingerprints: Vec<u8> = file
+        .header_part
+        .rot_fingerprints
+        .iter()
+        .flat_map(|fingerprint| fingerprint.0)
+        .collect();
+    println!(
+        "rotkh: {}",
+        hexstr!(&Certificates::fingerprint_from_bytes(&rot_fingerprints).0)
+    );
+    for (section, boot_tag) in file.sections.iter().zip(&file.boot_tags) {
+        println!("section {}:", section.id);
+        println!("  boot tag: {:?}", boot_tag);
+        println!("  commands:");
+        for command in &section.commands {
+            match command {
+                BootCommand::Load { address, data } => {
+                    println!(
+                        "  - Load {{ address: 0x{:x}, bytes: {} }}",
+                        address,
+                        data.len()
+                    )
+                }
+                command => println!("  - {:?}", command),
             }
         }
     }
 
-    println!("crc32(123456789) = 0x{:x}", crc32(b"123456789"));
-    todo!();
+    let verification = file.verify();
+    let status = |ok: bool| if ok { "ok" } else { "FAILED" };
+    println!("verification:");
+    println!(
+        "  certificate chain: {}",
+        status(verification.certificate_chain)
+    );
+    println!("  root of trust: {}", status(verification.root_of_trust));
+    println!("  signature: {}", status(verification.signature));
+    println!("  boot tag HMAC: {}", status(verification.boot_tag_hmac));
+    println!("  section HMAC: {}", status(verification.section_hmac));
+    println!("  digest HMAC: {}", status(verification.digest_hmac));
+
+    Ok(verification)
 }
 
 pub struct CertificateBlockHeader {
@@ -973,7 +1447,7 @@ impl Version {
     /// The minor version component in its interpretation as days since 2020-01-01
     pub fn minor_as_date(&self) -> NaiveDate {
         use chrono::Duration;
-        let epoch = NaiveDate::from_ymd(2020, 1, 1);
+        let epoch = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
         epoch + Duration::days(self.minor as _)
     }
 
@@ -991,10 +1465,13 @@ impl Version {
 
     pub fn timestamp_micros(&self) -> u64 {
         use chrono::Duration;
-        let epoch = NaiveDate::from_ymd(2020, 1, 1).and_hms(12, 0, 0);
+        let epoch = NaiveDate::from_ymd_opt(2020, 1, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
         let date = epoch + Duration::days(self.minor as _);
 
-        (date.timestamp_millis() * 1000) as _
+        (date.and_utc().timestamp_millis() * 1000) as _
     }
 }
 
@@ -1116,11 +1593,10 @@ impl<'de> serde::Deserialize<'de> for Version {
 
 #[allow(non_snake_case)]
 fn aes_wrap(key: [u8; 32], data: &[u8]) -> Vec<u8> {
-    #![allow(non_snake_case)]
-    if key.len() % 8 != 0 {
+    if !key.len().is_multiple_of(8) {
         todo!();
     }
-    assert!(data.len() % 8 == 0);
+    assert!(data.len().is_multiple_of(8));
     use aes::cipher::generic_array::GenericArray;
     use aes::{BlockCipher, BlockEncrypt, NewBlockCipher};
     let aes = aes::Aes256::new(&key.into());
@@ -1141,7 +1617,7 @@ fn aes_wrap(key: [u8; 32], data: &[u8]) -> Vec<u8> {
             // i.e., B = AES(A | R[i])
             aes.encrypt_block(GenericArray::from_mut_slice(&mut B));
 
-            let t = (n * j + i) as u64;
+            let t = n * j + i;
             A = u64::from_be_bytes(B[..8].try_into().unwrap());
             // i.e., MSB(64, B) ^ t
             A ^= t;
@@ -1156,13 +1632,14 @@ fn aes_wrap(key: [u8; 32], data: &[u8]) -> Vec<u8> {
     C
 }
 
-fn aes_unwrap(key: [u8; 32], wrapped: &[u8]) -> Vec<u8> {
+/// Returns `None` if the integrity check fails, e.g. because the key is wrong.
+fn aes_unwrap(key: [u8; 32], wrapped: &[u8]) -> Option<Vec<u8>> {
     #![allow(non_snake_case)]
-    if key.len() % 8 != 0 {
+    if !key.len().is_multiple_of(8) {
         // return Err(());
         todo!();
     }
-    assert!(wrapped.len() % 8 == 0);
+    assert!(wrapped.len().is_multiple_of(8));
     assert!(!wrapped.is_empty());
     use aes::cipher::generic_array::GenericArray;
     use aes::{BlockCipher, BlockDecrypt, NewBlockCipher};
@@ -1177,7 +1654,7 @@ fn aes_unwrap(key: [u8; 32], wrapped: &[u8]) -> Vec<u8> {
     let mut B = [0u8; 16];
     for j in (0..=5).rev() {
         for i in (1..=n).rev() {
-            let t = (n * j + i) as u64;
+            let t = n * j + i;
             B[..8].copy_from_slice(&(A ^ t).to_be_bytes());
             B[8..].copy_from_slice(&R[i as usize].to_be_bytes());
             // let mut B = ((A ^ t) | R[i as usize]).to_be_bytes();
@@ -1186,15 +1663,15 @@ fn aes_unwrap(key: [u8; 32], wrapped: &[u8]) -> Vec<u8> {
             R[i as usize] = u64::from_be_bytes(B[8..].try_into().unwrap());
         }
     }
-    println!("A = {}", A);
-    println!("A = {}", hex_str!(&A.to_be_bytes()));
     // A ?= 'A6 A6 A6 A6 A6 A6 A6 A6'
-    assert_eq!(A, 12008468691120727718);
+    if A != u64::from_be_bytes([0xA6u8; 8]) {
+        return None;
+    }
     let mut P = Vec::new();
     for i in 1..=n {
         P.extend_from_slice(&R[i as usize].to_be_bytes());
     }
-    P
+    Some(P)
 }
 
 #[cfg(test)]
@@ -1205,12 +1682,19 @@ mod aes_keywrap {
     fn test() {
         let key = [42; 32];
         let msg: &[u8] = &[];
-        assert_eq!(&msg, &aes_unwrap(key, &aes_wrap(key, &msg)).as_slice());
+        assert_eq!(
+            &msg,
+            &aes_unwrap(key, &aes_wrap(key, msg)).unwrap().as_slice()
+        );
         let msg = [
             1, 2, 3, 4, 5, 6, 7, 8,
             // 1, 2, 3, 4, 5, 6, 7, 8,
         ];
-        assert_eq!(&msg, aes_unwrap(key, &aes_wrap(key, &msg)).as_slice());
+        assert_eq!(
+            &msg,
+            aes_unwrap(key, &aes_wrap(key, &msg)).unwrap().as_slice()
+        );
+        assert_eq!(None, aes_unwrap([43; 32], &aes_wrap(key, &msg)));
     }
 
     #[test]
@@ -1269,71 +1753,68 @@ pub struct Keyblob {
 
 impl Keyblob {
     /// Conor picked this KEK once, all zeros would work too, but why not 101010....101010 ;)
+    ///
+    /// Used unless `keystore.sbkek` is configured, cf. [`KeystoreKeys`].
     pub const SBKEK: &'static [u8; 32] = b"\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA\xAA";
 
-    fn to_bytes(&self) -> [u8; 80] {
+    /// AES-keywraps DEK and MAC key with `sbkek`, padded to the 80 bytes of SB2.1 files.
+    pub fn wrap(&self, sbkek: &[u8; 32]) -> [u8; 80] {
         let mut keys = [0u8; 64];
         keys[..32].copy_from_slice(&self.dek);
         keys[32..].copy_from_slice(&self.mac);
-        let wrapped = aes_wrap(*Self::SBKEK, &keys);
+        let wrapped = aes_wrap(*sbkek, &keys);
         let mut padded = [0u8; 80];
         padded[..72].copy_from_slice(&wrapped);
         padded
     }
 
-    fn from_bytes(i: &[u8]) -> nom::IResult<&[u8], Self, ()> {
-        // if i.len() != 0x60 {
-        //     return Err(anyhow::anyhow!("wrong size for SB2 header"));
-        // }
-
-        println!("{}", hex_str!(Self::SBKEK));
-        let (i, encapsulated) = take(72u8)(i)?;
-        let (i, _) = take(8u8)(i)?;
-        println!("{}", hex_str!(encapsulated));
-        // let unwrapped = b"\x15\x17\xba\x1c\x12\xe2:!\r\xeb\xf1p7\xc3=\x17\x06e:S\xd2\xb7\xf4P-\x11\x01-m\x0f\x8d\x8ey\x17\xd9\xc6x\xc7\xb0\x18\xd9\xf8\x17\xb4fws\"_\xb2\x10\xd3\x9f\x10\xa2\xb5K\x18\xd9\x1d\x1c\xd6\"\x85";
-        // println!("wrapped: \n{}", hex_str!(
-        //     &Aes256KeyWrap::new(Self::SBKEK).encapsulate(unwrapped).unwrap()));
-
-        // let keywrap = rust_aes_keywrap::Aes256KeyWrap::new(Self::SBKEK);
-        // let decapsulated = keywrap.decapsulate(encapsulated, 64).unwrap();
-        let decapsulated = aes_unwrap(*Self::SBKEK, encapsulated); //apsulate(encapsulated, 64).unwrap();
-                                                                   // let mut nonce = [0u32; 4];
-        println!("decapsulated = {}", hex_str!(&decapsulated));
+    /// Unwraps DEK and MAC key from an encrypted keyblob, as found in SB2.1 files.
+    pub fn try_unwrap(encrypted: &[u8; 80], sbkek: &[u8; 32]) -> Result<Self> {
+        let keys = aes_unwrap(*sbkek, &encrypted[..72])
+            .ok_or_else(|| anyhow::anyhow!("keyblob does not unwrap with the given SBKEK"))?;
         let mut dek = [0u8; 32];
         let mut mac = [0u8; 32];
-        dek.copy_from_slice(&decapsulated[..32]);
-        mac.copy_from_slice(&decapsulated[32..]);
-        Ok((i, Self { dek, mac }))
+        dek.copy_from_slice(&keys[..32]);
+        mac.copy_from_slice(&keys[32..]);
+        Ok(Self { dek, mac })
+    }
+
+    pub fn dek(&self) -> &[u8; 32] {
+        &self.dek
+    }
+
+    pub fn mac(&self) -> &[u8; 32] {
+        &self.mac
     }
 }
 
 /// full size: 0x60 = 96 bytes
 #[derive(Clone, Debug)]
 pub struct Sb2Header {
-    nonce: [u32; 4],
+    pub nonce: [u32; 4],
     // nonce: [u8; 16],
-    header_version_minor: u8,
-    flags: u16,
-    image_size_blocks: u32,
-    boot_tag_offset_blocks: u32,
-    boot_section_id: u32,
-    certificate_block_header_offset_bytes: u32,
-    header_size_blocks: u16,
-    keyblob_offset_blocks: u16,
-    keyblob_size_blocks: u16,
-    max_section_mac_count: u16,
+    pub header_version_minor: u8,
+    pub flags: u16,
+    pub image_size_blocks: u32,
+    pub boot_tag_offset_blocks: u32,
+    pub boot_section_id: u32,
+    pub certificate_block_header_offset_bytes: u32,
+    pub header_size_blocks: u16,
+    pub keyblob_offset_blocks: u16,
+    pub keyblob_size_blocks: u16,
+    pub max_section_mac_count: u16,
     // flags: Sb2Flags,
     // image_size: usize,
     // boot_tag_offset: usize,
     // certificate_offset: usize,
     // keyblob_offset,
     // max_hmac_table_entries: u16,
-    timestamp_microseconds_since_millenium: u64,
-    product_version: Version,
-    component_version: Version,
-    build_number: u32,
+    pub timestamp_microseconds_since_millenium: u64,
+    pub product_version: Version,
+    pub component_version: Version,
+    pub build_number: u32,
     /// For some reason, NXP thinks it's good to pad with random data here instead of zeros
-    sb_header_padding: [u8; 4],
+    pub sb_header_padding: [u8; 4],
 }
 
 // struct certificate_block_header_t {
@@ -1350,11 +1831,53 @@ pub struct Sb2Header {
 
 #[derive(Clone, Debug)]
 pub struct FullCertificateBlockHeader {
-    header_length_in_bytes: u32,
-    build_number: u32,
-    total_image_length_in_bytes: u32,
+    pub header_length_in_bytes: u32,
+    pub build_number: u32,
+    pub total_image_length_in_bytes: u32,
+    pub certificate_count: u32,
+    pub certificate_table_length_in_bytes: u32,
+}
+
+/// Parses the certificate table following a [`FullCertificateBlockHeader`].
+///
+/// Returns the certificates, and the word-padded entries as they appear in the table.
+#[allow(clippy::type_complexity)]
+pub(crate) fn certificate_table_from_bytes(
+    mut i: &[u8],
     certificate_count: u32,
-    certificate_table_length_in_bytes: u32,
+) -> Result<(&[u8], CertificateChain, Vec<Vec<u8>>)> {
+    let mut padded_certs = Vec::new();
+    let mut certificates = Vec::new();
+    for _ in 0..certificate_count {
+        let (rest, padded_cert) =
+            length_data(le_u32::<_, ()>)(i).context("truncated certificate table")?;
+        i = rest;
+        // the length prefix includes the word padding
+        let (padding, _) = X509Certificate::from_der(padded_cert).context("invalid certificate")?;
+        let der = &padded_cert[..padded_cert.len() - padding.len()];
+        certificates.push(Certificate::try_from_der(der)?);
+        padded_certs.push(Vec::from(padded_cert));
+    }
+    let mut certificates = certificates.into_iter();
+    let root = certificates
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("empty certificate table"))?;
+    Ok((
+        i,
+        CertificateChain::new(root, certificates.collect()),
+        padded_certs,
+    ))
+}
+
+/// Parses the table of the four RoT fingerprints following the certificate table.
+pub(crate) fn rot_fingerprints_from_bytes(mut i: &[u8]) -> Result<(&[u8], [Sha256Hash; 4])> {
+    let mut rot_fingerprints = [Sha256Hash::default(); 4];
+    for fingerprint in rot_fingerprints.iter_mut() {
+        let (rest, bytes) = take::<_, _, ()>(32u8)(i).context("truncated RoT fingerprints")?;
+        fingerprint.0.copy_from_slice(bytes);
+        i = rest;
+    }
+    Ok((i, rot_fingerprints))
 }
 
 impl FullCertificateBlockHeader {
@@ -1376,7 +1899,7 @@ impl FullCertificateBlockHeader {
         raw_bytes
     }
 
-    fn from_bytes(i: &[u8]) -> nom::IResult<&[u8], Self, ()> {
+    pub(crate) fn from_bytes(i: &[u8]) -> nom::IResult<&[u8], Self, ()> {
         // let literal_u8 = |x: u8| verify(u8, move |y| *y == x);
         let literal_u16 = |x: u16| verify(le_u16, move |y| *y == x);
 
@@ -1474,7 +1997,6 @@ impl Sb2Header {
         // are these not somewhere in `nom` already??
         let literal_u8 = |x: u8| verify(u8, move |y| *y == x);
         let literal_u16 = |x: u16| verify(le_u16, move |y| *y == x);
-        let literal_u32 = |x: u32| verify(le_u32, move |y| *y == x);
 
         // header_version_major should be 2u8
         let (i, _) = literal_u8(2u8)(i)?;
@@ -1484,7 +2006,7 @@ impl Sb2Header {
         let (i, flags) = le_u16(i)?;
         let (i, image_size_blocks) = le_u32(i)?;
         let (i, boot_tag_offset_blocks) = le_u32(i)?;
-        let (i, boot_section_id) = literal_u32(0)(i)?;
+        let (i, boot_section_id) = le_u32(i)?;
         let (i, certificate_block_header_offset_bytes) = le_u32(i)?;
         let (i, header_size_blocks) = literal_u16(6)(i)?;
         let (i, keyblob_offset_blocks) = literal_u16(8)(i)?;
@@ -1522,3 +2044,299 @@ impl Sb2Header {
         ))
     }
 }
+
+#[cfg(test)]
+mod decoder {
+    use super::*;
+    use crate::pki::CertificateUriChain;
+
+    fn example(name: &str) -> Vec<u8> {
+        fs::read(format!("example-binaries/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn decode_and_verify() {
+        for name in ["blinky-red.sb2", "elftosb-blinky-red.sb2"] {
+            let data = example(name);
+            let file = SignedSb21File::from_bytes(&data, Keyblob::SBKEK).unwrap();
+            assert!(file.verify().is_valid());
+            assert_eq!(file.certificates.len(), 1);
+            assert_eq!(file.sections.len(), 1);
+            assert!(matches!(
+                file.boot_tags[0],
+                BootCommand::Tag { last: true, .. }
+            ));
+            assert_eq!(
+                file.sections[0].commands[0],
+                BootCommand::EraseRegion {
+                    address: 0,
+                    bytes: 3072
+                }
+            );
+            assert_eq!(file.to_bytes(), data);
+        }
+    }
+
+    #[test]
+    fn certificate_chain() {
+        let mut config = Config::try_from("example-cfgs/example-cfg.toml").unwrap();
+        config.commands = vec![BootCommandDescription::Single(
+            SingleBootCommandDescription::Load {
+                file: "example-binaries/blinky-red.elf".into(),
+                src: 0,
+                dst: 0,
+                len: None,
+            },
+        )];
+        config.pki.certificates[0] = CertificateUriChain::Chain {
+            root: "file:example-file-certs/ca4096_certificate.der".to_string(),
+            chain: vec!["file:example-file-certs/isk_certificate.der".to_string()],
+        };
+        config.pki.signing_key = "file:example-file-certs/isk_private_key.pem".to_string();
+        let signing_key = SigningKey::try_from_uri(&config.pki.signing_key).unwrap();
+        let unsigned = UnsignedSb21File::try_assemble_from(&config).unwrap();
+        assert_eq!(unsigned.slot, CertificateSlot::from(0));
+
+        let data = unsigned.sign(&signing_key).to_bytes();
+        let file = SignedSb21File::from_bytes(&data, Keyblob::SBKEK).unwrap();
+        assert!(file.verify().is_valid());
+        assert_eq!(file.certificates.len(), 2);
+
+        // the intermediate certificate is not issued by this root
+        config.pki.certificates[0] = CertificateUriChain::Chain {
+            root: "file:example-file-certs/ca_certificate_0.der".to_string(),
+            chain: vec!["file:example-file-certs/isk_certificate.der".to_string()],
+        };
+        assert!(UnsignedSb21File::try_assemble_from(&config).is_err());
+    }
+
+    #[test]
+    fn wrong_sbkek() {
+        let data = example("blinky-red.sb2");
+        assert!(SignedSb21File::from_bytes(&data, &[0x55; 32]).is_err());
+    }
+
+    #[test]
+    fn tampered() {
+        let data = example("blinky-red.sb2");
+
+        // digest HMAC, directly after the SB2 header
+        let mut tampered = data.clone();
+        tampered[Sb2Header::LEN] ^= 1;
+        let verification = SignedSb21File::from_bytes(&tampered, Keyblob::SBKEK)
+            .unwrap()
+            .verify();
+        assert!(!verification.digest_hmac);
+        assert!(!verification.signature);
+        assert!(verification.certificate_chain && verification.section_hmac);
+
+        // section HMAC, after encrypted boot tag and its HMAC
+        let file = SignedSb21File::from_bytes(&data, Keyblob::SBKEK).unwrap();
+        let offset = 16 * file.header_part.header.boot_tag_offset_blocks as usize + 16 + 32;
+        let mut tampered = data;
+        tampered[offset] ^= 1;
+        let verification = SignedSb21File::from_bytes(&tampered, Keyblob::SBKEK)
+            .unwrap()
+            .verify();
+        assert!(!verification.section_hmac);
+        assert!(!verification.digest_hmac);
+        assert!(verification.signature && verification.boot_tag_hmac);
+        assert!(!verification.is_valid());
+    }
+
+    #[test]
+    fn short_files() {
+        let data = example("elftosb-blinky-red.sb2");
+        for len in [0, 3, 4, 24, 56, 100, data.len() - 1] {
+            assert!(SignedSb21File::from_bytes(&data[..len], Keyblob::SBKEK).is_err());
+        }
+        assert!(sniff(&[0x00, 0x00, 0x04, 0x20]).is_err());
+        assert!(sniff(&data[..52]).is_err());
+        assert_eq!(sniff(&data[..56]).unwrap(), Filetype::Sb21);
+    }
+}
+
+#[cfg(test)]
+mod confidential {
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::try_from("example-cfgs/example-cfg.toml").unwrap();
+        config.reproducibility = Default::default();
+        config.commands = vec![BootCommandDescription::Single(
+            SingleBootCommandDescription::Load {
+                file: "example-binaries/blinky-red.elf".into(),
+                src: 0,
+                dst: 0,
+                len: None,
+            },
+        )];
+        config.confidentiality.enabled = true;
+        config
+    }
+
+    fn signed(unsigned: &UnsignedSb21File) -> Vec<u8> {
+        let signing_key = SigningKey::try_from_uri("file:example-file-certs/ca_private_key_0.pem");
+        unsigned.sign(&signing_key.unwrap()).to_bytes()
+    }
+
+    #[test]
+    fn malformed_keys() {
+        let key = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+        assert!(toml::from_str::<Reproducibility>(&format!("dek = \"{}\"", key)).is_ok());
+        // typos are errors, not panics
+        for dek in [&key[..62], &key.replace('f', "g")] {
+            assert!(toml::from_str::<Reproducibility>(&format!("dek = \"{}\"", dek)).is_err());
+        }
+    }
+
+    #[test]
+    fn needs_secret_sbkek() {
+        let mut config = config();
+        assert!(UnsignedSb21File::try_assemble_from(&config).is_err());
+        config.keystore.sbkek = "AA".repeat(32);
+        assert!(UnsignedSb21File::try_assemble_from(&config).is_err());
+        config.keystore.sbkek = "00".repeat(32);
+        assert!(UnsignedSb21File::try_assemble_from(&config).is_err());
+    }
+
+    #[test]
+    fn sidecar_needs_confidential_mode() {
+        let mut config = config();
+        config.keystore.sbkek = "42".repeat(32);
+        config.confidentiality.sidecar = "blinky.sb2.sealed".into();
+        assert_eq!(config.confidentiality.sidecar(), Some("blinky.sb2.sealed"));
+        assert!(UnsignedSb21File::try_assemble_from(&config).is_ok());
+
+        config.confidentiality.enabled = false;
+        assert_eq!(config.confidentiality.sidecar(), None);
+        assert!(UnsignedSb21File::try_assemble_from(&config).is_err());
+    }
+
+    #[test]
+    fn random_keys_and_sidecar() {
+        let mut config = config();
+        let sbkek = [0x42; 32];
+        config.keystore.sbkek = hex::encode(sbkek);
+
+        let unsigned = UnsignedSb21File::try_assemble_from(&config).unwrap();
+        let reproducibility = unsigned.reproducibility();
+        assert_ne!(reproducibility.dek, [0; 32]);
+        assert_ne!(reproducibility.mac, [0; 32]);
+        assert_ne!(reproducibility.nonce, [0; 4]);
+        let other = UnsignedSb21File::try_assemble_from(&config).unwrap();
+        assert_ne!(other.reproducibility().dek, reproducibility.dek);
+
+        let data = signed(&unsigned);
+        assert!(SignedSb21File::from_bytes(&data, Keyblob::SBKEK).is_err());
+        let file = SignedSb21File::from_bytes(&data, &sbkek).unwrap();
+        assert!(file.verify().is_valid());
+        assert_eq!(file.keyblob.dek(), &reproducibility.dek);
+
+        let sidecar = unsigned.sealed_sidecar();
+        assert!(Reproducibility::unseal(&sidecar, Keyblob::SBKEK).is_err());
+        let unsealed = Reproducibility::unseal(&sidecar, &sbkek).unwrap();
+        assert_eq!(unsealed, reproducibility);
+        for len in [8, 16, 24] {
+            let error = Reproducibility::unseal(&sidecar[..len], &sbkek).unwrap_err();
+            assert_eq!(error.to_string(), "truncated sidecar");
+        }
+
+        // the unsealed values reproduce the file
+        config.reproducibility = unsealed;
+        let reproduced = UnsignedSb21File::try_assemble_from(&config).unwrap();
+        assert_eq!(signed(&reproduced), data);
+    }
+}
+
+#[cfg(test)]
+mod sections {
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::try_from("example-cfgs/example-cfg.toml").unwrap();
+        config.commands.clear();
+        #[derive(Deserialize)]
+        struct Sections {
+            sections: Vec<BootSectionDescription>,
+        }
+        config.sections = toml::from_str::<Sections>(
+            r#"
+            [[sections]]
+            id = 0
+
+            [[sections.commands]]
+            cmd = "Erase"
+            start = 0
+            end = 0x4_0000
+
+            [[sections]]
+            id = 7
+            flags = 0
+
+            [[sections.commands]]
+            cmd = "Load"
+            file = "example-binaries/blinky-red.elf"
+            dst = 0x4_0000
+            "#,
+        )
+        .unwrap()
+        .sections;
+        config
+    }
+
+    #[test]
+    fn round_trip() {
+        let unsigned = UnsignedSb21File::try_assemble_from(&config()).unwrap();
+        let signing_key = SigningKey::try_from_uri("file:example-file-certs/ca_private_key_0.pem");
+        let data = unsigned.sign(&signing_key.unwrap()).to_bytes();
+        assert_eq!(data.len(), unsigned.total_serialized_length());
+
+        let file = SignedSb21File::from_bytes(&data, Keyblob::SBKEK).unwrap();
+        assert!(file.verify().is_valid());
+        assert_eq!(file.sections, unsigned.sections);
+        assert_eq!(file.sections[1].id, 7);
+        assert_eq!(file.sections[1].flags, 0);
+        assert!(matches!(
+            file.boot_tags[0],
+            BootCommand::Tag { last: false, .. }
+        ));
+        assert!(matches!(
+            file.boot_tags[1],
+            BootCommand::Tag {
+                last: true,
+                tag: 7,
+                ..
+            }
+        ));
+        assert_eq!(file.to_bytes(), data);
+
+        // section HMAC of the second section, after its encrypted boot tag and its HMAC
+        let offset = data.len() - file.command_parts[1].encrypted_section.len() - 32;
+        let mut tampered = data;
+        tampered[offset] ^= 1;
+        let verification = SignedSb21File::from_bytes(&tampered, Keyblob::SBKEK)
+            .unwrap()
+            .verify();
+        assert!(!verification.section_hmac);
+        // only the first section's HMACs are signed
+        assert!(verification.digest_hmac && verification.signature);
+    }
+
+    #[test]
+    fn commands_or_sections() {
+        let mut config = config();
+        config.sections[1].id = 0;
+        assert!(config.sections().is_err());
+
+        let mut config = self::config();
+        config.commands = config.sections[0].commands.clone();
+        assert!(config.sections().is_err());
+
+        config.sections.clear();
+        let sections = config.sections().unwrap();
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].id, 0);
+        assert_eq!(sections[0].flags, BootSectionDescription::BOOTABLE);
+    }
+}
@@ -0,0 +1,817 @@
+//! Compiler for elftosb command files (`.bd`), cf. `example-cfgs/command.bd`.
+//!
+//! The subset of the BD language that SB2.1 files for the LPC55 need is supported:
+//! - `options`: `productVersion`, `componentVersion`, `buildNumber` and `secureBinaryVersion`
+//!   (which must be "2.1"); other options, such as `flags`, are ignored
+//! - `constants`: integer constants
+//! - `sources`: `extern(N)`, the N-th file passed to [`compile`], or a path in quotes
+//! - `section (N) { ... }`, with the statements
+//!   - `erase all;` and `erase START..END;`
+//!   - `load SOURCE;` and `load SOURCE > ADDRESS;`
+//!   - `load PATTERN > START..END;`, which fills (`fill PATTERN > START..END;` is accepted too)
+//!   - `jump ADDRESS;`, `jump_sp STACK_POINTER ADDRESS;` and `call ADDRESS;`,
+//!     optionally with an argument, as in `call ADDRESS(ARGUMENT);`
+//!   - `version_check sec VERSION;` and `version_check nsec VERSION;`
+//!
+//! Integers are decimal, `0x` hex or `0b` binary, optionally with a `K` or `M` multiplier.
+//! Fill patterns are replicated to 32 bits according to their size, which is set by
+//! a `.b`, `.h` or `.w` suffix, or else is the smallest that fits the value (as in elftosb).
+//! Comments are C and C++ style.
+//!
+//! Not supported are expressions, `if` statements, ELF sections and symbols, and section options.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context as _, Result};
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_until, take_while, take_while1},
+    character::complete::{char, digit1, hex_digit1, multispace1, one_of, satisfy},
+    combinator::{all_consuming, cut, map, map_res, opt, recognize, value, verify},
+    multi::many0,
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+};
+
+use super::command::{
+    BootCommandDescription, BootSectionDescription, SingleBootCommandDescription,
+};
+use super::{Config, Sb21FileParameters, Version};
+
+type IResult<'a, T> = nom::IResult<&'a str, T>;
+
+/// Compiled command file, cf. [`compile`].
+#[derive(Clone, Debug)]
+pub struct CommandFile {
+    /// Versions and build number from the `options`; missing versions are 0.0.0,
+    /// a missing build number is 0.
+    pub parameters: Sb21FileParameters,
+    pub sections: Vec<BootSectionDescription>,
+}
+
+impl CommandFile {
+    /// Replaces firmware versions, build number and commands of `config` with those of the
+    /// command file.
+    pub fn apply(&self, config: &mut Config) {
+        config.firmware.product = self.parameters.product;
+        config.firmware.component = self.parameters.component;
+        config.firmware.build = self.parameters.build;
+        config.commands.clear();
+        config.sections = self.sections.clone();
+    }
+}
+
+/// Reads and compiles a command file, cf. [`compile`].
+pub fn read<P: AsRef<Path>>(path: P, externs: &[&str]) -> Result<CommandFile> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read command file {}", path.display()))?;
+    compile(&source, externs).map_err(|error| anyhow!("{}: {:#}", path.display(), error))
+}
+
+/// Compiles a command file, `externs` are the files for `extern(0)`, `extern(1)`, etc.
+pub fn compile(source: &str, externs: &[&str]) -> Result<CommandFile> {
+    let blocks = parse(source)?;
+
+    let mut compiler = Compiler::default();
+    let mut parameters = Sb21FileParameters {
+        nonce: [0; 4],
+        timestamp: 0,
+        product: Version::from("0.0.0"),
+        component: Version::from("0.0.0"),
+        build: 0,
+        sb_header_padding: [0; 4],
+    };
+    let mut sections: Vec<BootSectionDescription> = Vec::new();
+
+    for block in blocks {
+        match block {
+            Block::Options(options) => {
+                for (name, value) in options {
+                    match name.as_str() {
+                        "productVersion" => parameters.product = compiler.version(&value)?,
+                        "componentVersion" => parameters.component = compiler.version(&value)?,
+                        "buildNumber" => parameters.build = compiler.int(&value)?.value,
+                        "secureBinaryVersion" => {
+                            let version = compiler.string(&value)?;
+                            if version != "2.1" {
+                                return Err(anyhow!(
+                                    "secureBinaryVersion {} is not supported, only 2.1",
+                                    version
+                                ));
+                            }
+                        }
+                        // signed images, the only kind lpc55 generates
+                        "flags" if compiler.int(&value)?.value == 0x8 => {}
+                        name => warn!("ignoring option {}", name),
+                    }
+                }
+            }
+            Block::Constants(constants) => {
+                for (name, value) in constants {
+                    let value = compiler.int(&value)?;
+                    compiler.constants.insert(name, value);
+                }
+            }
+            Block::Sources(sources) => {
+                for (name, source) in sources {
+                    let path = match source {
+                        Source::Extern(index) => {
+                            let index = compiler.int(&index)?.value as usize;
+                            externs
+                                .get(index)
+                                .ok_or_else(|| {
+                                    anyhow!(
+                                        "source {} is extern({}), but {} file(s) are given",
+                                        name,
+                                        index,
+                                        externs.len()
+                                    )
+                                })?
+                                .to_string()
+                        }
+                        Source::Path(path) => path,
+                    };
+                    compiler.sources.insert(name, path);
+                }
+            }
+            Block::Section(id, statements) => {
+                let id = compiler.int(&id)?.value;
+                if sections.iter().any(|section| section.id == id) {
+                    return Err(anyhow!("duplicate section ({})", id));
+                }
+                let commands = statements
+                    .iter()
+                    .map(|statement| compiler.command(statement))
+                    .collect::<Result<_>>()
+                    .with_context(|| format!("in section ({})", id))?;
+                sections.push(BootSectionDescription {
+                    id,
+                    flags: BootSectionDescription::BOOTABLE,
+                    commands,
+                });
+            }
+        }
+    }
+
+    if sections.is_empty() {
+        return Err(anyhow!("command file has no section"));
+    }
+    Ok(CommandFile {
+        parameters,
+        sections,
+    })
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Int {
+    value: u32,
+    /// In bytes, if given by a suffix
+    size: Option<u32>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Value {
+    Int(Int),
+    Str(String),
+    Ident(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Source {
+    Extern(Value),
+    Path(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Target {
+    Address(Value),
+    Range(Value, Value),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Statement {
+    EraseAll,
+    Erase(Value, Value),
+    Load(Value, Option<Target>),
+    Fill(Value, Value, Value),
+    Jump {
+        stack_pointer: Option<Value>,
+        address: Value,
+        argument: Option<Value>,
+    },
+    Call {
+        address: Value,
+        argument: Option<Value>,
+    },
+    VersionCheck {
+        secure: bool,
+        version: Value,
+    },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Block {
+    Options(Vec<(String, Value)>),
+    Constants(Vec<(String, Value)>),
+    Sources(Vec<(String, Source)>),
+    Section(Value, Vec<Statement>),
+}
+
+#[derive(Default)]
+struct Compiler {
+    constants: BTreeMap<String, Int>,
+    sources: BTreeMap<String, String>,
+}
+
+impl Compiler {
+    fn int(&self, value: &Value) -> Result<Int> {
+        match value {
+            Value::Int(int) => Ok(*int),
+            Value::Ident(name) => self
+                .constants
+                .get(name)
+                .copied()
+                .ok_or_else(|| anyhow!("unknown constant {}", name)),
+            Value::Str(string) => Err(anyhow!("expected an integer, found \"{}\"", string)),
+        }
+    }
+
+    fn string<'a>(&self, value: &'a Value) -> Result<&'a str> {
+        match value {
+            Value::Str(string) => Ok(string),
+            value => Err(anyhow!("expected a string, found {:?}", value)),
+        }
+    }
+
+    fn version(&self, value: &Value) -> Result<Version> {
+        let string = self.string(value)?;
+        let parts: Vec<u16> = string
+            .split('.')
+            .map(str::parse)
+            .collect::<core::result::Result<_, _>>()
+            .map_err(|_| anyhow!("invalid version \"{}\"", string))?;
+        match parts[..] {
+            [major, minor, patch] => Ok(Version {
+                major,
+                minor,
+                patch,
+            }),
+            _ => Err(anyhow!("version \"{}\" is not MAJOR.MINOR.PATCH", string)),
+        }
+    }
+
+    /// The pattern, replicated to 32 bits.
+    fn pattern(&self, value: &Value) -> Result<u32> {
+        let Int { value, size } = self.int(value)?;
+        let size = size.unwrap_or(match value {
+            0..=0xff => 1,
+            0x100..=0xffff => 2,
+            _ => 4,
+        });
+        Ok(match size {
+            1 if value <= 0xff => value * 0x0101_0101,
+            2 if value <= 0xffff => value * 0x0001_0001,
+            4 => value,
+            _ => {
+                return Err(anyhow!(
+                    "pattern 0x{:x} does not fit {} byte(s)",
+                    value,
+                    size
+                ))
+            }
+        })
+    }
+
+    fn fill(&self, pattern: &Value, start: &Value, end: &Value) -> Result<BootCommandDescription> {
+        Ok(BootCommandDescription::Single(
+            SingleBootCommandDescription::Fill {
+                start: self.int(start)?.value,
+                end: self.int(end)?.value,
+                pattern: self.pattern(pattern)?,
+            },
+        ))
+    }
+
+    fn argument(&self, argument: &Option<Value>) -> Result<u32> {
+        Ok(match argument {
+            Some(argument) => self.int(argument)?.value,
+            None => 0,
+        })
+    }
+
+    fn command(&self, statement: &Statement) -> Result<BootCommandDescription> {
+        use SingleBootCommandDescription::*;
+        Ok(BootCommandDescription::Single(match statement {
+            Statement::EraseAll => EraseAll,
+            Statement::Erase(start, end) => Erase {
+                start: self.int(start)?.value,
+                end: self.int(end)?.value,
+            },
+            Statement::Load(Value::Ident(name), target) if self.sources.contains_key(name) => {
+                let dst = match target {
+                    None => 0,
+                    Some(Target::Address(address)) => self.int(address)?.value,
+                    Some(Target::Range(..)) => {
+                        return Err(anyhow!("loading source {} into a range", name))
+                    }
+                };
+                Load {
+                    file: self.sources[name].clone(),
+                    src: 0,
+                    dst,
+                    len: None,
+                }
+            }
+            Statement::Load(Value::Str(_), _) => {
+                return Err(anyhow!("loading strings is not supported"))
+            }
+            Statement::Load(pattern, Some(Target::Range(start, end)))
+            | Statement::Fill(pattern, start, end) => return self.fill(pattern, start, end),
+            Statement::Load(pattern, _) => {
+                // a pattern, or an unknown source
+                self.pattern(pattern)?;
+                return Err(anyhow!("loading a pattern needs a range, as in START..END"));
+            }
+            Statement::Jump {
+                stack_pointer,
+                address,
+                argument,
+            } => Jump {
+                address: self.int(address)?.value,
+                argument: self.argument(argument)?,
+                stack_pointer: match stack_pointer {
+                    Some(stack_pointer) => Some(self.int(stack_pointer)?.value),
+                    None => None,
+                },
+            },
+            Statement::Call { address, argument } => Call {
+                address: self.int(address)?.value,
+                argument: self.argument(argument)?,
+            },
+            Statement::VersionCheck { secure, version } => {
+                let version = self.int(version)?.value;
+                if *secure {
+                    CheckSecureFirmwareVersion { version }
+                } else {
+                    CheckNonsecureFirmwareVersion { version }
+                }
+            }
+        }))
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Block>> {
+    let error = |remaining: &str| {
+        let offset = source.len() - remaining.len();
+        let line = source[..offset].matches('\n').count() + 1;
+        let text = source.lines().nth(line - 1).unwrap_or_default().trim();
+        anyhow!("syntax error in line {}: {}", line, text)
+    };
+    match all_consuming(terminated(many0(block), space))(source) {
+        Ok((_, blocks)) => Ok(blocks),
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => Err(error(e.input)),
+        Err(nom::Err::Incomplete(_)) => Err(error("")),
+    }
+}
+
+fn comment(i: &str) -> IResult<'_, &str> {
+    alt((
+        recognize(pair(tag("//"), take_while(|c| c != '\n'))),
+        recognize(tuple((tag("/*"), take_until("*/"), tag("*/")))),
+    ))(i)
+}
+
+/// Whitespace and comments
+fn space(i: &str) -> IResult<'_, ()> {
+    value((), many0(alt((multispace1, comment))))(i)
+}
+
+fn symbol<'a>(symbol: &'static str) -> impl FnMut(&'a str) -> IResult<'a, &'a str> {
+    preceded(space, tag(symbol))
+}
+
+fn ident(i: &str) -> IResult<'_, String> {
+    map(
+        preceded(
+            space,
+            recognize(pair(
+                satisfy(|c| c.is_ascii_alphabetic() || c == '_'),
+                take_while(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+            )),
+        ),
+        String::from,
+    )(i)
+}
+
+fn keyword<'a>(keyword: &'static str) -> impl FnMut(&'a str) -> IResult<'a, ()> {
+    value((), verify(ident, move |ident: &String| ident == keyword))
+}
+
+fn int(i: &str) -> IResult<'_, Int> {
+    let (i, value) = preceded(
+        space,
+        alt((
+            map_res(preceded(alt((tag("0x"), tag("0X"))), hex_digit1), |s| {
+                u32::from_str_radix(s, 16)
+            }),
+            map_res(
+                preceded(
+                    alt((tag("0b"), tag("0B"))),
+                    take_while1(|c| c == '0' || c == '1'),
+                ),
+                |s| u32::from_str_radix(s, 2),
+            ),
+            map_res(digit1, str::parse::<u32>),
+        )),
+    )(i)?;
+    let (i, multiplier) = opt(one_of("KM"))(i)?;
+    let value = match multiplier {
+        Some('K') => value.checked_mul(1 << 10),
+        Some('M') => value.checked_mul(1 << 20),
+        _ => Some(value),
+    }
+    .ok_or_else(|| nom::Err::Failure(nom::error::Error::new(i, nom::error::ErrorKind::TooLarge)))?;
+    let (i, size) = opt(preceded(char('.'), one_of("bhw")))(i)?;
+    let size = size.map(|size| match size {
+        'b' => 1,
+        'h' => 2,
+        _ => 4,
+    });
+    Ok((i, Int { value, size }))
+}
+
+fn string(i: &str) -> IResult<'_, String> {
+    map(
+        preceded(
+            space,
+            delimited(char('"'), take_while(|c| c != '"'), char('"')),
+        ),
+        String::from,
+    )(i)
+}
+
+fn val(i: &str) -> IResult<'_, Value> {
+    alt((
+        map(int, Value::Int),
+        map(string, Value::Str),
+        map(ident, Value::Ident),
+    ))(i)
+}
+
+fn range(i: &str) -> IResult<'_, (Value, Value)> {
+    separated_pair(val, symbol(".."), val)(i)
+}
+
+fn argument(i: &str) -> IResult<'_, Option<Value>> {
+    opt(delimited(symbol("("), val, symbol(")")))(i)
+}
+
+fn statement(i: &str) -> IResult<'_, Statement> {
+    let erase = preceded(
+        keyword("erase"),
+        cut(alt((
+            value(Statement::EraseAll, keyword("all")),
+            map(range, |(start, end)| Statement::Erase(start, end)),
+        ))),
+    );
+    let load = preceded(
+        keyword("load"),
+        cut(map(
+            pair(
+                val,
+                opt(preceded(
+                    symbol(">"),
+                    alt((
+                        map(range, |(start, end)| Target::Range(start, end)),
+                        map(val, Target::Address),
+                    )),
+                )),
+            ),
+            |(data, target)| Statement::Load(data, target),
+        )),
+    );
+    let fill = preceded(
+        keyword("fill"),
+        cut(map(
+            pair(val, preceded(symbol(">"), range)),
+            |(pattern, (start, end))| Statement::Fill(pattern, start, end),
+        )),
+    );
+    let jump = preceded(
+        keyword("jump"),
+        cut(map(pair(val, argument), |(address, argument)| {
+            Statement::Jump {
+                stack_pointer: None,
+                address,
+                argument,
+            }
+        })),
+    );
+    let jump_sp = preceded(
+        keyword("jump_sp"),
+        cut(map(
+            tuple((val, val, argument)),
+            |(stack_pointer, address, argument)| Statement::Jump {
+                stack_pointer: Some(stack_pointer),
+                address,
+                argument,
+            },
+        )),
+    );
+    let call = preceded(
+        keyword("call"),
+        cut(map(pair(val, argument), |(address, argument)| {
+            Statement::Call { address, argument }
+        })),
+    );
+    let version_check = preceded(
+        keyword("version_check"),
+        cut(map(
+            pair(
+                alt((value(true, keyword("sec")), value(false, keyword("nsec")))),
+                val,
+            ),
+            |(secure, version)| Statement::VersionCheck { secure, version },
+        )),
+    );
+    terminated(
+        alt((erase, load, fill, jump, jump_sp, call, version_check)),
+        cut(symbol(";")),
+    )(i)
+}
+
+fn assignments<'a, O>(
+    rhs: impl FnMut(&'a str) -> IResult<'a, O>,
+) -> impl FnMut(&'a str) -> IResult<'a, Vec<(String, O)>> {
+    delimited(
+        symbol("{"),
+        many0(terminated(
+            separated_pair(ident, cut(symbol("=")), cut(rhs)),
+            cut(symbol(";")),
+        )),
+        cut(symbol("}")),
+    )
+}
+
+fn source(i: &str) -> IResult<'_, Source> {
+    alt((
+        map(
+            preceded(
+                keyword("extern"),
+                cut(delimited(symbol("("), val, symbol(")"))),
+            ),
+            Source::Extern,
+        ),
+        map(string, Source::Path),
+    ))(i)
+}
+
+fn block(i: &str) -> IResult<'_, Block> {
+    alt((
+        map(
+            preceded(keyword("options"), cut(assignments(val))),
+            Block::Options,
+        ),
+        map(
+            preceded(keyword("constants"), cut(assignments(val))),
+            Block::Constants,
+        ),
+        map(
+            preceded(keyword("sources"), cut(assignments(source))),
+            Block::Sources,
+        ),
+        map(
+            preceded(
+                keyword("section"),
+                cut(pair(
+                    delimited(symbol("("), val, symbol(")")),
+                    delimited(symbol("{"), many0(statement), symbol("}")),
+                )),
+            ),
+            |(id, statements)| Block::Section(id, statements),
+        ),
+    ))(i)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::secure_binary::command::BootCommand;
+    use SingleBootCommandDescription::*;
+
+    fn commands(file: &CommandFile, section: usize) -> Vec<SingleBootCommandDescription> {
+        file.sections[section]
+            .commands
+            .iter()
+            .map(|command| match command {
+                BootCommandDescription::Single(command) => command.clone(),
+                command => panic!("unexpected {:?}", command),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn example() {
+        let file = read("example-cfgs/command.bd", &["signed.bin"]).unwrap();
+        assert_eq!(file.parameters.build, 1);
+        assert_eq!(file.parameters.product, Version::from("0.0.0"));
+        assert_eq!(file.sections.len(), 1);
+        assert_eq!(file.sections[0].id, 0);
+        assert_eq!(
+            commands(&file, 0),
+            [
+                Erase {
+                    start: 0,
+                    end: 0xC00
+                },
+                Load {
+                    file: "signed.bin".into(),
+                    src: 0,
+                    dst: 0,
+                    len: None
+                },
+            ]
+        );
+
+        assert!(read("example-cfgs/command.bd", &[]).is_err());
+    }
+
+    #[test]
+    fn statements() {
+        let file = compile(
+            r#"
+            // options
+            options {
+                productVersion = "1.02.3";
+                componentVersion = "4.5.6";
+                buildNumber = 0x10;
+                toolset = "GCC"; /* ignored */
+            }
+            constants {
+                app = 64K;
+                version = 7;
+            }
+            sources {
+                bootloader = extern(1);
+                application = "app.bin";
+            }
+            section (0) {
+                erase all;
+                load bootloader;
+                version_check sec version;
+                version_check nsec 0x8;
+            }
+            section (app) {
+                load 0xff > app..0x20000;
+                fill 0x1234.w > 0x20000..0x20010;
+                load 0x1234 > 0x20010..0x20020;
+                load application > app;
+                call 0x100(2);
+                jump_sp 0x20040000 app;
+            }
+            "#,
+            &["a.bin", "b.bin"],
+        )
+        .unwrap();
+
+        assert_eq!(file.parameters.product, Version::from("1.2.3"));
+        assert_eq!(file.parameters.component, Version::from("4.5.6"));
+        assert_eq!(file.parameters.build, 16);
+        assert_eq!(file.sections[1].id, 0x1_0000);
+        assert_eq!(
+            commands(&file, 0),
+            [
+                EraseAll,
+                Load {
+                    file: "b.bin".into(),
+                    src: 0,
+                    dst: 0,
+                    len: None
+                },
+                CheckSecureFirmwareVersion { version: 7 },
+                CheckNonsecureFirmwareVersion { version: 8 },
+            ]
+        );
+        assert_eq!(
+            commands(&file, 1),
+            [
+                Fill {
+                    start: 0x1_0000,
+                    end: 0x2_0000,
+                    pattern: 0xffff_ffff
+                },
+                Fill {
+                    start: 0x2_0000,
+                    end: 0x2_0010,
+                    pattern: 0x1234
+                },
+                Fill {
+                    start: 0x2_0010,
+                    end: 0x2_0020,
+                    pattern: 0x1234_1234
+                },
+                Load {
+                    file: "app.bin".into(),
+                    src: 0,
+                    dst: 0x1_0000,
+                    len: None
+                },
+                Call {
+                    address: 0x100,
+                    argument: 2
+                },
+                Jump {
+                    address: 0x1_0000,
+                    argument: 0,
+                    stack_pointer: Some(0x2004_0000)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source: &str| compile(source, &["a.bin"]).unwrap_err().to_string();
+
+        assert_eq!(
+            error("section (0) {\n  erase 0..4;\n  laod x > 0;\n}"),
+            "syntax error in line 3: laod x > 0;"
+        );
+        assert_eq!(
+            error("section (0) {\n  erase 0..4\n}"),
+            "syntax error in line 3: }"
+        );
+        assert!(
+            error("options { secureBinaryVersion = \"2.2\"; } section (0) {}").contains("only 2.1")
+        );
+        assert!(format!(
+            "{:#}",
+            compile("section (0) { load image > 0; }", &[]).unwrap_err()
+        )
+        .contains("unknown constant image"));
+        assert!(error("section (0) {} section (0) {}").contains("duplicate"));
+        assert!(error("options {}").contains("no section"));
+        assert!(format!(
+            "{:#}",
+            compile("section (0) { load 0x1ff.b > 0..4; }", &[]).unwrap_err()
+        )
+        .contains("does not fit"));
+    }
+
+    fn boot_commands(source: &str, externs: &[&str]) -> Result<Vec<BootCommand>> {
+        let file = compile(source, externs)?;
+        commands(&file, 0)
+            .iter()
+            .map(BootCommand::try_from)
+            .collect()
+    }
+
+    #[test]
+    fn ram_and_protected_flash() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("img.bin");
+        std::fs::write(&image, [0x42u8; 0x40]).unwrap();
+        let image = image.display().to_string();
+        let section = |statements: &str| {
+            format!(
+                "sources {{ img = extern(0); }} section (0) {{ {} }}",
+                statements
+            )
+        };
+
+        let commands = boot_commands(
+            &section("fill 0x00000000.w > 0x20000000..0x20000100; load img > 0x20000000;"),
+            &[&image],
+        )
+        .unwrap();
+        assert_eq!(
+            commands[0],
+            BootCommand::Fill {
+                address: 0x2000_0000,
+                bytes: 0x100,
+                pattern: 0
+            }
+        );
+        for command in &commands {
+            command.to_bytes();
+        }
+        // up to the PFR is fine
+        assert!(boot_commands(&section("erase 0x9C000..0x9DE00;"), &[&image]).is_ok());
+
+        for statements in [
+            "fill 0x00.w > 0x9DE00..0x9E000;",
+            "erase 0x1009E000..0x1009F000;",
+            "load img > 0x9DDF0;",
+            "load img > 0xFFFFFFF0;",
+            "erase 0x2000..0x1000;",
+        ] {
+            assert!(
+                boot_commands(&section(statements), &[&image]).is_err(),
+                "{}",
+                statements
+            );
+        }
+    }
+}
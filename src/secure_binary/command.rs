@@ -22,7 +22,32 @@ use nom::{
 use crate::crypto::crc32;
 use crate::util::is_default;
 
-const START_OF_PROTECTED_FLASH: u32 = 0x9_DE00;
+/// The protected flash area (PFR), and its secure alias.
+const PROTECTED_FLASH: [core::ops::Range<u32>; 2] = [0x9_DE00..0xA_0000, 0x1009_DE00..0x100A_0000];
+
+/// Checks `address..address + bytes` is in the address space, and outside the PFR.
+fn check_destination(command: &str, address: u32, bytes: u32) -> anyhow::Result<()> {
+    let end = address.checked_add(bytes).ok_or_else(|| {
+        anyhow::anyhow!(
+            "{} at 0x{:x} (0x{:x} bytes) exceeds the address space",
+            command,
+            address,
+            bytes
+        )
+    })?;
+    if PROTECTED_FLASH
+        .iter()
+        .any(|pfr| address < pfr.end && pfr.start < end)
+    {
+        return Err(anyhow::anyhow!(
+            "{} at 0x{:x}..0x{:x} overlaps the protected flash area, this is nearly always a mistake",
+            command,
+            address,
+            end
+        ));
+    }
+    Ok(())
+}
 
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -77,7 +102,9 @@ impl RawBootCommand {
         let calculated_checksum = bytes[1..16]
             .iter()
             .fold(0x5au8, |acc, x| acc.wrapping_add(*x));
-        assert_eq!(calculated_checksum, checksum);
+        if calculated_checksum != checksum {
+            return Err(nom::Err::Failure(()));
+        }
 
         Ok((
             i,
@@ -138,8 +165,20 @@ pub enum SingleBootCommandDescription {
         start: u32,
         end: u32,
     },
+
+    /// Erases all of flash.
+    EraseAll,
+
+    /// Fills `start..end` with the 32-bit `pattern`, cf. [`BootCommand::Fill`].
+    Fill {
+        start: u32,
+        end: u32,
+        pattern: u32,
+    },
     /// Load (part) of the data reference in `source` to flash.
     ///
+    /// ELF files are flattened to a plain image first, cf. [`crate::elf::read_image`].
+    ///
     /// The syntax is such that if source data and destination flash were slices
     /// `src: &[u8]` and `dst: &mut [u8]`, this command would do:
     /// ```ignore
@@ -173,6 +212,24 @@ pub enum SingleBootCommandDescription {
     CheckSecureFirmwareVersion {
         version: u32,
     },
+
+    /// Jumps to `address`, with `argument` in `r0`, optionally setting the stack pointer first.
+    Jump {
+        address: u32,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "is_default")]
+        argument: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stack_pointer: Option<u32>,
+    },
+
+    /// Calls the function at `address` with `argument`, the ROM continues after it returns.
+    Call {
+        address: u32,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "is_default")]
+        argument: u32,
+    },
 }
 
 /// High level commands that lpc55 will convert safely into commands used to define SB2.1 files
@@ -218,31 +275,100 @@ pub enum BootCommandDescription {
     Sequence(BootCommandSequenceDescription),
 }
 
+/// Boot section of an SB2.1 file, with its own boot tag
+///
+/// The ROM processes the first section, further sections (e.g., an application section next to
+/// a bootloader section) are addressed by their `id`.
+///
+/// ### Example
+/// ```ignore
+/// [[sections]]
+/// id = 0
+///
+/// [[sections.commands]]
+/// seq = "UploadSignedImage"
+///
+/// [[sections]]
+/// id = 1
+///
+/// [[sections.commands]]
+/// cmd = "Load"
+/// file = "app.bin"
+/// dst = 0x4_0000
+/// ```
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BootSectionDescription {
+    /// section identifier
+    pub id: u32,
+
+    /// section flags in the boot tag, bit 0 is "bootable", bit 1 is "cleartext" (default 1)
+    #[serde(default = "BootSectionDescription::default_flags")]
+    pub flags: u32,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<BootCommandDescription>,
+}
+
+impl BootSectionDescription {
+    pub const BOOTABLE: u32 = 0x1;
+
+    fn default_flags() -> u32 {
+        Self::BOOTABLE
+    }
+}
+
 impl<'a> TryFrom<&'a SingleBootCommandDescription> for BootCommand {
     type Error = anyhow::Error;
 
     fn try_from(cmd: &'a SingleBootCommandDescription) -> anyhow::Result<BootCommand> {
         use SingleBootCommandDescription::*;
         Ok(match cmd {
-            Erase { start, end } => BootCommand::EraseRegion {
-                address: *start,
-                bytes: core::cmp::max(0, *end - *start),
-            },
+            Erase { start, end } => {
+                let bytes = end.checked_sub(*start).ok_or_else(|| {
+                    anyhow::anyhow!("erase end 0x{:x} is before start 0x{:x}", end, start)
+                })?;
+                check_destination("erase", *start, bytes)?;
+                BootCommand::EraseRegion {
+                    address: *start,
+                    bytes,
+                }
+            }
+            EraseAll => BootCommand::EraseAll,
+            Fill {
+                start,
+                end,
+                pattern,
+            } => {
+                let bytes = end.checked_sub(*start).ok_or_else(|| {
+                    anyhow::anyhow!("fill end 0x{:x} is before start 0x{:x}", end, start)
+                })?;
+                check_destination("fill", *start, bytes)?;
+                BootCommand::Fill {
+                    address: *start,
+                    bytes,
+                    pattern: *pattern,
+                }
+            }
             Load {
                 file,
                 src,
                 dst,
                 len,
             } => {
-                let image = fs::read(file)?;
+                let image = crate::elf::read_image(file)?;
 
-                if let Some(len) = len {
-                    if (image.len() as u32) < len + src {
-                        return Err(anyhow::anyhow!("image too small!"));
-                    }
+                let src_len = image
+                    .len()
+                    .checked_sub(*src as usize)
+                    .ok_or_else(|| anyhow::anyhow!("image too small!"))?;
+                let len = len.map_or(src_len, |len| len as usize);
+                if src_len < len {
+                    return Err(anyhow::anyhow!("image too small!"));
                 }
-                let src_len = image.len() - *src as usize;
-                let len = len.unwrap_or(src_len as u32) as usize;
+                let bytes = u32::try_from(len)
+                    .map_err(|_| anyhow::anyhow!("image of {} bytes is too large", len))?;
+                check_destination("load", *dst, bytes)?;
                 let data = Vec::from(&image[*src as usize..][..len]);
                 BootCommand::Load {
                     address: *dst,
@@ -255,6 +381,19 @@ impl<'a> TryFrom<&'a SingleBootCommandDescription> for BootCommand {
             CheckSecureFirmwareVersion { version } => {
                 BootCommand::CheckSecureFirmwareVersion { version: *version }
             }
+            Jump {
+                address,
+                argument,
+                stack_pointer,
+            } => BootCommand::Jump {
+                address: *address,
+                argument: *argument,
+                stack_pointer: *stack_pointer,
+            },
+            Call { address, argument } => BootCommand::Call {
+                address: *address,
+                argument: *argument,
+            },
         })
     }
 }
@@ -305,6 +444,16 @@ pub enum BootCommand {
     CheckNonsecureFirmwareVersion {
         version: u32,
     },
+    // elftosb sets flag bit 1 if a stack pointer is given, which goes into `count`
+    Jump {
+        address: u32,
+        argument: u32,
+        stack_pointer: Option<u32>,
+    },
+    Call {
+        address: u32,
+        argument: u32,
+    },
 }
 
 impl BootCommand {
@@ -323,20 +472,14 @@ impl BootCommand {
                 cipher_blocks,
             } => {
                 cmd.tag = BootTag::Tag as u8;
-                if *last {
-                    cmd.flags = 1;
-                } else {
-                    cmd.flags = 0x8001;
-                }
+                // bit 0 marks the last section, elftosb sets bit 15 on all boot tags
+                cmd.flags = 0x8000 | *last as u16;
                 cmd.address = *tag;
                 cmd.data = *flags;
                 cmd.count = *cipher_blocks;
                 Vec::from(cmd.to_bytes().as_ref())
             }
             Load { address, data } => {
-                if address + data.len() as u32 >= START_OF_PROTECTED_FLASH {
-                    panic!("It is nearly always a mistake to write into the protected flash area");
-                }
                 //           CRC|tag|flags  addr     count    data
                 // expected:  54|02|0000    00000000 78090000 7E976AF8
                 // generated: 03|02|0000    00000000 78090000 FD96E7AC (...)
@@ -347,7 +490,7 @@ impl BootCommand {
                 // adds "padding till multiple of 16 bytes with zeros"
                 // to the CRC calculation.
                 cmd.data = crc32(data);
-                let blocks = (data.len() + 15) / 16;
+                let blocks = data.len().div_ceil(16);
                 // let blocks = (data.len() + 3) / 4;
                 // let padding = blocks*16 - data.len();
                 let mut vec = Vec::from(cmd.to_bytes().as_ref());
@@ -362,15 +505,23 @@ impl BootCommand {
                 vec.resize(16 + 16 * blocks, 0);
                 vec
             }
+            Fill {
+                address,
+                bytes,
+                pattern,
+            } => {
+                cmd.tag = BootTag::Fill as u8;
+                cmd.address = *address;
+                cmd.count = *bytes;
+                cmd.data = *pattern;
+                Vec::from(cmd.to_bytes().as_ref())
+            }
             EraseAll => {
                 cmd.tag = BootTag::Erase as u8;
                 cmd.flags = 1;
                 Vec::from(cmd.to_bytes().as_ref())
             }
             EraseRegion { address, bytes } => {
-                if address + bytes >= START_OF_PROTECTED_FLASH {
-                    panic!("It is nearly always a mistake to erase the protected flash area");
-                }
                 cmd.tag = BootTag::Erase as u8;
                 cmd.address = *address;
                 cmd.count = *bytes;
@@ -389,7 +540,26 @@ impl BootCommand {
                 cmd.count = *version;
                 Vec::from(cmd.to_bytes().as_ref())
             }
-            _ => todo!(),
+            Jump {
+                address,
+                argument,
+                stack_pointer,
+            } => {
+                cmd.tag = BootTag::Jump as u8;
+                if let Some(stack_pointer) = stack_pointer {
+                    cmd.flags = 2;
+                    cmd.count = *stack_pointer;
+                }
+                cmd.address = *address;
+                cmd.data = *argument;
+                Vec::from(cmd.to_bytes().as_ref())
+            }
+            Call { address, argument } => {
+                cmd.tag = BootTag::Call as u8;
+                cmd.address = *address;
+                cmd.data = *argument;
+                Vec::from(cmd.to_bytes().as_ref())
+            }
         }
     }
     pub fn from_bytes(bytes: &[u8]) -> nom::IResult<&[u8], Self, ()> {
@@ -412,7 +582,7 @@ impl BootCommand {
             ),
             // BootTag::Load => {
             2 => {
-                let blocks = (raw.count as usize + 15) / 16;
+                let blocks = (raw.count as usize).div_ceil(16);
                 let (i, data_ref) = take(blocks * 16)(i)?;
                 let data = Vec::from(&data_ref[..raw.count as usize]);
                 if raw.count as usize != data_ref.len() {
@@ -424,7 +594,9 @@ impl BootCommand {
                 // verify "CRC-32" calculation:
                 // raw.data == CRC over entire contents of `data_ref`, including padding
                 let calculated_crc = crc32(data_ref);
-                assert_eq!(calculated_crc, raw.data);
+                if calculated_crc != raw.data {
+                    return Err(nom::Err::Failure(()));
+                }
                 (
                     i,
                     Self::Load {
@@ -443,15 +615,32 @@ impl BootCommand {
                     pattern: raw.data,
                 },
             ),
+            // BootTag::Jump => {
+            4 => (
+                i,
+                Self::Jump {
+                    address: raw.address,
+                    argument: raw.data,
+                    stack_pointer: ((raw.flags & 2) != 0).then_some(raw.count),
+                },
+            ),
+            // BootTag::Call => {
+            5 => (
+                i,
+                Self::Call {
+                    address: raw.address,
+                    argument: raw.data,
+                },
+            ),
             // BootTag::Erase => {
             7 => {
                 let erase_all = (raw.flags & 1) != 0;
                 let disable_flash_security_state = (raw.flags & 2) != 0;
-                // not supported yet
-                assert!(!disable_flash_security_state);
                 let memory_controller_id = (raw.flags >> 8) & 0b1111;
-                // expect "internal" flash"
-                assert_eq!(memory_controller_id, 0x0);
+                // not supported yet, and expect "internal" flash
+                if disable_flash_security_state || memory_controller_id != 0x0 {
+                    return Err(nom::Err::Failure(()));
+                }
 
                 if erase_all {
                     // raw.address and raw.count are ignored
@@ -484,7 +673,8 @@ impl BootCommand {
                     (i, Self::CheckSecureFirmwareVersion { version: raw.count })
                 }
             }
-            _ => todo!("implement other boot commands"),
+            // other boot commands are not implemented
+            _ => return Err(nom::Err::Failure(())),
         })
     }
 }
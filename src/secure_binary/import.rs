@@ -0,0 +1,466 @@
+//! Import of NXP master boot image configurations.
+//!
+//! Both the elftosb JSON (cf. `example-cfgs/elftosb-signing.json`) and the spsdk YAML
+//! (`nxpimage mbi`) flavors are read, their keys largely coincide. Encrypted images, plain
+//! RAM images and TrustZone presets (their format differs from
+//! [`crate::signed_binary::trustzone::TzPreset`]) are not carried over. Such settings, as
+//! well as unknown keys, are reported in [`Import::unsupported`] instead of failing the
+//! import, so existing configurations can be migrated step by step.
+//!
+//! As with elftosb and spsdk, relative paths are looked up next to the imported file first.
+
+use core::fmt;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context as _, Result};
+use serde_yaml::Value;
+
+use super::{Config, Firmware, TrustZone, Version};
+use crate::pki::{CertificateUriChain, Certificates, Pki, SigningKey};
+use crate::signed_binary::ImageType;
+
+/// Result of [`import`].
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub config: Config,
+    /// Settings that the config does not reflect, sorted by key
+    pub unsupported: Vec<Unsupported>,
+}
+
+/// A setting of the imported file that lpc55 does not support.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Unsupported {
+    pub key: String,
+    pub value: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}: {}", self.key, self.value, self.reason)
+    }
+}
+
+/// Reads an elftosb JSON or spsdk YAML file, cf. [`from_str`].
+pub fn import<P: AsRef<Path>>(path: P) -> Result<Import> {
+    let path = path.as_ref();
+    let data =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    from_str(&data, path.parent())
+}
+
+/// Converts an elftosb JSON or spsdk YAML configuration into a [`Config`].
+///
+/// Relative paths are resolved against `base` if the file exists there.
+pub fn from_str(data: &str, base: Option<&Path>) -> Result<Import> {
+    // YAML parses JSON too, including the trailing commas elftosb accepts
+    let settings: BTreeMap<String, Value> =
+        serde_yaml::from_str(data).context("neither JSON nor YAML")?;
+
+    let path = |value: &Value| -> Option<String> {
+        let path = value.as_str().filter(|path| !path.is_empty())?;
+        Some(match base.map(|base| base.join(path)) {
+            Some(joined) if Path::new(path).is_relative() && joined.exists() => {
+                joined.display().to_string()
+            }
+            _ => path.to_string(),
+        })
+    };
+
+    let mut unsupported = Vec::new();
+    let mut report = |key: &str, value: &Value, reason: &str| {
+        unsupported.push(Unsupported {
+            key: key.to_string(),
+            value: display(value),
+            reason: reason.to_string(),
+        })
+    };
+
+    let mut image = String::new();
+    let mut signed_image = String::new();
+    let mut build = 0;
+    let mut signing_key = String::new();
+    let mut roots: [Option<String>; 4] = Default::default();
+    let mut chains: [BTreeMap<u32, String>; 4] = Default::default();
+    let mut main_root = None;
+    let mut trust_zone = TrustZone::default();
+    let mut ram = None;
+    let mut authentication = Authentication::Signed;
+    let mut address_key = None;
+
+    for (key, value) in &settings {
+        match key.as_str() {
+            "family" => match value.as_str().unwrap_or_default().to_ascii_lowercase() {
+                family if family.starts_with("lpc55s3") => report(
+                    key,
+                    value,
+                    "LPC55S3x uses SB3.1 containers, cf. `assemble-sb31`",
+                ),
+                family if family.starts_with("lpc55") => {}
+                _ => report(key, value, "not an LPC55 family"),
+            },
+            "inputImageFile" => image = path(value).unwrap_or_default(),
+            "masterBootOutputFile" => signed_image = path(value).unwrap_or_default(),
+            "imageBuildNumber" => match int(value) {
+                Some(number) if number <= u32::MAX as u64 => build = number as u32,
+                _ => report(key, value, "not a 32-bit build number"),
+            },
+            "imageLinkAddress" | "outputImageExecutionAddress" => match int(value) {
+                Some(address) if address <= u32::MAX as u64 => {
+                    address_key = Some((key, address as u32))
+                }
+                _ => report(key, value, "not a 32-bit address"),
+            },
+            "outputImageExecutionTarget" => {
+                match value
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_ascii_lowercase()
+                    .as_str()
+                {
+                    "xip" | "internal flash (xip)" => ram = None,
+                    "load-to-ram" | "ram" => ram = Some((key, value)),
+                    _ => report(key, value, "only execute-in-place or RAM images"),
+                }
+            }
+            "outputImageAuthenticationType" => {
+                match value
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_ascii_lowercase()
+                    .as_str()
+                {
+                    "plain" => authentication = Authentication::Plain,
+                    "crc" => authentication = Authentication::Crc,
+                    "signed" => authentication = Authentication::Signed,
+                    _ => report(key, value, "only plain, CRC or signed images"),
+                }
+            }
+            "enableTrustZone" => match value.as_bool() {
+                Some(enabled) => trust_zone.enabled = enabled,
+                None => report(key, value, "not a boolean"),
+            },
+            "enableHwUserModeKeys" => {
+                if value.as_bool() != Some(false) {
+                    report(key, value, "not supported")
+                }
+            }
+            "trustZonePresetFile" => {
+                if path(value).is_some() {
+                    report(
+                        key,
+                        value,
+                        "convert the preset to a `trust-zone.preset` file",
+                    )
+                }
+            }
+            "mainCertPrivateKeyFile" | "signPrivateKey" | "mainRootCertPrivateKeyFile" => {
+                if let Some(path) = path(value) {
+                    signing_key = format!("file:{}", path);
+                }
+            }
+            "mainCertChainId" | "mainRootCertId" => match int(value) {
+                Some(id) if id < 4 => main_root = Some((key, id as usize)),
+                _ => report(key, value, "root certificate IDs are 0 to 3"),
+            },
+            key => match certificate_key(key) {
+                Some((root, None)) => roots[root] = path(value),
+                Some((root, Some(index))) => {
+                    if let Some(path) = path(value) {
+                        chains[root].insert(index, path);
+                    }
+                }
+                None => report(key, value, "unknown setting"),
+            },
+        }
+    }
+
+    let image_type = match (authentication, ram) {
+        (Authentication::Plain, None) => ImageType::Plain,
+        (Authentication::Crc, None) => ImageType::CrcXip,
+        (Authentication::Signed, None) => ImageType::SignedXip,
+        (Authentication::Crc, Some(_)) => ImageType::CrcRam,
+        (Authentication::Signed, Some(_)) => ImageType::SignedRam,
+        (Authentication::Plain, Some((key, value))) => {
+            unsupported.push(Unsupported {
+                key: key.clone(),
+                value: display(value),
+                reason: "plain images execute in place".into(),
+            });
+            ImageType::Plain
+        }
+    };
+    let mut load_address = 0;
+    if let Some((key, address)) = address_key {
+        match image_type.is_ram() {
+            true => load_address = address,
+            false if address != 0 => unsupported.push(Unsupported {
+                key: key.clone(),
+                value: format!("0x{:x}", address),
+                reason: "images in flash are linked to its start".into(),
+            }),
+            false => {}
+        }
+    }
+
+    let certificates = roots
+        .iter()
+        .zip(chains)
+        .enumerate()
+        .map(|(n, (root, chain))| {
+            // unsigned images need no certificates, but the config has them
+            if !image_type.is_signed() && roots.iter().all(Option::is_none) {
+                return Ok((String::new(), Vec::new()));
+            }
+            let root = root.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "rootCertificate{}File is missing, lpc55 needs four root certificates",
+                    n
+                )
+            })?;
+            Ok((
+                format!("file:{}", root),
+                chain
+                    .into_values()
+                    .map(|path| format!("file:{}", path))
+                    .collect::<Vec<_>>(),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // TOML has no mixed arrays, so chains for all roots, if any
+    let chained = certificates.iter().any(|(_, chain)| !chain.is_empty());
+    let certificates: Vec<CertificateUriChain> = certificates
+        .into_iter()
+        .map(|(root, chain)| match chained {
+            true => CertificateUriChain::Chain { root, chain },
+            false => CertificateUriChain::Root(root),
+        })
+        .collect();
+
+    let pki = Pki {
+        signing_key,
+        certificates: certificates.try_into().unwrap(),
+    };
+
+    // the signing key selects the certificate, check it's the one the file meant
+    if let Some((key, id)) = main_root.filter(|_| !pki.signing_key.is_empty()) {
+        if let (Ok(certificates), Ok(signing_key)) = (
+            Certificates::try_from_pki(&pki),
+            SigningKey::try_from_uri(pki.signing_key.as_ref()),
+        ) {
+            match signing_key
+                .public_key()
+                .and_then(|key| certificates.index_of(key))
+            {
+                Ok(slot) if usize::from(slot) == id => {}
+                _ => unsupported.push(Unsupported {
+                    key: key.clone(),
+                    value: id.to_string(),
+                    reason: "the signing key does not belong to this root certificate".into(),
+                }),
+            }
+        }
+    }
+
+    let config = Config {
+        firmware: Firmware {
+            image,
+            signed_image,
+            secure_boot_image: Default::default(),
+            build,
+            component: Version::from("0.0.0"),
+            product: Version::from("0.0.0"),
+            image_type,
+            load_address,
+        },
+        pki,
+        reproducibility: Default::default(),
+        factory_settings: Default::default(),
+        customer_settings: Default::default(),
+        confidentiality: Default::default(),
+        keystore: Default::default(),
+        trust_zone,
+        commands: Default::default(),
+        sections: Default::default(),
+    };
+    Ok(Import {
+        config,
+        unsupported,
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Authentication {
+    Plain,
+    Crc,
+    Signed,
+}
+
+/// `rootCertificate{N}File` and `chainCertificate{N}File{M}`
+fn certificate_key(key: &str) -> Option<(usize, Option<u32>)> {
+    let root = |n: &str| n.parse().ok().filter(|n: &usize| *n < 4);
+    if let Some(n) = key
+        .strip_prefix("rootCertificate")
+        .and_then(|key| key.strip_suffix("File"))
+    {
+        return Some((root(n)?, None));
+    }
+    let (n, m) = key.strip_prefix("chainCertificate")?.split_once("File")?;
+    Some((root(n)?, Some(m.parse().ok()?)))
+}
+
+/// Integer, or string with decimal or `0x` hex integer
+fn int(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(string) => match string.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => string.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(string) => format!("{:?}", string),
+        value => serde_yaml::to_string(value)
+            .map(|yaml| yaml.trim_start_matches("---").trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn elftosb_json() {
+        let import = import("example-cfgs/elftosb-signing.json").unwrap();
+        assert_eq!(import.unsupported, []);
+        let config = import.config;
+        assert!(!config.trust_zone.enabled);
+        assert_eq!(config.firmware.image, "./blinky-red.bin");
+        assert_eq!(
+            config.firmware.signed_image,
+            "elftosb-blinky-red-signed.bin"
+        );
+        assert_eq!(
+            config.pki.signing_key,
+            "file:example-root-certs/ca_private_key_0.pem"
+        );
+        assert_eq!(
+            config.pki.certificates[3],
+            CertificateUriChain::Root("file:example-root-certs/ca_certificate_3.der".into())
+        );
+        // round trips through TOML
+        let toml = toml::to_string(&config).unwrap();
+        assert!(toml::from_str::<Config>(&toml).is_ok());
+    }
+
+    #[test]
+    fn spsdk_yaml() {
+        let yaml = r#"
+family: lpc55s6x
+inputImageFile: blinky-red.bin
+masterBootOutputFile: blinky-red-signed.bin
+outputImageExecutionTarget: xip
+outputImageAuthenticationType: signed
+outputImageExecutionAddress: 0x1000
+enableTrustZone: true
+trustZonePresetFile: tz.yaml
+imageBuildNumber: 7
+mainRootCertId: 1
+signPrivateKey: ca_private_key_0.pem
+rootCertificate0File: ca_certificate_0.der
+rootCertificate1File: ca_certificate_1.der
+rootCertificate2File: ca_certificate_2.der
+rootCertificate3File: ca_certificate_3.der
+"#;
+        let import = from_str(yaml, Some(Path::new("example-file-certs"))).unwrap();
+        let config = &import.config;
+        assert_eq!(config.firmware.build, 7);
+        assert!(config.trust_zone.enabled);
+        // found next to the (virtual) imported file
+        assert_eq!(
+            config.pki.signing_key,
+            "file:example-file-certs/ca_private_key_0.pem"
+        );
+
+        let keys: Vec<&str> = import.unsupported.iter().map(|u| u.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "trustZonePresetFile",
+                "outputImageExecutionAddress",
+                // the key belongs to root 0
+                "mainRootCertId",
+            ]
+        );
+        assert_eq!(
+            import.unsupported[0].to_string(),
+            "trustZonePresetFile = \"tz.yaml\": convert the preset to a `trust-zone.preset` file"
+        );
+
+        let chained = format!(
+            "{}chainCertificate1File1: leaf.der\nchainCertificate1File0: intermediate.der\n",
+            yaml
+        );
+        let config = from_str(&chained, Some(Path::new("example-file-certs")))
+            .unwrap()
+            .config;
+        assert_eq!(
+            config.pki.certificates[1].chain(),
+            ["file:intermediate.der", "file:leaf.der"]
+        );
+        assert!(config.pki.certificates[0].chain().is_empty());
+        let toml = toml::to_string(&config).unwrap();
+        assert!(toml::from_str::<Config>(&toml).is_ok());
+    }
+
+    #[test]
+    fn image_types() {
+        let roots: String = (0..4)
+            .map(|n| format!("rootCertificate{}File: ca_certificate_{}.der\n", n, n))
+            .collect();
+        let import = |settings: &str| from_str(&format!("{}{}", settings, roots), None).unwrap();
+
+        let config = import("outputImageAuthenticationType: CRC\n").config;
+        assert_eq!(config.firmware.image_type, ImageType::CrcXip);
+        // no certificates needed
+        let config = from_str("outputImageAuthenticationType: CRC\n", None)
+            .unwrap()
+            .config;
+        assert_eq!(config.firmware.image_type, ImageType::CrcXip);
+        assert!(from_str("outputImageAuthenticationType: signed\n", None).is_err());
+
+        let import = import(
+            "outputImageExecutionTarget: load-to-ram\noutputImageAuthenticationType: signed\noutputImageExecutionAddress: 0x20000000\n",
+        );
+        assert_eq!(import.unsupported, []);
+        assert_eq!(import.config.firmware.image_type, ImageType::SignedRam);
+        assert_eq!(import.config.firmware.load_address, 0x2000_0000);
+
+        let unsupported = from_str(
+            &format!(
+                "outputImageExecutionTarget: RAM\noutputImageAuthenticationType: Plain\n{}",
+                roots
+            ),
+            None,
+        )
+        .unwrap()
+        .unsupported;
+        assert_eq!(
+            unsupported[0].to_string(),
+            "outputImageExecutionTarget = \"RAM\": plain images execute in place"
+        );
+    }
+
+    #[test]
+    fn missing_root() {
+        let error = from_str("rootCertificate0File: a.der\nfoo: 1", None).unwrap_err();
+        assert!(error.to_string().contains("rootCertificate1File"));
+    }
+}
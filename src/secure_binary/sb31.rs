@@ -0,0 +1,1288 @@
+//! Generator and parser for SB3.1 containers, as used by the LPC55S3x family
+//!
+//! The format is as follows:
+//!
+//! Header part (signed):
+//! - Sb31Header (60B)
+//! - hash of the first data block (32B or 48B)
+//! - CertificateBlock (version 2.1)
+//!   - header ("chdr", 12B)
+//!   - root key record: flags (4B), hashes of all root keys if more than one, root public key
+//!   - optional ISK certificate: the image signing key (ISK), signed by the root key
+//! - ECDSA signature (r || s) by the ISK, or the root key if there is no ISK
+//!
+//! Data blocks (block size in header):
+//! - block number (4B, starting at 1)
+//! - 256B of the encrypted command section
+//! - hash of the next block (all zeros for the last block)
+//!
+//! The curve of the root keys determines the hash: P-256 goes with SHA-256, P-384 with SHA-384.
+//!
+//! The command section is a 16B section header, followed by the commands, each with a 16B header
+//! starting with the tag 0x55AAAA55. It is zero-padded to 256B blocks, each of which is encrypted
+//! with AES-256-CBC. The block keys are derived from the part common key (PCK) via a key
+//! derivation key (KDK), which itself is derived from the PCK and the header timestamp.
+
+use core::convert::{TryFrom, TryInto};
+use std::fs;
+
+use anyhow::{Context as _, Result};
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{le_u16, le_u32, le_u64},
+    sequence::tuple,
+};
+use p256::elliptic_curve::sec1::ToEncodedPoint as _;
+use p256::pkcs8::{DecodePrivateKey as _, DecodePublicKey as _};
+use serde::{Deserialize, Serialize};
+
+use super::command::{BootCommand, SingleBootCommandDescription};
+use super::{sniff, Filetype};
+use crate::crypto::{aes_cbc_decrypt, aes_cbc_encrypt, cmac, sha256, sha384};
+use crate::pki::split_once;
+use crate::util::{block_pad, block_pad_len, hex_deserialize_256, hex_serialize, is_default};
+
+/// Configuration file format for SB3.1 containers.
+///
+/// The counterpart of [`Config`](super::Config) for the LPC55S3x family,
+/// using ECC instead of RSA keys.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub firmware: Firmware,
+    pub pki: Pki,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub reproducibility: Reproducibility,
+
+    /// Commands for the SB file
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub commands: Vec<SingleBootCommandDescription>,
+}
+
+impl TryFrom<&'_ str> for Config {
+    type Error = anyhow::Error;
+    fn try_from(config_filename: &str) -> anyhow::Result<Self> {
+        let config = fs::read_to_string(config_filename)
+            .with_context(|| format!("Failed to read config from {}", config_filename))?;
+        let config: Config = toml::from_str(&config)?;
+        trace!("{:#?}", &config);
+        Ok(config)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Firmware {
+    /// Path to place signed SB3.1 file
+    pub secure_boot_image: String,
+
+    /// Firmware version, compared with the device's firmware version counter
+    pub version: u32,
+
+    /// Free text of at most 16 bytes
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub description: String,
+
+    /// Part common key (PCK), as provisioned in the device.
+    ///
+    /// The keys encrypting the command section are derived from it.
+    #[serde(serialize_with = "hex_serialize")]
+    #[serde(deserialize_with = "hex_deserialize_256")]
+    pub pck: [u8; 32],
+}
+
+/// ECC keys for SB3.1 containers.
+///
+/// Only `file:` URIs are supported, pointing to PEM files.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Pki {
+    /// Root public keys (SubjectPublicKeyInfo), one to four, all on the same curve.
+    pub root_keys: Vec<String>,
+
+    /// Private key (PKCS #8 or SEC1) of one of the root keys.
+    pub signing_key: String,
+
+    /// Private key of an image signing key (ISK).
+    ///
+    /// If set, the root key signs an ISK certificate, and the ISK signs the container.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isk_key: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Reproducibility {
+    /// Timestamp in seconds since 1970-01-01, also input to the key derivation.
+    ///
+    /// If left out, zero is used.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Curve {
+    P256,
+    P384,
+}
+
+impl Curve {
+    /// Size of a coordinate, a signature component, and the associated hash
+    pub fn size(&self) -> usize {
+        match self {
+            Curve::P256 => 32,
+            Curve::P384 => 48,
+        }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Curve::P256 => sha256(data).to_vec(),
+            Curve::P384 => sha384(data).to_vec(),
+        }
+    }
+
+    fn key_type(&self) -> u32 {
+        match self {
+            Curve::P256 => 1,
+            Curve::P384 => 2,
+        }
+    }
+
+    fn from_key_type(key_type: u32) -> Option<Self> {
+        match key_type {
+            1 => Some(Curve::P256),
+            2 => Some(Curve::P384),
+            _ => None,
+        }
+    }
+}
+
+fn read_pem(uri: &str) -> Result<String> {
+    match split_once(uri, ':') {
+        Some(("file", path)) => fs::read_to_string(path)
+            .with_context(|| format!("Failed to read key from PEM file {}", path)),
+        _ => Err(anyhow::anyhow!("only file key URIs supported, not {}", uri)),
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EcPublicKey {
+    P256(p256::PublicKey),
+    P384(p384::PublicKey),
+}
+
+impl EcPublicKey {
+    pub fn try_from_uri(uri: &str) -> Result<Self> {
+        let pem = read_pem(uri)?;
+        if let Ok(key) = p256::PublicKey::from_public_key_pem(&pem) {
+            return Ok(Self::P256(key));
+        }
+        p384::PublicKey::from_public_key_pem(&pem)
+            .map(Self::P384)
+            .map_err(|_| anyhow::anyhow!("{} is neither a P-256 nor a P-384 public key", uri))
+    }
+
+    pub fn curve(&self) -> Curve {
+        match self {
+            Self::P256(_) => Curve::P256,
+            Self::P384(_) => Curve::P384,
+        }
+    }
+
+    /// Uncompressed coordinates X || Y
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let point = match self {
+            Self::P256(key) => key.to_encoded_point(false).as_bytes().to_vec(),
+            Self::P384(key) => key.to_encoded_point(false).as_bytes().to_vec(),
+        };
+        // drop the SEC1 tag
+        point[1..].to_vec()
+    }
+
+    pub fn from_bytes(curve: Curve, bytes: &[u8]) -> Result<Self> {
+        let point = [&[0x04], bytes].concat();
+        Ok(match curve {
+            Curve::P256 => Self::P256(p256::PublicKey::from_sec1_bytes(&point)?),
+            Curve::P384 => Self::P384(p384::PublicKey::from_sec1_bytes(&point)?),
+        })
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        self.curve().hash(&self.to_bytes())
+    }
+
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        use p256::ecdsa::signature::Verifier as _;
+        match self {
+            Self::P256(key) => p256::ecdsa::Signature::try_from(signature)
+                .map(|signature| {
+                    p256::ecdsa::VerifyingKey::from(key)
+                        .verify(data, &signature)
+                        .is_ok()
+                })
+                .unwrap_or(false),
+            Self::P384(key) => p384::ecdsa::Signature::try_from(signature)
+                .map(|signature| {
+                    p384::ecdsa::VerifyingKey::from(key)
+                        .verify(data, &signature)
+                        .is_ok()
+                })
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum EcSigningKey {
+    P256(p256::ecdsa::SigningKey),
+    P384(p384::ecdsa::SigningKey),
+}
+
+impl EcSigningKey {
+    pub fn try_from_uri(uri: &str) -> Result<Self> {
+        let pem = read_pem(uri)?;
+        if let Ok(key) =
+            p256::SecretKey::from_pkcs8_pem(&pem).or_else(|_| p256::SecretKey::from_sec1_pem(&pem))
+        {
+            return Ok(Self::P256(key.into()));
+        }
+        p384::SecretKey::from_pkcs8_pem(&pem)
+            .or_else(|_| p384::SecretKey::from_sec1_pem(&pem))
+            .map(|key| Self::P384(key.into()))
+            .map_err(|_| anyhow::anyhow!("{} is neither a P-256 nor a P-384 private key", uri))
+    }
+
+    pub fn public_key(&self) -> EcPublicKey {
+        match self {
+            Self::P256(key) => EcPublicKey::P256(key.verifying_key().into()),
+            Self::P384(key) => EcPublicKey::P384(key.verifying_key().into()),
+        }
+    }
+
+    /// ECDSA signature r || s, with the hash matching the curve
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        use p256::ecdsa::signature::Signer as _;
+        match self {
+            Self::P256(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(data);
+                signature.as_ref().to_vec()
+            }
+            Self::P384(key) => {
+                let signature: p384::ecdsa::Signature = key.sign(data);
+                signature.as_ref().to_vec()
+            }
+        }
+    }
+}
+
+/// full size: 60 bytes
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sb31Header {
+    pub flags: u32,
+    pub block_count: u32,
+    pub block_size: u32,
+    /// seconds since 1970-01-01
+    pub timestamp: u64,
+    pub firmware_version: u32,
+    /// length of the header part, including the signature
+    pub image_total_length: u32,
+    pub image_type: u32,
+    pub certificate_block_offset: u32,
+    pub description: [u8; 16],
+}
+
+impl Sb31Header {
+    pub const LEN: usize = 60;
+    /// as opposed to NXP's own containers (7)
+    const IMAGE_TYPE: u32 = 6;
+
+    fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = Vec::from(b"sbv3".as_ref());
+        // format version 3.1, minor first
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&3u16.to_le_bytes());
+        bytes.extend_from_slice(&self.flags.to_le_bytes());
+        bytes.extend_from_slice(&self.block_count.to_le_bytes());
+        bytes.extend_from_slice(&self.block_size.to_le_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_le_bytes());
+        bytes.extend_from_slice(&self.firmware_version.to_le_bytes());
+        bytes.extend_from_slice(&self.image_total_length.to_le_bytes());
+        bytes.extend_from_slice(&self.image_type.to_le_bytes());
+        bytes.extend_from_slice(&self.certificate_block_offset.to_le_bytes());
+        bytes.extend_from_slice(&self.description);
+
+        bytes.try_into().unwrap()
+    }
+
+    fn from_bytes(i: &[u8]) -> nom::IResult<&[u8], Self, ()> {
+        let literal_u16 = |x: u16| nom::combinator::verify(le_u16, move |y| *y == x);
+
+        let (i, _magic) = tag("sbv3")(i)?;
+        let (i, _minor) = literal_u16(1)(i)?;
+        let (i, _major) = literal_u16(3)(i)?;
+        let (
+            i,
+            (
+                flags,
+                block_count,
+                block_size,
+                timestamp,
+                firmware_version,
+                image_total_length,
+                image_type,
+                certificate_block_offset,
+            ),
+        ) = tuple((
+            le_u32, le_u32, le_u32, le_u64, le_u32, le_u32, le_u32, le_u32,
+        ))(i)?;
+        let (i, description) = take(16u8)(i)?;
+
+        Ok((
+            i,
+            Self {
+                flags,
+                block_count,
+                block_size,
+                timestamp,
+                firmware_version,
+                image_total_length,
+                image_type,
+                certificate_block_offset,
+                description: description.try_into().unwrap(),
+            },
+        ))
+    }
+}
+
+/// Certificate block, version 2.1
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CertificateBlock {
+    /// Hashes of all root keys, only serialized if there is more than one
+    pub root_key_hashes: Vec<Vec<u8>>,
+    /// Index of `root_key` among the root keys
+    pub used_root_key: u32,
+    pub root_key: EcPublicKey,
+    pub isk: Option<IskCertificate>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IskCertificate {
+    pub constraints: u32,
+    pub public_key: EcPublicKey,
+    /// Signature by the root key over the root key record and the preceding ISK fields
+    pub signature: Vec<u8>,
+}
+
+impl CertificateBlock {
+    /// Sets up the certificate block, signing the ISK certificate with the root key if needed.
+    pub fn new(
+        root_keys: &[EcPublicKey],
+        root_signing_key: &EcSigningKey,
+        isk: Option<&EcPublicKey>,
+    ) -> Result<Self> {
+        if root_keys.is_empty() || root_keys.len() > 4 {
+            return Err(anyhow::anyhow!("need one to four root keys"));
+        }
+        let root_key = root_signing_key.public_key();
+        if root_keys.iter().any(|key| key.curve() != root_key.curve()) {
+            return Err(anyhow::anyhow!("root keys must all be on the same curve"));
+        }
+        let used_root_key = root_keys
+            .iter()
+            .position(|key| *key == root_key)
+            .ok_or_else(|| anyhow::anyhow!("signing key is none of the root keys"))?;
+
+        let mut block = Self {
+            root_key_hashes: root_keys.iter().map(|key| key.hash()).collect(),
+            used_root_key: used_root_key as u32,
+            root_key,
+            isk: None,
+        };
+        if let Some(public_key) = isk {
+            let constraints = 1;
+            let signature = root_signing_key.sign(&block.isk_signed_data(constraints, public_key));
+            block.isk = Some(IskCertificate {
+                constraints,
+                public_key: public_key.clone(),
+                signature,
+            });
+        }
+        Ok(block)
+    }
+
+    /// The key signing the container
+    pub fn signer(&self) -> &EcPublicKey {
+        self.isk
+            .as_ref()
+            .map(|isk| &isk.public_key)
+            .unwrap_or(&self.root_key)
+    }
+
+    /// Root key table hash (RKTH), to be provisioned in the CMPA
+    pub fn rkth(&self) -> Vec<u8> {
+        if self.root_key_hashes.len() > 1 {
+            self.root_key.curve().hash(&self.root_key_hashes.concat())
+        } else {
+            self.root_key.hash()
+        }
+    }
+
+    /// Checks the ISK certificate, if any
+    pub fn verify(&self) -> bool {
+        let root_key_listed =
+            self.root_key_hashes.get(self.used_root_key as usize) == Some(&self.root_key.hash());
+        let isk_signed = self.isk.as_ref().is_none_or(|isk| {
+            self.root_key.verify(
+                &self.isk_signed_data(isk.constraints, &isk.public_key),
+                &isk.signature,
+            )
+        });
+        root_key_listed && isk_signed
+    }
+
+    fn root_key_record(&self, uses_isk: bool) -> Vec<u8> {
+        let mut flags = self.root_key.curve().key_type()
+            | ((self.root_key_hashes.len() as u32) << 4)
+            | (self.used_root_key << 8);
+        if !uses_isk {
+            // CA flag: the root key signs the container itself
+            flags |= 1 << 31;
+        }
+        let mut record = Vec::from(flags.to_le_bytes().as_ref());
+        if self.root_key_hashes.len() > 1 {
+            record.extend_from_slice(&self.root_key_hashes.concat());
+        }
+        record.extend_from_slice(&self.root_key.to_bytes());
+        record
+    }
+
+    fn isk_signed_data(&self, constraints: u32, public_key: &EcPublicKey) -> Vec<u8> {
+        let public_key = public_key.to_bytes();
+        let mut data = self.root_key_record(true);
+        let signature_offset = 12 + public_key.len() as u32;
+        data.extend_from_slice(&signature_offset.to_le_bytes());
+        data.extend_from_slice(&constraints.to_le_bytes());
+        data.extend_from_slice(&public_key_flags(public_key.len()).to_le_bytes());
+        data.extend_from_slice(&public_key);
+        data
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let record = self.root_key_record(self.isk.is_some());
+        let mut isk = Vec::new();
+        if let Some(certificate) = &self.isk {
+            let signed_data =
+                self.isk_signed_data(certificate.constraints, &certificate.public_key);
+            isk.extend_from_slice(&signed_data[record.len()..]);
+            isk.extend_from_slice(&certificate.signature);
+        }
+
+        let mut bytes = Vec::from(b"chdr".as_ref());
+        // format version 2.1, minor first
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&((12 + record.len() + isk.len()) as u32).to_le_bytes());
+        bytes.extend_from_slice(&record);
+        bytes.extend_from_slice(&isk);
+        bytes
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<(&[u8], Self)> {
+        let (i, (_, _minor, _major, length, flags)) =
+            tuple((tag::<_, _, ()>("chdr"), le_u16, le_u16, le_u32, le_u32))(data)
+                .context("malformed certificate block header")?;
+        if data.len() < length as usize {
+            return Err(anyhow::anyhow!("truncated certificate block"));
+        }
+        let remainder = &data[length as usize..];
+
+        let curve = Curve::from_key_type(flags & 0xF)
+            .ok_or_else(|| anyhow::anyhow!("unknown root key type {}", flags & 0xF))?;
+        let count = ((flags >> 4) & 0xF) as usize;
+        let used_root_key = (flags >> 8) & 0xF;
+        let uses_isk = (flags >> 31) == 0;
+
+        let (i, root_key_hashes) = if count > 1 {
+            let (i, table) =
+                take::<_, _, ()>(count * curve.size())(i).context("truncated root key table")?;
+            (i, table.chunks(curve.size()).map(Vec::from).collect())
+        } else {
+            (i, Vec::new())
+        };
+        let (i, root_key) = take::<_, _, ()>(2 * curve.size())(i).context("truncated root key")?;
+        let root_key = EcPublicKey::from_bytes(curve, root_key)?;
+        let root_key_hashes = match count {
+            1 => vec![root_key.hash()],
+            _ => root_key_hashes,
+        };
+
+        let isk = if uses_isk {
+            let (i, (_signature_offset, constraints, isk_flags)) =
+                tuple((le_u32::<_, ()>, le_u32, le_u32))(i).context("truncated ISK certificate")?;
+            let isk_curve = Curve::from_key_type(isk_flags & 0xF)
+                .ok_or_else(|| anyhow::anyhow!("unknown ISK type {}", isk_flags & 0xF))?;
+            let (i, public_key) =
+                take::<_, _, ()>(2 * isk_curve.size())(i).context("truncated ISK")?;
+            let (_, signature) =
+                take::<_, _, ()>(2 * curve.size())(i).context("truncated ISK signature")?;
+            Some(IskCertificate {
+                constraints,
+                public_key: EcPublicKey::from_bytes(isk_curve, public_key)?,
+                signature: Vec::from(signature),
+            })
+        } else {
+            None
+        };
+
+        Ok((
+            remainder,
+            Self {
+                root_key_hashes,
+                used_root_key,
+                root_key,
+                isk,
+            },
+        ))
+    }
+}
+
+/// ISK flags, encoding the key type from the raw public key size
+fn public_key_flags(raw_len: usize) -> u32 {
+    match raw_len {
+        64 => Curve::P256.key_type(),
+        _ => Curve::P384.key_type(),
+    }
+}
+
+/// Commands of the boot section of SB3.1 files
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Sb31Command {
+    Erase {
+        address: u32,
+        bytes: u32,
+    },
+    Load {
+        address: u32,
+        data: Vec<u8>,
+    },
+    Execute {
+        address: u32,
+    },
+    Call {
+        address: u32,
+    },
+    Fill {
+        address: u32,
+        bytes: u32,
+        pattern: u32,
+    },
+    CheckSecureFirmwareVersion {
+        version: u32,
+    },
+    CheckNonsecureFirmwareVersion {
+        version: u32,
+    },
+    Reset,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Sb31Tag {
+    Erase = 1,
+    Load = 2,
+    Execute = 3,
+    Call = 4,
+    Fill = 12,
+    CheckFirmwareVersion = 13,
+    Reset = 14,
+}
+
+impl Sb31Command {
+    const TAG: u32 = 0x55AA_AA55;
+    const NONSECURE_COUNTER: u32 = 1;
+    const SECURE_COUNTER: u32 = 2;
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        use Sb31Command::*;
+        let header = |address: u32, length: u32, tag: Sb31Tag| {
+            let mut bytes = Vec::new();
+            for word in [Self::TAG, address, length, tag as u32] {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+            bytes
+        };
+        // memory ID and reserved words, or pattern for Fill
+        let extension = |first: u32| {
+            let mut bytes = Vec::from(first.to_le_bytes().as_ref());
+            bytes.resize(16, 0);
+            bytes
+        };
+        match self {
+            Erase { address, bytes } => {
+                let mut command = header(*address, *bytes, Sb31Tag::Erase);
+                command.append(&mut extension(0));
+                command
+            }
+            Load { address, data } => {
+                let mut command = header(*address, data.len() as u32, Sb31Tag::Load);
+                command.append(&mut extension(0));
+                command.extend_from_slice(data);
+                block_pad(&mut command);
+                command
+            }
+            Execute { address } => header(*address, 0, Sb31Tag::Execute),
+            Call { address } => header(*address, 0, Sb31Tag::Call),
+            Fill {
+                address,
+                bytes,
+                pattern,
+            } => {
+                let mut command = header(*address, *bytes, Sb31Tag::Fill);
+                command.append(&mut extension(*pattern));
+                command
+            }
+            CheckSecureFirmwareVersion { version } => header(
+                *version,
+                Self::SECURE_COUNTER,
+                Sb31Tag::CheckFirmwareVersion,
+            ),
+            CheckNonsecureFirmwareVersion { version } => header(
+                *version,
+                Self::NONSECURE_COUNTER,
+                Sb31Tag::CheckFirmwareVersion,
+            ),
+            Reset => header(0, 0, Sb31Tag::Reset),
+        }
+    }
+
+    pub fn from_bytes(i: &[u8]) -> nom::IResult<&[u8], Self, ()> {
+        use Sb31Command::*;
+        let (i, (_, address, length, tag)) = tuple((
+            nom::combinator::verify(le_u32, |tag| *tag == Self::TAG),
+            le_u32,
+            le_u32,
+            le_u32,
+        ))(i)?;
+        // memory ID, only internal flash is supported
+        let internal = |i| {
+            let (i, (memory_id, _reserved)) = tuple((le_u32, take(12u8)))(i)?;
+            match memory_id {
+                0 => Ok((i, ())),
+                _ => Err(nom::Err::Failure(())),
+            }
+        };
+
+        Ok(match tag {
+            1 => (
+                internal(i)?.0,
+                Erase {
+                    address,
+                    bytes: length,
+                },
+            ),
+            2 => {
+                let (i, ()) = internal(i)?;
+                let (i, data) = take(block_pad_len(length as usize))(i)?;
+                (
+                    i,
+                    Load {
+                        address,
+                        data: Vec::from(&data[..length as usize]),
+                    },
+                )
+            }
+            3 => (i, Execute { address }),
+            4 => (i, Call { address }),
+            12 => {
+                let (i, (pattern, _reserved)) = tuple((le_u32, take(12u8)))(i)?;
+                (
+                    i,
+                    Fill {
+                        address,
+                        bytes: length,
+                        pattern,
+                    },
+                )
+            }
+            13 => match length {
+                Self::SECURE_COUNTER => (i, CheckSecureFirmwareVersion { version: address }),
+                Self::NONSECURE_COUNTER => (i, CheckNonsecureFirmwareVersion { version: address }),
+                _ => return Err(nom::Err::Failure(())),
+            },
+            14 => (i, Reset),
+            // other commands are not implemented
+            _ => return Err(nom::Err::Failure(())),
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a SingleBootCommandDescription> for Sb31Command {
+    type Error = anyhow::Error;
+
+    fn try_from(cmd: &'a SingleBootCommandDescription) -> anyhow::Result<Sb31Command> {
+        Ok(match BootCommand::try_from(cmd)? {
+            BootCommand::EraseRegion { address, bytes } => Sb31Command::Erase { address, bytes },
+            BootCommand::Load { address, data } => Sb31Command::Load { address, data },
+            BootCommand::CheckSecureFirmwareVersion { version } => {
+                Sb31Command::CheckSecureFirmwareVersion { version }
+            }
+            BootCommand::CheckNonsecureFirmwareVersion { version } => {
+                Sb31Command::CheckNonsecureFirmwareVersion { version }
+            }
+            command => {
+                return Err(anyhow::anyhow!(
+                    "{:?} is not supported in SB3.1 files",
+                    command
+                ))
+            }
+        })
+    }
+}
+
+const BLOCK_DATA_LEN: usize = 256;
+
+/// Access rights of the derived keys
+const KDK_ACCESS_RIGHTS: u8 = 3;
+
+#[repr(u8)]
+#[derive(Clone, Copy)]
+enum KeyDerivationMode {
+    Kdk = 1,
+    Block = 2,
+}
+
+/// Input of the `iteration`th CMAC: the label (the constant as 12 byte little-endian number,
+/// access rights, mode, two zero bytes), an all-zero 8 byte context, then key length in bits
+/// and iteration as big-endian words, as in spsdk's `_get_key_derivation_data`.
+fn derivation_data(constant: u64, mode: KeyDerivationMode, iteration: u32) -> [u8; 32] {
+    let mut data = [0u8; 32];
+    data[..8].copy_from_slice(&constant.to_le_bytes());
+    data[12] = KDK_ACCESS_RIGHTS << 6;
+    data[13] = mode as u8;
+    data[24..28].copy_from_slice(&256u32.to_be_bytes());
+    data[28..].copy_from_slice(&iteration.to_be_bytes());
+    data
+}
+
+/// NIST SP 800-108 key derivation in counter mode, with AES-256-CMAC.
+fn derive_key(key: [u8; 32], constant: u64, mode: KeyDerivationMode) -> [u8; 32] {
+    let mut derived = [0u8; 32];
+    for (i, chunk) in derived.chunks_mut(16).enumerate() {
+        chunk.copy_from_slice(&cmac(key, &derivation_data(constant, mode, i as u32 + 1)));
+    }
+    derived
+}
+
+fn block_cipher(pck: [u8; 32], timestamp: u64) -> impl Fn(u32) -> [u8; 32] {
+    let kdk = derive_key(pck, timestamp, KeyDerivationMode::Kdk);
+    move |block_number| derive_key(kdk, block_number as u64, KeyDerivationMode::Block)
+}
+
+#[derive(Clone, Debug)]
+pub struct UnsignedSb31File {
+    pub timestamp: u64,
+    pub firmware_version: u32,
+    pub description: [u8; 16],
+    pub pck: [u8; 32],
+    pub certificate_block: CertificateBlock,
+    pub commands: Vec<Sb31Command>,
+}
+
+impl UnsignedSb31File {
+    pub fn try_assemble_from(config: &Config) -> Result<Self> {
+        let description = config.firmware.description.as_bytes();
+        if description.len() > 16 {
+            return Err(anyhow::anyhow!("description is longer than 16 bytes"));
+        }
+        let mut padded_description = [0u8; 16];
+        padded_description[..description.len()].copy_from_slice(description);
+
+        let root_keys = config
+            .pki
+            .root_keys
+            .iter()
+            .map(|uri| EcPublicKey::try_from_uri(uri))
+            .collect::<Result<Vec<_>>>()?;
+        let root_signing_key = EcSigningKey::try_from_uri(&config.pki.signing_key)?;
+        let isk = match &config.pki.isk_key {
+            Some(uri) => Some(EcSigningKey::try_from_uri(uri)?.public_key()),
+            None => None,
+        };
+        let certificate_block = CertificateBlock::new(&root_keys, &root_signing_key, isk.as_ref())?;
+
+        let commands = config
+            .commands
+            .iter()
+            .map(Sb31Command::try_from)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            timestamp: config.reproducibility.timestamp,
+            firmware_version: config.firmware.version,
+            description: padded_description,
+            pck: config.firmware.pck,
+            certificate_block,
+            commands,
+        })
+    }
+
+    /// Section header and commands, zero-padded to blocks
+    fn section(&self) -> Vec<u8> {
+        let commands: Vec<u8> = self.commands.iter().flat_map(|c| c.to_bytes()).collect();
+        let mut section = Vec::new();
+        // section UID, section type, length, reserved
+        for word in [1, 1, commands.len() as u32, 0] {
+            section.extend_from_slice(&word.to_le_bytes());
+        }
+        section.extend_from_slice(&commands);
+        section.resize(BLOCK_DATA_LEN * section.len().div_ceil(BLOCK_DATA_LEN), 0);
+        section
+    }
+
+    /// Signs with the ISK if there is one, with the root key otherwise.
+    pub fn sign(&self, signing_key: &EcSigningKey) -> Result<SignedSb31File> {
+        if signing_key.public_key() != *self.certificate_block.signer() {
+            return Err(anyhow::anyhow!(
+                "signing key does not match the certificate block"
+            ));
+        }
+        let curve = self.certificate_block.root_key.curve();
+
+        let block_key = block_cipher(self.pck, self.timestamp);
+        let encrypted: Vec<Vec<u8>> = self
+            .section()
+            .chunks(BLOCK_DATA_LEN)
+            .enumerate()
+            .map(|(i, chunk)| aes_cbc_encrypt(block_key(i as u32 + 1), [0u8; 16], chunk))
+            .collect();
+
+        // block number, hash of the next block (zero in the last one), encrypted data
+        let mut next_hash = vec![0u8; curve.size()];
+        let mut blocks = Vec::new();
+        for (i, data) in encrypted.iter().enumerate().rev() {
+            let mut block = Vec::from((i as u32 + 1).to_le_bytes().as_ref());
+            block.extend_from_slice(&next_hash);
+            block.extend_from_slice(data);
+            next_hash = curve.hash(&block);
+            blocks.push(block);
+        }
+        blocks.reverse();
+
+        let certificate_block_offset = Sb31Header::LEN + curve.size();
+        let signature_len = 2 * self.certificate_block.signer().curve().size();
+        let header = Sb31Header {
+            flags: 0,
+            block_count: blocks.len() as u32,
+            block_size: (4 + BLOCK_DATA_LEN + curve.size()) as u32,
+            timestamp: self.timestamp,
+            firmware_version: self.firmware_version,
+            image_total_length: (certificate_block_offset
+                + self.certificate_block.to_bytes().len()
+                + signature_len) as u32,
+            image_type: Sb31Header::IMAGE_TYPE,
+            certificate_block_offset: certificate_block_offset as u32,
+            description: self.description,
+        };
+
+        let mut file = SignedSb31File {
+            header,
+            first_block_hash: next_hash,
+            certificate_block: self.certificate_block.clone(),
+            signature: Vec::new(),
+            blocks,
+            commands: self.commands.clone(),
+        };
+        file.signature = signing_key.sign(&file.signed_data());
+        Ok(file)
+    }
+}
+
+/// A signed SB3.1 file, either freshly signed or decoded via [`SignedSb31File::from_bytes`].
+#[derive(Clone, Debug)]
+pub struct SignedSb31File {
+    pub header: Sb31Header,
+    pub first_block_hash: Vec<u8>,
+    pub certificate_block: CertificateBlock,
+    pub signature: Vec<u8>,
+    /// Serialized data blocks
+    pub blocks: Vec<Vec<u8>>,
+    /// Decrypted commands
+    pub commands: Vec<Sb31Command>,
+}
+
+/// Outcome of the checks of [`SignedSb31File::verify`].
+///
+/// Each field is `true` if the corresponding check passed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sb31Verification {
+    /// The used root key is in the root key table, and signed the ISK certificate (if any).
+    pub certificate_block: bool,
+    /// The ECDSA signature over the header part verifies with the ISK or root key.
+    pub signature: bool,
+    /// The hash chain from the header through all data blocks is intact.
+    pub block_hashes: bool,
+}
+
+impl Sb31Verification {
+    pub fn is_valid(&self) -> bool {
+        self.certificate_block && self.signature && self.block_hashes
+    }
+}
+
+impl SignedSb31File {
+    fn signed_data(&self) -> Vec<u8> {
+        let mut data = Vec::from(self.header.to_bytes().as_ref());
+        data.extend_from_slice(&self.first_block_hash);
+        data.extend_from_slice(&self.certificate_block.to_bytes());
+        data
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signed_data();
+        bytes.extend_from_slice(&self.signature);
+        for block in &self.blocks {
+            bytes.extend_from_slice(block);
+        }
+        bytes
+    }
+
+    /// Reads and decodes an SB3.1 file, cf. [`from_bytes`](Self::from_bytes).
+    pub fn read<P: AsRef<std::path::Path>>(path: P, pck: &[u8; 32]) -> Result<Self> {
+        let data = fs::read(path.as_ref())
+            .with_context(|| format!("Failed to read data from {}", path.as_ref().display()))?;
+        Self::from_bytes(&data, pck)
+    }
+
+    /// Parses an SB3.1 file and decrypts its command section with keys derived from `pck`.
+    ///
+    /// Malformed files are rejected, but neither signatures nor hashes are checked here,
+    /// use [`verify`](Self::verify) for this.
+    pub fn from_bytes(data: &[u8], pck: &[u8; 32]) -> Result<Self> {
+        if sniff(data)? != Filetype::Sb31 {
+            return Err(anyhow::anyhow!("Doesn't look like an SB 3.1 file"));
+        }
+
+        let (i, header) = Sb31Header::from_bytes(data).context("malformed SB3.1 header")?;
+        let offset = header.certificate_block_offset as usize;
+        if offset < Sb31Header::LEN || offset > data.len() {
+            return Err(anyhow::anyhow!("certificate block offset out of bounds"));
+        }
+        let first_block_hash = Vec::from(&i[..offset - Sb31Header::LEN]);
+        let (i, certificate_block) = CertificateBlock::from_bytes(&data[offset..])?;
+
+        let curve = certificate_block.root_key.curve();
+        if first_block_hash.len() != curve.size() {
+            return Err(anyhow::anyhow!("block hash does not match root key curve"));
+        }
+        let (i, signature) = take::<_, _, ()>(2 * certificate_block.signer().curve().size())(i)
+            .context("truncated signature")?;
+        if data.len() - i.len() != header.image_total_length as usize {
+            return Err(anyhow::anyhow!("header part length differs from header"));
+        }
+
+        let block_size = 4 + BLOCK_DATA_LEN + curve.size();
+        if header.block_size as usize != block_size
+            || i.len() != header.block_count as usize * block_size
+        {
+            return Err(anyhow::anyhow!("data blocks do not match header"));
+        }
+        let block_key = block_cipher(*pck, header.timestamp);
+        let mut blocks = Vec::new();
+        let mut section = Vec::new();
+        for (n, block) in i.chunks(block_size).enumerate() {
+            let block_number = n as u32 + 1;
+            if block[..4] != block_number.to_le_bytes() {
+                return Err(anyhow::anyhow!("block {} is out of order", block_number));
+            }
+            section.extend(aes_cbc_decrypt(
+                block_key(block_number),
+                [0u8; 16],
+                &block[4 + curve.size()..],
+            ));
+            blocks.push(Vec::from(block));
+        }
+
+        let (i, (_uid, _type, length, _reserved)) =
+            tuple((le_u32::<_, ()>, le_u32, le_u32, le_u32))(&section[..])
+                .context("truncated section header")?;
+        let (_, mut i) = take::<_, _, ()>(length)(i)
+            .context("section length exceeds data blocks, wrong PCK?")?;
+        let mut commands = Vec::new();
+        while !i.is_empty() {
+            let offset = length as usize - i.len();
+            let (rest, command) = Sb31Command::from_bytes(i)
+                .with_context(|| format!("invalid command at offset 0x{:x}, wrong PCK?", offset))?;
+            trace!("command: {:?}", &command);
+            commands.push(command);
+            i = rest;
+        }
+
+        Ok(Self {
+            header,
+            first_block_hash,
+            certificate_block,
+            signature: Vec::from(signature),
+            blocks,
+            commands,
+        })
+    }
+
+    /// Checks certificate block, ECDSA signature and block hashes.
+    pub fn verify(&self) -> Sb31Verification {
+        let curve = self.certificate_block.root_key.curve();
+        let mut expected_hash = self.first_block_hash.clone();
+        let mut block_hashes = true;
+        for block in &self.blocks {
+            block_hashes &= curve.hash(block) == expected_hash;
+            expected_hash = Vec::from(&block[4..][..curve.size()]);
+        }
+        block_hashes &= expected_hash.iter().all(|byte| *byte == 0);
+
+        Sb31Verification {
+            certificate_block: self.certificate_block.verify(),
+            signature: self
+                .certificate_block
+                .signer()
+                .verify(&self.signed_data(), &self.signature),
+            block_hashes,
+        }
+    }
+}
+
+/// Decodes an SB3.1 file, prints a summary and returns its verification report.
+pub fn show(filename: &str, pck: &[u8; 32]) -> Result<Sb31Verification> {
+    let file = SignedSb31File::read(filename, pck)?;
+    let header = &file.header;
+    let certificate_block = &file.certificate_block;
+
+    println!("firmware version: {}", header.firmware_version);
+    println!(
+        "description: {}",
+        String::from_utf8_lossy(&header.description).trim_end_matches('\0')
+    );
+    println!("timestamp: {}s since 1970-01-01", header.timestamp);
+    println!(
+        "root keys: {} on {:?}, using #{}",
+        certificate_block.root_key_hashes.len(),
+        certificate_block.root_key.curve(),
+        certificate_block.used_root_key
+    );
+    println!("rkth: {}", hexstr!(&certificate_block.rkth()));
+    if let Some(isk) = &certificate_block.isk {
+        println!("isk: {}", hexstr!(&isk.public_key.hash()));
+    }
+    println!("commands:");
+    for command in &file.commands {
+        match command {
+            Sb31Command::Load { address, data } => {
+                println!(
+                    "- Load {{ address: 0x{:x}, bytes: {} }}",
+                    address,
+                    data.len()
+                )
+            }
+            command => println!("- {:?}", command),
+        }
+    }
+
+    let verification = file.verify();
+    let status = |ok: bool| if ok { "ok" } else { "FAILED" };
+    println!("verification:");
+    println!(
+        "  certificate block: {}",
+        status(verification.certificate_block)
+    );
+    println!("  signature: {}", status(verification.signature));
+    println!("  block hashes: {}", status(verification.block_hashes));
+
+    Ok(verification)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(pki: Pki) -> Config {
+        Config {
+            firmware: Firmware {
+                secure_boot_image: String::new(),
+                version: 7,
+                description: "blinky".to_string(),
+                pck: [0x42; 32],
+            },
+            pki,
+            reproducibility: Reproducibility { timestamp: 1 },
+            commands: vec![
+                SingleBootCommandDescription::Erase {
+                    start: 0,
+                    end: 0x4000,
+                },
+                SingleBootCommandDescription::Load {
+                    file: "example-binaries/blinky-red.elf".to_string(),
+                    src: 0,
+                    dst: 0,
+                    len: None,
+                },
+                SingleBootCommandDescription::CheckSecureFirmwareVersion { version: 7 },
+            ],
+        }
+    }
+
+    fn assemble(config: &Config) -> (SignedSb31File, Vec<u8>) {
+        let unsigned = UnsignedSb31File::try_assemble_from(config).unwrap();
+        let signing_key = config
+            .pki
+            .isk_key
+            .as_ref()
+            .unwrap_or(&config.pki.signing_key);
+        let signed = unsigned
+            .sign(&EcSigningKey::try_from_uri(signing_key).unwrap())
+            .unwrap();
+        let bytes = signed.to_bytes();
+        (signed, bytes)
+    }
+
+    #[test]
+    fn roundtrip_p256() {
+        let config = config(Pki {
+            root_keys: vec![
+                "file:example-ec-keys/rot0_p256.pub.pem".to_string(),
+                "file:example-ec-keys/rot1_p256.pub.pem".to_string(),
+            ],
+            signing_key: "file:example-ec-keys/rot1_p256.pem".to_string(),
+            isk_key: None,
+        });
+        let (signed, bytes) = assemble(&config);
+        assert_eq!(sniff(&bytes).unwrap(), Filetype::Sb31);
+
+        let file = SignedSb31File::from_bytes(&bytes, &[0x42; 32]).unwrap();
+        assert!(file.verify().is_valid());
+        assert_eq!(file.commands, signed.commands);
+        assert_eq!(file.commands.len(), 3);
+        assert_eq!(file.certificate_block.used_root_key, 1);
+        assert_eq!(file.to_bytes(), bytes);
+
+        // wrong PCK garbles the command section
+        assert!(SignedSb31File::from_bytes(&bytes, &[0x43; 32]).is_err());
+    }
+
+    #[test]
+    fn roundtrip_p384_with_isk() {
+        let config = config(Pki {
+            root_keys: vec!["file:example-ec-keys/rot0_p384.pub.pem".to_string()],
+            signing_key: "file:example-ec-keys/rot0_p384.pem".to_string(),
+            isk_key: Some("file:example-ec-keys/isk_p256.pem".to_string()),
+        });
+        let (_, bytes) = assemble(&config);
+
+        let file = SignedSb31File::from_bytes(&bytes, &[0x42; 32]).unwrap();
+        assert!(file.verify().is_valid());
+        assert_eq!(file.certificate_block.root_key.curve(), Curve::P384);
+        assert_eq!(
+            file.certificate_block.signer().curve(),
+            Curve::P256,
+            "ISK signs the container"
+        );
+        assert_eq!(file.header.block_size as usize, 4 + 256 + 48);
+    }
+
+    #[test]
+    fn tampered() {
+        let config = config(Pki {
+            root_keys: vec!["file:example-ec-keys/rot0_p256.pub.pem".to_string()],
+            signing_key: "file:example-ec-keys/rot0_p256.pem".to_string(),
+            isk_key: None,
+        });
+        let (signed, bytes) = assemble(&config);
+
+        // firmware version in header
+        let mut tampered = bytes.clone();
+        tampered[28] ^= 1;
+        let verification = SignedSb31File::from_bytes(&tampered, &[0x42; 32])
+            .unwrap()
+            .verify();
+        assert!(!verification.signature);
+        assert!(verification.block_hashes);
+
+        // hash of the second block, after the number of the first block
+        let mut tampered = bytes;
+        let first_block = signed.header.image_total_length as usize;
+        tampered[first_block + 4] ^= 1;
+        let verification = SignedSb31File::from_bytes(&tampered, &[0x42; 32])
+            .unwrap()
+            .verify();
+        assert!(verification.signature);
+        assert!(!verification.block_hashes);
+        assert!(!verification.is_valid());
+    }
+
+    #[test]
+    fn derivation_data() {
+        let data = super::derivation_data(0x0102_0304_0506_0708, KeyDerivationMode::Kdk, 1);
+        assert_eq!(
+            hex::encode(data),
+            concat!(
+                "080706050403020100000000", // constant, 12 bytes little-endian
+                "c0010000",                 // access rights << 6, mode, zero
+                "0000000000000000",         // context
+                "00000100",                 // 256 bit key
+                "00000001",                 // iteration
+            )
+        );
+        let data = super::derivation_data(3, KeyDerivationMode::Block, 2);
+        assert_eq!(data[..4], [3, 0, 0, 0]);
+        assert_eq!(data[12..14], [0xc0, 2]);
+        assert_eq!(data[28..], [0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn block_layout() {
+        let config = config(Pki {
+            root_keys: vec!["file:example-ec-keys/rot0_p256.pub.pem".to_string()],
+            signing_key: "file:example-ec-keys/rot0_p256.pem".to_string(),
+            isk_key: None,
+        });
+        let (signed, bytes) = assemble(&config);
+        let curve = signed.certificate_block.root_key.curve();
+        assert_eq!(signed.header.block_size as usize, 4 + 32 + BLOCK_DATA_LEN);
+        assert!(signed.blocks.len() > 1);
+
+        let blocks = &bytes[signed.header.image_total_length as usize..];
+        let kdk = derive_key([0x42; 32], 1, KeyDerivationMode::Kdk);
+        let mut section = Vec::new();
+        for (n, block) in blocks.chunks(signed.header.block_size as usize).enumerate() {
+            // block number, hash of the next block, encrypted data
+            assert_eq!(block[..4], (n as u32 + 1).to_le_bytes());
+            let next_hash = &block[4..36];
+            match signed.blocks.get(n + 1) {
+                Some(next) => assert_eq!(next_hash, curve.hash(next)),
+                None => assert!(next_hash.iter().all(|byte| *byte == 0)),
+            }
+            let block_key = derive_key(kdk, n as u64 + 1, KeyDerivationMode::Block);
+            section.extend(aes_cbc_decrypt(block_key, [0u8; 16], &block[36..]));
+        }
+        assert_eq!(signed.first_block_hash, curve.hash(&signed.blocks[0]));
+        // section UID and type
+        assert_eq!(section[..8], [1, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn signing_key_must_be_root_key() {
+        let config = config(Pki {
+            root_keys: vec!["file:example-ec-keys/rot0_p256.pub.pem".to_string()],
+            signing_key: "file:example-ec-keys/rot1_p256.pem".to_string(),
+            isk_key: None,
+        });
+        assert!(UnsignedSb31File::try_assemble_from(&config).is_err());
+    }
+}
@@ -1,16 +1,342 @@
-use std::fs;
+use core::convert::TryInto as _;
+use core::fmt;
+
+use anyhow::{anyhow, Context as _, Result};
+use nom::bytes::complete::take;
+use rsa::PublicKeyParts as _;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{crc32_mpeg2, sha256};
+use crate::elf::read_image_at;
+use crate::pki::{
+    Certificate, CertificateChain, CertificateSlot, Certificates, Sha256Hash, SigningKey,
+};
+use crate::secure_binary::{
+    certificate_table_from_bytes, rot_fingerprints_from_bytes, Config, FullCertificateBlockHeader,
+};
+use crate::util::word_padded;
 
-use anyhow::{Context as _, Result};
+pub mod trustzone;
+
+/// Image types the ROM boots (`firmware.image-type`).
+///
+/// RAM images are copied to `firmware.load-address` before the ROM checks and runs them,
+/// the others execute in place from flash.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageType {
+    Plain,
+    CrcXip,
+    CrcRam,
+    #[default]
+    SignedXip,
+    SignedRam,
+}
 
-use crate::pki::{Certificate, CertificateSlot, Certificates, SigningKey};
-use crate::secure_binary::Config;
-use crate::util::word_padded;
+impl ImageType {
+    /// Low byte of the header's image type (UM11126, Chap. 7, Table 183)
+    pub fn code(self) -> u32 {
+        match self {
+            ImageType::Plain => 0x00,
+            ImageType::SignedRam => 0x01,
+            ImageType::CrcRam => 0x02,
+            ImageType::SignedXip => 0x04,
+            ImageType::CrcXip => 0x05,
+        }
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Some(match code & 0xff {
+            0x00 => ImageType::Plain,
+            0x01 => ImageType::SignedRam,
+            0x02 => ImageType::CrcRam,
+            0x04 => ImageType::SignedXip,
+            0x05 => ImageType::CrcXip,
+            _ => return None,
+        })
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, ImageType::SignedXip | ImageType::SignedRam)
+    }
+
+    pub fn is_crc(self) -> bool {
+        matches!(self, ImageType::CrcXip | ImageType::CrcRam)
+    }
+
+    pub fn is_ram(self) -> bool {
+        matches!(self, ImageType::CrcRam | ImageType::SignedRam)
+    }
+}
+
+impl fmt::Display for ImageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ImageType::Plain => "plain",
+            ImageType::CrcXip => "crc-xip",
+            ImageType::CrcRam => "crc-ram",
+            ImageType::SignedXip => "signed-xip",
+            ImageType::SignedRam => "signed-ram",
+        })
+    }
+}
+
+/// Image header, in reserved words of the vector table (cf. `modify_header`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ImageHeader {
+    /// Size of the whole image, including certificate block and signature
+    pub total_size: usize,
+    /// [`ImageType`] and TrustZone-M flags, cf. [`trustzone::TZM_PRESET`]
+    pub image_type: u32,
+    /// Certificate block offset of signed images, CRC32 of CRC images
+    pub header_offset: u32,
+    /// Where RAM images are copied to
+    pub load_address: u32,
+}
+
+impl ImageHeader {
+    pub const SIZE: usize = 0x38;
+
+    pub fn from_bytes(image: &[u8]) -> Result<Self> {
+        if image.len() < Self::SIZE {
+            return Err(anyhow!("truncated image header"));
+        }
+        let field = |offset: usize| u32::from_le_bytes(image[offset..][..4].try_into().unwrap());
+        Ok(Self {
+            total_size: field(0x20) as usize,
+            image_type: field(0x24),
+            header_offset: field(0x28),
+            load_address: field(0x34),
+        })
+    }
+
+    pub fn kind(&self) -> Result<ImageType> {
+        ImageType::from_code(self.image_type)
+            .ok_or_else(|| anyhow!("unknown image type 0x{:02x}", self.image_type & 0xff))
+    }
+}
+
+/// CRC32 of a CRC image, over all of it except the CRC itself (at 0x28)
+pub fn image_crc(image: &[u8]) -> u32 {
+    let mut data = Vec::from(&image[..0x28]);
+    data.extend_from_slice(&image[0x2c..]);
+    crc32_mpeg2(&data)
+}
 
 pub struct SignedImage(pub Vec<u8>);
 
+/// Outcome of [`SignedImage::verify`].
+#[derive(Clone, Debug)]
+pub struct SignedImageVerification {
+    /// Size of the plain image (including TrustZone-M preset data), i.e. offset of the
+    /// certificate block
+    pub image_size: usize,
+    /// Image type and flags, cf. [`ImageHeader::image_type`]
+    pub image_type: u32,
+    /// Where the image is copied to, if it's a RAM image
+    pub load_address: Option<u32>,
+    pub build_number: u32,
+    pub certificates: CertificateChain,
+    pub rot_fingerprints: [Sha256Hash; 4],
+    /// Slot in the RoT key table of the root certificate, if it is there
+    pub slot: Option<CertificateSlot>,
+    /// Each certificate is signed by its predecessor.
+    pub certificate_chain: bool,
+    /// The RSA signature over the image verifies with the last certificate.
+    pub signature: bool,
+}
+
+impl SignedImageVerification {
+    /// Chain and signature are valid, and the root certificate is in the RoT key table.
+    pub fn is_valid(&self) -> bool {
+        self.slot.is_some() && self.certificate_chain && self.signature
+    }
+
+    /// The hash of the RoT key table, as in `FactorySettings::rot_fingerprint`
+    pub fn rotkh(&self) -> Sha256Hash {
+        let table: Vec<u8> = self.rot_fingerprints.iter().flat_map(|f| f.0).collect();
+        Certificates::fingerprint_from_bytes(&table)
+    }
+
+    /// The RoT key table consists of exactly these certificates.
+    pub fn matches_certificates(&self, certificates: &Certificates) -> bool {
+        self.rot_fingerprints == certificates.fingerprints()
+    }
+
+    /// The RoT key table is what a device with this ROTKH accepts.
+    pub fn matches_rot_fingerprint(&self, rot_fingerprint: &Sha256Hash) -> bool {
+        self.rotkh() == *rot_fingerprint
+    }
+}
+
+impl SignedImage {
+    /// Parses the image header, certificate block and RoT key table, and checks signatures.
+    ///
+    /// Malformed images are rejected, failed checks are reported in the returned verification.
+    pub fn verify(&self) -> Result<SignedImageVerification> {
+        let image = &self.0;
+        // cf. `modify_header`
+        let image_header = ImageHeader::from_bytes(image)?;
+        let kind = image_header.kind()?;
+        if !kind.is_signed() {
+            return Err(anyhow!("{} image, not signed", kind));
+        }
+        let total_image_size = image_header.total_size;
+        let image_size = image_header.header_offset as usize;
+        if total_image_size != image.len() {
+            return Err(anyhow::anyhow!(
+                "image is {} bytes, but header has {}",
+                image.len(),
+                total_image_size
+            ));
+        }
+        let certificate_block = image
+            .get(image_size..)
+            .ok_or_else(|| anyhow::anyhow!("certificate block offset out of bounds"))?;
+
+        let (i, header) = FullCertificateBlockHeader::from_bytes(certificate_block)
+            .map_err(|_| anyhow::anyhow!("malformed certificate block header"))?;
+        let (i, certificates, _) = certificate_table_from_bytes(i, header.certificate_count)?;
+        let (i, rot_fingerprints) = rot_fingerprints_from_bytes(i)?;
+
+        let signed_size = image.len() - i.len();
+        if header.total_image_length_in_bytes as usize != signed_size {
+            return Err(anyhow::anyhow!(
+                "signed part is {} bytes, but certificate block header has {}",
+                signed_size,
+                header.total_image_length_in_bytes
+            ));
+        }
+        let signature_length = certificates.signer().public_key().0.size();
+        let (rest, signature) =
+            take::<_, _, ()>(signature_length)(i).context("truncated signature")?;
+        if !rest.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{} trailing bytes after signature",
+                rest.len()
+            ));
+        }
+
+        let certificate_chain = certificates.verify_signatures();
+
+        let signature = {
+            use rsa::PublicKey as _;
+            let padding_scheme = rsa::PaddingScheme::new_pkcs1v15_sign(Some(rsa::Hash::SHA2_256));
+            certificates
+                .signer()
+                .public_key()
+                .0
+                .verify(padding_scheme, &sha256(&image[..signed_size]), signature)
+                .is_ok()
+        };
+
+        let root = certificates.root().fingerprint();
+        let slot = rot_fingerprints
+            .iter()
+            .position(|fingerprint| *fingerprint == root)
+            .map(CertificateSlot::from);
+
+        Ok(SignedImageVerification {
+            image_size,
+            image_type: image_header.image_type,
+            load_address: kind.is_ram().then_some(image_header.load_address),
+            build_number: header.build_number,
+            certificates,
+            rot_fingerprints,
+            slot,
+            certificate_chain,
+            signature,
+        })
+    }
+}
+
+/// Image, TrustZone-M preset and header fields, as for all image types.
+struct Payload {
+    plain_image: Vec<u8>,
+    image_type: u32,
+    load_address: Option<u32>,
+    trust_zone_preset: Vec<u8>,
+}
+
+impl Payload {
+    fn try_from(config: &Config) -> Result<Self> {
+        let firmware = &config.firmware;
+        let kind = firmware.image_type;
+        let load_address = match (kind.is_ram(), firmware.load_address) {
+            (true, 0) => return Err(anyhow!("{} images need a firmware.load-address", kind)),
+            (true, address) => Some(address),
+            (false, 0) => None,
+            (false, _) => {
+                return Err(anyhow!(
+                    "firmware.load-address is only for RAM images, not {}",
+                    kind
+                ))
+            }
+        };
+        let plain_image = read_image_at(&firmware.image, load_address.unwrap_or(0))?;
+        if plain_image.len() < ImageHeader::SIZE {
+            return Err(anyhow!(
+                "{} is {} bytes, too short for an image header",
+                firmware.image,
+                plain_image.len()
+            ));
+        }
+
+        let trust_zone_preset = match config.trust_zone.preset()? {
+            Some(preset) => preset.to_bytes()?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            plain_image,
+            image_type: config.trust_zone.image_type(kind),
+            load_address,
+            trust_zone_preset,
+        })
+    }
+
+    /// Word padded image, followed by the preset block, which counts as part of the image
+    fn padded_image(&self) -> Vec<u8> {
+        let mut image = word_padded(&self.plain_image);
+        image.extend_from_slice(&self.trust_zone_preset);
+        image
+    }
+}
+
+/// Assembles the image `config.firmware.image-type` calls for.
+///
+/// Signed images are assembled by [`ImageSigningRequest`], plain and CRC images only get
+/// their header filled in.
+pub fn assemble_image(config: &Config) -> Result<Vec<u8>> {
+    if config.firmware.image_type.is_signed() {
+        return Ok(ImageSigningRequest::try_from(config)?.sign().0);
+    }
+    let payload = Payload::try_from(config)?;
+    let mut image = payload.padded_image();
+    let total_image_size = image.len();
+    modify_header(
+        &mut image,
+        total_image_size,
+        payload.image_type,
+        0,
+        payload.load_address,
+    );
+    if config.firmware.image_type.is_crc() {
+        let crc = image_crc(&image);
+        image[0x28..][..4].copy_from_slice(&crc.to_le_bytes());
+    }
+    Ok(image)
+}
+
 /// Technically probably incorrect naming, as no ownership of RoT keys is asserted.
 pub struct ImageSigningRequest {
     pub plain_image: Vec<u8>,
+    /// Header image type, cf. [`crate::secure_binary::TrustZone::image_type`]
+    pub image_type: u32,
+    /// Where signed RAM images are copied to
+    pub load_address: Option<u32>,
+    /// TrustZone-M preset block, empty without preset
+    pub trust_zone_preset: Vec<u8>,
     certificates: Certificates,
     signing_key: SigningKey,
     pub slot: CertificateSlot,
@@ -23,20 +349,38 @@ impl ImageSigningRequest {
 
     /// Parse config, load all data checking for validity.
     pub fn try_from(config: &Config) -> Result<Self> {
-        let plain_image = fs::read(&config.firmware.image).with_context(|| {
-            format!(
-                "Failed to read firmware image from {}",
-                config.firmware.image
-            )
-        })?;
+        if !config.firmware.image_type.is_signed() {
+            return Err(anyhow!(
+                "{} images are not signed, cf. `assemble_image`",
+                config.firmware.image_type
+            ));
+        }
+        let Payload {
+            plain_image,
+            image_type,
+            load_address,
+            trust_zone_preset,
+        } = Payload::try_from(config)?;
         let certificates = Certificates::try_from_pki(&config.pki)?;
 
         let signing_key = SigningKey::try_from_uri(config.pki.signing_key.as_ref())?;
 
-        let slot = certificates.index_of(signing_key.public_key())?;
+        let slot = certificates.index_of(signing_key.public_key()?)?;
+        certificates
+            .chain(slot)
+            .check(
+                config
+                    .factory_settings
+                    .secure_boot_configuration
+                    .use_rsa4096_keys,
+            )
+            .with_context(|| format!("certificate chain {}", usize::from(slot)))?;
 
         Ok(Self {
             plain_image,
+            image_type,
+            load_address,
+            trust_zone_preset,
             certificates,
             signing_key,
             slot,
@@ -66,25 +410,42 @@ impl ImageSigningRequest {
 
     fn assemble_unsigned_image(&self, i: CertificateSlot) -> Vec<u8> {
         let mut image = word_padded(&self.plain_image);
-
-        let certificate = word_padded(self.certificates.certificate_der(i));
-
-        let total_image_size = modify_header(&mut image, certificate.len());
+        // the preset block is part of the image, as far as the header is concerned
+        image.extend_from_slice(&self.trust_zone_preset);
+
+        // root certificate first, the signer's last
+        let certificates: Vec<Vec<u8>> = self.certificates.chain_der(i).map(word_padded).collect();
+        let certificate_table_length = certificates.iter().map(|c| 4 + c.len()).sum::<usize>();
+        let signature_length = self.selected_certificate().public_key().0.size();
+
+        let image_size = image.len();
+        let total_image_size = image_size
+            + certificate_block_and_signature_size(certificate_table_length, signature_length);
+        modify_header(
+            &mut image,
+            total_image_size,
+            self.image_type,
+            image_size as u32,
+            self.load_address,
+        );
         // println!("{:x}", total_image_size);
 
         let build_number = 1;
         let certificate_block_header = certificate_block_header_bytes(
             // total image size sans signature
-            total_image_size - 256,
-            certificate.len(),
+            total_image_size - signature_length,
+            certificates.len(),
+            certificate_table_length,
             build_number,
         );
         // certificate block header
         image.extend_from_slice(&certificate_block_header);
 
         // certificate block
-        extendu(&mut image, certificate.len());
-        image.extend_from_slice(&certificate);
+        for certificate in certificates {
+            extendu(&mut image, certificate.len());
+            image.extend_from_slice(&certificate);
+        }
 
         // ROT key hash table
         for h in self.certificates.fingerprints() {
@@ -94,38 +455,143 @@ impl ImageSigningRequest {
     }
 }
 
-// UM11126, Chap. 6, Table 172, "Image header"
-fn modify_header(padded_image: &mut Vec<u8>, padded_certificate_length: usize) -> usize {
-    let image_size = padded_image.len();
+/// Verifies a signed image, prints a summary and returns the verification report.
+pub fn show(filename: &str) -> Result<SignedImageVerification> {
+    let image = SignedImage(
+        std::fs::read(filename)
+            .with_context(|| format!("Failed to read image from {}", filename))?,
+    );
+    let verification = image.verify()?;
+
+    println!("image size: {} bytes", verification.image_size);
+    show_image_type(verification.image_type, verification.load_address);
+    println!("build number: {}", verification.build_number);
+    println!("certificates:");
+    for certificate in verification.certificates.all() {
+        println!(
+            "- {} (fingerprint {})",
+            certificate.certificate().subject(),
+            hexstr!(&certificate.fingerprint().0)
+        );
+    }
+    println!("rotkh: {}", hexstr!(&verification.rotkh().0));
+    match verification.slot {
+        Some(slot) => println!("slot: {}", usize::from(slot)),
+        None => println!("slot: none"),
+    }
 
-    let non_image_size =
-        // certificate block header
-        32 +
-        // certificate table size (each is u32(certificate size) + certificate, we have only one)
-        (4 + padded_certificate_length) +
-        // 4x ROT key SHA256 hash
-        4*32 +
-        // RSA2K signature
-        256;
+    let status = |ok: bool| if ok { "ok" } else { "FAILED" };
+    println!("verification:");
+    println!(
+        "  certificate chain: {}",
+        status(verification.certificate_chain)
+    );
+    println!("  root of trust: {}", status(verification.slot.is_some()));
+    println!("  signature: {}", status(verification.signature));
+
+    Ok(verification)
+}
+
+/// Checks a plain or CRC image, prints a summary and returns whether the CRC matches.
+///
+/// Plain images have nothing to check.
+pub fn show_unsigned(filename: &str) -> Result<bool> {
+    let image = std::fs::read(filename)
+        .with_context(|| format!("Failed to read image from {}", filename))?;
+    let header = ImageHeader::from_bytes(&image)?;
+    let kind = header.kind()?;
+    if kind.is_signed() {
+        return Err(anyhow!("{} image, cf. `show`", kind));
+    }
+    if header.total_size != image.len() {
+        return Err(anyhow!(
+            "image is {} bytes, but header has {}",
+            image.len(),
+            header.total_size
+        ));
+    }
+
+    println!("image size: {} bytes", image.len());
+    show_image_type(
+        header.image_type,
+        kind.is_ram().then_some(header.load_address),
+    );
+    if !kind.is_crc() {
+        return Ok(true);
+    }
+    let crc = image_crc(&image);
+    let ok = crc == header.header_offset;
+    println!("verification:");
+    println!(
+        "  crc: {} (0x{:08x})",
+        if ok { "ok" } else { "FAILED" },
+        header.header_offset
+    );
+    Ok(ok)
+}
+
+fn show_image_type(image_type: u32, load_address: Option<u32>) {
+    match ImageType::from_code(image_type) {
+        Some(kind) => println!("image type: 0x{:08x} ({})", image_type, kind),
+        None => println!("image type: 0x{:08x}", image_type),
+    }
+    if let Some(load_address) = load_address {
+        println!("load address: 0x{:08x}", load_address);
+    }
+    println!(
+        "trustzone: {}",
+        match (
+            image_type & trustzone::TZM_DISABLED != 0,
+            image_type & trustzone::TZM_PRESET != 0
+        ) {
+            (true, _) => "disabled",
+            (false, true) => "enabled, with preset",
+            (false, false) => "enabled",
+        }
+    );
+}
 
-    let total_image_size = image_size + non_image_size;
+fn certificate_block_and_signature_size(
+    certificate_table_length: usize,
+    signature_length: usize,
+) -> usize {
+    // certificate block header
+    32 +
+    // certificate table size (each is u32(certificate size) + certificate)
+    certificate_table_length +
+    // 4x ROT key SHA256 hash
+    4*32 +
+    // RSA2K or RSA4K signature
+    signature_length
+}
 
+// UM11126, Chap. 6, Table 172, "Image header"
+fn modify_header(
+    padded_image: &mut [u8],
+    total_image_size: usize,
+    image_type: u32,
+    header_offset: u32,
+    load_address: Option<u32>,
+) {
     // 0x20: total image size
     padded_image[0x20..][..4].copy_from_slice((total_image_size as u32).to_le_bytes().as_ref());
-    // 0x24: image type "SPT" = [XIP Signed, TZ enabled, 0, 0-]
+    // 0x24: image type "SPT" = [XIP Signed, TZ flags, 0, 0-], by default TZ enabled
     // This doesn't seem to match UM 11126, Chap. 7, Table 183 at all :)
     // NOTE: Setting "TZ disabled" (0x40 in second byte) prevents use of the
     // "boot to bootrom" method in lpc55-hal.
-    padded_image[0x24..][..4].copy_from_slice(&[0x04, 0x00, 0x00, 0x00]);
-    // "header offset", i.e. image size
-    padded_image[0x28..][..4].copy_from_slice((image_size as u32).to_le_bytes().as_ref());
-
-    total_image_size
+    padded_image[0x24..][..4].copy_from_slice(&image_type.to_le_bytes());
+    // "header offset", i.e. image size of signed images, CRC of CRC images (filled in later)
+    padded_image[0x28..][..4].copy_from_slice(&header_offset.to_le_bytes());
+    // 0x34: load address of RAM images
+    if let Some(load_address) = load_address {
+        padded_image[0x34..][..4].copy_from_slice(&load_address.to_le_bytes());
+    }
 }
 
 fn certificate_block_header_bytes(
     total_image_length: usize,
-    aligned_cert_length: usize,
+    certificate_count: usize,
+    certificate_table_length: usize,
     build_number: u32,
 ) -> Vec<u8> {
     let mut bytes = Vec::new();
@@ -150,11 +616,10 @@ fn certificate_block_header_bytes(
     extendu(&mut bytes, total_image_length);
     // println!("set length to {:x}", total_image_length);
 
-    let certificates: u32 = 1;
-    // one certificate
-    extend32(&mut bytes, certificates);
-    // 0x480 = 1152
-    extendu(&mut bytes, aligned_cert_length + 4);
+    // root certificate, followed by the chain
+    extendu(&mut bytes, certificate_count);
+    // 0x480 = 1152 for a single RSA2K certificate
+    extendu(&mut bytes, certificate_table_length);
 
     assert_eq!(32, bytes.len());
 
@@ -178,3 +643,177 @@ fn extendu(bytes: &mut Vec<u8>, value: usize) {
     // on desktop, usize is u64
     bytes.extend_from_slice((value as u32).to_le_bytes().as_ref());
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pki::CertificateUriChain;
+
+    fn signed_image() -> (Config, SignedImage) {
+        let mut config = Config::try_from("example-cfgs/example-cfg.toml").unwrap();
+        config.firmware.image = "example-binaries/blinky-red.elf".to_string();
+        let image = ImageSigningRequest::try_from(&config).unwrap().sign();
+        (config, image)
+    }
+
+    #[test]
+    fn verify() {
+        let (config, image) = signed_image();
+        let verification = image.verify().unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.slot, Some(CertificateSlot::from(0)));
+        assert_eq!(verification.image_size, 856);
+
+        let certificates = Certificates::try_from_pki(&config.pki).unwrap();
+        assert!(verification.matches_certificates(&certificates));
+        assert!(verification.matches_rot_fingerprint(&config.factory_settings.rot_fingerprint));
+        assert!(!verification.matches_rot_fingerprint(&Sha256Hash([0x42; 32])));
+    }
+
+    #[test]
+    fn tampered() {
+        let (_, mut image) = signed_image();
+        image.0[0x100] ^= 1;
+        let verification = image.verify().unwrap();
+        assert!(verification.certificate_chain);
+        assert!(!verification.signature);
+        assert!(!verification.is_valid());
+
+        let (_, mut image) = signed_image();
+        image.0.push(0);
+        assert!(image.verify().is_err());
+    }
+
+    #[test]
+    fn trust_zone() {
+        let (mut config, image) = signed_image();
+        assert_eq!(image.verify().unwrap().image_type, 0x04);
+
+        config.trust_zone.preset = "example-cfgs/example-tz-preset.toml".into();
+        let image = ImageSigningRequest::try_from(&config).unwrap().sign();
+        let verification = image.verify().unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.image_type, 0x2004);
+        assert_eq!(verification.image_size, 856 + trustzone::TzPreset::SIZE);
+        let preset = trustzone::TzPreset::read(&config.trust_zone.preset).unwrap();
+        assert_eq!(
+            &image.0[856..verification.image_size],
+            preset.to_bytes().unwrap()
+        );
+
+        config.trust_zone.enabled = false;
+        assert!(ImageSigningRequest::try_from(&config).is_err());
+        config.trust_zone.preset.clear();
+        let image = ImageSigningRequest::try_from(&config).unwrap().sign();
+        assert_eq!(image.verify().unwrap().image_type, 0x4004);
+    }
+
+    #[test]
+    fn crc_and_plain() {
+        let (mut config, _) = signed_image();
+        config.firmware.image_type = ImageType::CrcXip;
+        assert!(ImageSigningRequest::try_from(&config).is_err());
+        let image = assemble_image(&config).unwrap();
+        assert_eq!(image.len(), 856);
+        let header = ImageHeader::from_bytes(&image).unwrap();
+        assert_eq!(header.total_size, 856);
+        assert_eq!(header.image_type, 0x05);
+        assert_eq!(header.header_offset, image_crc(&image));
+        assert!(SignedImage(image).verify().is_err());
+
+        config.firmware.image_type = ImageType::Plain;
+        let image = assemble_image(&config).unwrap();
+        let header = ImageHeader::from_bytes(&image).unwrap();
+        assert_eq!(header.kind().unwrap(), ImageType::Plain);
+        assert_eq!(header.header_offset, 0);
+
+        // the load address is only for RAM images
+        config.firmware.load_address = 0x2000_0000;
+        assert!(assemble_image(&config).is_err());
+        config.firmware.image_type = ImageType::CrcRam;
+        // blinky is linked to flash
+        assert!(assemble_image(&config).is_err());
+    }
+
+    #[test]
+    fn signed_ram() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut elf = std::fs::read("example-binaries/blinky-red.elf").unwrap();
+        for n in 0..3 {
+            let paddr = &mut elf[52 + n * 32 + 12..][..4];
+            let address = u32::from_le_bytes(paddr.try_into().unwrap());
+            paddr.copy_from_slice(&(0x2000_0000 + address).to_le_bytes());
+        }
+        let path = dir.path().join("blinky-ram.elf");
+        std::fs::write(&path, &elf).unwrap();
+
+        let (mut config, _) = signed_image();
+        config.firmware.image = path.display().to_string();
+        config.firmware.image_type = ImageType::SignedRam;
+        assert!(assemble_image(&config).is_err());
+
+        config.firmware.load_address = 0x2000_0000;
+        let image = SignedImage(assemble_image(&config).unwrap());
+        let verification = image.verify().unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.image_type, 0x01);
+        assert_eq!(verification.load_address, Some(0x2000_0000));
+        assert_eq!(verification.image_size, 856);
+    }
+
+    fn chain_config() -> Config {
+        let mut config = Config::try_from("example-cfgs/example-cfg.toml").unwrap();
+        config.firmware.image = "example-binaries/blinky-red.elf".to_string();
+        config.pki.signing_key = "file:example-file-certs/isk_private_key.pem".to_string();
+        config.pki.certificates[0] = CertificateUriChain::Chain {
+            root: "file:example-file-certs/ca4096_certificate.der".to_string(),
+            chain: vec!["file:example-file-certs/isk_certificate.der".to_string()],
+        };
+        config
+    }
+
+    #[test]
+    fn certificate_chain() {
+        let config = chain_config();
+        let image = ImageSigningRequest::try_from(&config).unwrap().sign();
+        let verification = image.verify().unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.slot, Some(CertificateSlot::from(0)));
+        assert_eq!(verification.certificates.len(), 2);
+        assert_eq!(verification.image_size, 856);
+
+        // certificate block header: two certificates, RSA4K signature
+        let header = ImageHeader::from_bytes(&image.0).unwrap();
+        let block = &image.0[header.header_offset as usize..];
+        let word = |offset: usize| u32::from_le_bytes(block[offset..][..4].try_into().unwrap());
+        assert_eq!(word(0x14) as usize, image.0.len() - 512);
+        assert_eq!(word(0x18), 2);
+
+        let mut image = image;
+        image.0[0x100] ^= 1;
+        assert!(!image.verify().unwrap().is_valid());
+
+        // factory settings that only allow RSA4K keys
+        let mut config = chain_config();
+        config
+            .factory_settings
+            .secure_boot_configuration
+            .use_rsa4096_keys = true;
+        assert!(ImageSigningRequest::try_from(&config).is_ok());
+        config.pki.signing_key = "file:example-file-certs/ca_private_key_1.pem".to_string();
+        assert!(ImageSigningRequest::try_from(&config).is_err());
+
+        // signing key does not match the end of the chain
+        let mut config = chain_config();
+        config.pki.signing_key = "file:example-file-certs/ca4096_private_key.pem".to_string();
+        assert!(ImageSigningRequest::try_from(&config).is_err());
+
+        // intermediate not issued by the root
+        let mut config = chain_config();
+        config.pki.certificates[0] = CertificateUriChain::Chain {
+            root: "file:example-file-certs/ca_certificate_0.der".to_string(),
+            chain: vec!["file:example-file-certs/isk_certificate.der".to_string()],
+        };
+        assert!(ImageSigningRequest::try_from(&config).is_err());
+    }
+}
@@ -0,0 +1,464 @@
+//! TrustZone-M preset data of signed images.
+//!
+//! With a preset, the ROM sets up the secure and non-secure vector tables, the interrupt
+//! targets, the SAU and the AHB secure controller before it jumps to the image, so the
+//! secure firmware starts out with its memory map already in place. The preset block sits
+//! between the image and the certificate block, the image type marks its presence
+//! (cf. [`TZM_PRESET`]).
+//!
+//! UM11126 does not spell out the block layout. [`TzPreset::to_bytes`] follows the
+//! LPC55S6x preset template of the vendor's tools (spsdk, elftosb): one little-endian word
+//! per register, in template order. The ROM does not check the block, a preset written in
+//! another order silently programs the wrong registers.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::util::is_default;
+
+/// Image type flag: TrustZone-M preset data follows the image
+pub const TZM_PRESET: u32 = 1 << 13;
+/// Image type flag: the image does not use TrustZone-M
+pub const TZM_DISABLED: u32 = 1 << 14;
+
+/// Number of SAU regions of the Cortex-M33 cores
+pub const SAU_REGIONS: usize = 8;
+
+/// Registers of the AHB secure controller that are part of the preset, in block order.
+pub const AHB_SECURE_CONTROLLER: &[&str] = &[
+    "SEC_CTRL_FLASH_ROM_SLAVE_RULE",
+    "SEC_CTRL_FLASH_MEM_RULE0",
+    "SEC_CTRL_FLASH_MEM_RULE1",
+    "SEC_CTRL_FLASH_MEM_RULE2",
+    "SEC_CTRL_ROM_MEM_RULE0",
+    "SEC_CTRL_ROM_MEM_RULE1",
+    "SEC_CTRL_ROM_MEM_RULE2",
+    "SEC_CTRL_ROM_MEM_RULE3",
+    "SEC_CTRL_RAMX_SLAVE_RULE",
+    "SEC_CTRL_RAMX_MEM_RULE0",
+    "SEC_CTRL_RAM0_SLAVE_RULE",
+    "SEC_CTRL_RAM0_MEM_RULE0",
+    "SEC_CTRL_RAM0_MEM_RULE1",
+    "SEC_CTRL_RAM1_SLAVE_RULE",
+    "SEC_CTRL_RAM1_MEM_RULE0",
+    "SEC_CTRL_RAM1_MEM_RULE1",
+    "SEC_CTRL_RAM2_SLAVE_RULE",
+    "SEC_CTRL_RAM2_MEM_RULE0",
+    "SEC_CTRL_RAM2_MEM_RULE1",
+    "SEC_CTRL_RAM3_SLAVE_RULE",
+    "SEC_CTRL_RAM3_MEM_RULE0",
+    "SEC_CTRL_RAM3_MEM_RULE1",
+    "SEC_CTRL_RAM4_SLAVE_RULE",
+    "SEC_CTRL_RAM4_MEM_RULE0",
+    "SEC_CTRL_APB_BRIDGE_SLAVE_RULE",
+    "SEC_CTRL_APB_BRIDGE0_MEM_CTRL0",
+    "SEC_CTRL_APB_BRIDGE0_MEM_CTRL1",
+    "SEC_CTRL_APB_BRIDGE0_MEM_CTRL2",
+    "SEC_CTRL_APB_BRIDGE0_MEM_CTRL3",
+    "SEC_CTRL_APB_BRIDGE1_MEM_CTRL0",
+    "SEC_CTRL_APB_BRIDGE1_MEM_CTRL1",
+    "SEC_CTRL_APB_BRIDGE1_MEM_CTRL2",
+    "SEC_CTRL_APB_BRIDGE1_MEM_CTRL3",
+    "SEC_CTRL_AHB_PORT8_SLAVE0_RULE",
+    "SEC_CTRL_AHB_PORT8_SLAVE1_RULE",
+    "SEC_CTRL_AHB_PORT9_SLAVE0_RULE",
+    "SEC_CTRL_AHB_PORT9_SLAVE1_RULE",
+    "SEC_CTRL_AHB_PORT10_SLAVE0_RULE",
+    "SEC_CTRL_AHB_PORT10_SLAVE1_RULE",
+    "SEC_CTRL_USB_HS_SLAVE_RULE",
+    "SEC_CTRL_USB_HS_MEM_RULE",
+    "SEC_GPIO_MASK0",
+    "SEC_GPIO_MASK1",
+    "SEC_CPU_INT_MASK0",
+    "SEC_CPU_INT_MASK1",
+    "SEC_MASK_LOCK",
+    "MASTER_SEC_LEVEL",
+    "MASTER_SEC_ANTI_POL_REG",
+    "CPU0_LOCK_REG",
+    "CPU1_LOCK_REG",
+    "MISC_CTRL_DP_REG",
+    "MISC_CTRL_REG",
+];
+
+/// TrustZone-M preset, as read from TOML or YAML.
+///
+/// ```toml
+/// vector-table = 0x1000_0000
+/// non-secure-vector-table = 0x0001_0000
+///
+/// [sau]
+/// enabled = true
+/// regions = [
+///     { base = 0x0001_0000, limit = 0x0009_ffff },
+///     { base = 0x1000_fe00, limit = 0x1000_ffff, non-secure-callable = true },
+/// ]
+///
+/// [ahb-secure-controller]
+/// SEC_CTRL_FLASH_MEM_RULE0 = 0x0000_3333
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct TzPreset {
+    /// Secure vector table (`VTOR_S`)
+    #[serde(default)]
+    pub vector_table: u32,
+    /// Non-secure vector table (`VTOR_NS`)
+    #[serde(default)]
+    pub non_secure_vector_table: u32,
+    /// Interrupts targeting the non-secure state (`NVIC_ITNS0` and `NVIC_ITNS1`)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub non_secure_interrupts: [u32; 2],
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub stack_limits: StackLimits,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub sau: Sau,
+    /// Registers by name, cf. [`AHB_SECURE_CONTROLLER`].
+    ///
+    /// Registers left out are zero, also those with another reset value.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub ahb_secure_controller: BTreeMap<String, u32>,
+}
+
+/// Stack pointer limits of the secure and non-secure state.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct StackLimits {
+    /// `MSPLIM_S`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub main: u32,
+    /// `PSPLIM_S`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub process: u32,
+    /// `MSPLIM_NS`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub non_secure_main: u32,
+    /// `PSPLIM_NS`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub non_secure_process: u32,
+}
+
+/// Security attribution unit.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Sau {
+    /// `SAU_CTRL.ENABLE`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub enabled: bool,
+    /// `SAU_CTRL.ALLNS`, memory is non-secure if the SAU is disabled
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub all_non_secure: bool,
+    /// Regions 0 to 7, in order; all others are disabled
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub regions: Vec<SauRegion>,
+}
+
+/// SAU region, non-secure or non-secure callable.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct SauRegion {
+    /// First address, 32 byte aligned
+    pub base: u32,
+    /// Last address, i.e. one less than a multiple of 32
+    pub limit: u32,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub non_secure_callable: bool,
+}
+
+impl SauRegion {
+    /// `SAU_RBAR`
+    pub fn rbar(&self) -> u32 {
+        self.base
+    }
+
+    /// `SAU_RLAR`, the region is enabled
+    pub fn rlar(&self) -> u32 {
+        (self.limit & !0x1f) | ((self.non_secure_callable as u32) << 1) | 1
+    }
+}
+
+impl TzPreset {
+    /// Size of the preset block
+    pub const SIZE: usize = 4 * (9 + 2 * SAU_REGIONS + AHB_SECURE_CONTROLLER.len());
+
+    /// Reads a preset from a YAML (`.yaml` or `.yml`) or TOML file.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read TrustZone preset from {}", path.display()))?;
+        let preset = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&data).map_err(anyhow::Error::from),
+            _ => toml::from_str(&data).map_err(anyhow::Error::from),
+        };
+        let preset: Self = preset.map_err(|error| anyhow!("{}: {:#}", path.display(), error))?;
+        preset.check()?;
+        Ok(preset)
+    }
+
+    /// Checks the SAU regions and AHB secure controller register names.
+    pub fn check(&self) -> Result<()> {
+        if self.sau.regions.len() > SAU_REGIONS {
+            return Err(anyhow!(
+                "{} SAU regions, there are {}",
+                self.sau.regions.len(),
+                SAU_REGIONS
+            ));
+        }
+        for (n, region) in self.sau.regions.iter().enumerate() {
+            if region.base % 32 != 0 || region.limit % 32 != 31 || region.limit < region.base {
+                return Err(anyhow!(
+                    "SAU region {} (0x{:08x} to 0x{:08x}) does not cover whole 32 byte blocks",
+                    n,
+                    region.base,
+                    region.limit
+                ));
+            }
+        }
+        if let Some(name) = self
+            .ahb_secure_controller
+            .keys()
+            .find(|name| !AHB_SECURE_CONTROLLER.contains(&name.as_str()))
+        {
+            return Err(anyhow!("unknown AHB secure controller register {}", name));
+        }
+        Ok(())
+    }
+
+    /// The preset block: `VTOR_S`, `VTOR_NS`, `NVIC_ITNS0..1`, `MSPLIM_S`, `PSPLIM_S`,
+    /// `MSPLIM_NS`, `PSPLIM_NS`, `SAU_CTRL`, `SAU_RBAR` and `SAU_RLAR` of the eight regions,
+    /// then the [`AHB_SECURE_CONTROLLER`] registers.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.check()?;
+        let mut words = vec![
+            self.vector_table,
+            self.non_secure_vector_table,
+            self.non_secure_interrupts[0],
+            self.non_secure_interrupts[1],
+            self.stack_limits.main,
+            self.stack_limits.process,
+            self.stack_limits.non_secure_main,
+            self.stack_limits.non_secure_process,
+            self.sau.enabled as u32 | ((self.sau.all_non_secure as u32) << 1),
+        ];
+        for n in 0..SAU_REGIONS {
+            match self.sau.regions.get(n) {
+                Some(region) => words.extend([region.rbar(), region.rlar()]),
+                None => words.extend([0, 0]),
+            }
+        }
+        words.extend(
+            AHB_SECURE_CONTROLLER
+                .iter()
+                .map(|name| self.ahb_secure_controller.get(*name).copied().unwrap_or(0)),
+        );
+
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        debug_assert_eq!(bytes.len(), Self::SIZE);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn word(bytes: &[u8], index: usize) -> u32 {
+        u32::from_le_bytes(bytes[4 * index..][..4].try_into().unwrap())
+    }
+
+    #[test]
+    fn to_bytes() {
+        let preset: TzPreset = toml::from_str(
+            r#"
+vector-table = 0x1000_0000
+non-secure-vector-table = 0x0001_0000
+non-secure-interrupts = [0x10, 0]
+
+[stack-limits]
+main = 0x2000_0400
+non-secure-process = 0x2002_0800
+
+[sau]
+enabled = true
+regions = [
+    { base = 0x0001_0000, limit = 0x0009_ffff },
+    { base = 0x1000_fe00, limit = 0x1000_ffff, non-secure-callable = true },
+]
+
+[ahb-secure-controller]
+SEC_CTRL_FLASH_MEM_RULE0 = 0x3333
+MISC_CTRL_REG = 0xaaaa
+"#,
+        )
+        .unwrap();
+
+        let bytes = preset.to_bytes().unwrap();
+        assert_eq!(bytes.len(), TzPreset::SIZE);
+        assert_eq!(word(&bytes, 0), 0x1000_0000);
+        assert_eq!(word(&bytes, 1), 0x0001_0000);
+        assert_eq!(word(&bytes, 2), 0x10);
+        // MSPLIM_S, PSPLIM_NS
+        assert_eq!(word(&bytes, 4), 0x2000_0400);
+        assert_eq!(word(&bytes, 7), 0x2002_0800);
+        // SAU_CTRL
+        assert_eq!(word(&bytes, 8), 1);
+        assert_eq!(word(&bytes, 9), 0x0001_0000);
+        assert_eq!(word(&bytes, 10), 0x0009_ffe1);
+        assert_eq!(word(&bytes, 12), 0x1000_ffe3);
+        // unused regions
+        assert_eq!(word(&bytes, 13), 0);
+        assert_eq!(word(&bytes, 24), 0);
+        // AHB secure controller
+        assert_eq!(word(&bytes, 26), 0x3333);
+        assert_eq!(word(&bytes, TzPreset::SIZE / 4 - 1), 0xaaaa);
+    }
+
+    /// Offsets of the LPC55S6x preset template of spsdk and elftosb.
+    #[test]
+    fn template() {
+        let template: &[(usize, &str)] = &[
+            (0x000, "VTOR_S"),
+            (0x004, "VTOR_NS"),
+            (0x008, "NVIC_ITNS0"),
+            (0x00c, "NVIC_ITNS1"),
+            (0x010, "MSPLIM_S"),
+            (0x014, "PSPLIM_S"),
+            (0x018, "MSPLIM_NS"),
+            (0x01c, "PSPLIM_NS"),
+            (0x020, "SAU_CTRL"),
+            (0x024, "SAU_RBAR0"),
+            (0x028, "SAU_RLAR0"),
+            (0x060, "SAU_RLAR7"),
+            (0x064, "SEC_CTRL_FLASH_ROM_SLAVE_RULE"),
+            (0x068, "SEC_CTRL_FLASH_MEM_RULE0"),
+            (0x074, "SEC_CTRL_ROM_MEM_RULE0"),
+            (0x088, "SEC_CTRL_RAMX_MEM_RULE0"),
+            (0x0c4, "SEC_CTRL_APB_BRIDGE_SLAVE_RULE"),
+            (0x0e8, "SEC_CTRL_AHB_PORT8_SLAVE0_RULE"),
+            (0x100, "SEC_CTRL_USB_HS_SLAVE_RULE"),
+            (0x108, "SEC_GPIO_MASK0"),
+            (0x118, "SEC_MASK_LOCK"),
+            (0x11c, "MASTER_SEC_LEVEL"),
+            (0x124, "CPU0_LOCK_REG"),
+            (0x130, "MISC_CTRL_REG"),
+        ];
+        assert_eq!(TzPreset::SIZE, 0x134);
+
+        let preset = TzPreset {
+            vector_table: 0x1000_0000,
+            non_secure_vector_table: 0x0004_0000,
+            non_secure_interrupts: [0x11, 0x22],
+            stack_limits: StackLimits {
+                main: 0x3000_0000,
+                process: 0x3000_0004,
+                non_secure_main: 0x3000_0008,
+                non_secure_process: 0x3000_000c,
+            },
+            sau: Sau {
+                enabled: true,
+                all_non_secure: false,
+                regions: vec![
+                    SauRegion {
+                        base: 0x0004_0000,
+                        limit: 0x0009_ffff,
+                        non_secure_callable: false,
+                    };
+                    SAU_REGIONS
+                ],
+            },
+            ahb_secure_controller: AHB_SECURE_CONTROLLER
+                .iter()
+                .enumerate()
+                .map(|(n, name)| (name.to_string(), 0x5000_0000 + n as u32))
+                .collect(),
+        };
+        let bytes = preset.to_bytes().unwrap();
+        for (offset, name) in template {
+            let expected = match *name {
+                "VTOR_S" => 0x1000_0000,
+                "VTOR_NS" => 0x0004_0000,
+                "NVIC_ITNS0" => 0x11,
+                "NVIC_ITNS1" => 0x22,
+                "MSPLIM_S" => 0x3000_0000,
+                "PSPLIM_S" => 0x3000_0004,
+                "MSPLIM_NS" => 0x3000_0008,
+                "PSPLIM_NS" => 0x3000_000c,
+                "SAU_CTRL" => 1,
+                "SAU_RBAR0" => 0x0004_0000,
+                "SAU_RLAR0" | "SAU_RLAR7" => 0x0009_ffe1,
+                name => {
+                    let n = AHB_SECURE_CONTROLLER.iter().position(|r| *r == name);
+                    0x5000_0000 + n.unwrap() as u32
+                }
+            };
+            assert_eq!(
+                word(&bytes, offset / 4),
+                expected,
+                "{} at 0x{:03x}",
+                name,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn yaml() {
+        let preset: TzPreset = serde_yaml::from_str(
+            "vector-table: 0x10000000\nsau:\n  all-non-secure: true\nahb-secure-controller:\n  SEC_GPIO_MASK0: 0xffffffff\n",
+        )
+        .unwrap();
+        assert_eq!(preset.vector_table, 0x1000_0000);
+        assert!(preset.sau.all_non_secure);
+        assert_eq!(preset.ahb_secure_controller["SEC_GPIO_MASK0"], u32::MAX);
+        assert_eq!(word(&preset.to_bytes().unwrap(), 8), 2);
+    }
+
+    #[test]
+    fn invalid() {
+        let region = |base, limit| TzPreset {
+            sau: Sau {
+                regions: vec![SauRegion {
+                    base,
+                    limit,
+                    non_secure_callable: false,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(region(0x1000, 0x1fff).check().is_ok());
+        assert!(region(0x1010, 0x1fff).check().is_err());
+        assert!(region(0x1000, 0x1fe0).check().is_err());
+        assert!(region(0x2000, 0x1fff).check().is_err());
+
+        let mut preset = region(0, 31);
+        preset.sau.regions = vec![preset.sau.regions[0]; 9];
+        assert!(preset.to_bytes().is_err());
+
+        let mut preset = TzPreset::default();
+        preset
+            .ahb_secure_controller
+            .insert("SEC_CTRL_FLASH_MEM_RULE9".into(), 0);
+        let error = preset.check().unwrap_err().to_string();
+        assert!(error.contains("SEC_CTRL_FLASH_MEM_RULE9"));
+    }
+}
@@ -12,38 +12,29 @@ where
     s.serialize_str(&hex::encode(x.as_ref()))
 }
 
-// fn hex_deserialize<'a, 'de, D, T>(deserializer: D) -> Result<T, D::Error>
-// where
-//     D: serde::Deserializer<'de>,
-//     T: From<&'a [u8]>,
-// {
-//     let s: &str = serde::de::Deserialize::deserialize(deserializer)?;
-//     let mut s = String::from(s);
-//     s.retain(|c| !c.is_whitespace());
-//     // let v = hex::decode(&s).expect(format!("Hex decoding failed for {}", &s));
-//     let v = hex::decode(&s).expect("Hex decoding failed!");
-
-//     let t = T::from(&v);
-//     Ok(t)
-// }
-
-// NB: const-generics for this case coming soooon (Rust 1.51?)
-pub fn hex_deserialize_256<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+/// Deserializes `N` bytes from hex digits, whitespace is ignored.
+pub fn hex_deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<T, D::Error>
 where
     D: serde::Deserializer<'de>,
-    T: From<[u8; 32]>,
+    T: From<[u8; N]>,
 {
+    use serde::de::Error as _;
     let s: &str = serde::de::Deserialize::deserialize(deserializer)?;
     let mut s = String::from(s);
     s.retain(|c| !c.is_whitespace());
-    // let v = hex::decode(&s).expect(format!("Hex decoding failed for {}", &s));
-    let v: [u8; 32] = hex::decode(&s)
-        .expect("Hex decoding failed!")
-        .try_into()
-        .unwrap();
+    let v = hex::decode(&s).map_err(|e| D::Error::custom(format!("invalid hex: {}", e)))?;
+    let v: [u8; N] = v.try_into().map_err(|v: Vec<u8>| {
+        D::Error::custom(format!("expected {} bytes of hex, got {}", N, v.len()))
+    })?;
+    Ok(T::from(v))
+}
 
-    let t = T::from(v);
-    Ok(t)
+pub fn hex_deserialize_256<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: From<[u8; 32]>,
+{
+    hex_deserialize(deserializer)
 }
 
 pub fn hex_deserialize_32<'de, D, T>(deserializer: D) -> Result<T, D::Error>
@@ -51,22 +42,12 @@ where
     D: serde::Deserializer<'de>,
     T: From<[u8; 4]>,
 {
-    let s: &str = serde::de::Deserialize::deserialize(deserializer)?;
-    let mut s = String::from(s);
-    s.retain(|c| !c.is_whitespace());
-    // let v = hex::decode(&s).expect(format!("Hex decoding failed for {}", &s));
-    let v: [u8; 4] = hex::decode(&s)
-        .expect("Hex decoding failed!")
-        .try_into()
-        .unwrap();
-
-    let t = T::from(v);
-    Ok(t)
+    hex_deserialize(deserializer)
 }
 
 /// Length after block padding
 pub fn block_pad_len(len: usize) -> usize {
-    16 * ((len + 15) / 16)
+    16 * len.div_ceil(16)
 }
 
 /// Pad to multiple of AES block (16 bytes = 128 bits)
@@ -84,7 +65,7 @@ pub fn block_padded(data: &[u8]) -> Vec<u8> {
 
 /// Length after word-padding
 pub fn word_pad_len(len: usize) -> usize {
-    4 * ((len + 3) / 4)
+    4 * len.div_ceil(4)
 }
 
 /// Pad to multiple of machine word (4 bytes = 32 bits)
@@ -116,7 +116,9 @@ rot-keys-status = ["Enabled", "Enabled", "Enabled", "Enabled"]
         .arg(binfile_path.clone())
         .arg(cfgfile_path);
 
-    cmd.assert().success();
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("warning [checks-skipped]"));
 
     let data = fs::read(binfile_path).expect("Unable to read output customer file");
 
@@ -156,6 +158,8 @@ debug-access = {{ Custom = {{ nonsecure-noninvasive = "Disabled", secure-invasiv
     let mut cmd = Command::cargo_bin("lpc55").unwrap();
     cmd.arg("configure")
         .arg("factory-settings")
+        // DICE is left enabled
+        .arg("--force")
         .arg("-o")
         .arg(binfile_path.clone())
         .arg(cfgfile_path);
@@ -197,6 +201,8 @@ seal = true
     let mut cmd = Command::cargo_bin("lpc55").unwrap();
     cmd.arg("configure")
         .arg("factory-settings")
+        // DICE is left enabled
+        .arg("--force")
         .arg("-o")
         .arg(binfile_path.clone())
         .arg(cfgfile_path);
@@ -212,6 +218,41 @@ seal = true
     );
 }
 
+#[test]
+fn factory_settings_rules() {
+    let dir = tempdir().unwrap();
+
+    let cfgfile_path = dir.path().join("cfg.toml");
+    let binfile_path = dir.path().join("factory.bin");
+
+    fs::write(
+        &cfgfile_path,
+        r#"
+[factory-settings.secure-boot-configuration]
+secure-boot-enabled = true
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["configure", "factory-settings", "-o"])
+        .arg(&binfile_path)
+        .arg(&cfgfile_path);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("error [dice-disabled]"))
+        .stderr(predicate::str::contains("error [secure-boot-needs-rotkh]"))
+        .stderr(predicate::str::contains("use --force to override"));
+    assert!(!binfile_path.exists());
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["configure", "factory-settings", "--force", "-o"])
+        .arg(&binfile_path)
+        .arg(&cfgfile_path);
+    cmd.assert().success();
+    assert_eq!(fs::read(&binfile_path).unwrap().len(), 512);
+}
+
 #[test]
 fn rotkh() {
     let mut cmd = Command::cargo_bin("lpc55").unwrap();
@@ -222,3 +263,805 @@ fn rotkh() {
         "D826E2FD 44F5C254 BC58C62E BF96A938 95C19DC2 25810C95 C8B9E6FD 9F7CC9CB",
     ));
 }
+
+#[test]
+fn sb_show() {
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["sb", "show", "example-binaries/elftosb-blinky-red.sb2"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("signature: ok"))
+        .stdout(predicate::str::contains(
+            "rotkh: D826E2FD44F5C254BC58C62EBF96A93895C19DC225810C95C8B9E6FD9F7CC9CB",
+        ));
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["sb", "show", "example-binaries/blinky-red.sb2", "--sbkek"])
+        .arg("55".repeat(32));
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("does not unwrap"));
+}
+
+fn simulated(state: &std::path::Path) -> Command {
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.arg("--simulator").arg(state);
+    cmd
+}
+
+#[test]
+fn simulated_flash_roundtrip() {
+    let dir = tempdir().unwrap();
+    let state = dir.path().join("simulator.bin");
+
+    let firmware_path = dir.path().join("firmware.bin");
+    let firmware: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    fs::write(&firmware_path, &firmware).unwrap();
+
+    simulated(&state)
+        .arg("write-flash")
+        .arg("--address")
+        .arg("4096")
+        .arg(&firmware_path)
+        .assert()
+        .success();
+
+    let output_path = dir.path().join("readback.bin");
+    simulated(&state)
+        .arg("read-memory")
+        .arg("4096")
+        .arg("1000")
+        .arg("-o")
+        .arg(&output_path)
+        .assert()
+        .success();
+
+    assert_eq!(fs::read(output_path).unwrap(), firmware);
+}
+
+#[test]
+fn simulated_provision_and_info() {
+    let dir = tempdir().unwrap();
+    let state = dir.path().join("simulator.bin");
+
+    simulated(&state)
+        .arg("provision")
+        .arg("example-cfgs/example-cfg.toml")
+        .assert()
+        .success();
+
+    simulated(&state)
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("flash_size: 646656"));
+}
+
+#[test]
+fn simulated_pfr_diff() {
+    let dir = tempdir().unwrap();
+    let state = dir.path().join("simulator.bin");
+    let before_path = dir.path().join("before.bin");
+
+    let output = simulated(&state)
+        .args(["pfr", "--format", "raw"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    fs::write(&before_path, &output.stdout).unwrap();
+
+    let cfgfile_path = dir.path().join("cfg.toml");
+    fs::write(
+        &cfgfile_path,
+        r#"
+[factory-settings]
+debug-access = "Disabled"
+
+[factory-settings.secure-boot-configuration]
+dice-computation-disabled = true
+"#,
+    )
+    .unwrap();
+    simulated(&state)
+        .args(["configure", "factory-settings"])
+        .arg(&cfgfile_path)
+        .assert()
+        .success();
+
+    simulated(&state)
+        .args(["pfr", "diff"])
+        .arg(&before_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "factory-settings.secure-boot-configuration.dice-computation-disabled: (default) -> true",
+        ))
+        .stdout(predicate::str::contains(
+            "factory-settings.debug-access.jtag-tap: Default -> Disabled",
+        ))
+        .stdout(predicate::str::contains("customer.").not());
+
+    simulated(&state)
+        .args(["pfr", "diff"])
+        .arg(&before_path)
+        .arg(&before_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn pfr_erased_dump() {
+    let dir = tempdir().unwrap();
+    let erased = dir.path().join("erased.pfr");
+    fs::write(&erased, [0xffu8; 7 * 512]).unwrap();
+
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["pfr", "diff"])
+        .arg(&erased)
+        .arg(&erased)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not a valid PFR dump"))
+        .stderr(predicate::str::contains("panicked").not());
+
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["pfr", "keystore"])
+        .arg(&erased)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not a valid PFR dump"))
+        .stderr(predicate::str::contains("panicked").not());
+}
+
+#[test]
+fn simulated_pfr_keystore() {
+    let dir = tempdir().unwrap();
+    let state = dir.path().join("simulator.bin");
+
+    simulated(&state)
+        .args(["pfr", "keystore"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("activation code: blank"))
+        .stdout(predicate::str::contains("prince-region-0 (7): not set"));
+
+    // provisions a 16 byte SBKEK, and 16 byte PRINCE keys
+    simulated(&state)
+        .arg("provision")
+        .arg("example-cfgs/example-cfg.toml")
+        .assert()
+        .success();
+
+    simulated(&state)
+        .args(["pfr", "keystore"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("header: 0x95959595 (enrolled)"))
+        .stdout(predicate::str::contains(
+            "prince-region-0 (7): generated key, index 7, 16 bytes",
+        ))
+        .stdout(predicate::str::contains(
+            "error [key-size]: secure-boot-kek is 16 bytes, but needs to be 32 bytes",
+        ))
+        .stderr(predicate::str::contains("1 error(s)"));
+}
+
+#[test]
+fn simulated_keystore_backup_and_restore() {
+    let dir = tempdir().unwrap();
+    let state = dir.path().join("simulator.bin");
+    let backup = dir.path().join("keystore.bin");
+
+    simulated(&state)
+        .arg("provision")
+        .arg("example-cfgs/example-cfg.toml")
+        .assert()
+        .success();
+    simulated(&state)
+        .args(["keystore", "backup", "-o"])
+        .arg(&backup)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "prince-region-0 (7): generated key, index 7, 16 bytes",
+        ));
+
+    // re-enrolling loses the keys
+    let reenroll = dir.path().join("reenroll.toml");
+    fs::write(
+        &reenroll,
+        r#"
+[[provisions]]
+cmd = "Keystore"
+sub-cmd = "Enroll"
+
+[[provisions]]
+cmd = "Keystore"
+sub-cmd = "WriteNonVolatile"
+"#,
+    )
+    .unwrap();
+    simulated(&state)
+        .arg("provision")
+        .arg(&reenroll)
+        .assert()
+        .success();
+    simulated(&state)
+        .args(["pfr", "keystore"])
+        .assert()
+        .stdout(predicate::str::contains("prince-region-0 (7): not set"));
+    simulated(&state)
+        .arg("pfr")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("prince-region-0 (7): not set"));
+
+    simulated(&state)
+        .args(["keystore", "restore"])
+        .arg(&backup)
+        .assert()
+        .success();
+    simulated(&state)
+        .args(["pfr", "keystore"])
+        .assert()
+        .stdout(predicate::str::contains(
+            "prince-region-0 (7): generated key, index 7, 16 bytes",
+        ));
+
+    let mut data = fs::read(&backup).unwrap();
+    data[42] ^= 1;
+    fs::write(&backup, &data).unwrap();
+    simulated(&state)
+        .args(["keystore", "restore"])
+        .arg(&backup)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("checksum mismatch"));
+}
+
+#[test]
+fn simulated_receive_sb_file() {
+    let dir = tempdir().unwrap();
+    let state = dir.path().join("simulator.bin");
+
+    simulated(&state)
+        .arg("receive-sb-file")
+        .arg("example-binaries/blinky-red.sb2")
+        .assert()
+        .success();
+}
+
+#[test]
+fn simulated_rejected_sb_file() {
+    let dir = tempdir().unwrap();
+    let state = dir.path().join("simulator.bin");
+
+    let garbage_path = dir.path().join("garbage.sb2");
+    fs::write(&garbage_path, [0u8; 1024]).unwrap();
+
+    simulated(&state)
+        .arg("receive-sb-file")
+        .arg(&garbage_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "ReceiveSbFile failed with status SbLoader(Signature)",
+        ))
+        .stderr(predicate::str::contains("panicked").not());
+}
+
+#[test]
+fn simulated_fill_memory() {
+    let dir = tempdir().unwrap();
+    let state = dir.path().join("simulator.bin");
+
+    simulated(&state)
+        .args(["fill-memory", "1024", "8", "305419896"])
+        .assert()
+        .success();
+
+    simulated(&state)
+        .args(["read-memory", "1024", "8"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("7856341278563412"));
+
+    // the LPC55 ROM does not support the one time programmable area
+    simulated(&state)
+        .args(["flash-read-once", "0"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("FlashReadOnce failed"));
+}
+
+#[test]
+fn assemble_sb31() {
+    let dir = tempdir().unwrap();
+    let sb3 = dir.path().join("blinky-red.sb3");
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["assemble-sb31", "example-cfgs/example-sb31-cfg.toml"])
+        .arg("--secure-boot-image")
+        .arg(&sb3);
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["sb", "show"]).arg(&sb3);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("description: blinky-red"))
+        .stdout(predicate::str::contains("root keys: 2 on P256, using #0"))
+        .stdout(predicate::str::contains("signature: ok"))
+        .stdout(predicate::str::contains("block hashes: ok"));
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["sb", "show"])
+        .arg(&sb3)
+        .arg("--pck")
+        .arg("55".repeat(32));
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("wrong PCK"));
+}
+
+#[test]
+fn sign_fw_from_elf() {
+    let dir = tempdir().unwrap();
+    let signed = dir.path().join("blinky-red-signed.bin");
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["sign-fw", "example-cfgs/example-cfg.toml"])
+        .args(["--image", "example-binaries/blinky-red.elf"])
+        .arg("--signed-image")
+        .arg(&signed);
+    cmd.assert().success();
+
+    let signed = std::fs::read(&signed).unwrap();
+    // flattened image of 856 bytes, followed by the certificate block
+    assert_eq!(&signed[..4], &[0x00, 0x00, 0x04, 0x20]);
+    assert_eq!(&signed[0x28..0x2c], &856u32.to_le_bytes());
+    assert_eq!(&signed[856..860], b"cert");
+}
+
+#[test]
+fn assemble_confidential_sb() {
+    let dir = tempdir().unwrap();
+    let signed = dir.path().join("blinky-red-signed.bin");
+    let sb2 = dir.path().join("blinky-red.sb2");
+    let sidecar = dir.path().join("blinky-red.sb2.sealed");
+    let sbkek = dir.path().join("sbkek.txt");
+    fs::write(&sbkek, "42".repeat(32)).unwrap();
+
+    let config = dir.path().join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            r#"
+[firmware]
+image = "example-binaries/blinky-red.elf"
+signed-image = "{}"
+secure-boot-image = "{}"
+build = 1
+component = "0.0.0"
+product = "0.0.0"
+
+[pki]
+signing-key = "file:example-file-certs/ca_private_key_0.pem"
+certificates = [
+    "file:example-file-certs/ca_certificate_0.der",
+    "file:example-file-certs/ca_certificate_1.der",
+    "file:example-file-certs/ca_certificate_2.der",
+    "file:example-file-certs/ca_certificate_3.der",
+]
+
+[keystore]
+sbkek = "env:LPC55_CLI_TEST_SBKEK"
+
+[confidentiality]
+enabled = true
+sidecar = "{}"
+
+[[commands]]
+seq = "UploadSignedImage"
+"#,
+            signed.display(),
+            sb2.display(),
+            sidecar.display()
+        ),
+    )
+    .unwrap();
+
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["sign-fw"])
+        .arg(&config)
+        .assert()
+        .success();
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["assemble-sb"])
+        .arg(&config)
+        .env("LPC55_CLI_TEST_SBKEK", "42".repeat(32))
+        .assert()
+        .success();
+
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["sb", "show"])
+        .arg(&sb2)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not unwrap"));
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["sb", "show"])
+        .arg(&sb2)
+        .arg("--config")
+        .arg(&config)
+        .env("LPC55_CLI_TEST_SBKEK", "42".repeat(32))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("signature: ok"));
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["sb", "show"])
+        .arg(&sb2)
+        .arg("--sbkek")
+        .arg(format!("file:{}", sbkek.display()))
+        .assert()
+        .success();
+
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["sb", "unseal"])
+        .arg(&sidecar)
+        .arg("--sbkek")
+        .arg(format!("file:{}", sbkek.display()))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[reproducibility]"))
+        .stdout(predicate::str::contains("dek = "))
+        .stdout(predicate::str::contains("nonce = "));
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["sb", "unseal"])
+        .arg(&sidecar)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not unwrap"));
+}
+
+#[test]
+fn assemble_sb_from_command_file() {
+    let dir = tempdir().unwrap();
+    let sb2 = dir.path().join("blinky-red.sb2");
+    let config = dir.path().join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            r#"
+[firmware]
+secure-boot-image = "{}"
+build = 42
+component = "0.0.0"
+product = "0.0.0"
+
+[pki]
+signing-key = "file:example-file-certs/ca_private_key_0.pem"
+certificates = [
+    "file:example-file-certs/ca_certificate_0.der",
+    "file:example-file-certs/ca_certificate_1.der",
+    "file:example-file-certs/ca_certificate_2.der",
+    "file:example-file-certs/ca_certificate_3.der",
+]
+"#,
+            sb2.display(),
+        ),
+    )
+    .unwrap();
+
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["assemble-sb"])
+        .arg(&config)
+        .args(["--command-file", "example-cfgs/command.bd"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("extern(0)"));
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["assemble-sb"])
+        .arg(&config)
+        .args(["--command-file", "example-cfgs/command.bd"])
+        .arg("example-binaries/blinky-red.elf")
+        .assert()
+        .success();
+
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["sb", "show"])
+        .arg(&sb2)
+        .assert()
+        .success()
+        // buildNumber of the command file
+        .stdout(predicate::str::contains("build number: 1\n"))
+        .stdout(predicate::str::contains(
+            "- EraseRegion { address: 0, bytes: 3072 }",
+        ))
+        .stdout(predicate::str::contains(
+            "- Load { address: 0x0, bytes: 856 }",
+        ));
+}
+
+#[test]
+fn import_elftosb_config() {
+    let dir = tempdir().unwrap();
+    let json = dir.path().join("signing.json");
+    let config = dir.path().join("config.toml");
+    let signed = dir.path().join("blinky-red-signed.bin");
+    fs::write(
+        &json,
+        format!(
+            r#"{{
+    "family": "lpc55xx",
+    "inputImageFile": "example-binaries/blinky-red.elf",
+    "imageLinkAddress": "0x0",
+    "outputImageExecutionTarget": "Internal flash (XIP)",
+    "outputImageAuthenticationType": "Signed",
+    "enableTrustZone": true,
+    "trustZonePresetFile": "tz.json",
+    "rootCertificate0File": "example-file-certs/ca_certificate_0.der",
+    "rootCertificate1File": "example-file-certs/ca_certificate_1.der",
+    "rootCertificate2File": "example-file-certs/ca_certificate_2.der",
+    "rootCertificate3File": "example-file-certs/ca_certificate_3.der",
+    "mainCertChainId": 0,
+    "mainCertPrivateKeyFile": "example-file-certs/ca_private_key_0.pem",
+    "masterBootOutputFile": "{}",
+}}"#,
+            signed.display()
+        ),
+    )
+    .unwrap();
+
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["import-config"])
+        .arg(&json)
+        .arg("-o")
+        .arg(&config)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "not imported: trustZonePresetFile = \"tz.json\"",
+        ));
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .args(["sign-fw"])
+        .arg(&config)
+        .assert()
+        .success();
+    assert_eq!(&fs::read(&signed).unwrap()[856..860], b"cert");
+}
+
+#[test]
+fn cargo_lpc55_signs_packages_and_flashes() {
+    let dir = tempdir().unwrap();
+    let repo = env!("CARGO_MANIFEST_DIR");
+    let manifest = dir.path().join("Cargo.toml");
+    let certificates: Vec<String> = (0..4)
+        .map(|i| {
+            format!(
+                "\"file:{}/example-file-certs/ca_certificate_{}.der\"",
+                repo, i
+            )
+        })
+        .collect();
+    fs::write(
+        &manifest,
+        format!(
+            r#"
+[package]
+name = "blinky"
+version = "0.1.0"
+
+[package.metadata.lpc55.firmware]
+build = 1
+component = "0.0.0"
+product = "0.0.0"
+
+[package.metadata.lpc55.pki]
+signing-key = "file:{repo}/example-file-certs/ca_private_key_0.pem"
+certificates = [{certificates}]
+
+[[package.metadata.lpc55.commands]]
+seq = "UploadSignedImage"
+"#,
+            repo = repo,
+            certificates = certificates.join(", ")
+        ),
+    )
+    .unwrap();
+
+    let elf = dir.path().join("blinky");
+    fs::copy("example-binaries/blinky-red.elf", &elf).unwrap();
+    let state = dir.path().join("simulator.bin");
+
+    let mut cmd = Command::cargo_bin("cargo-lpc55").unwrap();
+    cmd.arg("lpc55")
+        .arg("--manifest-path")
+        .arg(&manifest)
+        .arg("--elf")
+        .arg(&elf)
+        .arg("--flash")
+        .arg("--simulator")
+        .arg(&state);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("flashed"));
+
+    assert!(dir.path().join("blinky.signed.bin").exists());
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["sb", "show"]).arg(dir.path().join("blinky.sb2"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("signature: ok"));
+}
+
+#[test]
+fn verify_fw() {
+    let dir = tempdir().unwrap();
+    let signed = dir.path().join("blinky-red-signed.bin");
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["sign-fw", "example-cfgs/example-cfg.toml"])
+        .args(["--image", "example-binaries/blinky-red.elf"])
+        .arg("--signed-image")
+        .arg(&signed);
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.arg("verify-fw")
+        .arg(&signed)
+        .args(["--config", "example-cfgs/example-cfg.toml"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("slot: 0"))
+        .stdout(predicate::str::contains(
+            "rotkh: D826E2FD44F5C254BC58C62EBF96A93895C19DC225810C95C8B9E6FD9F7CC9CB",
+        ))
+        .stdout(predicate::str::contains("config certificates: ok"));
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.arg("verify-fw")
+        .arg(&signed)
+        .arg("--rot-fingerprint")
+        .arg("00".repeat(32));
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("FAILED"));
+}
+
+#[test]
+fn crc_image() {
+    let dir = tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    let image = dir.path().join("blinky-red-crc.bin");
+    let example = fs::read_to_string("example-cfgs/example-cfg.toml").unwrap();
+    fs::write(
+        &config,
+        example.replace("[firmware]\n", "[firmware]\nimage-type = \"crc-xip\"\n"),
+    )
+    .unwrap();
+
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .arg("sign-fw")
+        .arg(&config)
+        .args(["--image", "example-binaries/blinky-red.elf"])
+        .arg("--signed-image")
+        .arg(&image)
+        .assert()
+        .success();
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .arg("verify-fw")
+        .arg(&image)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("image type: 0x00000005 (crc-xip)"))
+        .stdout(predicate::str::contains("crc: ok"));
+
+    let mut data = fs::read(&image).unwrap();
+    data[0x100] ^= 1;
+    fs::write(&image, data).unwrap();
+    Command::cargo_bin("lpc55")
+        .unwrap()
+        .arg("verify-fw")
+        .arg(&image)
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("crc: FAILED"));
+}
+
+#[test]
+fn debug_credential() {
+    let dir = tempdir().unwrap();
+    let credential = dir.path().join("dc.bin");
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args([
+        "debug-credential",
+        "create",
+        "example-cfgs/example-debug-credential.toml",
+        "--device-uuid",
+        "fedcba9876543210fedcba9876543210",
+        "-o",
+    ])
+    .arg(&credential);
+    cmd.assert().success();
+    assert_eq!(fs::read(&credential).unwrap().len(), 940);
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["debug-credential", "show"]).arg(&credential);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "uuid: fedcba9876543210fedcba9876543210",
+        ))
+        .stdout(predicate::str::contains(
+            "rotkh: D826E2FD44F5C254BC58C62EBF96A93895C19DC225810C95C8B9E6FD9F7CC9CB",
+        ))
+        .stdout(predicate::str::contains("permissions: 0x02bf"))
+        .stdout(predicate::str::contains("signature: ok"));
+
+    // challenge as sent by the device, cf. `debug_auth` tests
+    let challenge = dir.path().join("dac.bin");
+    let response = dir.path().join("dar.bin");
+    fs::write(
+        &challenge,
+        hex::decode(concat!(
+            "0100000001000000fedcba9876543210fedcba987654321000000000",
+            "d826e2fd44f5c254bc58c62ebf96a93895c19dc225810c95c8b9e6fd9f7cc9cb",
+            "0000ffff0000ffff00000000",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        ))
+        .unwrap(),
+    )
+    .unwrap();
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["debug-credential", "respond"])
+        .arg(&challenge)
+        .arg(&credential)
+        .args(["--dck", "file:example-file-certs/dck_private_key.pem", "-o"])
+        .arg(&response);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("jtag_tap: Authenticate"));
+    assert_eq!(fs::read(&response).unwrap().len(), 940 + 4 + 32 + 256);
+
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["debug-credential", "respond"])
+        .arg(&challenge)
+        .arg(&credential)
+        .args([
+            "--dck",
+            "file:example-file-certs/ca_private_key_0.pem",
+            "-o",
+        ])
+        .arg(&response);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("not the Debug Credential Key"));
+
+    let mut data = fs::read(&credential).unwrap();
+    data[8] ^= 1;
+    fs::write(&credential, data).unwrap();
+    let mut cmd = Command::cargo_bin("lpc55").unwrap();
+    cmd.args(["debug-credential", "show"]).arg(&credential);
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("signature: FAILED"));
+}